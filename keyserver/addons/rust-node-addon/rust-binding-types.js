@@ -1,7 +1,10 @@
 // @flow
 
 import type { SignedIdentityKeysBlob } from 'lib/types/crypto-types.js';
-import type { UserLoginResponse } from 'lib/types/identity-service-types.js';
+import type {
+  TotpEnrollment,
+  UserLoginResponse,
+} from 'lib/types/identity-service-types.js';
 
 type InboundKeyInfoResponse = {
   +payload: string,
@@ -25,6 +28,10 @@ type RustNativeBindingAPI = {
     contentOneTimeKeys: $ReadOnlyArray<string>,
     notifOneTimeKeys: $ReadOnlyArray<string>,
   ) => Promise<UserLoginResponse>,
+  +loginUserTwoFactor: (
+    twoFactorSessionId: string,
+    code: string,
+  ) => Promise<UserLoginResponse>,
   +registerUser: (
     username: string,
     password: string,
@@ -57,6 +64,28 @@ type RustNativeBindingAPI = {
     contentOneTimePreKeys: $ReadOnlyArray<string>,
     notifOneTimePreKeys: $ReadOnlyArray<string>,
   ) => Promise<boolean>,
+  +enrollTotp: (
+    userId: string,
+    deviceId: string,
+    accessToken: string,
+  ) => Promise<TotpEnrollment>,
+  +confirmTotp: (
+    userId: string,
+    deviceId: string,
+    accessToken: string,
+    code: string,
+  ) => Promise<$ReadOnlyArray<string>>,
+  +disableTotp: (
+    userId: string,
+    deviceId: string,
+    accessToken: string,
+    code: string,
+  ) => Promise<boolean>,
+  +refreshAccessToken: (
+    userId: string,
+    deviceId: string,
+    accessToken: string,
+  ) => Promise<string>,
   +getInboundKeysForUserDevice: (
     identifierType: string,
     identifierValue: string,
@@ -80,6 +80,7 @@ pub async fn login_user(
   let user_info = UserLoginInfo {
     user_id: login_finish_response.user_id,
     access_token: login_finish_response.access_token,
+    two_factor_session_id: login_finish_response.two_factor_session_id,
   };
 
   Ok(user_info)
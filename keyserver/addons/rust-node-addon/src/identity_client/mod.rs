@@ -2,8 +2,10 @@ pub mod add_reserved_usernames;
 pub mod get_inbound_keys_for_user;
 pub mod login;
 pub mod prekey;
+pub mod refresh_access_token;
 pub mod register_user;
 pub mod remove_reserved_usernames;
+pub mod two_factor;
 pub mod upload_one_time_keys;
 
 use client_proto::identity_client_service_client::IdentityClientServiceClient;
@@ -120,6 +122,9 @@ pub struct SignedIdentityKeysBlob {
 pub struct UserLoginInfo {
   pub user_id: String,
   pub access_token: String,
+  // Set instead of the access token if the user has two-factor
+  // authentication enabled, see `login_user_two_factor`
+  pub two_factor_session_id: Option<String>,
 }
 
 #[napi(object)]
@@ -0,0 +1,30 @@
+use super::*;
+
+use grpc_clients::identity::protos::unauthenticated::RefreshAccessTokenRequest;
+use tracing::debug;
+
+/// Returns the new access token. The previous one stops working.
+#[napi]
+#[instrument(skip_all)]
+pub async fn refresh_access_token(
+  user_id: String,
+  device_id: String,
+  access_token: String,
+) -> Result<String> {
+  let mut identity_client = get_identity_client().await?;
+
+  let refresh_request = RefreshAccessTokenRequest {
+    access_token,
+    user_id,
+    device_id_key: device_id,
+  };
+
+  debug!("Refreshing access token");
+  let response = identity_client
+    .refresh_access_token(refresh_request)
+    .await
+    .map_err(handle_grpc_error)?
+    .into_inner();
+
+  Ok(response.access_token)
+}
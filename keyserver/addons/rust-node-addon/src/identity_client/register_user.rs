@@ -78,6 +78,7 @@ pub async fn register_user(
   let user_info = UserLoginInfo {
     user_id: registration_response.user_id,
     access_token: registration_response.access_token,
+    two_factor_session_id: None,
   };
 
   Ok(user_info)
@@ -0,0 +1,105 @@
+use super::*;
+
+use grpc_clients::identity::protos::authenticated::{
+  ConfirmTotpRequest, DisableTotpRequest,
+};
+use grpc_clients::identity::protos::unauthenticated::{
+  Empty, TwoFactorLoginRequest,
+};
+use tracing::debug;
+
+#[napi(object)]
+pub struct TotpEnrollment {
+  #[napi(js_name = "otpauthURI")]
+  pub otpauth_uri: String,
+  pub secret: String,
+}
+
+#[napi]
+#[instrument(skip_all)]
+pub async fn login_user_two_factor(
+  two_factor_session_id: String,
+  code: String,
+) -> Result<UserLoginInfo> {
+  let mut identity_client = get_identity_client().await?;
+
+  debug!("Sending second factor to identity service");
+  let login_response = identity_client
+    .login_password_user_two_factor(TwoFactorLoginRequest {
+      two_factor_session_id,
+      code,
+    })
+    .await
+    .map_err(handle_grpc_error)?
+    .into_inner();
+
+  let user_info = UserLoginInfo {
+    user_id: login_response.user_id,
+    access_token: login_response.access_token,
+    two_factor_session_id: None,
+  };
+
+  Ok(user_info)
+}
+
+#[napi]
+#[instrument(skip_all)]
+pub async fn enroll_totp(
+  user_id: String,
+  device_id: String,
+  access_token: String,
+) -> Result<TotpEnrollment> {
+  let mut identity_client =
+    get_authenticated_identity_client(user_id, device_id, access_token).await?;
+
+  let response = identity_client
+    .enroll_totp(Empty {})
+    .await
+    .map_err(handle_grpc_error)?
+    .into_inner();
+
+  Ok(TotpEnrollment {
+    otpauth_uri: response.otpauth_uri,
+    secret: response.secret,
+  })
+}
+
+/// Returns the recovery codes, which are shown to the user only once
+#[napi]
+#[instrument(skip_all)]
+pub async fn confirm_totp(
+  user_id: String,
+  device_id: String,
+  access_token: String,
+  code: String,
+) -> Result<Vec<String>> {
+  let mut identity_client =
+    get_authenticated_identity_client(user_id, device_id, access_token).await?;
+
+  let response = identity_client
+    .confirm_totp(ConfirmTotpRequest { code })
+    .await
+    .map_err(handle_grpc_error)?
+    .into_inner();
+
+  Ok(response.recovery_codes)
+}
+
+#[napi]
+#[instrument(skip_all)]
+pub async fn disable_totp(
+  user_id: String,
+  device_id: String,
+  access_token: String,
+  code: String,
+) -> Result<bool> {
+  let mut identity_client =
+    get_authenticated_identity_client(user_id, device_id, access_token).await?;
+
+  identity_client
+    .disable_totp(DisableTotpRequest { code })
+    .await
+    .map_err(handle_grpc_error)?;
+
+  Ok(true)
+}
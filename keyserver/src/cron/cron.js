@@ -25,7 +25,10 @@ import { deleteStaleSIWENonceEntries } from '../deleters/siwe-nonce-deleters.js'
 import { deleteInaccessibleThreads } from '../deleters/thread-deleters.js';
 import { deleteExpiredUpdates } from '../deleters/update-deleters.js';
 import { deleteUnassignedUploads } from '../deleters/upload-deleters.js';
+import { createAndMaintainTunnelbrokerWebsocket } from '../socket/tunnelbroker.js';
 import { fetchCallUpdateOlmAccount } from '../updaters/olm-account-updater.js';
+import { fetchIdentityInfo } from '../user/identity.js';
+import { refreshIdentityAccessToken } from '../user/login.js';
 import { validateAndUploadAccountPrekeys } from '../utils/olm-utils.js';
 
 if (cluster.isMaster) {
@@ -126,4 +129,25 @@ if (cluster.isMaster) {
       }
     },
   );
+  schedule.scheduleJob(
+    '0 4 * * *', // every day at 4:00 AM in the keyserver's timezone
+    async () => {
+      try {
+        const identityInfo = await fetchIdentityInfo();
+        if (!identityInfo) {
+          return;
+        }
+        const refreshedIdentityInfo =
+          await refreshIdentityAccessToken(identityInfo);
+        // Tunnelbroker closes connections authenticated with the previous
+        // token
+        await createAndMaintainTunnelbrokerWebsocket(refreshedIdentityInfo);
+      } catch (e) {
+        console.warn(
+          'encountered error while trying to refresh identity access token',
+          e,
+        );
+      }
+    },
+  );
 }
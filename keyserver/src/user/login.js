@@ -16,6 +16,7 @@ import { getMessageForException } from '../responders/utils.js';
 import { fetchCallUpdateOlmAccount } from '../updaters/olm-account-updater.js';
 import {
   getAccountPrekeysSet,
+  getContentSigningKey,
   validateAccountPrekey,
 } from '../utils/olm-utils.js';
 
@@ -58,7 +59,15 @@ async function verifyUserLoggedIn(): Promise<IdentityInfo> {
   const result = await fetchIdentityInfo();
 
   if (result) {
-    return result;
+    // Refreshing also checks that the token hasn't expired or been revoked
+    try {
+      return await refreshIdentityAccessToken(result);
+    } catch (e) {
+      console.warn(
+        'Failed to refresh identity access token: ' +
+          getMessageForException(e),
+      );
+    }
   }
 
   const identityInfo = await registerOrLogin();
@@ -167,4 +176,23 @@ async function registerOrLogin(): Promise<IdentityInfo> {
   }
 }
 
-export { verifyUserLoggedIn };
+// Access tokens expire, so they're refreshed daily. The previous token stops
+// working.
+async function refreshIdentityAccessToken(
+  identityInfo: IdentityInfo,
+): Promise<IdentityInfo> {
+  const [rustAPI, deviceID] = await Promise.all([
+    getRustAPI(),
+    getContentSigningKey(),
+  ]);
+  const accessToken = await rustAPI.refreshAccessToken(
+    identityInfo.userId,
+    deviceID,
+    identityInfo.accessToken,
+  );
+  const refreshedIdentityInfo = { userId: identityInfo.userId, accessToken };
+  await saveIdentityInfo(refreshedIdentityInfo);
+  return refreshedIdentityInfo;
+}
+
+export { verifyUserLoggedIn, refreshIdentityAccessToken };
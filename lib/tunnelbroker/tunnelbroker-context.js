@@ -152,6 +152,11 @@ function TunnelbrokerProvider(props: Props): React.Node {
             console.log('SerializationError for message: ', status.data);
           } else if (status.type === 'InvalidRequest') {
             console.log('Tunnelbroker recorded InvalidRequest');
+          } else if (status.type === 'Throttled') {
+            promises.current[status.data.id]?.reject(
+              `Throttled, retry after ${status.data.retryAfterMs}ms`,
+            );
+            delete promises.current[status.data.id];
           }
         }
       } else if (message.type === tunnelbrokerMessageTypes.HEARTBEAT) {
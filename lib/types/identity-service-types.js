@@ -3,6 +3,14 @@
 export type UserLoginResponse = {
   +userId: string,
   +accessToken: string,
+  // Set instead of the access token if the user has two-factor
+  // authentication enabled
+  +twoFactorSessionId?: ?string,
+};
+
+export type TotpEnrollment = {
+  +otpauthURI: string,
+  +secret: string,
 };
 
 // This type should not be altered without also updating
@@ -0,0 +1,32 @@
+// @flow
+
+import type { TInterface } from 'tcomb';
+import t from 'tcomb';
+
+import { tShape, tString } from '../../utils/validation-utils.js';
+
+export type DevicePresenceSubscription = {
+  +type: 'DevicePresenceSubscription',
+  +subscribe: boolean,
+};
+
+export const devicePresenceSubscriptionValidator: TInterface<DevicePresenceSubscription> =
+  tShape<DevicePresenceSubscription>({
+    type: tString('DevicePresenceSubscription'),
+    subscribe: t.Boolean,
+  });
+
+export type DevicePresenceUpdate = {
+  +type: 'DevicePresenceUpdate',
+  +deviceID: string,
+  +connected: boolean,
+  +connectedSince?: ?string,
+};
+
+export const devicePresenceUpdateValidator: TInterface<DevicePresenceUpdate> =
+  tShape<DevicePresenceUpdate>({
+    type: tString('DevicePresenceUpdate'),
+    deviceID: t.String,
+    connected: t.Boolean,
+    connectedSince: t.maybe(t.String),
+  });
@@ -0,0 +1,21 @@
+// @flow
+
+import type { TInterface } from 'tcomb';
+import t from 'tcomb';
+
+import { tShape, tString } from '../../utils/validation-utils.js';
+
+export type MessageDelivered = {
+  +type: 'MessageDelivered',
+  +messageID: string,
+  +clientMessageID: string,
+  +deviceID: string,
+};
+
+export const messageDeliveredValidator: TInterface<MessageDelivered> =
+  tShape<MessageDelivered>({
+    type: tString('MessageDelivered'),
+    messageID: t.String,
+    clientMessageID: t.String,
+    deviceID: t.String,
+  });
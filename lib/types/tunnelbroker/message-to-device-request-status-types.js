@@ -15,17 +15,29 @@ const failureValidator: TInterface<Failure> = tShape<Failure>({
   error: t.String,
 });
 
+export type Throttled = {
+  +id: string,
+  +retryAfterMs: number,
+};
+
+const throttledValidator: TInterface<Throttled> = tShape<Throttled>({
+  id: t.String,
+  retryAfterMs: t.Number,
+});
+
 export type MessageSentStatus =
   | { +type: 'Success', +data: string }
   | { +type: 'Error', +data: Failure }
   | { +type: 'InvalidRequest' }
-  | { +type: 'SerializationError', +data: string };
+  | { +type: 'SerializationError', +data: string }
+  | { +type: 'Throttled', +data: Throttled };
 
 const messageSentStatusValidator = t.union([
   tShape({ type: tString('Success'), data: t.String }),
   tShape({ type: tString('Error'), data: failureValidator }),
   tShape({ type: tString('InvalidRequest') }),
   tShape({ type: tString('SerializationError'), data: t.String }),
+  tShape({ type: tString('Throttled'), data: throttledValidator }),
 ]);
 
 export type MessageToDeviceRequestStatus = {
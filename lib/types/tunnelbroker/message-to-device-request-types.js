@@ -10,6 +10,7 @@ export type MessageToDeviceRequest = {
   +clientMessageID: string,
   +deviceID: string,
   +payload: string,
+  +requestDeliveryReceipt?: boolean,
 };
 
 export const messageToDeviceRequestValidator: TInterface<MessageToDeviceRequest> =
@@ -18,4 +19,5 @@ export const messageToDeviceRequestValidator: TInterface<MessageToDeviceRequest>
     clientMessageID: t.String,
     deviceID: t.String,
     payload: t.String,
+    requestDeliveryReceipt: t.maybe(t.Boolean),
   });
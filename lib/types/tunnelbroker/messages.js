@@ -7,11 +7,21 @@ import {
   type ConnectionInitializationResponse,
   connectionInitializationResponseValidator,
 } from './connection-initialization-response-types.js';
+import {
+  type DevicePresenceSubscription,
+  devicePresenceSubscriptionValidator,
+  type DevicePresenceUpdate,
+  devicePresenceUpdateValidator,
+} from './device-presence-types.js';
 import { type Heartbeat, heartbeatValidator } from './heartbeat-types.js';
 import {
   type RefreshKeyRequest,
   refreshKeysRequestValidator,
 } from './keys-types.js';
+import {
+  type MessageDelivered,
+  messageDeliveredValidator,
+} from './message-delivered-types.js';
 import {
   type MessageReceiveConfirmation,
   messageReceiveConfirmationValidator,
@@ -28,6 +38,10 @@ import {
   type MessageToDevice,
   messageToDeviceValidator,
 } from './message-to-device-types.js';
+import {
+  type ServerShutdown,
+  serverShutdownValidator,
+} from './server-shutdown-types.js';
 import {
   type ConnectionInitializationMessage,
   connectionInitializationMessageValidator,
@@ -52,6 +66,10 @@ export const tunnelbrokerMessageTypes = Object.freeze({
   MESSAGE_TO_DEVICE_REQUEST: 'MessageToDeviceRequest',
   MESSAGE_TO_DEVICE: 'MessageToDevice',
   MESSAGE_RECEIVE_CONFIRMATION: 'MessageReceiveConfirmation',
+  MESSAGE_DELIVERED: 'MessageDelivered',
+  DEVICE_PRESENCE_SUBSCRIPTION: 'DevicePresenceSubscription',
+  DEVICE_PRESENCE_UPDATE: 'DevicePresenceUpdate',
+  SERVER_SHUTDOWN: 'ServerShutdown',
   HEARTBEAT: 'Heartbeat',
 });
 
@@ -64,6 +82,10 @@ export const tunnelbrokerMessageValidator: TUnion<TunnelbrokerMessage> =
     messageToDeviceRequestValidator,
     messageToDeviceValidator,
     messageReceiveConfirmationValidator,
+    messageDeliveredValidator,
+    devicePresenceSubscriptionValidator,
+    devicePresenceUpdateValidator,
+    serverShutdownValidator,
     heartbeatValidator,
   ]);
 
@@ -75,4 +97,8 @@ export type TunnelbrokerMessage =
   | MessageToDeviceRequest
   | MessageToDevice
   | MessageReceiveConfirmation
+  | MessageDelivered
+  | DevicePresenceSubscription
+  | DevicePresenceUpdate
+  | ServerShutdown
   | Heartbeat;
@@ -0,0 +1,17 @@
+// @flow
+
+import type { TInterface } from 'tcomb';
+import t from 'tcomb';
+
+import { tShape, tString } from '../../utils/validation-utils.js';
+
+export type ServerShutdown = {
+  +type: 'ServerShutdown',
+  +maxReconnectDelayMs: number,
+};
+
+export const serverShutdownValidator: TInterface<ServerShutdown> =
+  tShape<ServerShutdown>({
+    type: tString('ServerShutdown'),
+    maxReconnectDelayMs: t.Number,
+  });
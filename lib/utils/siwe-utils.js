@@ -24,6 +24,9 @@ function isValidPrimaryIdentityPublicKey(candidate: string): boolean {
 const siweStatementLegalAgreement: string =
   'By continuing, I accept the Comm Terms of Service: https://comm.app/terms';
 
+// The identity service rejects SIWE messages without an expiration time
+const siweMessageLifetime = 10 * 60 * 1000; // ten minutes
+
 function createSIWEMessage(
   address: string,
   statement: string,
@@ -40,6 +43,7 @@ function createSIWEMessage(
     version: '1',
     chainId: '1',
     nonce,
+    expirationTime: new Date(Date.now() + siweMessageLifetime).toISOString(),
   });
   return message.prepareMessage();
 }
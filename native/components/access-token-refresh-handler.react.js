@@ -0,0 +1,82 @@
+// @flow
+
+import AsyncStorage from '@react-native-async-storage/async-storage';
+import * as React from 'react';
+import { useDispatch } from 'react-redux';
+
+import { setAccessTokenActionType } from 'lib/actions/user-actions.js';
+import { getMessageForException } from 'lib/utils/errors.js';
+
+import { commCoreModule, commRustModule } from '../native-modules.js';
+import { useSelector } from '../redux/redux-utils.js';
+
+const ACCESS_TOKEN_REFRESH_TIME_STORAGE_KEY = 'ACCESS_TOKEN_REFRESH_TIME';
+// Access tokens expire after 30 days, and can be refreshed for a week after
+// that, so a daily refresh keeps devices logged in
+const accessTokenRefreshInterval = 24 * 60 * 60 * 1000; // one day
+
+function AccessTokenRefreshHandler(): null {
+  const accessToken = useSelector(state => state.commServicesAccessToken);
+  const dispatch = useDispatch();
+
+  React.useEffect(() => {
+    if (!accessToken) {
+      return undefined;
+    }
+
+    const refreshAccessToken = async () => {
+      try {
+        const { userID, deviceID } =
+          await commCoreModule.getCommServicesAuthMetadata();
+        if (!userID || !deviceID) {
+          return;
+        }
+        const newAccessToken = await commRustModule.refreshAccessToken(
+          userID,
+          deviceID,
+          accessToken,
+        );
+        await commCoreModule.setCommServicesAccessToken(newAccessToken);
+        await AsyncStorage.setItem(
+          ACCESS_TOKEN_REFRESH_TIME_STORAGE_KEY,
+          Date.now().toString(),
+        );
+        dispatch({
+          type: setAccessTokenActionType,
+          payload: newAccessToken,
+        });
+      } catch (e) {
+        console.log(
+          `Failed to refresh access token: ${getMessageForException(e) ?? ''}`,
+        );
+      }
+    };
+
+    let cancelled = false;
+    let timeoutID: ?TimeoutID;
+    (async () => {
+      const lastRefreshTime = Number(
+        await AsyncStorage.getItem(ACCESS_TOKEN_REFRESH_TIME_STORAGE_KEY),
+      );
+      if (cancelled) {
+        return;
+      }
+      const delay = Math.max(
+        0,
+        lastRefreshTime + accessTokenRefreshInterval - Date.now(),
+      );
+      timeoutID = setTimeout(refreshAccessToken, delay);
+    })();
+
+    return () => {
+      cancelled = true;
+      if (timeoutID) {
+        clearTimeout(timeoutID);
+      }
+    };
+  }, [accessToken, dispatch]);
+
+  return null;
+}
+
+export default AccessTokenRefreshHandler;
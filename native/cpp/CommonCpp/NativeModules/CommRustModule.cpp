@@ -123,6 +123,28 @@ jsi::Value CommRustModule::loginPasswordUser(
       });
 }
 
+jsi::Value CommRustModule::loginPasswordUserTwoFactor(
+    jsi::Runtime &rt,
+    jsi::String twoFactorSessionID,
+    jsi::String code) {
+  return createPromiseAsJSIValue(
+      rt,
+      [this, &twoFactorSessionID, &code](
+          jsi::Runtime &innerRt, std::shared_ptr<Promise> promise) {
+        std::string error;
+        try {
+          auto currentID = RustPromiseManager::instance.addPromise(
+              promise, this->jsInvoker_, innerRt);
+          identityLoginPasswordUserTwoFactor(
+              jsiStringToRustString(twoFactorSessionID, innerRt),
+              jsiStringToRustString(code, innerRt),
+              currentID);
+        } catch (const std::exception &e) {
+          error = e.what();
+        };
+      });
+}
+
 jsi::Value CommRustModule::loginWalletUser(
     jsi::Runtime &rt,
     jsi::String siweMessage,
@@ -223,6 +245,106 @@ jsi::Value CommRustModule::deleteUser(
       });
 }
 
+jsi::Value CommRustModule::refreshAccessToken(
+    jsi::Runtime &rt,
+    jsi::String userID,
+    jsi::String deviceID,
+    jsi::String accessToken) {
+  return createPromiseAsJSIValue(
+      rt,
+      [this, &userID, &deviceID, &accessToken](
+          jsi::Runtime &innerRt, std::shared_ptr<Promise> promise) {
+        std::string error;
+        try {
+          auto currentID = RustPromiseManager::instance.addPromise(
+              promise, this->jsInvoker_, innerRt);
+          identityRefreshAccessToken(
+              jsiStringToRustString(userID, innerRt),
+              jsiStringToRustString(deviceID, innerRt),
+              jsiStringToRustString(accessToken, innerRt),
+              currentID);
+        } catch (const std::exception &e) {
+          error = e.what();
+        };
+      });
+}
+
+jsi::Value CommRustModule::enrollTotp(
+    jsi::Runtime &rt,
+    jsi::String userID,
+    jsi::String deviceID,
+    jsi::String accessToken) {
+  return createPromiseAsJSIValue(
+      rt,
+      [this, &userID, &deviceID, &accessToken](
+          jsi::Runtime &innerRt, std::shared_ptr<Promise> promise) {
+        std::string error;
+        try {
+          auto currentID = RustPromiseManager::instance.addPromise(
+              promise, this->jsInvoker_, innerRt);
+          identityEnrollTotp(
+              jsiStringToRustString(userID, innerRt),
+              jsiStringToRustString(deviceID, innerRt),
+              jsiStringToRustString(accessToken, innerRt),
+              currentID);
+        } catch (const std::exception &e) {
+          error = e.what();
+        };
+      });
+}
+
+jsi::Value CommRustModule::confirmTotp(
+    jsi::Runtime &rt,
+    jsi::String userID,
+    jsi::String deviceID,
+    jsi::String accessToken,
+    jsi::String code) {
+  return createPromiseAsJSIValue(
+      rt,
+      [this, &userID, &deviceID, &accessToken, &code](
+          jsi::Runtime &innerRt, std::shared_ptr<Promise> promise) {
+        std::string error;
+        try {
+          auto currentID = RustPromiseManager::instance.addPromise(
+              promise, this->jsInvoker_, innerRt);
+          identityConfirmTotp(
+              jsiStringToRustString(userID, innerRt),
+              jsiStringToRustString(deviceID, innerRt),
+              jsiStringToRustString(accessToken, innerRt),
+              jsiStringToRustString(code, innerRt),
+              currentID);
+        } catch (const std::exception &e) {
+          error = e.what();
+        };
+      });
+}
+
+jsi::Value CommRustModule::disableTotp(
+    jsi::Runtime &rt,
+    jsi::String userID,
+    jsi::String deviceID,
+    jsi::String accessToken,
+    jsi::String code) {
+  return createPromiseAsJSIValue(
+      rt,
+      [this, &userID, &deviceID, &accessToken, &code](
+          jsi::Runtime &innerRt, std::shared_ptr<Promise> promise) {
+        std::string error;
+        try {
+          auto currentID = RustPromiseManager::instance.addPromise(
+              promise, this->jsInvoker_, innerRt);
+          identityDisableTotp(
+              jsiStringToRustString(userID, innerRt),
+              jsiStringToRustString(deviceID, innerRt),
+              jsiStringToRustString(accessToken, innerRt),
+              jsiStringToRustString(code, innerRt),
+              currentID);
+        } catch (const std::exception &e) {
+          error = e.what();
+        };
+      });
+}
+
 jsi::Value CommRustModule::getOutboundKeysForUserDevice(
     jsi::Runtime &rt,
     jsi::String identifierType,
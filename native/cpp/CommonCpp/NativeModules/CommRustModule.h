@@ -36,6 +36,10 @@ class CommRustModule : public facebook::react::CommRustModuleSchemaCxxSpecJSI {
       jsi::String notifPrekeySignature,
       jsi::Array contentOneTimeKeys,
       jsi::Array notifOneTimeKeys) override;
+  virtual jsi::Value loginPasswordUserTwoFactor(
+      jsi::Runtime &rt,
+      jsi::String twoFactorSessionID,
+      jsi::String code) override;
   virtual jsi::Value loginWalletUser(
       jsi::Runtime &rt,
       jsi::String siweMessage,
@@ -60,6 +64,28 @@ class CommRustModule : public facebook::react::CommRustModuleSchemaCxxSpecJSI {
       jsi::String userID,
       jsi::String deviceID,
       jsi::String accessToken) override;
+  virtual jsi::Value refreshAccessToken(
+      jsi::Runtime &rt,
+      jsi::String userID,
+      jsi::String deviceID,
+      jsi::String accessToken) override;
+  virtual jsi::Value enrollTotp(
+      jsi::Runtime &rt,
+      jsi::String userID,
+      jsi::String deviceID,
+      jsi::String accessToken) override;
+  virtual jsi::Value confirmTotp(
+      jsi::Runtime &rt,
+      jsi::String userID,
+      jsi::String deviceID,
+      jsi::String accessToken,
+      jsi::String code) override;
+  virtual jsi::Value disableTotp(
+      jsi::Runtime &rt,
+      jsi::String userID,
+      jsi::String deviceID,
+      jsi::String accessToken,
+      jsi::String code) override;
   virtual jsi::Value getOutboundKeysForUserDevice(
       jsi::Runtime &rt,
       jsi::String identifierType,
@@ -23,9 +23,14 @@ public:
   virtual jsi::Value generateNonce(jsi::Runtime &rt) = 0;
   virtual jsi::Value registerUser(jsi::Runtime &rt, jsi::String username, jsi::String password, jsi::String keyPayload, jsi::String keyPayloadSignature, jsi::String contentPrekey, jsi::String contentPrekeySignature, jsi::String notifPrekey, jsi::String notifPrekeySignature, jsi::Array contentOneTimeKeys, jsi::Array notifOneTimeKeys) = 0;
   virtual jsi::Value loginPasswordUser(jsi::Runtime &rt, jsi::String username, jsi::String password, jsi::String keyPayload, jsi::String keyPayloadSignature, jsi::String contentPrekey, jsi::String contentPrekeySignature, jsi::String notifPrekey, jsi::String notifPrekeySignature, jsi::Array contentOneTimeKeys, jsi::Array notifOneTimeKeys) = 0;
+  virtual jsi::Value loginPasswordUserTwoFactor(jsi::Runtime &rt, jsi::String twoFactorSessionID, jsi::String code) = 0;
   virtual jsi::Value loginWalletUser(jsi::Runtime &rt, jsi::String siweMessage, jsi::String siweSignature, jsi::String keyPayload, jsi::String keyPayloadSignature, jsi::String contentPrekey, jsi::String contentPrekeySignature, jsi::String notifPrekey, jsi::String notifPrekeySignature, jsi::Array contentOneTimeKeys, jsi::Array notifOneTimeKeys, jsi::String socialProof) = 0;
   virtual jsi::Value updatePassword(jsi::Runtime &rt, jsi::String userID, jsi::String deviceID, jsi::String accessToken, jsi::String password) = 0;
   virtual jsi::Value deleteUser(jsi::Runtime &rt, jsi::String userID, jsi::String deviceID, jsi::String accessToken) = 0;
+  virtual jsi::Value refreshAccessToken(jsi::Runtime &rt, jsi::String userID, jsi::String deviceID, jsi::String accessToken) = 0;
+  virtual jsi::Value enrollTotp(jsi::Runtime &rt, jsi::String userID, jsi::String deviceID, jsi::String accessToken) = 0;
+  virtual jsi::Value confirmTotp(jsi::Runtime &rt, jsi::String userID, jsi::String deviceID, jsi::String accessToken, jsi::String code) = 0;
+  virtual jsi::Value disableTotp(jsi::Runtime &rt, jsi::String userID, jsi::String deviceID, jsi::String accessToken, jsi::String code) = 0;
   virtual jsi::Value getOutboundKeysForUserDevice(jsi::Runtime &rt, jsi::String identifierType, jsi::String identifierValue, jsi::String deviceID) = 0;
 
 };
@@ -72,6 +77,14 @@ private:
       return bridging::callFromJs<jsi::Value>(
           rt, &T::loginPasswordUser, jsInvoker_, instance_, std::move(username), std::move(password), std::move(keyPayload), std::move(keyPayloadSignature), std::move(contentPrekey), std::move(contentPrekeySignature), std::move(notifPrekey), std::move(notifPrekeySignature), std::move(contentOneTimeKeys), std::move(notifOneTimeKeys));
     }
+    jsi::Value loginPasswordUserTwoFactor(jsi::Runtime &rt, jsi::String twoFactorSessionID, jsi::String code) override {
+      static_assert(
+          bridging::getParameterCount(&T::loginPasswordUserTwoFactor) == 3,
+          "Expected loginPasswordUserTwoFactor(...) to have 3 parameters");
+
+      return bridging::callFromJs<jsi::Value>(
+          rt, &T::loginPasswordUserTwoFactor, jsInvoker_, instance_, std::move(twoFactorSessionID), std::move(code));
+    }
     jsi::Value loginWalletUser(jsi::Runtime &rt, jsi::String siweMessage, jsi::String siweSignature, jsi::String keyPayload, jsi::String keyPayloadSignature, jsi::String contentPrekey, jsi::String contentPrekeySignature, jsi::String notifPrekey, jsi::String notifPrekeySignature, jsi::Array contentOneTimeKeys, jsi::Array notifOneTimeKeys, jsi::String socialProof) override {
       static_assert(
           bridging::getParameterCount(&T::loginWalletUser) == 12,
@@ -96,6 +109,38 @@ private:
       return bridging::callFromJs<jsi::Value>(
           rt, &T::deleteUser, jsInvoker_, instance_, std::move(userID), std::move(deviceID), std::move(accessToken));
     }
+    jsi::Value refreshAccessToken(jsi::Runtime &rt, jsi::String userID, jsi::String deviceID, jsi::String accessToken) override {
+      static_assert(
+          bridging::getParameterCount(&T::refreshAccessToken) == 4,
+          "Expected refreshAccessToken(...) to have 4 parameters");
+
+      return bridging::callFromJs<jsi::Value>(
+          rt, &T::refreshAccessToken, jsInvoker_, instance_, std::move(userID), std::move(deviceID), std::move(accessToken));
+    }
+    jsi::Value enrollTotp(jsi::Runtime &rt, jsi::String userID, jsi::String deviceID, jsi::String accessToken) override {
+      static_assert(
+          bridging::getParameterCount(&T::enrollTotp) == 4,
+          "Expected enrollTotp(...) to have 4 parameters");
+
+      return bridging::callFromJs<jsi::Value>(
+          rt, &T::enrollTotp, jsInvoker_, instance_, std::move(userID), std::move(deviceID), std::move(accessToken));
+    }
+    jsi::Value confirmTotp(jsi::Runtime &rt, jsi::String userID, jsi::String deviceID, jsi::String accessToken, jsi::String code) override {
+      static_assert(
+          bridging::getParameterCount(&T::confirmTotp) == 5,
+          "Expected confirmTotp(...) to have 5 parameters");
+
+      return bridging::callFromJs<jsi::Value>(
+          rt, &T::confirmTotp, jsInvoker_, instance_, std::move(userID), std::move(deviceID), std::move(accessToken), std::move(code));
+    }
+    jsi::Value disableTotp(jsi::Runtime &rt, jsi::String userID, jsi::String deviceID, jsi::String accessToken, jsi::String code) override {
+      static_assert(
+          bridging::getParameterCount(&T::disableTotp) == 5,
+          "Expected disableTotp(...) to have 5 parameters");
+
+      return bridging::callFromJs<jsi::Value>(
+          rt, &T::disableTotp, jsInvoker_, instance_, std::move(userID), std::move(deviceID), std::move(accessToken), std::move(code));
+    }
     jsi::Value getOutboundKeysForUserDevice(jsi::Runtime &rt, jsi::String identifierType, jsi::String identifierValue, jsi::String deviceID) override {
       static_assert(
           bridging::getParameterCount(&T::getOutboundKeysForUserDevice) == 4,
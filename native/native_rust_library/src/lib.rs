@@ -1,15 +1,19 @@
 use crate::ffi::{string_callback, void_callback};
 use comm_opaque2::client::{Login, Registration};
 use comm_opaque2::grpc::opaque_error_to_grpc_status as handle_error;
-use grpc_clients::identity::get_unauthenticated_client;
+use grpc_clients::identity::protos::authenticated::{
+  ConfirmTotpRequest, DisableTotpRequest,
+};
 use grpc_clients::identity::protos::client::{
   outbound_keys_for_user_request::Identifier, DeleteUserRequest,
   DeviceKeyUpload, DeviceType, Empty, IdentityKeyInfo,
   OpaqueLoginFinishRequest, OpaqueLoginStartRequest, OutboundKeyInfo,
-  OutboundKeysForUserRequest, PreKey, RegistrationFinishRequest,
-  RegistrationStartRequest, UpdateUserPasswordFinishRequest,
-  UpdateUserPasswordStartRequest, WalletLoginRequest,
+  OutboundKeysForUserRequest, PreKey, RefreshAccessTokenRequest,
+  RegistrationFinishRequest, RegistrationStartRequest, TwoFactorLoginRequest,
+  UpdateUserPasswordFinishRequest, UpdateUserPasswordStartRequest,
+  WalletLoginRequest,
 };
+use grpc_clients::identity::{get_auth_client, get_unauthenticated_client};
 use lazy_static::lazy_static;
 use serde::Serialize;
 use std::sync::Arc;
@@ -78,6 +82,13 @@ mod ffi {
       promise_id: u32,
     );
 
+    #[cxx_name = "identityLoginPasswordUserTwoFactor"]
+    fn login_password_user_two_factor(
+      two_factor_session_id: String,
+      code: String,
+      promise_id: u32,
+    );
+
     #[cxx_name = "identityLoginWalletUser"]
     fn login_wallet_user(
       siwe_message: String,
@@ -111,6 +122,40 @@ mod ffi {
       promise_id: u32,
     );
 
+    #[cxx_name = "identityRefreshAccessToken"]
+    fn refresh_access_token(
+      user_id: String,
+      device_id: String,
+      access_token: String,
+      promise_id: u32,
+    );
+
+    #[cxx_name = "identityEnrollTotp"]
+    fn enroll_totp(
+      user_id: String,
+      device_id: String,
+      access_token: String,
+      promise_id: u32,
+    );
+
+    #[cxx_name = "identityConfirmTotp"]
+    fn confirm_totp(
+      user_id: String,
+      device_id: String,
+      access_token: String,
+      code: String,
+      promise_id: u32,
+    );
+
+    #[cxx_name = "identityDisableTotp"]
+    fn disable_totp(
+      user_id: String,
+      device_id: String,
+      access_token: String,
+      code: String,
+      promise_id: u32,
+    );
+
     #[cxx_name = "identityGetOutboundKeysForUserDevice"]
     fn get_outbound_keys_for_user_device(
       identifier_type: String,
@@ -239,6 +284,11 @@ struct UserIDAndDeviceAccessToken {
   #[serde(rename = "userID")]
   user_id: String,
   access_token: String,
+  // Set instead of the access token if the user has two-factor
+  // authentication enabled
+  #[serde(rename = "twoFactorSessionID")]
+  #[serde(skip_serializing_if = "Option::is_none")]
+  two_factor_session_id: Option<String>,
 }
 
 async fn register_user_helper(
@@ -300,6 +350,7 @@ async fn register_user_helper(
   let user_id_and_access_token = UserIDAndDeviceAccessToken {
     user_id: registration_finish_response.user_id,
     access_token: registration_finish_response.access_token,
+    two_factor_session_id: None,
   };
   Ok(serde_json::to_string(&user_id_and_access_token)?)
 }
@@ -393,6 +444,46 @@ async fn login_password_user_helper(
   let user_id_and_access_token = UserIDAndDeviceAccessToken {
     user_id: login_finish_response.user_id,
     access_token: login_finish_response.access_token,
+    two_factor_session_id: login_finish_response.two_factor_session_id,
+  };
+  Ok(serde_json::to_string(&user_id_and_access_token)?)
+}
+
+#[instrument]
+fn login_password_user_two_factor(
+  two_factor_session_id: String,
+  code: String,
+  promise_id: u32,
+) {
+  RUNTIME.spawn(async move {
+    let result =
+      login_password_user_two_factor_helper(two_factor_session_id, code).await;
+    handle_string_result_as_callback(result, promise_id);
+  });
+}
+
+async fn login_password_user_two_factor_helper(
+  two_factor_session_id: String,
+  code: String,
+) -> Result<String, Error> {
+  let mut identity_client = get_unauthenticated_client(
+    "http://127.0.0.1:50054",
+    CODE_VERSION,
+    DEVICE_TYPE.as_str_name().to_lowercase(),
+  )
+  .await?;
+
+  let login_response = identity_client
+    .login_password_user_two_factor(TwoFactorLoginRequest {
+      two_factor_session_id,
+      code,
+    })
+    .await?
+    .into_inner();
+  let user_id_and_access_token = UserIDAndDeviceAccessToken {
+    user_id: login_response.user_id,
+    access_token: login_response.access_token,
+    two_factor_session_id: None,
   };
   Ok(serde_json::to_string(&user_id_and_access_token)?)
 }
@@ -486,6 +577,7 @@ async fn login_wallet_user_helper(
   let user_id_and_access_token = UserIDAndDeviceAccessToken {
     user_id: login_response.user_id,
     access_token: login_response.access_token,
+    two_factor_session_id: None,
   };
   Ok(serde_json::to_string(&user_id_and_access_token)?)
 }
@@ -593,6 +685,169 @@ async fn delete_user_helper(auth_info: AuthInfo) -> Result<(), Error> {
   Ok(())
 }
 
+fn refresh_access_token(
+  user_id: String,
+  device_id: String,
+  access_token: String,
+  promise_id: u32,
+) {
+  RUNTIME.spawn(async move {
+    let auth_info = AuthInfo {
+      access_token,
+      user_id,
+      device_id,
+    };
+    let result = refresh_access_token_helper(auth_info).await;
+    handle_string_result_as_callback(result, promise_id);
+  });
+}
+
+async fn refresh_access_token_helper(
+  auth_info: AuthInfo,
+) -> Result<String, Error> {
+  let refresh_access_token_request = RefreshAccessTokenRequest {
+    access_token: auth_info.access_token,
+    user_id: auth_info.user_id,
+    device_id_key: auth_info.device_id,
+  };
+  let mut identity_client = get_unauthenticated_client(
+    "http://127.0.0.1:50054",
+    CODE_VERSION,
+    DEVICE_TYPE.as_str_name().to_lowercase(),
+  )
+  .await?;
+  let access_token = identity_client
+    .refresh_access_token(refresh_access_token_request)
+    .await?
+    .into_inner()
+    .access_token;
+
+  Ok(access_token)
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct TotpEnrollment {
+  #[serde(rename = "otpauthURI")]
+  otpauth_uri: String,
+  secret: String,
+}
+
+fn enroll_totp(
+  user_id: String,
+  device_id: String,
+  access_token: String,
+  promise_id: u32,
+) {
+  RUNTIME.spawn(async move {
+    let auth_info = AuthInfo {
+      access_token,
+      user_id,
+      device_id,
+    };
+    let result = enroll_totp_helper(auth_info).await;
+    handle_string_result_as_callback(result, promise_id);
+  });
+}
+
+async fn enroll_totp_helper(auth_info: AuthInfo) -> Result<String, Error> {
+  let mut identity_client = get_auth_client(
+    "http://127.0.0.1:50054",
+    auth_info.user_id,
+    auth_info.device_id,
+    auth_info.access_token,
+    CODE_VERSION,
+    DEVICE_TYPE.as_str_name().to_lowercase(),
+  )
+  .await?;
+  let response = identity_client.enroll_totp(Empty {}).await?.into_inner();
+
+  let enrollment = TotpEnrollment {
+    otpauth_uri: response.otpauth_uri,
+    secret: response.secret,
+  };
+  Ok(serde_json::to_string(&enrollment)?)
+}
+
+fn confirm_totp(
+  user_id: String,
+  device_id: String,
+  access_token: String,
+  code: String,
+  promise_id: u32,
+) {
+  RUNTIME.spawn(async move {
+    let auth_info = AuthInfo {
+      access_token,
+      user_id,
+      device_id,
+    };
+    let result = confirm_totp_helper(auth_info, code).await;
+    handle_string_result_as_callback(result, promise_id);
+  });
+}
+
+/// Returns the recovery codes as a JSON array
+async fn confirm_totp_helper(
+  auth_info: AuthInfo,
+  code: String,
+) -> Result<String, Error> {
+  let mut identity_client = get_auth_client(
+    "http://127.0.0.1:50054",
+    auth_info.user_id,
+    auth_info.device_id,
+    auth_info.access_token,
+    CODE_VERSION,
+    DEVICE_TYPE.as_str_name().to_lowercase(),
+  )
+  .await?;
+  let recovery_codes = identity_client
+    .confirm_totp(ConfirmTotpRequest { code })
+    .await?
+    .into_inner()
+    .recovery_codes;
+
+  Ok(serde_json::to_string(&recovery_codes)?)
+}
+
+fn disable_totp(
+  user_id: String,
+  device_id: String,
+  access_token: String,
+  code: String,
+  promise_id: u32,
+) {
+  RUNTIME.spawn(async move {
+    let auth_info = AuthInfo {
+      access_token,
+      user_id,
+      device_id,
+    };
+    let result = disable_totp_helper(auth_info, code).await;
+    handle_void_result_as_callback(result, promise_id);
+  });
+}
+
+async fn disable_totp_helper(
+  auth_info: AuthInfo,
+  code: String,
+) -> Result<(), Error> {
+  let mut identity_client = get_auth_client(
+    "http://127.0.0.1:50054",
+    auth_info.user_id,
+    auth_info.device_id,
+    auth_info.access_token,
+    CODE_VERSION,
+    DEVICE_TYPE.as_str_name().to_lowercase(),
+  )
+  .await?;
+  identity_client
+    .disable_totp(DisableTotpRequest { code })
+    .await?;
+
+  Ok(())
+}
+
 struct GetOutboundKeysRequestInfo {
   identifier_type: String,
   identifier_value: String,
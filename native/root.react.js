@@ -33,6 +33,7 @@ import BackupHandler from './backup/backup-handler.js';
 import { BottomSheetProvider } from './bottom-sheet/bottom-sheet-provider.react.js';
 import ChatContextProvider from './chat/chat-context-provider.react.js';
 import MessageEditingContextProvider from './chat/message-editing-context-provider.react.js';
+import AccessTokenRefreshHandler from './components/access-token-refresh-handler.react.js';
 import { FeatureFlagsProvider } from './components/feature-flags-provider.react.js';
 import PersistedStateGate from './components/persisted-state-gate.js';
 import ConnectedStatusBar from './connected-status-bar.react.js';
@@ -250,6 +251,7 @@ function Root() {
       <OrientationHandler />
       <BackupHandler />
       <IntegrityHandler />
+      <AccessTokenRefreshHandler />
     </>
   );
   let navigation;
@@ -31,6 +31,10 @@ export interface Spec extends TurboModule {
     contentOneTimeKeys: $ReadOnlyArray<string>,
     notifOneTimeKeys: $ReadOnlyArray<string>,
   ) => Promise<string>;
+  +loginPasswordUserTwoFactor: (
+    twoFactorSessionID: string,
+    code: string,
+  ) => Promise<string>;
   +loginWalletUser: (
     siweMessage: string,
     siweSignature: string,
@@ -55,6 +59,28 @@ export interface Spec extends TurboModule {
     deviceID: string,
     accessToken: string,
   ) => Promise<void>;
+  +refreshAccessToken: (
+    userID: string,
+    deviceID: string,
+    accessToken: string,
+  ) => Promise<string>;
+  +enrollTotp: (
+    userID: string,
+    deviceID: string,
+    accessToken: string,
+  ) => Promise<string>;
+  +confirmTotp: (
+    userID: string,
+    deviceID: string,
+    accessToken: string,
+    code: string,
+  ) => Promise<string>;
+  +disableTotp: (
+    userID: string,
+    deviceID: string,
+    accessToken: string,
+    code: string,
+  ) => Promise<void>;
   +getOutboundKeysForUserDevice: (
     identifierType: string,
     identifierValue: string,
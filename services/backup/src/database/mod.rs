@@ -212,6 +212,51 @@ impl DatabaseClient {
     Ok(removed_backups)
   }
 
+  /// Removes all backups of the user, e.g. when the user is deleted
+  pub async fn remove_user_backups(
+    &self,
+    user_id: &str,
+  ) -> Result<Vec<BackupItem>, Error> {
+    let mut removed_backups = vec![];
+    let mut exclusive_start_key = None;
+
+    loop {
+      let response = self
+        .client
+        .query()
+        .table_name(backup_table::TABLE_NAME)
+        .key_condition_expression("#userID = :valueToMatch")
+        .expression_attribute_names("#userID", backup_table::attr::USER_ID)
+        .expression_attribute_values(
+          ":valueToMatch",
+          AttributeValue::S(user_id.to_string()),
+        )
+        .set_exclusive_start_key(exclusive_start_key)
+        .send()
+        .await
+        .map_err(|e| {
+          error!("DynamoDB client failed to fetch backups");
+          Error::AwsSdk(e.into())
+        })?;
+
+      for item in response.items.unwrap_or_default() {
+        let backup_item = BackupItem::try_from(item)?;
+        trace!("Removing backup item: {}", backup_item.backup_id);
+        if let Some(backup) = self
+          .remove_backup_item(user_id, &backup_item.backup_id)
+          .await?
+        {
+          removed_backups.push(backup);
+        }
+      }
+
+      exclusive_start_key = response.last_evaluated_key;
+      if exclusive_start_key.is_none() {
+        return Ok(removed_backups);
+      }
+    }
+  }
+
   fn get_item_key(
     user_id: &str,
     backup_id: &str,
@@ -0,0 +1,39 @@
+use actix_web::{error::ErrorForbidden, web, HttpResponse};
+use comm_services_lib::{
+  auth::AuthorizationCredential, blob::client::BlobServiceClient,
+};
+use tracing::{info, instrument, warn};
+
+use crate::{database::DatabaseClient, error::BackupError};
+
+/// Called by the identity service when a user is deleted. Removes all
+/// backups of the user and revokes their blob holders.
+#[instrument(name = "delete_user_data", skip_all, fields(user_id = %path.as_str()))]
+pub async fn delete_user_data(
+  requesting_identity: AuthorizationCredential,
+  path: web::Path<String>,
+  blob_client: web::Data<BlobServiceClient>,
+  db_client: web::Data<DatabaseClient>,
+) -> actix_web::Result<HttpResponse> {
+  if !matches!(
+    requesting_identity,
+    AuthorizationCredential::ServicesToken(_)
+  ) {
+    warn!("User data deletion requested without a services token");
+    return Err(ErrorForbidden("forbidden"));
+  }
+
+  let user_id = path.into_inner();
+  let removed_backups = db_client
+    .remove_user_backups(&user_id)
+    .await
+    .map_err(BackupError::from)?;
+  info!("Removed {} backups", removed_backups.len());
+
+  let blob_client = blob_client.with_authentication(requesting_identity);
+  for backup in removed_backups {
+    backup.revoke_holders(&blob_client).await;
+  }
+
+  Ok(HttpResponse::NoContent().finish())
+}
@@ -10,6 +10,7 @@ use crate::{database::DatabaseClient, CONFIG};
 
 mod handlers {
   pub(super) mod backup;
+  pub(super) mod user_data;
 }
 
 pub async fn run_http_server(
@@ -60,6 +61,15 @@ pub async fn run_http_server(
               .route(web::get().to(handlers::backup::download_user_data)),
           ),
       )
+      .service(
+        // Called by other services only
+        web::scope("/user_data")
+          .wrap(get_comm_authentication_middleware())
+          .service(
+            web::resource("{user_id}")
+              .route(web::delete().to(handlers::user_data::delete_user_data)),
+          ),
+      )
   })
   .bind(("0.0.0.0", CONFIG.http_port))?
   .run()
@@ -10,6 +10,10 @@ pub mod http;
 pub mod tools;
 
 mod reexports {
+  // Services using a different version of the AWS SDK need this to create
+  // an `AuthService`
+  pub use aws_config;
+
   #[cfg(feature = "blob-client")]
   pub use {bytes, reqwest};
 
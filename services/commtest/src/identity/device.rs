@@ -3,7 +3,7 @@ use grpc_clients::identity::get_unauthenticated_client;
 use rand::{distributions::Alphanumeric, Rng};
 
 use crate::identity::olm_account_infos::{
-  ClientPublicKeys, DEFAULT_CLIENT_KEYS,
+  MockOlmAccounts, DEFAULT_CLIENT_KEYS,
 };
 
 use crate::service_addr;
@@ -22,7 +22,37 @@ pub struct DeviceInfo {
   pub access_token: String,
 }
 
-pub async fn create_device(keys: Option<&ClientPublicKeys>) -> DeviceInfo {
+/// Keys uploaded by the device when registering or logging in
+pub fn device_key_upload(keys: &MockOlmAccounts) -> DeviceKeyUpload {
+  let example_payload = serde_json::to_string(&keys.public_keys())
+    .expect("Failed to serialize example payload");
+  let payload_signature =
+    keys.sign_with_content_account(example_payload.as_bytes());
+  let (content_prekey, content_prekey_signature) =
+    keys.generate_content_prekey();
+  let (notif_prekey, notif_prekey_signature) = keys.generate_notif_prekey();
+
+  DeviceKeyUpload {
+    device_key_info: Some(IdentityKeyInfo {
+      payload: example_payload,
+      payload_signature,
+      social_proof: None,
+    }),
+    content_upload: Some(PreKey {
+      pre_key: content_prekey,
+      pre_key_signature: content_prekey_signature,
+    }),
+    notif_upload: Some(PreKey {
+      pre_key: notif_prekey,
+      pre_key_signature: notif_prekey_signature,
+    }),
+    one_time_content_prekeys: Vec::new(),
+    one_time_notif_prekeys: Vec::new(),
+    device_type: DeviceType::Keyserver.into(),
+  }
+}
+
+pub async fn create_device(keys: Option<&MockOlmAccounts>) -> DeviceInfo {
   let password = "pass";
   let username: String = rand::thread_rng()
     .sample_iter(&Alphanumeric)
@@ -30,12 +60,9 @@ pub async fn create_device(keys: Option<&ClientPublicKeys>) -> DeviceInfo {
     .map(char::from)
     .collect();
 
-  // TODO: Generate dynamic valid olm account info
   let keys = keys.unwrap_or_else(|| &DEFAULT_CLIENT_KEYS);
-  let example_payload =
-    serde_json::to_string(&keys).expect("Failed to serialize example payload");
   // The ed25519 value from the olm payload
-  let device_id = &keys.primary_identity_public_keys.ed25519;
+  let device_id = keys.device_id();
 
   let mut client_registration = Registration::new();
   let opaque_registration_request =
@@ -43,24 +70,7 @@ pub async fn create_device(keys: Option<&ClientPublicKeys>) -> DeviceInfo {
   let registration_start_request = RegistrationStartRequest {
     opaque_registration_request,
     username: username.to_string(),
-    device_key_upload: Some(DeviceKeyUpload {
-      device_key_info: Some(IdentityKeyInfo {
-        payload: example_payload.to_string(),
-        payload_signature: "foo".to_string(),
-        social_proof: None,
-      }),
-      content_upload: Some(PreKey {
-        pre_key: "content_prekey".to_string(),
-        pre_key_signature: "content_prekey_sig".to_string(),
-      }),
-      notif_upload: Some(PreKey {
-        pre_key: "notif_prekey".to_string(),
-        pre_key_signature: "notif_prekey_sig".to_string(),
-      }),
-      one_time_content_prekeys: Vec::new(),
-      one_time_notif_prekeys: Vec::new(),
-      device_type: DeviceType::Keyserver.into(),
-    }),
+    device_key_upload: Some(device_key_upload(keys)),
   };
 
   let mut identity_client = get_unauthenticated_client(
@@ -96,7 +106,7 @@ pub async fn create_device(keys: Option<&ClientPublicKeys>) -> DeviceInfo {
 
   DeviceInfo {
     username: username.to_string(),
-    device_id: device_id.to_string(),
+    device_id,
     user_id: registration_finish_response.user_id,
     access_token: registration_finish_response.access_token,
   }
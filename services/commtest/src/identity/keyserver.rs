@@ -0,0 +1,30 @@
+use base64::{engine::general_purpose, Engine as _};
+use chrono::Utc;
+use ed25519_dalek::{Keypair, PublicKey, SecretKey, Signer};
+use serde_json::json;
+
+/// Seed of the key standing in for Ashoat's keyserver signing key. Its public
+/// key is identity's `KEYSERVER_PUBLIC_KEY` in docker-compose.tests.yml.
+const KEYSERVER_SIGNING_KEY_SEED: [u8; 32] = [7; 32];
+
+/// Returns a keyserver message with the statement and payload, and its
+/// signature, in the format identity verifies
+pub fn sign_keyserver_message(
+  statement: &str,
+  payload: serde_json::Value,
+) -> (String, String) {
+  let secret =
+    SecretKey::from_bytes(&KEYSERVER_SIGNING_KEY_SEED).expect("Invalid seed");
+  let public = PublicKey::from(&secret);
+  let keypair = Keypair { secret, public };
+
+  let message = json!({
+    "statement": statement,
+    "payload": payload,
+    "issuedAt": Utc::now().to_rfc3339(),
+  })
+  .to_string();
+  let signature = general_purpose::STANDARD_NO_PAD
+    .encode(keypair.sign(message.as_bytes()).to_bytes());
+  (message, signature)
+}
@@ -1,2 +1,3 @@
 pub mod device;
+pub mod keyserver;
 pub mod olm_account_infos;
@@ -1,3 +1,5 @@
+use base64::{engine::general_purpose, Engine as _};
+use ed25519_dalek::{Keypair, PublicKey, SecretKey, Signer};
 use lazy_static::lazy_static;
 use serde::{Deserialize, Serialize};
 
@@ -14,35 +16,108 @@ pub struct ClientPublicKeys {
   pub notification_identity_public_keys: IdentityPublicKeys,
 }
 
-lazy_static! {
-  pub static ref DEFAULT_CLIENT_KEYS: ClientPublicKeys = ClientPublicKeys {
-    primary_identity_public_keys: IdentityPublicKeys {
-      ed25519: "cSlL+VLLJDgtKSPlIwoCZg0h0EmHlQoJC08uV/O+jvg".to_string(),
-      curve25519: "Y4ZIqzpE1nv83kKGfvFP6rifya0itRg2hifqYtsISnk".to_string(),
-    },
-    notification_identity_public_keys: IdentityPublicKeys {
-      ed25519: "D0BV2Y7Qm36VUtjwyQTJJWYAycN7aMSJmhEsRJpW2mk".to_string(),
-      curve25519: "DYmV8VdkjwG/VtC8C53morogNJhpTPT/4jzW0/cxzQo".to_string(),
+/// Stands in for the content and notif Olm accounts of a device. Signing keys
+/// are real ed25519 keys, so that identity can verify signatures.
+pub struct MockOlmAccounts {
+  content_signing_key: Keypair,
+  notif_signing_key: Keypair,
+  content_curve25519: String,
+  notif_curve25519: String,
+}
+
+fn keypair_from_seed(seed: [u8; 32]) -> Keypair {
+  let secret = SecretKey::from_bytes(&seed).expect("Invalid seed");
+  let public = PublicKey::from(&secret);
+  Keypair { secret, public }
+}
+
+fn encode(bytes: &[u8]) -> String {
+  general_purpose::STANDARD_NO_PAD.encode(bytes)
+}
+
+impl MockOlmAccounts {
+  fn new(
+    content_seed: u8,
+    notif_seed: u8,
+    content_curve25519: &str,
+    notif_curve25519: &str,
+  ) -> Self {
+    Self {
+      content_signing_key: keypair_from_seed([content_seed; 32]),
+      notif_signing_key: keypair_from_seed([notif_seed; 32]),
+      content_curve25519: content_curve25519.to_string(),
+      notif_curve25519: notif_curve25519.to_string(),
     }
-  };
-  pub static ref MOCK_CLIENT_KEYS_1: ClientPublicKeys = ClientPublicKeys {
-    primary_identity_public_keys: IdentityPublicKeys {
-      ed25519: "lbp5cS9fH5NnWIJbZ57wGBzDBGvmjoq6gMBHsIyXfJ4".to_string(),
-      curve25519: "x74rEeVzfTcjm+B2yLN/wgfvHEzEtphQ/JeQfIrzPzQ".to_string(),
-    },
-    notification_identity_public_keys: IdentityPublicKeys {
-      ed25519: "+mi3TltiSK2883cm0TK2mkSKPcQb+WVfshltTSVgA2Y".to_string(),
-      curve25519: "GI8V9FwOYIqxB2TzQN31nXKR8y3/B3k+ZOCgxkTlUlI".to_string(),
-    },
-  };
-  pub static ref MOCK_CLIENT_KEYS_2: ClientPublicKeys = ClientPublicKeys {
-    primary_identity_public_keys: IdentityPublicKeys {
-      ed25519: "ZXx1ADCFxFm6P+UmVhX0A1tuqUoBU7lYjig/gMzSEJI".to_string(),
-      curve25519: "zHfP5eeD3slrgidtNRknHw3NKtJ7hA+vinaT3ACIhRA".to_string(),
-    },
-    notification_identity_public_keys: IdentityPublicKeys {
-      ed25519: "TqzVFQLnJvt9JfMVU54d6InEd/wQV3DCplBuj5axTlU".to_string(),
-      curve25519: "nRVVaf+Iz2MfEFtQtzrvV/EmTivqKpOeHlCt9OWYUxM".to_string(),
-    },
-  };
+  }
+
+  /// Accounts of a new device, unknown to the services
+  pub fn generate() -> Self {
+    let curve25519_key: [u8; 32] = rand::random();
+    let notif_curve25519_key: [u8; 32] = rand::random();
+    Self {
+      content_signing_key: keypair_from_seed(rand::random()),
+      notif_signing_key: keypair_from_seed(rand::random()),
+      content_curve25519: encode(&curve25519_key),
+      notif_curve25519: encode(&notif_curve25519_key),
+    }
+  }
+
+  pub fn public_keys(&self) -> ClientPublicKeys {
+    ClientPublicKeys {
+      primary_identity_public_keys: IdentityPublicKeys {
+        ed25519: encode(self.content_signing_key.public.as_bytes()),
+        curve25519: self.content_curve25519.clone(),
+      },
+      notification_identity_public_keys: IdentityPublicKeys {
+        ed25519: encode(self.notif_signing_key.public.as_bytes()),
+        curve25519: self.notif_curve25519.clone(),
+      },
+    }
+  }
+
+  /// The ed25519 key of the content account
+  pub fn device_id(&self) -> String {
+    encode(self.content_signing_key.public.as_bytes())
+  }
+
+  pub fn sign_with_content_account(&self, message: &[u8]) -> String {
+    encode(&self.content_signing_key.sign(message).to_bytes())
+  }
+
+  pub fn sign_with_notif_account(&self, message: &[u8]) -> String {
+    encode(&self.notif_signing_key.sign(message).to_bytes())
+  }
+
+  /// Returns a random prekey and its signature by the content account
+  pub fn generate_content_prekey(&self) -> (String, String) {
+    let prekey: [u8; 32] = rand::random();
+    (encode(&prekey), self.sign_with_content_account(&prekey))
+  }
+
+  /// Returns a random prekey and its signature by the notif account
+  pub fn generate_notif_prekey(&self) -> (String, String) {
+    let prekey: [u8; 32] = rand::random();
+    (encode(&prekey), self.sign_with_notif_account(&prekey))
+  }
+}
+
+lazy_static! {
+  pub static ref DEFAULT_CLIENT_KEYS: MockOlmAccounts = MockOlmAccounts::new(
+    1,
+    2,
+    "Y4ZIqzpE1nv83kKGfvFP6rifya0itRg2hifqYtsISnk",
+    "DYmV8VdkjwG/VtC8C53morogNJhpTPT/4jzW0/cxzQo",
+  );
+  pub static ref MOCK_CLIENT_KEYS_1: MockOlmAccounts = MockOlmAccounts::new(
+    3,
+    4,
+    "x74rEeVzfTcjm+B2yLN/wgfvHEzEtphQ/JeQfIrzPzQ",
+    "GI8V9FwOYIqxB2TzQN31nXKR8y3/B3k+ZOCgxkTlUlI",
+  );
+  pub static ref MOCK_CLIENT_KEYS_2: MockOlmAccounts = MockOlmAccounts::new(
+    5,
+    6,
+    "zHfP5eeD3slrgidtNRknHw3NKtJ7hA+vinaT3ACIhRA",
+    "nRVVaf+Iz2MfEFtQtzrvV/EmTivqKpOeHlCt9OWYUxM",
+  );
 }
@@ -1 +1,28 @@
 pub mod socket;
+
+use comm_services_lib::auth::{AuthorizationCredential, ServicesAuthToken};
+
+/// Services token in the local environment, set by
+/// services/terraform/modules/shared/secretsmanager.tf
+const DEV_SERVICES_TOKEN: &str = "super-secret";
+
+/// Wraps the message in a request authenticated with the services token,
+/// which Tunnelbroker requires for RPCs not meant to be called by devices
+pub fn services_request<T>(message: T) -> tonic::Request<T> {
+  let credential = AuthorizationCredential::ServicesToken(
+    ServicesAuthToken::new(DEV_SERVICES_TOKEN.to_string()),
+  );
+  let bearer = format!(
+    "Bearer {}",
+    credential
+      .as_authorization_token()
+      .expect("Unable to serialize services token")
+  );
+
+  let mut request = tonic::Request::new(message);
+  request.metadata_mut().insert(
+    "authorization",
+    bearer.parse().expect("Invalid authorization metadata"),
+  );
+  request
+}
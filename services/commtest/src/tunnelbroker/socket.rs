@@ -7,8 +7,9 @@ use tokio_tungstenite::tungstenite::Message;
 use tokio_tungstenite::{connect_async, MaybeTlsStream, WebSocketStream};
 use tunnelbroker_messages::{
   ConnectionInitializationMessage, ConnectionInitializationResponse,
-  ConnectionInitializationStatus, DeviceTypes, Heartbeat, MessageSentStatus,
-  MessageToDeviceRequest, MessageToDeviceRequestStatus, Messages,
+  ConnectionInitializationStatus, DeviceTypes, Heartbeat, MessageDelivered,
+  MessageSentStatus, MessageToDeviceRequest, MessageToDeviceRequestStatus,
+  Messages,
 };
 
 #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
@@ -62,12 +63,30 @@ pub async fn create_socket(
 pub async fn send_message(
   socket: &mut WebSocketStream<MaybeTlsStream<TcpStream>>,
   message: WebSocketMessageToDevice,
+) -> Result<String, Box<dyn std::error::Error>> {
+  send_message_request(socket, message, false).await
+}
+
+/// Sends a message and requests a delivery receipt, which can be received
+/// with `receive_delivery_receipt` once the recipient confirms the message
+pub async fn send_message_with_delivery_receipt(
+  socket: &mut WebSocketStream<MaybeTlsStream<TcpStream>>,
+  message: WebSocketMessageToDevice,
+) -> Result<String, Box<dyn std::error::Error>> {
+  send_message_request(socket, message, true).await
+}
+
+async fn send_message_request(
+  socket: &mut WebSocketStream<MaybeTlsStream<TcpStream>>,
+  message: WebSocketMessageToDevice,
+  request_delivery_receipt: bool,
 ) -> Result<String, Box<dyn std::error::Error>> {
   let client_message_id = uuid::Uuid::new_v4().to_string();
   let request = MessageToDeviceRequest {
     client_message_id: client_message_id.clone(),
     device_id: message.device_id,
     payload: message.payload,
+    request_delivery_receipt,
   };
 
   let serialized_request = serde_json::to_string(&request)?;
@@ -115,3 +134,32 @@ pub async fn receive_message(
 
   Err("Failed to receive message".into())
 }
+
+pub async fn receive_delivery_receipt(
+  socket: &mut WebSocketStream<MaybeTlsStream<TcpStream>>,
+) -> Result<MessageDelivered, Box<dyn std::error::Error>> {
+  while let Some(Ok(response)) = socket.next().await {
+    let message_str =
+      response.to_text().expect("Failed to get response content");
+    let message = serde_json::from_str::<Messages>(message_str).unwrap();
+    match message {
+      Messages::MessageDelivered(receipt) => {
+        let confirmation = tunnelbroker_messages::MessageReceiveConfirmation {
+          message_ids: vec![receipt.message_id.clone()],
+        };
+        let serialized_confirmation =
+          serde_json::to_string(&confirmation).unwrap();
+        socket.send(Message::Text(serialized_confirmation)).await?;
+        return Ok(receipt);
+      }
+      Messages::Heartbeat(Heartbeat {}) => {
+        let msg = Heartbeat {};
+        let serialized = serde_json::to_string(&msg).unwrap();
+        socket.send(Message::Text(serialized)).await?;
+      }
+      _ => return Err(format!("Unexpected message type {message:?}").into()),
+    }
+  }
+
+  Err("Failed to receive delivery receipt".into())
+}
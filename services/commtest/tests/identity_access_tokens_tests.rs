@@ -4,7 +4,10 @@ use commtest::identity::device::{
 use commtest::service_addr;
 use grpc_clients::identity::{
   get_unauthenticated_client,
-  protos::client::{UploadOneTimeKeysRequest, VerifyUserAccessTokenRequest},
+  protos::client::{
+    RefreshAccessTokenRequest, UploadOneTimeKeysRequest,
+    VerifyUserAccessTokenRequest,
+  },
 };
 
 #[tokio::test]
@@ -61,3 +64,55 @@ async fn upload_one_time_keys() {
     .await
     .unwrap();
 }
+
+#[tokio::test]
+async fn refresh_access_token() {
+  let identity_grpc_endpoint = service_addr::IDENTITY_GRPC.to_string();
+  let device_info = create_device(None).await;
+
+  let mut identity_client = get_unauthenticated_client(
+    &identity_grpc_endpoint,
+    PLACEHOLDER_CODE_VERSION,
+    DEVICE_TYPE.to_string(),
+  )
+  .await
+  .expect("Couldn't connect to identity service");
+
+  let refresh_request = RefreshAccessTokenRequest {
+    user_id: device_info.user_id.clone(),
+    device_id_key: device_info.device_id.clone(),
+    access_token: device_info.access_token.clone(),
+  };
+  let new_access_token = identity_client
+    .refresh_access_token(refresh_request.clone())
+    .await
+    .unwrap()
+    .into_inner()
+    .access_token;
+  assert_ne!(new_access_token, device_info.access_token);
+
+  let verify_token = |access_token: String| VerifyUserAccessTokenRequest {
+    user_id: device_info.user_id.clone(),
+    signing_public_key: device_info.device_id.clone(),
+    access_token,
+  };
+
+  let old_token_response = identity_client
+    .verify_user_access_token(verify_token(device_info.access_token.clone()))
+    .await
+    .unwrap();
+  assert!(!old_token_response.into_inner().token_valid);
+
+  let new_token_response = identity_client
+    .verify_user_access_token(verify_token(new_access_token))
+    .await
+    .unwrap();
+  assert!(new_token_response.into_inner().token_valid);
+
+  // The previous token can't be used to refresh again
+  let error = identity_client
+    .refresh_access_token(refresh_request)
+    .await
+    .unwrap_err();
+  assert_eq!(error.code(), grpc_clients::tonic::Code::PermissionDenied);
+}
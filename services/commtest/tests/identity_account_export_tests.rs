@@ -0,0 +1,44 @@
+use commtest::identity::device::{
+  create_device, DEVICE_TYPE, PLACEHOLDER_CODE_VERSION,
+};
+use commtest::service_addr;
+use grpc_clients::identity::{
+  get_auth_client,
+  protos::authenticated::{
+    export_account_data_response::Export, ExportAccountDataRequest,
+  },
+};
+
+#[tokio::test]
+async fn export_account_data() {
+  let device_info = create_device(None).await;
+
+  let mut auth_client = get_auth_client(
+    &service_addr::IDENTITY_GRPC.to_string(),
+    device_info.user_id.clone(),
+    device_info.device_id.clone(),
+    device_info.access_token.clone(),
+    PLACEHOLDER_CODE_VERSION,
+    DEVICE_TYPE.to_string(),
+  )
+  .await
+  .expect("Couldn't connect to identity service");
+
+  let response = auth_client
+    .export_account_data(ExportAccountDataRequest {
+      upload_to_blob: false,
+    })
+    .await
+    .unwrap()
+    .into_inner();
+  let Some(Export::ExportJson(export_json)) = response.export else {
+    panic!("Small exports should be returned inline");
+  };
+
+  let export: serde_json::Value = serde_json::from_str(&export_json).unwrap();
+  assert_eq!(export["user"]["userID"], device_info.user_id);
+  assert_eq!(export["user"]["username"], device_info.username);
+  assert_eq!(export["devices"][0]["deviceID"], device_info.device_id);
+  assert_eq!(export["accessTokens"][0]["deviceID"], device_info.device_id);
+  assert!(!export_json.contains(&device_info.access_token));
+}
@@ -0,0 +1,99 @@
+use comm_opaque2::client::Login;
+use commtest::identity::device::{
+  create_device, device_key_upload, DEVICE_TYPE, PLACEHOLDER_CODE_VERSION,
+};
+use commtest::identity::olm_account_infos::MockOlmAccounts;
+use commtest::service_addr;
+use grpc_clients::identity::{
+  get_auth_client, get_unauthenticated_client,
+  protos::authenticated::{
+    AuditEventOutcome, AuditEventType, GetAuditLogRequest,
+  },
+  protos::client::{OpaqueLoginFinishRequest, OpaqueLoginStartRequest},
+};
+
+#[tokio::test]
+async fn audit_log_lists_account_events() {
+  let device_info = create_device(None).await;
+
+  let mut identity_client = get_unauthenticated_client(
+    &service_addr::IDENTITY_GRPC.to_string(),
+    PLACEHOLDER_CODE_VERSION,
+    DEVICE_TYPE.to_string(),
+  )
+  .await
+  .expect("Couldn't connect to identity service");
+
+  let keys = MockOlmAccounts::generate();
+  let mut client_login = Login::new();
+  let login_start_response = identity_client
+    .login_password_user_start(OpaqueLoginStartRequest {
+      opaque_login_request: client_login.start("pass").unwrap(),
+      username: device_info.username,
+      device_key_upload: Some(device_key_upload(&keys)),
+    })
+    .await
+    .unwrap()
+    .into_inner();
+  let opaque_login_upload = client_login
+    .finish(&login_start_response.opaque_login_response)
+    .unwrap();
+  identity_client
+    .login_password_user_finish(OpaqueLoginFinishRequest {
+      session_id: login_start_response.session_id,
+      opaque_login_upload,
+    })
+    .await
+    .unwrap();
+
+  let mut auth_client = get_auth_client(
+    &service_addr::IDENTITY_GRPC.to_string(),
+    device_info.user_id,
+    device_info.device_id.clone(),
+    device_info.access_token,
+    PLACEHOLDER_CODE_VERSION,
+    DEVICE_TYPE.to_string(),
+  )
+  .await
+  .expect("Couldn't connect to identity service");
+
+  let response = auth_client
+    .get_audit_log(GetAuditLogRequest {
+      limit: None,
+      page_token: None,
+    })
+    .await
+    .unwrap()
+    .into_inner();
+  assert_eq!(response.next_page_token, None);
+
+  // Newest first
+  let events = response.events;
+  assert_eq!(events.len(), 2);
+  assert_eq!(events[0].event_type(), AuditEventType::Login);
+  assert_eq!(events[0].outcome(), AuditEventOutcome::Success);
+  assert_eq!(events[0].device_id, Some(keys.device_id()));
+  assert_eq!(events[0].auth_type.as_deref(), Some("password"));
+  assert_eq!(events[1].event_type(), AuditEventType::Registration);
+  assert_eq!(events[1].device_id, Some(device_info.device_id));
+
+  // Paginated
+  let first_page = auth_client
+    .get_audit_log(GetAuditLogRequest {
+      limit: Some(1),
+      page_token: None,
+    })
+    .await
+    .unwrap()
+    .into_inner();
+  assert_eq!(first_page.events, events[..1]);
+  let second_page = auth_client
+    .get_audit_log(GetAuditLogRequest {
+      limit: Some(1),
+      page_token: first_page.next_page_token,
+    })
+    .await
+    .unwrap()
+    .into_inner();
+  assert_eq!(second_page.events, events[1..]);
+}
@@ -0,0 +1,65 @@
+use commtest::identity::device::{
+  create_device, DEVICE_TYPE, PLACEHOLDER_CODE_VERSION,
+};
+use commtest::service_addr;
+use grpc_clients::identity::{
+  get_auth_client,
+  protos::{authenticated::RemoveDeviceRequest, client::Empty},
+};
+use grpc_clients::tonic::Code;
+
+#[tokio::test]
+async fn get_device_list() {
+  let device_info = create_device(None).await;
+
+  let mut client = get_auth_client(
+    &service_addr::IDENTITY_GRPC.to_string(),
+    device_info.user_id,
+    device_info.device_id.clone(),
+    device_info.access_token,
+    PLACEHOLDER_CODE_VERSION,
+    DEVICE_TYPE.to_string(),
+  )
+  .await
+  .expect("Couldn't connect to identity service");
+
+  let devices = client
+    .get_device_list(Empty {})
+    .await
+    .unwrap()
+    .into_inner()
+    .devices;
+
+  assert_eq!(devices.len(), 1);
+  assert_eq!(devices[0].device_id, device_info.device_id);
+  assert!(devices[0].last_login.is_some());
+  assert_eq!(devices[0].code_version, Some(PLACEHOLDER_CODE_VERSION));
+}
+
+#[tokio::test]
+async fn remove_device_rejects_invalid_devices() {
+  let device_info = create_device(None).await;
+
+  let mut client = get_auth_client(
+    &service_addr::IDENTITY_GRPC.to_string(),
+    device_info.user_id,
+    device_info.device_id.clone(),
+    device_info.access_token,
+    PLACEHOLDER_CODE_VERSION,
+    DEVICE_TYPE.to_string(),
+  )
+  .await
+  .expect("Couldn't connect to identity service");
+
+  let own_device = RemoveDeviceRequest {
+    device_id: device_info.device_id,
+  };
+  let error = client.remove_device(own_device).await.unwrap_err();
+  assert_eq!(error.code(), Code::InvalidArgument);
+
+  let unknown_device = RemoveDeviceRequest {
+    device_id: "unknown device".to_string(),
+  };
+  let error = client.remove_device(unknown_device).await.unwrap_err();
+  assert_eq!(error.code(), Code::NotFound);
+}
@@ -0,0 +1,89 @@
+use std::time::Duration;
+
+use comm_opaque2::client::Login;
+use commtest::identity::device::{
+  create_device, device_key_upload, DEVICE_TYPE, PLACEHOLDER_CODE_VERSION,
+};
+use commtest::identity::olm_account_infos::MockOlmAccounts;
+use commtest::service_addr;
+use grpc_clients::identity::{
+  get_unauthenticated_client,
+  protos::client::{OpaqueLoginFinishRequest, OpaqueLoginStartRequest},
+};
+use grpc_clients::tonic::Code;
+
+// Default number of failures before backoff kicks in
+const BACKOFF_THRESHOLD: usize = 3;
+
+#[tokio::test]
+async fn backoff_after_failed_logins() {
+  let device_info = create_device(None).await;
+  // A new device, so that failures of previous runs don't count
+  let keys = MockOlmAccounts::generate();
+
+  let mut identity_client = get_unauthenticated_client(
+    &service_addr::IDENTITY_GRPC.to_string(),
+    PLACEHOLDER_CODE_VERSION,
+    DEVICE_TYPE.to_string(),
+  )
+  .await
+  .expect("Couldn't connect to identity service");
+
+  // The client detects a wrong password after the first step and never
+  // finishes the login
+  for _ in 0..BACKOFF_THRESHOLD {
+    let mut client_login = Login::new();
+    let login_start_request = OpaqueLoginStartRequest {
+      opaque_login_request: client_login.start("wrong password").unwrap(),
+      username: device_info.username.clone(),
+      device_key_upload: Some(device_key_upload(&keys)),
+    };
+    let response = identity_client
+      .login_password_user_start(login_start_request)
+      .await
+      .unwrap()
+      .into_inner();
+    assert!(client_login
+      .finish(&response.opaque_login_response)
+      .is_err());
+  }
+
+  let mut client_login = Login::new();
+  let login_start_request = OpaqueLoginStartRequest {
+    opaque_login_request: client_login.start("pass").unwrap(),
+    username: device_info.username.clone(),
+    device_key_upload: Some(device_key_upload(&keys)),
+  };
+  let status = identity_client
+    .login_password_user_start(login_start_request.clone())
+    .await
+    .expect_err("Login attempt wasn't delayed");
+  assert_eq!(status.code(), Code::ResourceExhausted);
+
+  let retry_after: u64 = status
+    .metadata()
+    .get("retry-after")
+    .expect("Missing retry-after")
+    .to_str()
+    .unwrap()
+    .parse()
+    .unwrap();
+  tokio::time::sleep(Duration::from_secs(retry_after)).await;
+
+  // Successful login resets the counters
+  let response = identity_client
+    .login_password_user_start(login_start_request)
+    .await
+    .unwrap()
+    .into_inner();
+  let opaque_login_upload = client_login
+    .finish(&response.opaque_login_response)
+    .unwrap();
+  identity_client
+    .login_password_user_finish(OpaqueLoginFinishRequest {
+      session_id: response.session_id,
+      opaque_login_upload,
+    })
+    .await
+    .unwrap();
+}
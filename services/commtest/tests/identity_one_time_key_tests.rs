@@ -3,7 +3,8 @@ use commtest::identity::device::{
 };
 use commtest::service_addr;
 use grpc_clients::identity::{
-  get_unauthenticated_client, protos::client::UploadOneTimeKeysRequest,
+  get_auth_client, get_unauthenticated_client,
+  protos::client::{Empty, UploadOneTimeKeysRequest},
 };
 
 #[tokio::test]
@@ -34,3 +35,53 @@ async fn verify_access_token() {
     .await
     .unwrap();
 }
+
+#[tokio::test]
+async fn one_time_key_counts() {
+  let device_info = create_device(None).await;
+
+  let mut identity_client = get_unauthenticated_client(
+    &service_addr::IDENTITY_GRPC.to_string(),
+    PLACEHOLDER_CODE_VERSION,
+    DEVICE_TYPE.to_string(),
+  )
+  .await
+  .expect("Couldn't connect to identity service");
+
+  identity_client
+    .upload_one_time_keys(UploadOneTimeKeysRequest {
+      user_id: device_info.user_id.clone(),
+      device_id: device_info.device_id.clone(),
+      access_token: device_info.access_token.clone(),
+      content_one_time_pre_keys: vec![
+        "content1".to_string(),
+        "content2".to_string(),
+        "content3".to_string(),
+      ],
+      notif_one_time_pre_keys: vec!["notif1".to_string()],
+    })
+    .await
+    .unwrap();
+
+  let mut auth_client = get_auth_client(
+    &service_addr::IDENTITY_GRPC.to_string(),
+    device_info.user_id,
+    device_info.device_id.clone(),
+    device_info.access_token,
+    PLACEHOLDER_CODE_VERSION,
+    DEVICE_TYPE.to_string(),
+  )
+  .await
+  .expect("Couldn't connect to identity service");
+
+  let response = auth_client
+    .get_one_time_key_counts(Empty {})
+    .await
+    .unwrap()
+    .into_inner();
+  assert_eq!(response.devices.len(), 1);
+  assert_eq!(response.devices[0].device_id, device_info.device_id);
+  assert_eq!(response.devices[0].content_one_time_keys, 3);
+  assert_eq!(response.devices[0].notif_one_time_keys, 1);
+  assert!(response.minimum_threshold > 0);
+}
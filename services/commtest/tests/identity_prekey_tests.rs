@@ -1,11 +1,13 @@
 use commtest::identity::device::{
   create_device, DEVICE_TYPE, PLACEHOLDER_CODE_VERSION,
 };
+use commtest::identity::olm_account_infos::DEFAULT_CLIENT_KEYS;
 use commtest::service_addr;
 use grpc_clients::identity::{
   get_auth_client,
   protos::{authenticated::RefreshUserPreKeysRequest, client::PreKey},
 };
+use grpc_clients::tonic::Code;
 
 #[tokio::test]
 async fn set_prekey() {
@@ -22,20 +24,63 @@ async fn set_prekey() {
   .await
   .expect("Couldn't connect to identity service");
 
+  let (content_prekey, content_prekey_signature) =
+    DEFAULT_CLIENT_KEYS.generate_content_prekey();
+  let (notif_prekey, notif_prekey_signature) =
+    DEFAULT_CLIENT_KEYS.generate_notif_prekey();
+
+  let upload_request = RefreshUserPreKeysRequest {
+    new_content_pre_keys: Some(PreKey {
+      pre_key: content_prekey,
+      pre_key_signature: content_prekey_signature,
+    }),
+    new_notif_pre_keys: Some(PreKey {
+      pre_key: notif_prekey,
+      pre_key_signature: notif_prekey_signature,
+    }),
+  };
+
+  client
+    .refresh_user_pre_keys(upload_request)
+    .await
+    .expect("Failed to refresh prekeys");
+}
+
+#[tokio::test]
+async fn reject_invalid_prekey_signature() {
+  let device_info = create_device(None).await;
+
+  let mut client = get_auth_client(
+    &service_addr::IDENTITY_GRPC.to_string(),
+    device_info.user_id,
+    device_info.device_id,
+    device_info.access_token,
+    PLACEHOLDER_CODE_VERSION,
+    DEVICE_TYPE.to_string(),
+  )
+  .await
+  .expect("Couldn't connect to identity service");
+
+  let (content_prekey, content_prekey_signature) =
+    DEFAULT_CLIENT_KEYS.generate_content_prekey();
+  // Signed by the content account instead of the notif account
+  let (notif_prekey, notif_prekey_signature) =
+    DEFAULT_CLIENT_KEYS.generate_content_prekey();
+
   let upload_request = RefreshUserPreKeysRequest {
     new_content_pre_keys: Some(PreKey {
-      pre_key: "content_prekey".to_string(),
-      pre_key_signature: "content_prekey_signature".to_string(),
+      pre_key: content_prekey,
+      pre_key_signature: content_prekey_signature,
     }),
     new_notif_pre_keys: Some(PreKey {
-      pre_key: "content_prekey".to_string(),
-      pre_key_signature: "content_prekey_signature".to_string(),
+      pre_key: notif_prekey,
+      pre_key_signature: notif_prekey_signature,
     }),
   };
 
-  // This send will fail if the one-time keys weren't successfully added
-  println!(
-    "Error: {:?}",
-    client.refresh_user_pre_keys(upload_request).await
-  );
+  let status = client
+    .refresh_user_pre_keys(upload_request)
+    .await
+    .expect_err("Prekey with invalid signature was accepted");
+  assert_eq!(status.code(), Code::InvalidArgument);
 }
@@ -0,0 +1,193 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use comm_opaque2::client::Login;
+use commtest::identity::device::{
+  create_device, device_key_upload, DeviceInfo, DEVICE_TYPE,
+  PLACEHOLDER_CODE_VERSION,
+};
+use commtest::identity::olm_account_infos::MockOlmAccounts;
+use commtest::service_addr;
+use data_encoding::BASE32_NOPAD;
+use grpc_clients::identity::{
+  get_auth_client, get_unauthenticated_client,
+  protos::authenticated::ConfirmTotpRequest,
+  protos::client::{
+    Empty, OpaqueLoginFinishRequest, OpaqueLoginFinishResponse,
+    OpaqueLoginStartRequest, TwoFactorLoginRequest,
+  },
+};
+use grpc_clients::tonic::Code;
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+
+const TOTP_STEP_SECONDS: u64 = 30;
+
+fn current_step() -> u64 {
+  let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+  now.as_secs() / TOTP_STEP_SECONDS
+}
+
+/// Generates a 6 digit code like an authenticator app would (RFC 6238)
+fn totp_code(secret: &[u8], step: u64) -> String {
+  let mut mac = <Hmac<Sha1> as Mac>::new_from_slice(secret).unwrap();
+  mac.update(&step.to_be_bytes());
+  let hash = mac.finalize().into_bytes();
+  let offset = (hash[hash.len() - 1] & 0xf) as usize;
+  let binary = u32::from_be_bytes([
+    hash[offset] & 0x7f,
+    hash[offset + 1],
+    hash[offset + 2],
+    hash[offset + 3],
+  ]);
+  format!("{:06}", binary % 1_000_000)
+}
+
+/// Enables two-factor authentication, returning the secret and recovery codes
+async fn enable_two_factor(device_info: &DeviceInfo) -> (Vec<u8>, Vec<String>) {
+  let mut auth_client = get_auth_client(
+    &service_addr::IDENTITY_GRPC.to_string(),
+    device_info.user_id.clone(),
+    device_info.device_id.clone(),
+    device_info.access_token.clone(),
+    PLACEHOLDER_CODE_VERSION,
+    DEVICE_TYPE.to_string(),
+  )
+  .await
+  .expect("Couldn't connect to identity service");
+
+  let enrollment = auth_client
+    .enroll_totp(Empty {})
+    .await
+    .unwrap()
+    .into_inner();
+  assert!(enrollment.otpauth_uri.starts_with("otpauth://totp/"));
+  let secret = BASE32_NOPAD.decode(enrollment.secret.as_bytes()).unwrap();
+
+  let recovery_codes = auth_client
+    .confirm_totp(ConfirmTotpRequest {
+      code: totp_code(&secret, current_step()),
+    })
+    .await
+    .unwrap()
+    .into_inner()
+    .recovery_codes;
+
+  (secret, recovery_codes)
+}
+
+async fn login(username: &str) -> OpaqueLoginFinishResponse {
+  let mut identity_client = get_unauthenticated_client(
+    &service_addr::IDENTITY_GRPC.to_string(),
+    PLACEHOLDER_CODE_VERSION,
+    DEVICE_TYPE.to_string(),
+  )
+  .await
+  .expect("Couldn't connect to identity service");
+
+  let mut client_login = Login::new();
+  let login_start_response = identity_client
+    .login_password_user_start(OpaqueLoginStartRequest {
+      opaque_login_request: client_login.start("pass").unwrap(),
+      username: username.to_string(),
+      device_key_upload: Some(device_key_upload(&MockOlmAccounts::generate())),
+    })
+    .await
+    .unwrap()
+    .into_inner();
+  let opaque_login_upload = client_login
+    .finish(&login_start_response.opaque_login_response)
+    .unwrap();
+  identity_client
+    .login_password_user_finish(OpaqueLoginFinishRequest {
+      session_id: login_start_response.session_id,
+      opaque_login_upload,
+    })
+    .await
+    .unwrap()
+    .into_inner()
+}
+
+#[tokio::test]
+async fn login_with_totp_code() {
+  let device_info = create_device(None).await;
+  let (secret, _) = enable_two_factor(&device_info).await;
+
+  let login_response = login(&device_info.username).await;
+  assert!(login_response.access_token.is_empty());
+  let two_factor_session_id = login_response
+    .two_factor_session_id
+    .expect("Two-factor session not started");
+
+  let mut identity_client = get_unauthenticated_client(
+    &service_addr::IDENTITY_GRPC.to_string(),
+    PLACEHOLDER_CODE_VERSION,
+    DEVICE_TYPE.to_string(),
+  )
+  .await
+  .expect("Couldn't connect to identity service");
+
+  // The code used for confirmation can't be reused, so the code of the next
+  // step, which is accepted to allow for clock drift, is used
+  let two_factor_response = identity_client
+    .login_password_user_two_factor(TwoFactorLoginRequest {
+      two_factor_session_id,
+      code: totp_code(&secret, current_step() + 1),
+    })
+    .await
+    .unwrap()
+    .into_inner();
+  assert_eq!(two_factor_response.user_id, device_info.user_id);
+  assert!(!two_factor_response.access_token.is_empty());
+}
+
+#[tokio::test]
+async fn login_with_recovery_code() {
+  let device_info = create_device(None).await;
+  let (_, recovery_codes) = enable_two_factor(&device_info).await;
+
+  let mut identity_client = get_unauthenticated_client(
+    &service_addr::IDENTITY_GRPC.to_string(),
+    PLACEHOLDER_CODE_VERSION,
+    DEVICE_TYPE.to_string(),
+  )
+  .await
+  .expect("Couldn't connect to identity service");
+
+  // Invalid codes fail the session
+  let two_factor_session_id = login(&device_info.username)
+    .await
+    .two_factor_session_id
+    .unwrap();
+  let status = identity_client
+    .login_password_user_two_factor(TwoFactorLoginRequest {
+      two_factor_session_id: two_factor_session_id.clone(),
+      code: "00000-00000".to_string(),
+    })
+    .await
+    .unwrap_err();
+  assert_eq!(status.code(), Code::PermissionDenied);
+  let status = identity_client
+    .login_password_user_two_factor(TwoFactorLoginRequest {
+      two_factor_session_id,
+      code: recovery_codes[0].clone(),
+    })
+    .await
+    .unwrap_err();
+  assert_eq!(status.code(), Code::NotFound);
+
+  // Recovery codes can be used only once
+  for expected_code in [Code::Ok, Code::PermissionDenied] {
+    let two_factor_session_id = login(&device_info.username)
+      .await
+      .two_factor_session_id
+      .unwrap();
+    let result = identity_client
+      .login_password_user_two_factor(TwoFactorLoginRequest {
+        two_factor_session_id,
+        code: recovery_codes[0].clone(),
+      })
+      .await;
+    let code = result.map_or_else(|status| status.code(), |_| Code::Ok);
+    assert_eq!(code, expected_code);
+  }
+}
@@ -0,0 +1,53 @@
+use std::time::Duration;
+
+use commtest::identity::device::{
+  create_device, DEVICE_TYPE, PLACEHOLDER_CODE_VERSION,
+};
+use commtest::service_addr;
+use grpc_clients::identity::{
+  get_unauthenticated_client,
+  protos::client::{DeleteUserRequest, VerifyUserAccessTokenRequest},
+};
+
+#[tokio::test]
+async fn delete_user_revokes_access_tokens() {
+  let identity_grpc_endpoint = service_addr::IDENTITY_GRPC.to_string();
+  let device_info = create_device(None).await;
+
+  let mut identity_client = get_unauthenticated_client(
+    &identity_grpc_endpoint,
+    PLACEHOLDER_CODE_VERSION,
+    DEVICE_TYPE.to_string(),
+  )
+  .await
+  .expect("Couldn't connect to identity service");
+
+  let delete_request = DeleteUserRequest {
+    access_token: device_info.access_token.clone(),
+    user_id: device_info.user_id.clone(),
+    device_id_key: device_info.device_id.clone(),
+  };
+  identity_client.delete_user(delete_request).await.unwrap();
+
+  // Tokens are removed in the background after the user is deleted
+  let mut token_valid = true;
+  for _ in 0..10 {
+    let verify_request = VerifyUserAccessTokenRequest {
+      user_id: device_info.user_id.clone(),
+      signing_public_key: device_info.device_id.clone(),
+      access_token: device_info.access_token.clone(),
+    };
+    token_valid = identity_client
+      .verify_user_access_token(verify_request)
+      .await
+      .unwrap()
+      .into_inner()
+      .token_valid;
+    if !token_valid {
+      break;
+    }
+    tokio::time::sleep(Duration::from_millis(200)).await;
+  }
+
+  assert!(!token_valid, "Access token is still valid after deletion");
+}
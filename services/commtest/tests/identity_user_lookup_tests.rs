@@ -0,0 +1,59 @@
+use commtest::identity::device::{
+  create_device, DEVICE_TYPE, PLACEHOLDER_CODE_VERSION,
+};
+use commtest::service_addr;
+use grpc_clients::identity::{
+  get_unauthenticated_client,
+  protos::client::{
+    find_user_id_request, user_identity, FindUserIdRequest,
+    FindUserIdentitiesRequest,
+  },
+};
+use grpc_clients::tonic::Code;
+
+#[tokio::test]
+async fn find_user_by_username() {
+  let device_info = create_device(None).await;
+
+  let mut identity_client = get_unauthenticated_client(
+    &service_addr::IDENTITY_GRPC.to_string(),
+    PLACEHOLDER_CODE_VERSION,
+    DEVICE_TYPE.to_string(),
+  )
+  .await
+  .expect("Couldn't connect to identity service");
+
+  let response = identity_client
+    .find_user_id(FindUserIdRequest {
+      identifier: Some(find_user_id_request::Identifier::Username(
+        device_info.username.clone(),
+      )),
+    })
+    .await
+    .unwrap()
+    .into_inner();
+  assert_eq!(response.user_id, device_info.user_id);
+
+  let status = identity_client
+    .find_user_id(FindUserIdRequest {
+      identifier: Some(find_user_id_request::Identifier::Username(
+        "unknown_user".to_string(),
+      )),
+    })
+    .await
+    .unwrap_err();
+  assert_eq!(status.code(), Code::NotFound);
+
+  let response = identity_client
+    .find_user_identities(FindUserIdentitiesRequest {
+      user_ids: vec![device_info.user_id.clone(), "unknown".to_string()],
+    })
+    .await
+    .unwrap()
+    .into_inner();
+  assert_eq!(response.identities.len(), 1);
+  assert_eq!(
+    response.identities[&device_info.user_id].identifier,
+    Some(user_identity::Identifier::Username(device_info.username))
+  );
+}
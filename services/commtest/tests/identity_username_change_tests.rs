@@ -0,0 +1,217 @@
+use comm_opaque2::client::{Login, Registration};
+use commtest::identity::device::{
+  create_device, device_key_upload, DEVICE_TYPE, PLACEHOLDER_CODE_VERSION,
+};
+use commtest::identity::keyserver::sign_keyserver_message;
+use commtest::identity::olm_account_infos::MockOlmAccounts;
+use commtest::service_addr;
+use grpc_clients::identity::{
+  get_auth_client, get_unauthenticated_client,
+  protos::authenticated::{
+    ChangeUsernameFinishRequest, ChangeUsernameStartRequest,
+  },
+  protos::client::{
+    AddReservedUsernamesRequest, OpaqueLoginFinishRequest,
+    OpaqueLoginStartRequest, RegistrationStartRequest,
+  },
+};
+use grpc_clients::tonic::Code;
+use rand::{distributions::Alphanumeric, Rng};
+use serde_json::json;
+
+#[tokio::test]
+async fn change_username() {
+  let device_info = create_device(None).await;
+  let new_username: String = rand::thread_rng()
+    .sample_iter(&Alphanumeric)
+    .take(7)
+    .map(char::from)
+    .collect();
+
+  let mut auth_client = get_auth_client(
+    &service_addr::IDENTITY_GRPC.to_string(),
+    device_info.user_id.clone(),
+    device_info.device_id,
+    device_info.access_token,
+    PLACEHOLDER_CODE_VERSION,
+    DEVICE_TYPE.to_string(),
+  )
+  .await
+  .expect("Couldn't connect to identity service");
+
+  let mut client_registration = Registration::new();
+  let start_response = auth_client
+    .change_username_start(ChangeUsernameStartRequest {
+      new_username: new_username.clone(),
+      opaque_registration_request: client_registration.start("pass").unwrap(),
+      keyserver_message: None,
+      keyserver_signature: None,
+    })
+    .await
+    .unwrap()
+    .into_inner();
+  let opaque_registration_upload = client_registration
+    .finish("pass", &start_response.opaque_registration_response)
+    .unwrap();
+  auth_client
+    .change_username_finish(ChangeUsernameFinishRequest {
+      session_id: start_response.session_id,
+      opaque_registration_upload,
+    })
+    .await
+    .unwrap();
+
+  let mut identity_client = get_unauthenticated_client(
+    &service_addr::IDENTITY_GRPC.to_string(),
+    PLACEHOLDER_CODE_VERSION,
+    DEVICE_TYPE.to_string(),
+  )
+  .await
+  .expect("Couldn't connect to identity service");
+
+  // The password works with the new username
+  let keys = MockOlmAccounts::generate();
+  let mut client_login = Login::new();
+  let login_start_response = identity_client
+    .login_password_user_start(OpaqueLoginStartRequest {
+      opaque_login_request: client_login.start("pass").unwrap(),
+      username: new_username,
+      device_key_upload: Some(device_key_upload(&keys)),
+    })
+    .await
+    .unwrap()
+    .into_inner();
+  let opaque_login_upload = client_login
+    .finish(&login_start_response.opaque_login_response)
+    .unwrap();
+  let login_finish_response = identity_client
+    .login_password_user_finish(OpaqueLoginFinishRequest {
+      session_id: login_start_response.session_id,
+      opaque_login_upload,
+    })
+    .await
+    .unwrap()
+    .into_inner();
+  assert_eq!(login_finish_response.user_id, device_info.user_id);
+
+  // The old username is held for its previous owner
+  let mut client_registration = Registration::new();
+  let status = identity_client
+    .register_password_user_start(RegistrationStartRequest {
+      opaque_registration_request: client_registration.start("pass").unwrap(),
+      username: device_info.username,
+      device_key_upload: Some(device_key_upload(&MockOlmAccounts::generate())),
+    })
+    .await
+    .unwrap_err();
+  assert_eq!(status.code(), Code::AlreadyExists);
+}
+
+#[tokio::test]
+async fn change_username_to_taken_username() {
+  let device_info = create_device(None).await;
+  let other_device_info = create_device(None).await;
+
+  let mut auth_client = get_auth_client(
+    &service_addr::IDENTITY_GRPC.to_string(),
+    device_info.user_id,
+    device_info.device_id,
+    device_info.access_token,
+    PLACEHOLDER_CODE_VERSION,
+    DEVICE_TYPE.to_string(),
+  )
+  .await
+  .expect("Couldn't connect to identity service");
+
+  let mut client_registration = Registration::new();
+  let status = auth_client
+    .change_username_start(ChangeUsernameStartRequest {
+      new_username: other_device_info.username,
+      opaque_registration_request: client_registration.start("pass").unwrap(),
+      keyserver_message: None,
+      keyserver_signature: None,
+    })
+    .await
+    .unwrap_err();
+  assert_eq!(status.code(), Code::AlreadyExists);
+}
+
+#[tokio::test]
+async fn change_username_to_keyserver_reserved_username() {
+  let device_info = create_device(None).await;
+  let reserved_username: String = rand::thread_rng()
+    .sample_iter(&Alphanumeric)
+    .take(7)
+    .map(char::from)
+    .collect();
+
+  // Reserved without a user ID, like by the keyserver's cron job
+  let mut identity_client = get_unauthenticated_client(
+    &service_addr::IDENTITY_GRPC.to_string(),
+    PLACEHOLDER_CODE_VERSION,
+    DEVICE_TYPE.to_string(),
+  )
+  .await
+  .expect("Couldn't connect to identity service");
+  let (message, signature) = sign_keyserver_message(
+    "Add the following usernames to reserved list",
+    json!([reserved_username]),
+  );
+  identity_client
+    .add_reserved_usernames(AddReservedUsernamesRequest { message, signature })
+    .await
+    .unwrap();
+
+  let mut auth_client = get_auth_client(
+    &service_addr::IDENTITY_GRPC.to_string(),
+    device_info.user_id.clone(),
+    device_info.device_id,
+    device_info.access_token,
+    PLACEHOLDER_CODE_VERSION,
+    DEVICE_TYPE.to_string(),
+  )
+  .await
+  .expect("Couldn't connect to identity service");
+
+  // Without proof of ownership the username is taken
+  let mut client_registration = Registration::new();
+  let status = auth_client
+    .change_username_start(ChangeUsernameStartRequest {
+      new_username: reserved_username.clone(),
+      opaque_registration_request: client_registration.start("pass").unwrap(),
+      keyserver_message: None,
+      keyserver_signature: None,
+    })
+    .await
+    .unwrap_err();
+  assert_eq!(status.code(), Code::AlreadyExists);
+
+  let (keyserver_message, keyserver_signature) = sign_keyserver_message(
+    "This user is the owner of the following username and user ID",
+    json!({
+      "username": reserved_username,
+      "userID": device_info.user_id,
+    }),
+  );
+  let mut client_registration = Registration::new();
+  let start_response = auth_client
+    .change_username_start(ChangeUsernameStartRequest {
+      new_username: reserved_username,
+      opaque_registration_request: client_registration.start("pass").unwrap(),
+      keyserver_message: Some(keyserver_message),
+      keyserver_signature: Some(keyserver_signature),
+    })
+    .await
+    .unwrap()
+    .into_inner();
+  let opaque_registration_upload = client_registration
+    .finish("pass", &start_response.opaque_registration_response)
+    .unwrap();
+  auth_client
+    .change_username_finish(ChangeUsernameFinishRequest {
+      session_id: start_response.session_id,
+      opaque_registration_upload,
+    })
+    .await
+    .unwrap();
+}
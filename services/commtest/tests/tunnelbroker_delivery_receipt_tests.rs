@@ -0,0 +1,78 @@
+use commtest::identity::device::create_device;
+use commtest::identity::olm_account_infos::{
+  MOCK_CLIENT_KEYS_1, MOCK_CLIENT_KEYS_2,
+};
+use commtest::tunnelbroker::socket::{
+  create_socket, receive_delivery_receipt, receive_message,
+  send_message_with_delivery_receipt, WebSocketMessageToDevice,
+};
+use futures_util::SinkExt;
+use std::time::Duration;
+use tokio::time::sleep;
+use tokio_tungstenite::tungstenite::Message::Close;
+
+/// Tests of MessageDelivered receipts sent to the sender once the recipient
+/// confirms a message
+
+#[tokio::test]
+async fn receive_delivery_receipt_connected() {
+  let sender = create_device(Some(&MOCK_CLIENT_KEYS_1)).await;
+  let receiver = create_device(Some(&MOCK_CLIENT_KEYS_2)).await;
+
+  let mut receiver_socket = create_socket(&receiver).await.unwrap();
+  let mut sender_socket = create_socket(&sender).await.unwrap();
+
+  let request = WebSocketMessageToDevice {
+    device_id: receiver.device_id.clone(),
+    payload: "message with delivery receipt".to_string(),
+  };
+  let client_message_id =
+    send_message_with_delivery_receipt(&mut sender_socket, request.clone())
+      .await
+      .unwrap();
+
+  // receive_message confirms the message
+  let response = receive_message(&mut receiver_socket).await.unwrap();
+  assert_eq!(request.payload, response);
+
+  let receipt = receive_delivery_receipt(&mut sender_socket).await.unwrap();
+  assert_eq!(receipt.client_message_id, client_message_id);
+  assert_eq!(receipt.device_id, receiver.device_id);
+}
+
+#[tokio::test]
+async fn receive_delivery_receipt_not_connected() {
+  let sender = create_device(Some(&MOCK_CLIENT_KEYS_1)).await;
+  let receiver = create_device(Some(&MOCK_CLIENT_KEYS_2)).await;
+
+  let mut sender_socket = create_socket(&sender).await.unwrap();
+
+  let request = WebSocketMessageToDevice {
+    device_id: receiver.device_id.clone(),
+    payload: "message with delivery receipt to offline sender".to_string(),
+  };
+  let client_message_id =
+    send_message_with_delivery_receipt(&mut sender_socket, request.clone())
+      .await
+      .unwrap();
+
+  // sender disconnects before the message is confirmed
+  sender_socket
+    .send(Close(None))
+    .await
+    .expect("Failed to send message");
+  sleep(Duration::from_millis(200)).await;
+
+  let mut receiver_socket = create_socket(&receiver).await.unwrap();
+  let response = receive_message(&mut receiver_socket).await.unwrap();
+  assert_eq!(request.payload, response);
+
+  // wait a specified duration to ensure that receipt had time to persist
+  sleep(Duration::from_millis(100)).await;
+
+  // receipt is delivered once the sender reconnects
+  let mut sender_socket = create_socket(&sender).await.unwrap();
+  let receipt = receive_delivery_receipt(&mut sender_socket).await.unwrap();
+  assert_eq!(receipt.client_message_id, client_message_id);
+  assert_eq!(receipt.device_id, receiver.device_id);
+}
@@ -0,0 +1,82 @@
+mod proto {
+  tonic::include_proto!("tunnelbroker");
+}
+
+use commtest::identity::device::create_device;
+use commtest::service_addr;
+use commtest::tunnelbroker::services_request;
+use commtest::tunnelbroker::socket::create_socket;
+use futures_util::SinkExt;
+use proto::tunnelbroker_service_client::TunnelbrokerServiceClient;
+use proto::DevicePresenceRequest;
+use std::time::Duration;
+use tokio::time::sleep;
+use tokio_tungstenite::tungstenite::Message::Close;
+
+#[tokio::test]
+async fn presence_follows_connection() {
+  let device_info = create_device(None).await;
+
+  let mut tunnelbroker_client =
+    TunnelbrokerServiceClient::connect(service_addr::TUNNELBROKER_GRPC)
+      .await
+      .unwrap();
+
+  let presence_request = DevicePresenceRequest {
+    device_id: device_info.device_id.clone(),
+  };
+
+  // Device is not connected yet
+  let presence = tunnelbroker_client
+    .get_device_presence(services_request(presence_request.clone()))
+    .await
+    .unwrap()
+    .into_inner();
+  assert!(!presence.connected);
+  assert_eq!(presence.connected_since, None);
+
+  let mut socket = create_socket(&device_info).await.unwrap();
+
+  let presence = tunnelbroker_client
+    .get_device_presence(services_request(presence_request.clone()))
+    .await
+    .unwrap()
+    .into_inner();
+  assert!(presence.connected);
+  assert!(presence.instance_id.is_some());
+  assert!(presence.connected_since.is_some());
+
+  socket
+    .send(Close(None))
+    .await
+    .expect("Failed to close socket");
+
+  // Wait for the session to be unregistered
+  sleep(Duration::from_millis(200)).await;
+
+  let presence = tunnelbroker_client
+    .get_device_presence(services_request(presence_request))
+    .await
+    .unwrap()
+    .into_inner();
+  assert!(!presence.connected);
+}
+
+#[tokio::test]
+async fn presence_requires_services_token() {
+  let device_info = create_device(None).await;
+
+  let mut tunnelbroker_client =
+    TunnelbrokerServiceClient::connect(service_addr::TUNNELBROKER_GRPC)
+      .await
+      .unwrap();
+
+  let presence_request = DevicePresenceRequest {
+    device_id: device_info.device_id.clone(),
+  };
+  let status = tunnelbroker_client
+    .get_device_presence(presence_request)
+    .await
+    .unwrap_err();
+  assert_eq!(status.code(), tonic::Code::Unauthenticated);
+}
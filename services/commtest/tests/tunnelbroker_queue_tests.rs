@@ -0,0 +1,103 @@
+mod proto {
+  tonic::include_proto!("tunnelbroker");
+}
+use commtest::identity::device::create_device;
+use commtest::identity::olm_account_infos::{
+  MOCK_CLIENT_KEYS_1, MOCK_CLIENT_KEYS_2,
+};
+use commtest::service_addr;
+use commtest::tunnelbroker::services_request;
+use commtest::tunnelbroker::socket::{create_socket, receive_message};
+use proto::tunnelbroker_service_client::TunnelbrokerServiceClient;
+use proto::{DeviceQueueRequest, MessageToDevice, MessagesToDevices};
+
+/// Tests that a batch of messages is persisted for offline devices, can be
+/// inspected and purged
+
+#[tokio::test]
+async fn batch_send_inspect_and_purge() {
+  let first_device = create_device(Some(&MOCK_CLIENT_KEYS_1)).await;
+  let second_device = create_device(Some(&MOCK_CLIENT_KEYS_2)).await;
+
+  let mut tunnelbroker_client =
+    TunnelbrokerServiceClient::connect(service_addr::TUNNELBROKER_GRPC)
+      .await
+      .unwrap();
+
+  let messages = vec![
+    MessageToDevice {
+      device_id: first_device.device_id.clone(),
+      payload: "first message".to_string(),
+    },
+    MessageToDevice {
+      device_id: first_device.device_id.clone(),
+      payload: "second message".to_string(),
+    },
+    MessageToDevice {
+      device_id: second_device.device_id.clone(),
+      payload: "message to second device".to_string(),
+    },
+  ];
+  let response = tunnelbroker_client
+    .send_messages_to_devices(services_request(MessagesToDevices { messages }))
+    .await
+    .unwrap()
+    .into_inner();
+  assert!(response.failures.is_empty());
+
+  let first_queue = DeviceQueueRequest {
+    device_id: first_device.device_id.clone(),
+  };
+  let stats = tunnelbroker_client
+    .get_device_queue_stats(services_request(first_queue.clone()))
+    .await
+    .unwrap()
+    .into_inner();
+  assert_eq!(stats.undelivered_messages_count, 2);
+  assert!(stats.oldest_message_timestamp.is_some());
+
+  let purged = tunnelbroker_client
+    .purge_device_queue(services_request(first_queue.clone()))
+    .await
+    .unwrap()
+    .into_inner();
+  assert_eq!(purged.deleted_messages_count, 2);
+
+  let stats = tunnelbroker_client
+    .get_device_queue_stats(services_request(first_queue))
+    .await
+    .unwrap()
+    .into_inner();
+  assert_eq!(stats.undelivered_messages_count, 0);
+  assert_eq!(stats.oldest_message_timestamp, None);
+
+  // Queue of the other device is not affected
+  let mut socket = create_socket(&second_device).await.unwrap();
+  let response = receive_message(&mut socket).await.unwrap();
+  assert_eq!(response, "message to second device");
+}
+
+#[tokio::test]
+async fn queue_rpcs_require_services_token() {
+  let device_info = create_device(None).await;
+
+  let mut tunnelbroker_client =
+    TunnelbrokerServiceClient::connect(service_addr::TUNNELBROKER_GRPC)
+      .await
+      .unwrap();
+
+  let queue_request = DeviceQueueRequest {
+    device_id: device_info.device_id.clone(),
+  };
+  let status = tunnelbroker_client
+    .purge_device_queue(queue_request.clone())
+    .await
+    .unwrap_err();
+  assert_eq!(status.code(), tonic::Code::Unauthenticated);
+
+  let status = tunnelbroker_client
+    .get_device_queue_stats(queue_request)
+    .await
+    .unwrap_err();
+  assert_eq!(status.code(), tonic::Code::Unauthenticated);
+}
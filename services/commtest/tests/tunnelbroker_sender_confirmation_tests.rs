@@ -25,6 +25,7 @@ async fn get_confirmation() {
     client_message_id: client_message_id.clone(),
     device_id: receiver.device_id.clone(),
     payload: payload.to_string(),
+    request_delivery_receipt: false,
   };
 
   let serialized_request = serde_json::to_string(&request)
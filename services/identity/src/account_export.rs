@@ -0,0 +1,394 @@
+//! Export of everything identity stores about a user, as a single JSON
+//! document. Secrets, such as access tokens, password files and TOTP secrets,
+//! are never included.
+
+use chrono::{DateTime, Duration, Utc};
+use comm_services_lib::auth::{
+  AuthService, AuthorizationCredential, UserIdentity,
+};
+use comm_services_lib::blob::client::{BlobServiceClient, BlobServiceError};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use tonic::Status;
+use tracing::error;
+
+use crate::audit_log::{AuditEvent, AuditSubject};
+use crate::constants::{
+  ACCOUNT_EXPORT_BLOB_HOLDER_PREFIX, ACCOUNT_EXPORT_CLEANUP_INTERVAL_SECONDS,
+  ACCOUNT_EXPORT_LIFETIME_HOURS, AUDIT_LOG_MAX_PAGE_SIZE,
+};
+use crate::database::{DatabaseClient, UserDevice, UserRecord};
+use crate::error::{consume_error, Error};
+use crate::id::generate_uuid;
+use crate::token::{AccessTokenData, AuthType};
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AccountExport {
+  pub exported_at: String,
+  pub user: ExportedUser,
+  pub devices: Vec<ExportedDevice>,
+  pub access_tokens: Vec<ExportedAccessToken>,
+  pub reserved_username: Option<ExportedUsernameReservation>,
+  pub audit_events: Vec<ExportedAuditEvent>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportedUser {
+  #[serde(rename = "userID")]
+  pub user_id: String,
+  pub username: Option<String>,
+  pub wallet_address: Option<String>,
+  pub ens_name: Option<String>,
+  pub registration_time: Option<String>,
+  pub two_factor_enabled: bool,
+}
+
+impl ExportedUser {
+  fn new(user: UserRecord, two_factor_enabled: bool) -> Self {
+    ExportedUser {
+      user_id: user.user_id,
+      username: user.username,
+      wallet_address: user.wallet_address,
+      ens_name: user.ens_name,
+      registration_time: user.creation_time.as_ref().map(rfc3339),
+      two_factor_enabled,
+    }
+  }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportedDevice {
+  #[serde(rename = "deviceID")]
+  pub device_id: String,
+  pub device_type: String,
+  pub key_payload: String,
+  pub key_payload_signature: String,
+  pub last_login: Option<String>,
+  pub code_version: Option<u64>,
+}
+
+impl From<UserDevice> for ExportedDevice {
+  fn from(device: UserDevice) -> Self {
+    ExportedDevice {
+      device_id: device.device_id,
+      device_type: device.device_type,
+      key_payload: device.key_payload,
+      key_payload_signature: device.key_payload_signature,
+      last_login: device.last_login.as_ref().map(rfc3339),
+      code_version: device.code_version,
+    }
+  }
+}
+
+/// Metadata of an access token, without the token itself
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportedAccessToken {
+  #[serde(rename = "deviceID")]
+  pub device_id: String,
+  pub auth_type: &'static str,
+  pub created: String,
+  pub expires: String,
+  pub valid: bool,
+}
+
+impl From<AccessTokenData> for ExportedAccessToken {
+  fn from(token: AccessTokenData) -> Self {
+    ExportedAccessToken {
+      valid: token.is_valid(),
+      device_id: token.signing_public_key,
+      auth_type: auth_type_str(&token.auth_type),
+      created: rfc3339(&token.created),
+      expires: rfc3339(&token.expires),
+    }
+  }
+}
+
+/// Set if the user's username is reserved for them, e.g. because it was
+/// imported from Ashoat's keyserver
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportedUsernameReservation {
+  pub username: String,
+  // Set only for usernames held after a username change
+  pub held_until: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportedAuditEvent {
+  pub event_type: &'static str,
+  pub outcome: &'static str,
+  pub timestamp: String,
+  #[serde(rename = "deviceID")]
+  pub device_id: Option<String>,
+  pub auth_type: Option<&'static str>,
+  pub ip_address: Option<String>,
+  pub user_agent: Option<String>,
+}
+
+impl From<AuditEvent> for ExportedAuditEvent {
+  fn from(event: AuditEvent) -> Self {
+    ExportedAuditEvent {
+      event_type: event.event_type.as_str(),
+      outcome: event.outcome.as_str(),
+      timestamp: rfc3339(&event.timestamp),
+      device_id: event.device_id,
+      auth_type: event.auth_type.as_ref().map(auth_type_str),
+      ip_address: event.ip_address,
+      user_agent: event.user_agent,
+    }
+  }
+}
+
+// Timestamps are exported in the same format as in the audit log RPC
+fn rfc3339(time: &DateTime<Utc>) -> String {
+  time.to_rfc3339()
+}
+
+fn auth_type_str(auth_type: &AuthType) -> &'static str {
+  match auth_type {
+    AuthType::Password => "password",
+    AuthType::Wallet => "wallet",
+  }
+}
+
+/// Returns `None` if the user doesn't exist
+pub async fn export_account_data(
+  db_client: &DatabaseClient,
+  user_id: &str,
+) -> Result<Option<AccountExport>, Error> {
+  let Some(user) = db_client.get_user_record(user_id).await? else {
+    return Ok(None);
+  };
+
+  let two_factor_enabled = db_client
+    .get_totp_state(user_id)
+    .await?
+    .map_or(false, |totp_state| totp_state.confirmed);
+
+  let devices = db_client
+    .get_user_devices(user_id)
+    .await?
+    .unwrap_or_default()
+    .into_iter()
+    .map(ExportedDevice::from)
+    .collect();
+
+  let access_tokens = db_client
+    .get_access_tokens_for_user(user_id)
+    .await?
+    .into_iter()
+    .map(ExportedAccessToken::from)
+    .collect();
+
+  let reserved_username = match &user.username {
+    Some(username) => db_client
+      .get_username_reservation(username)
+      .await?
+      .filter(|reservation| reservation.user_id.as_deref() == Some(user_id))
+      .map(|reservation| ExportedUsernameReservation {
+        username: username.clone(),
+        held_until: reservation.expiration_time.as_ref().map(rfc3339),
+      }),
+    None => None,
+  };
+
+  let audit_events = get_all_audit_events(db_client, user_id)
+    .await?
+    .into_iter()
+    .map(ExportedAuditEvent::from)
+    .collect();
+
+  Ok(Some(AccountExport {
+    exported_at: rfc3339(&Utc::now()),
+    user: ExportedUser::new(user, two_factor_enabled),
+    devices,
+    access_tokens,
+    reserved_username,
+    audit_events,
+  }))
+}
+
+/// Events within the retention period, newest first
+async fn get_all_audit_events(
+  db_client: &DatabaseClient,
+  user_id: &str,
+) -> Result<Vec<AuditEvent>, Error> {
+  let subject = AuditSubject::User(user_id.to_string()).to_string();
+  let mut events = Vec::new();
+  let mut page_token = None;
+
+  loop {
+    let (page, next_page_token) = db_client
+      .get_audit_events(&subject, AUDIT_LOG_MAX_PAGE_SIZE as i32, page_token)
+      .await?;
+    events.extend(page);
+
+    page_token = next_page_token;
+    if page_token.is_none() {
+      return Ok(events);
+    }
+  }
+}
+
+/// An uploaded export, whose holder is revoked once it expires
+#[derive(Clone, Debug)]
+pub struct ExportBlob {
+  pub user_id: String,
+  pub holder: String,
+  pub blob_hash: String,
+  pub expiration_time: DateTime<Utc>,
+}
+
+/// Uploads the export with the credentials of the user's device. The blob
+/// service serves blobs to anyone who knows their hash, so the export is
+/// recorded before it's uploaded and its holder is revoked after
+/// `ACCOUNT_EXPORT_LIFETIME_HOURS`.
+pub async fn upload_export(
+  db_client: &DatabaseClient,
+  blob_client: &BlobServiceClient,
+  user_identity: UserIdentity,
+  export_json: String,
+) -> Result<ExportBlob, Error> {
+  let blob_hash = hex::encode(Sha256::digest(export_json.as_bytes()));
+  // Every export gets its own holder, even if identical exports share a blob
+  let holder = format!(
+    "{}:{}:{}",
+    ACCOUNT_EXPORT_BLOB_HOLDER_PREFIX,
+    user_identity.user_id,
+    generate_uuid()
+  );
+  let export_blob = ExportBlob {
+    user_id: user_identity.user_id.clone(),
+    holder,
+    blob_hash,
+    expiration_time: Utc::now()
+      + Duration::hours(ACCOUNT_EXPORT_LIFETIME_HOURS),
+  };
+  db_client.put_export_blob(&export_blob).await?;
+
+  let data_stream =
+    futures_util::stream::iter([Ok::<_, std::io::Error>(export_json)]);
+  blob_client
+    .with_user_identity(user_identity)
+    .simple_put(&export_blob.blob_hash, &export_blob.holder, data_stream)
+    .await
+    .map_err(|e| {
+      error!("Failed to upload account export: {}", e);
+      Error::Status(Status::unavailable("please retry"))
+    })?;
+
+  Ok(export_blob)
+}
+
+/// Revokes the holders of the exports and deletes their records. Holders
+/// which were never assigned, e.g. because the upload failed, are skipped.
+pub async fn revoke_export_blobs(
+  db_client: &DatabaseClient,
+  blob_client: &BlobServiceClient,
+  export_blobs: Vec<ExportBlob>,
+) -> Result<(), Error> {
+  for export_blob in export_blobs {
+    match blob_client
+      .revoke_holder(&export_blob.blob_hash, &export_blob.holder)
+      .await
+    {
+      Ok(()) | Err(BlobServiceError::NotFound) => (),
+      Err(e) => {
+        error!(
+          "Failed to revoke account export holder {}: {}",
+          export_blob.holder, e
+        );
+        return Err(Error::Status(Status::unavailable(format!("{}", e))));
+      }
+    }
+    db_client
+      .delete_export_blob(&export_blob.user_id, &export_blob.holder)
+      .await?;
+  }
+
+  Ok(())
+}
+
+/// Blob client authenticated as identity itself, for revoking holders of
+/// users who can't be asked for their credentials
+pub async fn services_blob_client(
+  blob_client: &BlobServiceClient,
+  auth_service: &AuthService,
+) -> Result<BlobServiceClient, Error> {
+  let services_token =
+    auth_service.get_services_token().await.map_err(|e| {
+      error!("Unable to get services token: {:?}", e);
+      Error::Status(Status::unavailable(format!("{}", e)))
+    })?;
+
+  Ok(
+    blob_client.with_authentication(AuthorizationCredential::ServicesToken(
+      services_token,
+    )),
+  )
+}
+
+/// Revokes expired export holders every
+/// `ACCOUNT_EXPORT_CLEANUP_INTERVAL_SECONDS`
+pub async fn run_export_cleanup_loop(
+  db_client: DatabaseClient,
+  blob_client: BlobServiceClient,
+  auth_service: AuthService,
+) {
+  let mut interval = tokio::time::interval(std::time::Duration::from_secs(
+    ACCOUNT_EXPORT_CLEANUP_INTERVAL_SECONDS,
+  ));
+  loop {
+    interval.tick().await;
+    let result =
+      revoke_expired_export_blobs(&db_client, &blob_client, &auth_service)
+        .await;
+    consume_error(result);
+  }
+}
+
+async fn revoke_expired_export_blobs(
+  db_client: &DatabaseClient,
+  blob_client: &BlobServiceClient,
+  auth_service: &AuthService,
+) -> Result<(), Error> {
+  let expired_blobs = db_client.get_expired_export_blobs(Utc::now()).await?;
+  if expired_blobs.is_empty() {
+    return Ok(());
+  }
+
+  let blob_client = services_blob_client(blob_client, auth_service).await?;
+  revoke_export_blobs(db_client, &blob_client, expired_blobs).await
+}
+
+#[cfg(test)]
+mod account_export_tests {
+  use super::*;
+
+  #[test]
+  fn test_access_token_is_not_exported() {
+    let token = AccessTokenData {
+      user_id: "user".to_string(),
+      signing_public_key: "device".to_string(),
+      access_token: "secret token".to_string(),
+      created: Utc::now() - Duration::days(1),
+      expires: Utc::now() - Duration::seconds(1),
+      auth_type: AuthType::Wallet,
+      valid: true,
+    };
+
+    let exported = ExportedAccessToken::from(token);
+    // Expired tokens are exported as invalid
+    assert!(!exported.valid);
+
+    let json = serde_json::to_value(&exported).unwrap();
+    assert_eq!(json["deviceID"], "device");
+    assert_eq!(json["authType"], "wallet");
+    assert!(!json.to_string().contains("secret token"));
+  }
+}
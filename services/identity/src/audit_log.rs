@@ -0,0 +1,246 @@
+//! Append-only log of security-relevant account events, such as logins and
+//! password updates, which users can review to spot unexpected activity.
+//! Events are stored in DynamoDB and deleted by TTL after the configured
+//! retention period.
+
+use std::fmt;
+use std::str::FromStr;
+
+use chrono::{DateTime, SecondsFormat, SubsecRound, Utc};
+use tonic::Request;
+
+use crate::config::CONFIG;
+use crate::database::DatabaseClient;
+use crate::error::consume_error;
+use crate::grpc_services::shared::{get_client_ip, get_value};
+use crate::id::generate_uuid;
+use crate::token::AuthType;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AuditEventType {
+  Registration,
+  Login,
+  PasswordUpdate,
+  Logout,
+  DeviceRemoval,
+  UsernameChange,
+  TwoFactorEnabled,
+  TwoFactorDisabled,
+  UserDeletion,
+  DataExport,
+  ReservedUsernameAdded,
+  ReservedUsernameRemoved,
+}
+
+impl AuditEventType {
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      AuditEventType::Registration => "registration",
+      AuditEventType::Login => "login",
+      AuditEventType::PasswordUpdate => "passwordUpdate",
+      AuditEventType::Logout => "logout",
+      AuditEventType::DeviceRemoval => "deviceRemoval",
+      AuditEventType::UsernameChange => "usernameChange",
+      AuditEventType::TwoFactorEnabled => "twoFactorEnabled",
+      AuditEventType::TwoFactorDisabled => "twoFactorDisabled",
+      AuditEventType::UserDeletion => "userDeletion",
+      AuditEventType::DataExport => "dataExport",
+      AuditEventType::ReservedUsernameAdded => "reservedUsernameAdded",
+      AuditEventType::ReservedUsernameRemoved => "reservedUsernameRemoved",
+    }
+  }
+}
+
+impl FromStr for AuditEventType {
+  type Err = ();
+
+  /// Parses the value returned by `AuditEventType::as_str()`
+  fn from_str(value: &str) -> Result<Self, Self::Err> {
+    match value {
+      "registration" => Ok(AuditEventType::Registration),
+      "login" => Ok(AuditEventType::Login),
+      "passwordUpdate" => Ok(AuditEventType::PasswordUpdate),
+      "logout" => Ok(AuditEventType::Logout),
+      "deviceRemoval" => Ok(AuditEventType::DeviceRemoval),
+      "usernameChange" => Ok(AuditEventType::UsernameChange),
+      "twoFactorEnabled" => Ok(AuditEventType::TwoFactorEnabled),
+      "twoFactorDisabled" => Ok(AuditEventType::TwoFactorDisabled),
+      "userDeletion" => Ok(AuditEventType::UserDeletion),
+      "dataExport" => Ok(AuditEventType::DataExport),
+      "reservedUsernameAdded" => Ok(AuditEventType::ReservedUsernameAdded),
+      "reservedUsernameRemoved" => Ok(AuditEventType::ReservedUsernameRemoved),
+      _ => Err(()),
+    }
+  }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AuditOutcome {
+  Success,
+  Failure,
+}
+
+impl AuditOutcome {
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      AuditOutcome::Success => "success",
+      AuditOutcome::Failure => "failure",
+    }
+  }
+}
+
+impl FromStr for AuditOutcome {
+  type Err = ();
+
+  fn from_str(value: &str) -> Result<Self, Self::Err> {
+    match value {
+      "success" => Ok(AuditOutcome::Success),
+      "failure" => Ok(AuditOutcome::Failure),
+      _ => Err(()),
+    }
+  }
+}
+
+/// Whose log an event belongs to. Reserved usernames are managed by Ashoat's
+/// keyserver and don't belong to any user yet, so their events are logged
+/// per username.
+#[derive(Clone)]
+pub enum AuditSubject {
+  User(String),
+  Username(String),
+}
+
+impl fmt::Display for AuditSubject {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      AuditSubject::User(user_id) => write!(f, "user#{}", user_id),
+      AuditSubject::Username(username) => write!(f, "username#{}", username),
+    }
+  }
+}
+
+/// Information about the client, read from request metadata before the
+/// request is consumed
+#[derive(Clone, Default)]
+pub struct RequestMetadata {
+  pub ip_address: Option<String>,
+  pub user_agent: Option<String>,
+}
+
+impl RequestMetadata {
+  pub fn from_request<T>(request: &Request<T>) -> Self {
+    RequestMetadata {
+      ip_address: get_client_ip(request),
+      user_agent: get_value(request, "user-agent"),
+    }
+  }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct AuditEvent {
+  // Sorts chronologically, unique within a subject
+  pub event_id: String,
+  pub event_type: AuditEventType,
+  pub outcome: AuditOutcome,
+  pub timestamp: DateTime<Utc>,
+  pub device_id: Option<String>,
+  pub auth_type: Option<AuthType>,
+  pub ip_address: Option<String>,
+  pub user_agent: Option<String>,
+}
+
+impl AuditEvent {
+  pub fn new(
+    event_type: AuditEventType,
+    outcome: AuditOutcome,
+    request_metadata: &RequestMetadata,
+  ) -> Self {
+    // Stored with millisecond precision
+    let timestamp = Utc::now().trunc_subsecs(3);
+    AuditEvent {
+      event_id: format!(
+        "{}#{}",
+        timestamp.to_rfc3339_opts(SecondsFormat::Millis, true),
+        generate_uuid()
+      ),
+      event_type,
+      outcome,
+      timestamp,
+      device_id: None,
+      auth_type: None,
+      ip_address: request_metadata.ip_address.clone(),
+      user_agent: request_metadata.user_agent.clone(),
+    }
+  }
+}
+
+/// Failing to record an event doesn't fail the request it belongs to
+pub async fn record_audit_event(
+  db_client: &DatabaseClient,
+  subject: AuditSubject,
+  event: AuditEvent,
+) {
+  record_audit_events(db_client, vec![(subject, event)]).await;
+}
+
+pub async fn record_audit_events(
+  db_client: &DatabaseClient,
+  events: Vec<(AuditSubject, AuditEvent)>,
+) {
+  let expiration_time = Utc::now() + CONFIG.audit_log_retention;
+  let events = events
+    .into_iter()
+    .map(|(subject, event)| (subject.to_string(), event))
+    .collect();
+  let result = db_client.put_audit_events(events, expiration_time).await;
+  consume_error(result);
+}
+
+#[cfg(test)]
+mod audit_log_tests {
+  use super::*;
+
+  #[test]
+  fn test_event_type_round_trip() {
+    let event_types = [
+      AuditEventType::Registration,
+      AuditEventType::Login,
+      AuditEventType::PasswordUpdate,
+      AuditEventType::Logout,
+      AuditEventType::DeviceRemoval,
+      AuditEventType::UsernameChange,
+      AuditEventType::TwoFactorEnabled,
+      AuditEventType::TwoFactorDisabled,
+      AuditEventType::UserDeletion,
+      AuditEventType::DataExport,
+      AuditEventType::ReservedUsernameAdded,
+      AuditEventType::ReservedUsernameRemoved,
+    ];
+    for event_type in event_types {
+      assert_eq!(event_type.as_str().parse(), Ok(event_type));
+    }
+    assert_eq!("unknown".parse::<AuditEventType>(), Err(()));
+  }
+
+  #[test]
+  fn test_subject() {
+    assert_eq!(AuditSubject::User("1".to_string()).to_string(), "user#1");
+    assert_eq!(
+      AuditSubject::Username("ashoat".to_string()).to_string(),
+      "username#ashoat"
+    );
+  }
+
+  #[test]
+  fn test_event_id_starts_with_timestamp() {
+    let event = AuditEvent::new(
+      AuditEventType::Logout,
+      AuditOutcome::Success,
+      &RequestMetadata::default(),
+    );
+    let (timestamp, _) = event.event_id.split_once('#').unwrap();
+    assert_eq!(timestamp.parse::<DateTime<Utc>>().unwrap(), event.timestamp);
+    // Fixed-width timestamps sort lexicographically in chronological order
+    assert_eq!(timestamp.len(), "2023-01-01T00:00:00.000Z".len());
+  }
+}
@@ -0,0 +1,41 @@
+use comm_services_lib::auth::{AuthService, AuthorizationCredential};
+use tonic::Status;
+use tracing::error;
+
+use crate::config::CONFIG;
+use crate::error::Error;
+
+/// Asks the backup service to delete backups of the user, along with their
+/// blob holders. The user's devices can't authenticate anymore at this point,
+/// so the request is authenticated with the services token.
+pub async fn delete_user_data(
+  auth_service: &AuthService,
+  http_client: &reqwest::Client,
+  user_id: &str,
+) -> Result<(), Error> {
+  let services_token =
+    auth_service.get_services_token().await.map_err(|e| {
+      error!("Unable to get services token: {:?}", e);
+      Error::Status(Status::unavailable(format!("{}", e)))
+    })?;
+  let bearer_token = AuthorizationCredential::ServicesToken(services_token)
+    .as_authorization_token()?;
+
+  let url = format!(
+    "{}/user_data/{}",
+    CONFIG.backup_service_url.trim_end_matches('/'),
+    user_id
+  );
+  http_client
+    .delete(url)
+    .bearer_auth(bearer_token)
+    .send()
+    .await
+    .and_then(|response| response.error_for_status())
+    .map_err(|e| {
+      error!("Unable to delete user data in backup service: {:?}", e);
+      Error::Status(Status::unavailable(format!("{}", e)))
+    })?;
+
+  Ok(())
+}
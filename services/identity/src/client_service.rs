@@ -1,47 +1,73 @@
 use std::collections::HashMap;
 // Standard library imports
 use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
 
 // External crate imports
 use aws_sdk_dynamodb::Error as DynamoDBError;
 use comm_opaque2::grpc::protocol_error_to_grpc_status;
-use moka::future::Cache;
+use comm_services_lib::auth::AuthService;
 use rand::rngs::OsRng;
+use serde::{Deserialize, Serialize};
 use siwe::eip55;
 use tonic::Response;
 use tracing::{debug, error};
 
 // Workspace crate imports
+use crate::audit_log::{
+  record_audit_event, record_audit_events, AuditEvent, AuditEventType,
+  AuditOutcome, AuditSubject, RequestMetadata,
+};
 use crate::client_service::client_proto::{
-  inbound_keys_for_user_request, outbound_keys_for_user_request,
-  AddReservedUsernamesRequest, DeleteUserRequest, Empty, GenerateNonceResponse,
+  find_user_id_request, inbound_keys_for_user_request,
+  outbound_keys_for_user_request, user_identity, AddReservedUsernamesRequest,
+  DeleteUserRequest, Empty, FindUserIdRequest, FindUserIdResponse,
+  FindUserIdentitiesRequest, FindUserIdentitiesResponse, GenerateNonceResponse,
   InboundKeyInfo, InboundKeysForUserRequest, InboundKeysForUserResponse,
   LogoutRequest, OpaqueLoginFinishRequest, OpaqueLoginFinishResponse,
   OpaqueLoginStartRequest, OpaqueLoginStartResponse, OutboundKeyInfo,
   OutboundKeysForUserRequest, OutboundKeysForUserResponse,
+  RefreshAccessTokenRequest, RefreshAccessTokenResponse,
   RegistrationFinishRequest, RegistrationFinishResponse,
   RegistrationStartRequest, RegistrationStartResponse,
   RemoveReservedUsernameRequest, ReservedRegistrationStartRequest,
-  UpdateUserPasswordFinishRequest, UpdateUserPasswordStartRequest,
-  UpdateUserPasswordStartResponse, UploadOneTimeKeysRequest,
-  VerifyUserAccessTokenRequest, VerifyUserAccessTokenResponse,
-  WalletLoginRequest, WalletLoginResponse,
+  TwoFactorLoginRequest, UpdateUserPasswordFinishRequest,
+  UpdateUserPasswordStartRequest, UpdateUserPasswordStartResponse,
+  UploadOneTimeKeysRequest, UserIdentity, VerifyUserAccessTokenRequest,
+  VerifyUserAccessTokenResponse, WalletLoginRequest, WalletLoginResponse,
 };
 use crate::config::CONFIG;
+use crate::constants::{
+  TWO_FACTOR_LOGIN_TTL_SECONDS, USER_LOOKUP_MAX_BATCH_SIZE,
+  WORKFLOW_IN_PROGRESS_TTL_SECONDS,
+};
 use crate::database::{
-  DBDeviceTypeInt, DatabaseClient, DeviceType, KeyPayload,
+  DBDeviceTypeInt, DatabaseClient, DeviceType, KeyPayload, UserIdentifier,
 };
-use crate::error::Error as DBError;
+use crate::error::{consume_error, Error as DBError};
+use crate::ethereum::{parse_address, EthereumClient};
+use crate::grpc_services::auth_layer::AuthCache;
+use crate::grpc_services::shared::{get_client_ip, get_code_version};
 use crate::grpc_utils::{DeviceInfoWithAuth, DeviceKeyUploadActions};
-use crate::id::generate_uuid;
+use crate::login_attempts::{
+  ensure_login_allowed, record_failed_login, reset_failed_logins, AttemptKey,
+};
 use crate::nonce::generate_nonce_data;
+use crate::rate_limiter::RateLimiter;
 use crate::reserved_users::{
   validate_account_ownership_message_and_get_user_id,
   validate_add_reserved_usernames_message,
   validate_remove_reserved_username_message,
 };
+use crate::signature::verify_device_key_upload;
 use crate::siwe::{is_valid_ethereum_address, parse_and_verify_siwe_message};
 use crate::token::{AccessTokenData, AuthType};
+use crate::totp::verify_second_factor;
+use crate::user_deletion::UserDeletionWorker;
+use crate::workflow_store::{
+  device_type_serde, opaque_server_login_serde, WorkflowStore,
+};
 pub use client_proto::identity_client_service_server::{
   IdentityClientService, IdentityClientServiceServer,
 };
@@ -50,33 +76,67 @@ pub mod client_proto {
   tonic::include_proto!("identity.client");
 }
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub enum WorkflowInProgress {
   Registration(Box<UserRegistrationInfo>),
   Login(Box<UserLoginInfo>),
   Update(UpdateState),
+  UsernameChange(UsernameChangeState),
+  TwoFactorLogin(Box<TwoFactorLoginState>),
+}
+
+impl WorkflowInProgress {
+  pub fn time_to_live(&self) -> Duration {
+    match self {
+      WorkflowInProgress::TwoFactorLogin(_) => {
+        Duration::from_secs(TWO_FACTOR_LOGIN_TTL_SECONDS)
+      }
+      _ => Duration::from_secs(WORKFLOW_IN_PROGRESS_TTL_SECONDS),
+    }
+  }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct UserRegistrationInfo {
   pub username: String,
   pub flattened_device_key_upload: FlattenedDeviceKeyUpload,
   pub user_id: Option<String>,
 }
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct UserLoginInfo {
   pub user_id: String,
+  pub username: String,
   pub flattened_device_key_upload: FlattenedDeviceKeyUpload,
+  #[serde(with = "opaque_server_login_serde")]
   pub opaque_server_login: comm_opaque2::server::Login,
 }
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct UpdateState {
   pub user_id: String,
 }
 
-#[derive(Clone)]
+/// Login of a user with two-factor authentication enabled, whose password has
+/// been verified
+#[derive(Clone, Serialize, Deserialize)]
+pub struct TwoFactorLoginState {
+  pub user_id: String,
+  pub username: String,
+  pub flattened_device_key_upload: FlattenedDeviceKeyUpload,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct UsernameChangeState {
+  pub user_id: String,
+  pub old_username: String,
+  pub new_username: String,
+  // Set if the new username is reserved for the user on Ashoat's keyserver
+  #[serde(default)]
+  pub keyserver_ownership_proven: bool,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
 pub struct FlattenedDeviceKeyUpload {
   pub device_id_key: String,
   pub key_payload: String,
@@ -87,13 +147,21 @@ pub struct FlattenedDeviceKeyUpload {
   pub notif_prekey: String,
   pub notif_prekey_signature: String,
   pub notif_one_time_keys: Vec<String>,
+  #[serde(with = "device_type_serde")]
   pub device_type: DeviceType,
+  // Version of the app which uploaded the keys, if reported
+  pub code_version: Option<u64>,
 }
 
 #[derive(derive_more::Constructor)]
 pub struct ClientService {
   client: DatabaseClient,
-  cache: Cache<String, WorkflowInProgress>,
+  workflow_store: Arc<dyn WorkflowStore>,
+  ethereum_client: Option<EthereumClient>,
+  lookup_rate_limiter: RateLimiter,
+  user_deletion_worker: UserDeletionWorker,
+  auth_service: AuthService,
+  auth_cache: AuthCache,
 }
 
 #[tonic::async_trait]
@@ -102,6 +170,7 @@ impl IdentityClientService for ClientService {
     &self,
     request: tonic::Request<RegistrationStartRequest>,
   ) -> Result<tonic::Response<RegistrationStartResponse>, tonic::Status> {
+    let code_version = get_code_version(&request);
     let message = request.into_inner();
     debug!("Received registration request for: {}", message.username);
 
@@ -122,7 +191,8 @@ impl IdentityClientService for ClientService {
       return Err(tonic::Status::invalid_argument("username reserved"));
     }
 
-    let registration_state = construct_user_registration_info(&message, None)?;
+    let registration_state =
+      construct_user_registration_info(&message, None, code_version)?;
     let server_registration = comm_opaque2::server::Registration::new();
     let server_message = server_registration
       .start(
@@ -132,10 +202,10 @@ impl IdentityClientService for ClientService {
       )
       .map_err(protocol_error_to_grpc_status)?;
     let session_id = self
-      .insert_into_cache(WorkflowInProgress::Registration(Box::new(
+      .insert_workflow(WorkflowInProgress::Registration(Box::new(
         registration_state,
       )))
-      .await;
+      .await?;
 
     let response = RegistrationStartResponse {
       session_id,
@@ -148,6 +218,7 @@ impl IdentityClientService for ClientService {
     &self,
     request: tonic::Request<ReservedRegistrationStartRequest>,
   ) -> Result<tonic::Response<RegistrationStartResponse>, tonic::Status> {
+    let code_version = get_code_version(&request);
     let message = request.into_inner();
     self.check_username_taken(&message.username).await?;
 
@@ -171,7 +242,7 @@ impl IdentityClientService for ClientService {
     )?;
 
     let registration_state =
-      construct_user_registration_info(&message, Some(user_id))?;
+      construct_user_registration_info(&message, Some(user_id), code_version)?;
     let server_registration = comm_opaque2::server::Registration::new();
     let server_message = server_registration
       .start(
@@ -182,10 +253,10 @@ impl IdentityClientService for ClientService {
       .map_err(protocol_error_to_grpc_status)?;
 
     let session_id = self
-      .insert_into_cache(WorkflowInProgress::Registration(Box::new(
+      .insert_workflow(WorkflowInProgress::Registration(Box::new(
         registration_state,
       )))
-      .await;
+      .await?;
 
     let response = RegistrationStartResponse {
       session_id,
@@ -198,13 +269,12 @@ impl IdentityClientService for ClientService {
     &self,
     request: tonic::Request<RegistrationFinishRequest>,
   ) -> Result<tonic::Response<RegistrationFinishResponse>, tonic::Status> {
+    let request_metadata = RequestMetadata::from_request(&request);
     let message = request.into_inner();
 
     if let Some(WorkflowInProgress::Registration(state)) =
-      self.cache.get(&message.session_id)
+      self.take_workflow(&message.session_id).await?
     {
-      self.cache.invalidate(&message.session_id).await;
-
       let server_registration = comm_opaque2::server::Registration::new();
       let password_file = server_registration
         .finish(&message.opaque_registration_upload)
@@ -220,7 +290,7 @@ impl IdentityClientService for ClientService {
       // Create access token
       let token = AccessTokenData::new(
         user_id.clone(),
-        device_id,
+        device_id.clone(),
         crate::token::AuthType::Password,
         &mut OsRng,
       );
@@ -233,6 +303,22 @@ impl IdentityClientService for ClientService {
         .await
         .map_err(handle_db_error)?;
 
+      let event = AuditEvent {
+        device_id: Some(device_id),
+        auth_type: Some(AuthType::Password),
+        ..AuditEvent::new(
+          AuditEventType::Registration,
+          AuditOutcome::Success,
+          &request_metadata,
+        )
+      };
+      record_audit_event(
+        &self.client,
+        AuditSubject::User(user_id.clone()),
+        event,
+      )
+      .await;
+
       let response = RegistrationFinishResponse {
         user_id,
         access_token,
@@ -277,8 +363,8 @@ impl IdentityClientService for ClientService {
       user_id: message.user_id,
     };
     let session_id = self
-      .insert_into_cache(WorkflowInProgress::Update(update_state))
-      .await;
+      .insert_workflow(WorkflowInProgress::Update(update_state))
+      .await?;
 
     let response = UpdateUserPasswordStartResponse {
       session_id,
@@ -291,13 +377,12 @@ impl IdentityClientService for ClientService {
     &self,
     request: tonic::Request<UpdateUserPasswordFinishRequest>,
   ) -> Result<tonic::Response<Empty>, tonic::Status> {
+    let request_metadata = RequestMetadata::from_request(&request);
     let message = request.into_inner();
 
     if let Some(WorkflowInProgress::Update(state)) =
-      self.cache.get(&message.session_id)
+      self.take_workflow(&message.session_id).await?
     {
-      self.cache.invalidate(&message.session_id).await;
-
       let server_registration = comm_opaque2::server::Registration::new();
       let password_file = server_registration
         .finish(&message.opaque_registration_upload)
@@ -305,10 +390,25 @@ impl IdentityClientService for ClientService {
 
       self
         .client
-        .update_user_password(state.user_id, password_file)
+        .update_user_password(state.user_id.clone(), password_file)
         .await
         .map_err(handle_db_error)?;
 
+      let event = AuditEvent {
+        auth_type: Some(AuthType::Password),
+        ..AuditEvent::new(
+          AuditEventType::PasswordUpdate,
+          AuditOutcome::Success,
+          &request_metadata,
+        )
+      };
+      record_audit_event(
+        &self.client,
+        AuditSubject::User(state.user_id),
+        event,
+      )
+      .await;
+
       let response = Empty {};
       Ok(Response::new(response))
     } else {
@@ -320,9 +420,17 @@ impl IdentityClientService for ClientService {
     &self,
     request: tonic::Request<OpaqueLoginStartRequest>,
   ) -> Result<tonic::Response<OpaqueLoginStartResponse>, tonic::Status> {
+    let code_version = get_code_version(&request);
+    let client_ip = get_client_ip(&request);
     let message = request.into_inner();
 
     debug!("Attempting to login user: {:?}", &message.username);
+    let ip_attempt_keys: Vec<AttemptKey> =
+      client_ip.map(AttemptKey::Ip).into_iter().collect();
+    let mut attempt_keys = ip_attempt_keys.clone();
+    attempt_keys.push(AttemptKey::Username(message.username.clone()));
+    ensure_login_allowed(&self.client, &attempt_keys).await?;
+
     let user_id_and_password_file = self
       .client
       .get_user_id_and_password_file_from_username(&message.username)
@@ -350,6 +458,7 @@ impl IdentityClientService for ClientService {
           ));
         }
 
+        record_failed_login(&self.client, &ip_attempt_keys).await;
         return Err(tonic::Status::not_found("user not found"));
       };
 
@@ -392,26 +501,39 @@ impl IdentityClientService for ClientService {
 
       let key_info = KeyPayload::from_str(&payload)
         .map_err(|_| tonic::Status::invalid_argument("malformed payload"))?;
+      let flattened_device_key_upload = FlattenedDeviceKeyUpload {
+        device_id_key: key_info.primary_identity_public_keys.ed25519,
+        key_payload: payload,
+        key_payload_signature: payload_signature,
+        content_prekey,
+        content_prekey_signature,
+        content_one_time_keys: one_time_content_prekeys,
+        notif_prekey,
+        notif_prekey_signature,
+        notif_one_time_keys: one_time_notif_prekeys,
+        device_type: DeviceType::try_from(DBDeviceTypeInt(device_type))
+          .map_err(handle_db_error)?,
+        code_version,
+      };
+      verify_device_key_upload(&flattened_device_key_upload)?;
+      let device_attempt_key =
+        AttemptKey::Device(flattened_device_key_upload.device_id_key.clone());
+      ensure_login_allowed(&self.client, &[device_attempt_key.clone()]).await?;
+
+      // The client can tell if the password is wrong from the response, so
+      // the attempt counts as failed until the login is finished
+      attempt_keys.push(device_attempt_key);
+      record_failed_login(&self.client, &attempt_keys).await;
+
       let login_state = UserLoginInfo {
         user_id,
+        username,
         opaque_server_login: server_login,
-        flattened_device_key_upload: FlattenedDeviceKeyUpload {
-          device_id_key: key_info.primary_identity_public_keys.ed25519,
-          key_payload: payload,
-          key_payload_signature: payload_signature,
-          content_prekey,
-          content_prekey_signature,
-          content_one_time_keys: one_time_content_prekeys,
-          notif_prekey,
-          notif_prekey_signature,
-          notif_one_time_keys: one_time_notif_prekeys,
-          device_type: DeviceType::try_from(DBDeviceTypeInt(device_type))
-            .map_err(handle_db_error)?,
-        },
+        flattened_device_key_upload,
       };
       let session_id = self
-        .insert_into_cache(WorkflowInProgress::Login(Box::new(login_state)))
-        .await;
+        .insert_workflow(WorkflowInProgress::Login(Box::new(login_state)))
+        .await?;
 
       let response = Response::new(OpaqueLoginStartResponse {
         session_id,
@@ -427,63 +549,181 @@ impl IdentityClientService for ClientService {
     &self,
     request: tonic::Request<OpaqueLoginFinishRequest>,
   ) -> Result<tonic::Response<OpaqueLoginFinishResponse>, tonic::Status> {
+    let request_metadata = RequestMetadata::from_request(&request);
     let message = request.into_inner();
 
     if let Some(WorkflowInProgress::Login(state)) =
-      self.cache.get(&message.session_id)
+      self.take_workflow(&message.session_id).await?
     {
-      self.cache.invalidate(&message.session_id).await;
-
       let mut server_login = state.opaque_server_login.clone();
-      server_login
-        .finish(&message.opaque_login_upload)
-        .map_err(protocol_error_to_grpc_status)?;
-
-      self
-        .client
-        .add_password_user_device_to_users_table(
-          state.user_id.clone(),
-          state.flattened_device_key_upload.clone(),
+      if let Err(e) = server_login.finish(&message.opaque_login_upload) {
+        let event = AuditEvent {
+          device_id: Some(
+            state.flattened_device_key_upload.device_id_key.clone(),
+          ),
+          auth_type: Some(AuthType::Password),
+          ..AuditEvent::new(
+            AuditEventType::Login,
+            AuditOutcome::Failure,
+            &request_metadata,
+          )
+        };
+        record_audit_event(
+          &self.client,
+          AuditSubject::User(state.user_id),
+          event,
         )
-        .await
-        .map_err(handle_db_error)?;
-
-      // Create access token
-      let token = AccessTokenData::new(
-        state.user_id.clone(),
-        state.flattened_device_key_upload.device_id_key,
-        crate::token::AuthType::Password,
-        &mut OsRng,
-      );
-
-      let access_token = token.access_token.clone();
+        .await;
+        return Err(protocol_error_to_grpc_status(e));
+      }
 
-      self
+      let totp_state = self
         .client
-        .put_access_token_data(token)
+        .get_totp_state(&state.user_id)
         .await
         .map_err(handle_db_error)?;
+      if totp_state.map_or(false, |totp_state| totp_state.confirmed) {
+        // Failed attempts are reset only once the second factor is verified
+        let two_factor_state = TwoFactorLoginState {
+          user_id: state.user_id.clone(),
+          username: state.username,
+          flattened_device_key_upload: state.flattened_device_key_upload,
+        };
+        let two_factor_session_id = self
+          .insert_workflow(WorkflowInProgress::TwoFactorLogin(Box::new(
+            two_factor_state,
+          )))
+          .await?;
+
+        let response = OpaqueLoginFinishResponse {
+          user_id: state.user_id,
+          access_token: String::new(),
+          two_factor_session_id: Some(two_factor_session_id),
+        };
+        return Ok(Response::new(response));
+      }
 
-      let response = OpaqueLoginFinishResponse {
-        user_id: state.user_id,
-        access_token,
-      };
+      let response = self
+        .complete_password_login(
+          state.user_id,
+          state.username,
+          state.flattened_device_key_upload,
+          &request_metadata,
+        )
+        .await?;
       Ok(Response::new(response))
     } else {
       Err(tonic::Status::not_found("session not found"))
     }
   }
 
+  async fn login_password_user_two_factor(
+    &self,
+    request: tonic::Request<TwoFactorLoginRequest>,
+  ) -> Result<tonic::Response<OpaqueLoginFinishResponse>, tonic::Status> {
+    let request_metadata = RequestMetadata::from_request(&request);
+    let message = request.into_inner();
+
+    let Some(WorkflowInProgress::TwoFactorLogin(state)) =
+      self.take_workflow(&message.two_factor_session_id).await?
+    else {
+      return Err(tonic::Status::not_found("session not found"));
+    };
+
+    let totp_state = self
+      .client
+      .get_totp_state(&state.user_id)
+      .await
+      .map_err(handle_db_error)?
+      .filter(|totp_state| totp_state.confirmed);
+    // Two-factor authentication could have been disabled from another device
+    // in the meantime
+    let code_valid = match totp_state {
+      Some(totp_state) => {
+        verify_second_factor(
+          &self.client,
+          &state.user_id,
+          &totp_state,
+          &message.code,
+        )
+        .await?
+      }
+      None => true,
+    };
+
+    if !code_valid {
+      let mut attempt_keys = vec![
+        AttemptKey::Username(state.username.clone()),
+        AttemptKey::Device(
+          state.flattened_device_key_upload.device_id_key.clone(),
+        ),
+      ];
+      attempt_keys
+        .extend(request_metadata.ip_address.clone().map(AttemptKey::Ip));
+      record_failed_login(&self.client, &attempt_keys).await;
+
+      let event = AuditEvent {
+        device_id: Some(state.flattened_device_key_upload.device_id_key),
+        auth_type: Some(AuthType::Password),
+        ..AuditEvent::new(
+          AuditEventType::Login,
+          AuditOutcome::Failure,
+          &request_metadata,
+        )
+      };
+      record_audit_event(
+        &self.client,
+        AuditSubject::User(state.user_id),
+        event,
+      )
+      .await;
+      return Err(tonic::Status::permission_denied("invalid code"));
+    }
+
+    let response = self
+      .complete_password_login(
+        state.user_id,
+        state.username,
+        state.flattened_device_key_upload,
+        &request_metadata,
+      )
+      .await?;
+    Ok(Response::new(response))
+  }
+
   async fn login_wallet_user(
     &self,
     request: tonic::Request<WalletLoginRequest>,
   ) -> Result<tonic::Response<WalletLoginResponse>, tonic::Status> {
+    let code_version = get_code_version(&request);
+    let request_metadata = RequestMetadata::from_request(&request);
     let message = request.into_inner();
 
-    let parsed_message = parse_and_verify_siwe_message(
+    let ip_attempt_keys: Vec<AttemptKey> = request_metadata
+      .ip_address
+      .clone()
+      .map(AttemptKey::Ip)
+      .into_iter()
+      .collect();
+    ensure_login_allowed(&self.client, &ip_attempt_keys).await?;
+
+    let parsed_message = match parse_and_verify_siwe_message(
       &message.siwe_message,
       &message.siwe_signature,
-    )?;
+      self.ethereum_client.as_ref(),
+    )
+    .await
+    {
+      Ok(parsed_message) => parsed_message,
+      Err(e) => {
+        record_failed_login(&self.client, &ip_attempt_keys).await;
+        return Err(e);
+      }
+    };
+
+    let wallet_address = eip55(&parsed_message.address);
+    let wallet_attempt_key = AttemptKey::WalletAddress(wallet_address.clone());
+    ensure_login_allowed(&self.client, &[wallet_attempt_key.clone()]).await?;
 
     match self
       .client
@@ -491,15 +731,19 @@ impl IdentityClientService for ClientService {
       .await
       .map_err(handle_db_error)?
     {
-      None => return Err(tonic::Status::invalid_argument("invalid nonce")),
+      None => {
+        let mut failed_attempt_keys = ip_attempt_keys;
+        failed_attempt_keys.push(wallet_attempt_key);
+        record_failed_login(&self.client, &failed_attempt_keys).await;
+        return Err(tonic::Status::invalid_argument("invalid nonce"));
+      }
       Some(_) => self
         .client
         .remove_nonce_from_nonces_table(&parsed_message.nonce)
         .await
         .map_err(handle_db_error)?,
     };
-
-    let wallet_address = eip55(&parsed_message.address);
+    reset_failed_logins(&self.client, &[wallet_attempt_key]).await;
 
     let (flattened_device_key_upload, social_proof) =
       if let client_proto::WalletLoginRequest {
@@ -544,14 +788,17 @@ impl IdentityClientService for ClientService {
             notif_one_time_keys: one_time_notif_prekeys,
             device_type: DeviceType::try_from(DBDeviceTypeInt(device_type))
               .map_err(handle_db_error)?,
+            code_version,
           },
           social_proof,
         )
       } else {
         return Err(tonic::Status::invalid_argument("unexpected message data"));
       };
+    verify_device_key_upload(&flattened_device_key_upload)?;
 
-    let user_id = match self
+    let device_id = flattened_device_key_upload.device_id_key.clone();
+    let (user_id, event_type) = match self
       .client
       .get_user_id_from_user_info(wallet_address.clone(), &AuthType::Wallet)
       .await
@@ -568,11 +815,11 @@ impl IdentityClientService for ClientService {
           )
           .await
           .map_err(handle_db_error)?;
-        id
+        (id, AuditEventType::Login)
       }
       None => {
         // User doesn't exist yet, so we should add a new user in DDB
-        self
+        let id = self
           .client
           .add_wallet_user_to_users_table(
             flattened_device_key_upload.clone(),
@@ -580,7 +827,8 @@ impl IdentityClientService for ClientService {
             social_proof,
           )
           .await
-          .map_err(handle_db_error)?
+          .map_err(handle_db_error)?;
+        (id, AuditEventType::Registration)
       }
     };
 
@@ -600,6 +848,29 @@ impl IdentityClientService for ClientService {
       .await
       .map_err(handle_db_error)?;
 
+    let event = AuditEvent {
+      device_id: Some(device_id),
+      auth_type: Some(AuthType::Wallet),
+      ..AuditEvent::new(event_type, AuditOutcome::Success, &request_metadata)
+    };
+    record_audit_event(
+      &self.client,
+      AuditSubject::User(user_id.clone()),
+      event,
+    )
+    .await;
+
+    if let (true, Some(ethereum_client)) =
+      (CONFIG.ens_reverse_resolution, &self.ethereum_client)
+    {
+      spawn_update_ens_name_task(
+        self.client.clone(),
+        ethereum_client.clone(),
+        user_id.clone(),
+        parsed_message.address,
+      );
+    }
+
     let response = WalletLoginResponse {
       user_id,
       access_token,
@@ -611,6 +882,7 @@ impl IdentityClientService for ClientService {
     &self,
     request: tonic::Request<LogoutRequest>,
   ) -> Result<tonic::Response<Empty>, tonic::Status> {
+    let request_metadata = RequestMetadata::from_request(&request);
     let message = request.into_inner();
 
     let token_is_valid = self
@@ -638,26 +910,98 @@ impl IdentityClientService for ClientService {
 
     self
       .client
-      .delete_access_token_data(message.user_id, message.device_id_key)
+      .delete_access_token_data(
+        message.user_id.clone(),
+        message.device_id_key.clone(),
+      )
       .await
       .map_err(handle_db_error)?;
 
+    let event = AuditEvent {
+      device_id: Some(message.device_id_key.clone()),
+      ..AuditEvent::new(
+        AuditEventType::Logout,
+        AuditOutcome::Success,
+        &request_metadata,
+      )
+    };
+    record_audit_event(
+      &self.client,
+      AuditSubject::User(message.user_id.clone()),
+      event,
+    )
+    .await;
+
+    self
+      .auth_cache
+      .invalidate(&message.user_id, Some(&message.device_id_key));
+    spawn_invalidate_access_tokens_task(
+      self.auth_service.clone(),
+      message.user_id,
+      Some(message.device_id_key),
+    );
+
     let response = Empty {};
 
     Ok(Response::new(response))
   }
 
+  async fn refresh_access_token(
+    &self,
+    request: tonic::Request<RefreshAccessTokenRequest>,
+  ) -> Result<tonic::Response<RefreshAccessTokenResponse>, tonic::Status> {
+    let message = request.into_inner();
+
+    let token_data = self
+      .client
+      .get_access_token_data(
+        message.user_id.clone(),
+        message.device_id_key.clone(),
+      )
+      .await
+      .map_err(handle_db_error)?
+      .filter(|token_data| token_data.verify_for_refresh(&message.access_token))
+      .ok_or_else(|| tonic::Status::permission_denied("bad token"))?;
+
+    let new_token_data = token_data.rotate(&mut OsRng);
+    let access_token = new_token_data.access_token.clone();
+
+    let token_replaced = self
+      .client
+      .replace_access_token_data(message.access_token, new_token_data)
+      .await
+      .map_err(handle_db_error)?;
+
+    // The token was refreshed or revoked concurrently
+    if !token_replaced {
+      return Err(tonic::Status::permission_denied("bad token"));
+    }
+
+    self
+      .auth_cache
+      .invalidate(&message.user_id, Some(&message.device_id_key));
+    spawn_invalidate_access_tokens_task(
+      self.auth_service.clone(),
+      message.user_id,
+      Some(message.device_id_key),
+    );
+
+    let response = RefreshAccessTokenResponse { access_token };
+    Ok(Response::new(response))
+  }
+
   async fn delete_user(
     &self,
     request: tonic::Request<DeleteUserRequest>,
   ) -> Result<tonic::Response<Empty>, tonic::Status> {
+    let request_metadata = RequestMetadata::from_request(&request);
     let message = request.into_inner();
 
     let token_is_valid = self
       .client
       .verify_access_token(
         message.user_id.clone(),
-        message.device_id_key,
+        message.device_id_key.clone(),
         message.access_token,
       )
       .await
@@ -667,12 +1011,51 @@ impl IdentityClientService for ClientService {
       return Err(tonic::Status::permission_denied("bad token"));
     }
 
-    self
+    let device_ids = self
       .client
-      .delete_user(message.user_id)
+      .get_user_devices(&message.user_id)
+      .await
+      .map_err(handle_db_error)?
+      .unwrap_or_default()
+      .into_iter()
+      .map(|device| device.device_id)
+      .collect();
+    // Stored before the user is deleted, so that the rest of the user's data
+    // is removed even if this request doesn't finish
+    let deletion = self
+      .user_deletion_worker
+      .start(message.user_id.clone(), device_ids)
       .await
       .map_err(handle_db_error)?;
 
+    if let Err(e) = self.client.delete_user(message.user_id.clone()).await {
+      // Otherwise the retries would remove data of a user who still exists
+      let abort_result =
+        self.user_deletion_worker.abort(&message.user_id).await;
+      consume_error(abort_result);
+      return Err(handle_db_error(e));
+    }
+    self.auth_cache.invalidate(&message.user_id, None);
+
+    // Kept until the end of the retention period, like other events of the
+    // user
+    let event = AuditEvent {
+      device_id: Some(message.device_id_key),
+      ..AuditEvent::new(
+        AuditEventType::UserDeletion,
+        AuditOutcome::Success,
+        &request_metadata,
+      )
+    };
+    record_audit_event(
+      &self.client,
+      AuditSubject::User(message.user_id.clone()),
+      event,
+    )
+    .await;
+
+    self.user_deletion_worker.spawn_run(deletion);
+
     let response = Empty {};
 
     Ok(Response::new(response))
@@ -856,10 +1239,93 @@ impl IdentityClientService for ClientService {
     Ok(response)
   }
 
+  async fn find_user_id(
+    &self,
+    request: tonic::Request<FindUserIdRequest>,
+  ) -> Result<tonic::Response<FindUserIdResponse>, tonic::Status> {
+    let client_ip = get_client_ip(&request);
+    self
+      .lookup_rate_limiter
+      .check(client_ip.as_deref(), 1)
+      .await?;
+    let message = request.into_inner();
+
+    use find_user_id_request::Identifier;
+    let (user_info, auth_type) = match message.identifier {
+      None => {
+        return Err(tonic::Status::invalid_argument("no identifier provided"))
+      }
+      Some(Identifier::Username(username)) => (username, AuthType::Password),
+      Some(Identifier::WalletAddress(address)) => {
+        // Wallet addresses are stored with EIP-55 checksum casing
+        let address = Some(address.as_str())
+          .filter(|address| is_valid_ethereum_address(address))
+          .and_then(parse_address)
+          .map(|address| eip55(&address))
+          .ok_or_else(|| {
+            tonic::Status::invalid_argument("invalid wallet address")
+          })?;
+        (address, AuthType::Wallet)
+      }
+    };
+
+    let user_id = self
+      .client
+      .get_user_id_from_user_info(user_info, &auth_type)
+      .await
+      .map_err(handle_db_error)?
+      .ok_or_else(|| tonic::Status::not_found("user not found"))?;
+
+    Ok(Response::new(FindUserIdResponse { user_id }))
+  }
+
+  async fn find_user_identities(
+    &self,
+    request: tonic::Request<FindUserIdentitiesRequest>,
+  ) -> Result<tonic::Response<FindUserIdentitiesResponse>, tonic::Status> {
+    let client_ip = get_client_ip(&request);
+    let message = request.into_inner();
+
+    if message.user_ids.len() > USER_LOOKUP_MAX_BATCH_SIZE {
+      return Err(tonic::Status::invalid_argument("too many user IDs"));
+    }
+    // Each user ID counts as a separate lookup
+    let cost = message.user_ids.len().max(1) as u32;
+    self
+      .lookup_rate_limiter
+      .check(client_ip.as_deref(), cost)
+      .await?;
+
+    let identities = self
+      .client
+      .get_user_identifiers(message.user_ids)
+      .await
+      .map_err(handle_db_error)?
+      .into_iter()
+      .map(|(user_id, identifier)| {
+        let identifier = match identifier {
+          UserIdentifier::Username(username) => {
+            user_identity::Identifier::Username(username)
+          }
+          UserIdentifier::WalletAddress(address) => {
+            user_identity::Identifier::WalletAddress(address)
+          }
+        };
+        let identity = UserIdentity {
+          identifier: Some(identifier),
+        };
+        (user_id, identity)
+      })
+      .collect();
+
+    Ok(Response::new(FindUserIdentitiesResponse { identities }))
+  }
+
   async fn add_reserved_usernames(
     &self,
     request: tonic::Request<AddReservedUsernamesRequest>,
   ) -> Result<tonic::Response<Empty>, tonic::Status> {
+    let request_metadata = RequestMetadata::from_request(&request);
     let message = request.into_inner();
 
     let usernames = validate_add_reserved_usernames_message(
@@ -873,11 +1339,23 @@ impl IdentityClientService for ClientService {
       .await
       .map_err(handle_db_error)?;
 
+    let events = filtered_usernames
+      .iter()
+      .map(|username| {
+        let event = AuditEvent::new(
+          AuditEventType::ReservedUsernameAdded,
+          AuditOutcome::Success,
+          &request_metadata,
+        );
+        (AuditSubject::Username(username.clone()), event)
+      })
+      .collect();
     self
       .client
       .add_usernames_to_reserved_usernames_table(filtered_usernames)
       .await
       .map_err(handle_db_error)?;
+    record_audit_events(&self.client, events).await;
 
     let response = Response::new(Empty {});
     Ok(response)
@@ -887,6 +1365,7 @@ impl IdentityClientService for ClientService {
     &self,
     request: tonic::Request<RemoveReservedUsernameRequest>,
   ) -> Result<tonic::Response<Empty>, tonic::Status> {
+    let request_metadata = RequestMetadata::from_request(&request);
     let message = request.into_inner();
 
     let username = validate_remove_reserved_username_message(
@@ -896,10 +1375,18 @@ impl IdentityClientService for ClientService {
 
     self
       .client
-      .delete_username_from_reserved_usernames_table(username)
+      .delete_username_from_reserved_usernames_table(username.clone())
       .await
       .map_err(handle_db_error)?;
 
+    let event = AuditEvent::new(
+      AuditEventType::ReservedUsernameRemoved,
+      AuditOutcome::Success,
+      &request_metadata,
+    );
+    record_audit_event(&self.client, AuditSubject::Username(username), event)
+      .await;
+
     let response = Response::new(Empty {});
     Ok(response)
   }
@@ -914,6 +1401,70 @@ impl IdentityClientService for ClientService {
 }
 
 impl ClientService {
+  /// Adds the device and issues its access token once the user has been
+  /// authenticated
+  async fn complete_password_login(
+    &self,
+    user_id: String,
+    username: String,
+    flattened_device_key_upload: FlattenedDeviceKeyUpload,
+    request_metadata: &RequestMetadata,
+  ) -> Result<OpaqueLoginFinishResponse, tonic::Status> {
+    let device_id = flattened_device_key_upload.device_id_key.clone();
+    let attempt_keys = [
+      AttemptKey::Username(username),
+      AttemptKey::Device(device_id.clone()),
+    ];
+    reset_failed_logins(&self.client, &attempt_keys).await;
+
+    self
+      .client
+      .add_password_user_device_to_users_table(
+        user_id.clone(),
+        flattened_device_key_upload.clone(),
+      )
+      .await
+      .map_err(handle_db_error)?;
+
+    // Create access token
+    let token = AccessTokenData::new(
+      user_id.clone(),
+      flattened_device_key_upload.device_id_key,
+      crate::token::AuthType::Password,
+      &mut OsRng,
+    );
+
+    let access_token = token.access_token.clone();
+
+    self
+      .client
+      .put_access_token_data(token)
+      .await
+      .map_err(handle_db_error)?;
+
+    let event = AuditEvent {
+      device_id: Some(device_id),
+      auth_type: Some(AuthType::Password),
+      ..AuditEvent::new(
+        AuditEventType::Login,
+        AuditOutcome::Success,
+        request_metadata,
+      )
+    };
+    record_audit_event(
+      &self.client,
+      AuditSubject::User(user_id.clone()),
+      event,
+    )
+    .await;
+
+    Ok(OpaqueLoginFinishResponse {
+      user_id,
+      access_token,
+      two_factor_session_id: None,
+    })
+  }
+
   async fn check_username_taken(
     &self,
     username: &str,
@@ -929,10 +1480,26 @@ impl ClientService {
     Ok(())
   }
 
-  async fn insert_into_cache(&self, workflow: WorkflowInProgress) -> String {
-    let session_id = generate_uuid();
-    self.cache.insert(session_id.clone(), workflow).await;
-    session_id
+  async fn insert_workflow(
+    &self,
+    workflow: WorkflowInProgress,
+  ) -> Result<String, tonic::Status> {
+    self
+      .workflow_store
+      .insert(workflow)
+      .await
+      .map_err(handle_db_error)
+  }
+
+  async fn take_workflow(
+    &self,
+    session_id: &str,
+  ) -> Result<Option<WorkflowInProgress>, tonic::Status> {
+    self
+      .workflow_store
+      .take(session_id)
+      .await
+      .map_err(handle_db_error)
   }
 }
 
@@ -955,27 +1522,70 @@ pub fn handle_db_error(db_error: DBError) -> tonic::Status {
 fn construct_user_registration_info(
   message: &impl DeviceKeyUploadActions,
   user_id: Option<String>,
+  code_version: Option<u64>,
 ) -> Result<UserRegistrationInfo, tonic::Status> {
   let key_info = KeyPayload::from_str(&message.payload()?)
     .map_err(|_| tonic::Status::invalid_argument("malformed payload"))?;
 
+  let flattened_device_key_upload = FlattenedDeviceKeyUpload {
+    device_id_key: key_info.primary_identity_public_keys.ed25519,
+    key_payload: message.payload()?,
+    key_payload_signature: message.payload_signature()?,
+    content_prekey: message.content_prekey()?,
+    content_prekey_signature: message.content_prekey_signature()?,
+    content_one_time_keys: message.one_time_content_prekeys()?,
+    notif_prekey: message.notif_prekey()?,
+    notif_prekey_signature: message.notif_prekey_signature()?,
+    notif_one_time_keys: message.one_time_notif_prekeys()?,
+    device_type: DeviceType::try_from(DBDeviceTypeInt(message.device_type()?))
+      .map_err(handle_db_error)?,
+    code_version,
+  };
+  verify_device_key_upload(&flattened_device_key_upload)?;
+
   Ok(UserRegistrationInfo {
     username: message.username(),
-    flattened_device_key_upload: FlattenedDeviceKeyUpload {
-      device_id_key: key_info.primary_identity_public_keys.ed25519,
-      key_payload: message.payload()?,
-      key_payload_signature: message.payload_signature()?,
-      content_prekey: message.content_prekey()?,
-      content_prekey_signature: message.content_prekey_signature()?,
-      content_one_time_keys: message.one_time_content_prekeys()?,
-      notif_prekey: message.notif_prekey()?,
-      notif_prekey_signature: message.notif_prekey_signature()?,
-      notif_one_time_keys: message.one_time_notif_prekeys()?,
-      device_type: DeviceType::try_from(DBDeviceTypeInt(
-        message.device_type()?,
-      ))
-      .map_err(handle_db_error)?,
-    },
+    flattened_device_key_upload,
     user_id,
   })
 }
+
+/// Revoked tokens could still be accepted by Tunnelbroker until its
+/// verification cache expires, so it's notified in the background
+pub(crate) fn spawn_invalidate_access_tokens_task(
+  auth_service: AuthService,
+  user_id: String,
+  device_id: Option<String>,
+) {
+  tokio::spawn(async move {
+    let result = crate::tunnelbroker::invalidate_access_tokens(
+      &auth_service,
+      &user_id,
+      device_id.as_deref(),
+    )
+    .await;
+    consume_error(result);
+  });
+}
+
+/// ENS lookups take a few JSON-RPC round trips, so they don't delay the login.
+/// A name that no longer resolves to the wallet is removed.
+fn spawn_update_ens_name_task(
+  db_client: DatabaseClient,
+  ethereum_client: EthereumClient,
+  user_id: String,
+  wallet_address: [u8; 20],
+) {
+  tokio::spawn(async move {
+    let ens_name = match ethereum_client.lookup_ens_name(&wallet_address).await
+    {
+      Ok(ens_name) => ens_name,
+      Err(e) => {
+        error!("Failed to look up ENS name of user {}: {}", user_id, e);
+        return;
+      }
+    };
+    let result = db_client.set_ens_name(user_id, ens_name).await;
+    consume_error(result);
+  });
+}
@@ -1,13 +1,35 @@
+use aes_gcm::{Aes256Gcm, Key};
 use base64::{engine::general_purpose, DecodeError, Engine as _};
+use chrono::Duration;
 use once_cell::sync::Lazy;
-use std::{collections::HashSet, env, fmt, fs, io, path};
+use std::{
+  collections::HashSet, env, fmt, fs, io, num::ParseIntError, path,
+  str::FromStr,
+};
 use tracing::{error, info};
 
 use crate::constants::{
-  DEFAULT_TUNNELBROKER_ENDPOINT, KEYSERVER_PUBLIC_KEY, LOCALSTACK_ENDPOINT,
-  OPAQUE_SERVER_SETUP, SECRETS_DIRECTORY, SECRETS_SETUP_FILE,
-  TUNNELBROKER_GRPC_ENDPOINT,
+  ACCESS_TOKEN_LIFETIME_DAYS, AUDIT_LOG_RETENTION_DAYS, BACKUP_SERVICE_URL,
+  BLOB_SERVICE_URL, DEFAULT_ACCESS_TOKEN_LIFETIME_DAYS,
+  DEFAULT_AUDIT_LOG_RETENTION_DAYS, DEFAULT_BACKUP_SERVICE_URL,
+  DEFAULT_BLOB_SERVICE_URL, DEFAULT_LOGIN_BACKOFF_THRESHOLD,
+  DEFAULT_LOGIN_LOCKOUT_DURATION_MINUTES, DEFAULT_LOGIN_LOCKOUT_THRESHOLD,
+  DEFAULT_ONE_TIME_KEY_MINIMUM_THRESHOLD, DEFAULT_SIWE_ALLOWED_CHAIN_IDS,
+  DEFAULT_SIWE_ALLOWED_DOMAINS, DEFAULT_SIWE_ALLOWED_URIS,
+  DEFAULT_SIWE_MAX_ISSUED_AT_AGE_MINUTES, DEFAULT_TUNNELBROKER_ENDPOINT,
+  DEFAULT_USERNAME_GRACE_PERIOD_DAYS, DEFAULT_USER_LOOKUP_RATE_LIMIT,
+  ENS_REVERSE_RESOLUTION, ETHEREUM_RPC_URL, IN_MEMORY_WORKFLOW_STORE,
+  KEYSERVER_PUBLIC_KEY, LOCALSTACK_ENDPOINT, LOGIN_BACKOFF_THRESHOLD,
+  LOGIN_BASE_BACKOFF_SECONDS, LOGIN_LOCKOUT_DURATION_MINUTES,
+  LOGIN_LOCKOUT_THRESHOLD, ONE_TIME_KEY_MINIMUM_THRESHOLD, OPAQUE_SERVER_SETUP,
+  SECRETS_DIRECTORY, SECRETS_SETUP_FILE, SIWE_ALLOWED_CHAIN_IDS,
+  SIWE_ALLOWED_CLOCK_SKEW_SECONDS, SIWE_ALLOWED_DOMAINS, SIWE_ALLOWED_URIS,
+  SIWE_MAX_ISSUED_AT_AGE_MINUTES, SIWE_REQUIRE_EXPIRATION_TIME,
+  TOTP_ENCRYPTION_KEY, TRUST_FORWARDED_FOR, TUNNELBROKER_GRPC_ENDPOINT,
+  USERNAME_GRACE_PERIOD_DAYS, USER_LOOKUP_RATE_LIMIT,
 };
+use crate::login_attempts::LoginAttemptPolicy;
+use crate::siwe::SiwePolicy;
 
 pub static CONFIG: Lazy<Config> =
   Lazy::new(|| Config::load().expect("failed to load config"));
@@ -25,6 +47,33 @@ pub struct Config {
   pub reserved_usernames: HashSet<String>,
   pub keyserver_public_key: Option<String>,
   pub tunnelbroker_endpoint: String,
+  // Large account data exports are uploaded here
+  pub blob_service_url: String,
+  // Notified when users are deleted
+  pub backup_service_url: String,
+  // Time after which access tokens expire and have to be refreshed
+  pub access_token_lifetime: Duration,
+  pub login_attempt_policy: LoginAttemptPolicy,
+  // Whether workflows in progress are kept in memory instead of DynamoDB
+  pub in_memory_workflow_store: bool,
+  // Whether client addresses are read from X-Forwarded-For
+  pub trust_forwarded_for: bool,
+  // Time for which old usernames are held after a username change, if at all
+  pub username_grace_period: Option<Duration>,
+  // Key for encrypting TOTP secrets. Two-factor authentication is unavailable
+  // without it.
+  pub totp_encryption_key: Option<Key<Aes256Gcm>>,
+  pub siwe_policy: SiwePolicy,
+  // Used for smart contract wallet signatures and ENS names, if set
+  pub ethereum_rpc_url: Option<String>,
+  // Whether primary ENS names of wallet users are looked up on login
+  pub ens_reverse_resolution: bool,
+  // Time for which audit log events are kept
+  pub audit_log_retention: Duration,
+  // Lookups allowed per client IP address per minute
+  pub user_lookup_rate_limit: u32,
+  // Devices with fewer one-time keys left are asked for more
+  pub one_time_key_minimum_threshold: usize,
 }
 
 impl Config {
@@ -49,6 +98,11 @@ impl Config {
       }
     };
 
+    let blob_service_url = env::var(BLOB_SERVICE_URL)
+      .unwrap_or_else(|_| DEFAULT_BLOB_SERVICE_URL.to_string());
+    let backup_service_url = env::var(BACKUP_SERVICE_URL)
+      .unwrap_or_else(|_| DEFAULT_BACKUP_SERVICE_URL.to_string());
+
     let mut path_buf = path::PathBuf::new();
     path_buf.push(SECRETS_DIRECTORY);
     path_buf.push(SECRETS_SETUP_FILE);
@@ -59,12 +113,84 @@ impl Config {
 
     let keyserver_public_key = env::var(KEYSERVER_PUBLIC_KEY).ok();
 
+    let access_token_lifetime = get_access_token_lifetime()?;
+
+    let login_attempt_policy = LoginAttemptPolicy {
+      backoff_threshold: get_env_number(
+        LOGIN_BACKOFF_THRESHOLD,
+        DEFAULT_LOGIN_BACKOFF_THRESHOLD,
+      )?,
+      base_backoff: Duration::seconds(LOGIN_BASE_BACKOFF_SECONDS),
+      lockout_threshold: get_env_number(
+        LOGIN_LOCKOUT_THRESHOLD,
+        DEFAULT_LOGIN_LOCKOUT_THRESHOLD,
+      )?,
+      lockout_duration: Duration::minutes(get_env_number(
+        LOGIN_LOCKOUT_DURATION_MINUTES,
+        DEFAULT_LOGIN_LOCKOUT_DURATION_MINUTES,
+      )?),
+    };
+
+    let in_memory_workflow_store = env::var(IN_MEMORY_WORKFLOW_STORE)
+      .map_or(false, |val| val == "true" || val == "1");
+    let trust_forwarded_for = env::var(TRUST_FORWARDED_FOR)
+      .map_or(false, |val| val == "true" || val == "1");
+
+    let username_grace_period_days = get_env_number(
+      USERNAME_GRACE_PERIOD_DAYS,
+      DEFAULT_USERNAME_GRACE_PERIOD_DAYS,
+    )?;
+    let username_grace_period = Some(username_grace_period_days)
+      .filter(|days| *days > 0)
+      .map(Duration::days);
+
+    let totp_encryption_key = get_totp_encryption_key()?;
+
+    let siwe_policy = get_siwe_policy()?;
+
+    let ethereum_rpc_url = env::var(ETHEREUM_RPC_URL).ok();
+    let ens_reverse_resolution = env::var(ENS_REVERSE_RESOLUTION)
+      .map_or(false, |val| val == "true" || val == "1");
+    if ens_reverse_resolution && ethereum_rpc_url.is_none() {
+      error!(
+        "{} is enabled, but {} isn't set",
+        ENS_REVERSE_RESOLUTION, ETHEREUM_RPC_URL
+      );
+    }
+
+    let audit_log_retention = Duration::days(get_env_number(
+      AUDIT_LOG_RETENTION_DAYS,
+      DEFAULT_AUDIT_LOG_RETENTION_DAYS,
+    )?);
+
+    let user_lookup_rate_limit =
+      get_env_number(USER_LOOKUP_RATE_LIMIT, DEFAULT_USER_LOOKUP_RATE_LIMIT)?;
+
+    let one_time_key_minimum_threshold = get_env_number(
+      ONE_TIME_KEY_MINIMUM_THRESHOLD,
+      DEFAULT_ONE_TIME_KEY_MINIMUM_THRESHOLD,
+    )?;
+
     Ok(Self {
       localstack_endpoint,
       server_setup,
       reserved_usernames,
       keyserver_public_key,
       tunnelbroker_endpoint,
+      blob_service_url,
+      backup_service_url,
+      access_token_lifetime,
+      login_attempt_policy,
+      in_memory_workflow_store,
+      trust_forwarded_for,
+      username_grace_period,
+      totp_encryption_key,
+      siwe_policy,
+      ethereum_rpc_url,
+      ens_reverse_resolution,
+      audit_log_retention,
+      user_lookup_rate_limit,
+      one_time_key_minimum_threshold,
     })
   }
 }
@@ -75,6 +201,24 @@ impl fmt::Debug for Config {
       .field("server_keypair", &"** redacted **")
       .field("keyserver_auth_token", &"** redacted **")
       .field("localstack_endpoint", &self.localstack_endpoint)
+      .field("blob_service_url", &self.blob_service_url)
+      .field("backup_service_url", &self.backup_service_url)
+      .field("access_token_lifetime", &self.access_token_lifetime)
+      .field("login_attempt_policy", &self.login_attempt_policy)
+      .field("in_memory_workflow_store", &self.in_memory_workflow_store)
+      .field("trust_forwarded_for", &self.trust_forwarded_for)
+      .field("username_grace_period", &self.username_grace_period)
+      .field("totp_encryption_key", &"** redacted **")
+      .field("siwe_policy", &self.siwe_policy)
+      // The URL may contain an API key
+      .field("ethereum_rpc_url", &"** redacted **")
+      .field("ens_reverse_resolution", &self.ens_reverse_resolution)
+      .field("audit_log_retention", &self.audit_log_retention)
+      .field("user_lookup_rate_limit", &self.user_lookup_rate_limit)
+      .field(
+        "one_time_key_minimum_threshold",
+        &self.one_time_key_minimum_threshold,
+      )
       .finish()
   }
 }
@@ -91,6 +235,10 @@ pub enum Error {
   Json(serde_json::Error),
   #[display(...)]
   Decode(DecodeError),
+  #[display(...)]
+  ParseInt(ParseIntError),
+  #[display(fmt = "Invalid TOTP encryption key length")]
+  InvalidKeyLength,
 }
 
 fn get_server_setup(
@@ -121,6 +269,119 @@ fn get_server_setup(
     .map_err(Error::Opaque)
 }
 
+fn get_access_token_lifetime() -> Result<Duration, Error> {
+  match env::var(ACCESS_TOKEN_LIFETIME_DAYS) {
+    Ok(val) => {
+      let days = val.parse()?;
+      info!("Using access token lifetime from env var: {} days", days);
+      Ok(Duration::days(days))
+    }
+    Err(env::VarError::NotPresent) => {
+      Ok(Duration::days(DEFAULT_ACCESS_TOKEN_LIFETIME_DAYS))
+    }
+    Err(e) => {
+      error!(
+        "Failed to read environment variable {}: {:?}",
+        ACCESS_TOKEN_LIFETIME_DAYS, e
+      );
+      Err(Error::Env(e))
+    }
+  }
+}
+
+fn get_env_number<T>(name: &str, default: T) -> Result<T, Error>
+where
+  T: FromStr<Err = ParseIntError> + fmt::Display,
+{
+  match env::var(name) {
+    Ok(val) => {
+      let number = val.parse()?;
+      info!("Using {} from env var: {}", name, number);
+      Ok(number)
+    }
+    Err(env::VarError::NotPresent) => Ok(default),
+    Err(e) => {
+      error!("Failed to read environment variable {}: {:?}", name, e);
+      Err(Error::Env(e))
+    }
+  }
+}
+
+fn get_env_list(name: &str, default: &[&str]) -> Result<Vec<String>, Error> {
+  match env::var(name) {
+    Ok(val) => {
+      info!("Using {} from env var: {}", name, val);
+      Ok(
+        val
+          .split(',')
+          .map(str::trim)
+          .filter(|item| !item.is_empty())
+          .map(str::to_string)
+          .collect(),
+      )
+    }
+    Err(env::VarError::NotPresent) => {
+      Ok(default.iter().map(|item| item.to_string()).collect())
+    }
+    Err(e) => {
+      error!("Failed to read environment variable {}: {:?}", name, e);
+      Err(Error::Env(e))
+    }
+  }
+}
+
+fn get_siwe_policy() -> Result<SiwePolicy, Error> {
+  let allowed_chain_ids =
+    get_env_list(SIWE_ALLOWED_CHAIN_IDS, DEFAULT_SIWE_ALLOWED_CHAIN_IDS)?
+      .iter()
+      .map(|chain_id| chain_id.parse())
+      .collect::<Result<_, _>>()?;
+  let require_expiration_time = env::var(SIWE_REQUIRE_EXPIRATION_TIME)
+    .map_or(true, |val| val != "false" && val != "0");
+
+  Ok(SiwePolicy {
+    allowed_domains: get_env_list(
+      SIWE_ALLOWED_DOMAINS,
+      DEFAULT_SIWE_ALLOWED_DOMAINS,
+    )?
+    .into_iter()
+    .collect(),
+    allowed_uris: get_env_list(SIWE_ALLOWED_URIS, DEFAULT_SIWE_ALLOWED_URIS)?
+      .into_iter()
+      .collect(),
+    allowed_chain_ids,
+    max_issued_at_age: Duration::minutes(get_env_number(
+      SIWE_MAX_ISSUED_AT_AGE_MINUTES,
+      DEFAULT_SIWE_MAX_ISSUED_AT_AGE_MINUTES,
+    )?),
+    require_expiration_time,
+    allowed_clock_skew: Duration::seconds(SIWE_ALLOWED_CLOCK_SKEW_SECONDS),
+  })
+}
+
+fn get_totp_encryption_key() -> Result<Option<Key<Aes256Gcm>>, Error> {
+  let encoded_key = match env::var(TOTP_ENCRYPTION_KEY) {
+    Ok(val) => val,
+    Err(env::VarError::NotPresent) => {
+      info!("TOTP encryption key not set, two-factor authentication disabled");
+      return Ok(None);
+    }
+    Err(e) => {
+      error!(
+        "Failed to read environment variable {}: {:?}",
+        TOTP_ENCRYPTION_KEY, e
+      );
+      return Err(Error::Env(e));
+    }
+  };
+
+  let key_bytes = general_purpose::STANDARD.decode(encoded_key)?;
+  if key_bytes.len() != 32 {
+    return Err(Error::InvalidKeyLength);
+  }
+  Ok(Some(*Key::<Aes256Gcm>::from_slice(&key_bytes)))
+}
+
 fn get_reserved_usernames_set() -> Result<HashSet<String>, Error> {
   // All entries in `reserved_usernames.json` must be lowercase and must also be
   // included in `lib/utils/reserved-users.js`!!
@@ -5,6 +5,11 @@ pub const SECRETS_SETUP_FILE: &str = "server_setup.txt";
 
 // DynamoDB
 
+// Unprocessed items of a BatchWriteItem call are retried with exponential
+// backoff, up to this many attempts in total
+pub const BATCH_WRITE_MAX_ATTEMPTS: u32 = 5;
+pub const BATCH_WRITE_BASE_BACKOFF_MILLIS: u64 = 100;
+
 // User table information, supporting opaque_ke 2.0 and X3DH information
 
 // Users can sign in either through username+password or Eth wallet.
@@ -66,6 +71,19 @@ pub const USERS_TABLE_DEVICES_MAP_NOTIF_ONE_TIME_KEYS_ATTRIBUTE_NAME: &str =
 pub const USERS_TABLE_WALLET_ADDRESS_ATTRIBUTE: &str = "walletAddress";
 pub const USERS_TABLE_DEVICES_MAP_SOCIAL_PROOF_ATTRIBUTE_NAME: &str =
   "socialProof";
+pub const USERS_TABLE_DEVICES_MAP_LAST_LOGIN_ATTRIBUTE_NAME: &str = "lastLogin";
+pub const USERS_TABLE_DEVICES_MAP_CODE_VERSION_ATTRIBUTE_NAME: &str =
+  "codeVersion";
+// Primary ENS name of a wallet user, set only if ENS reverse resolution is
+// enabled and the name resolves back to the wallet address
+pub const USERS_TABLE_ENS_NAME_ATTRIBUTE: &str = "ensName";
+// Missing for users registered before it was recorded
+pub const USERS_TABLE_CREATION_TIME_ATTRIBUTE: &str = "creationTime";
+// Two-factor authentication attributes, set only for users who enrolled
+pub const USERS_TABLE_TOTP_SECRET_ATTRIBUTE: &str = "totpSecret";
+pub const USERS_TABLE_TOTP_CONFIRMED_ATTRIBUTE: &str = "totpConfirmed";
+pub const USERS_TABLE_TOTP_RECOVERY_CODES_ATTRIBUTE: &str = "totpRecoveryCodes";
+pub const USERS_TABLE_TOTP_LAST_USED_STEP_ATTRIBUTE: &str = "totpLastUsedStep";
 pub const USERS_TABLE_USERNAME_INDEX: &str = "username-index";
 pub const USERS_TABLE_WALLET_ADDRESS_INDEX: &str = "walletAddress-index";
 
@@ -76,6 +94,11 @@ pub const ACCESS_TOKEN_TABLE_CREATED_ATTRIBUTE: &str = "created";
 pub const ACCESS_TOKEN_TABLE_AUTH_TYPE_ATTRIBUTE: &str = "authType";
 pub const ACCESS_TOKEN_TABLE_VALID_ATTRIBUTE: &str = "valid";
 pub const ACCESS_TOKEN_TABLE_TOKEN_ATTRIBUTE: &str = "token";
+// Time after which the token stops authenticating the device
+pub const ACCESS_TOKEN_TABLE_EXPIRES_ATTRIBUTE: &str = "expires";
+// Time after which the token can't be refreshed anymore and is deleted
+pub const ACCESS_TOKEN_TABLE_EXPIRATION_TIME_UNIX_ATTRIBUTE: &str =
+  "expirationTimeUnix";
 
 pub const NONCE_TABLE: &str = "identity-nonces";
 pub const NONCE_TABLE_PARTITION_KEY: &str = "nonce";
@@ -87,6 +110,13 @@ pub const NONCE_TABLE_EXPIRATION_TIME_UNIX_ATTRIBUTE: &str =
 // Usernames reserved because they exist in Ashoat's keyserver already
 pub const RESERVED_USERNAMES_TABLE: &str = "identity-reserved-usernames";
 pub const RESERVED_USERNAMES_TABLE_PARTITION_KEY: &str = "username";
+// Set only for usernames imported from the keyserver with PopulateDB
+pub const RESERVED_USERNAMES_TABLE_USER_ID_ATTRIBUTE: &str = "userID";
+pub const RESERVED_USERNAMES_TABLE_CREATION_TIME_ATTRIBUTE: &str =
+  "creationTime";
+// Set only for usernames held after a username change
+pub const RESERVED_USERNAMES_TABLE_EXPIRATION_TIME_UNIX_ATTRIBUTE: &str =
+  "expirationTimeUnix";
 
 // One time keys table, which need to exist in their own table to ensure
 // atomicity of additions and removals
@@ -100,10 +130,95 @@ pub mod one_time_keys_table {
   pub const ONE_TIME_KEY: &str = SORT_KEY;
 }
 
+// Failed login attempts, keyed by e.g. "username#${username}" or "ip#${ip}"
+pub mod login_attempts_table {
+  pub const NAME: &str = "identity-login-attempts";
+  pub const PARTITION_KEY: &str = "attemptKey";
+  pub const FAILED_ATTEMPTS: &str = "failedAttempts";
+  pub const LAST_FAILURE: &str = "lastFailure";
+  pub const LOCKED_UNTIL: &str = "lockedUntil";
+  pub const EXPIRATION_TIME_UNIX: &str = "expirationTimeUnix";
+}
+
+// Workflows in progress, e.g. logins between the start and finish requests
+pub mod workflows_in_progress_table {
+  pub const NAME: &str = "identity-workflows-in-progress";
+  pub const PARTITION_KEY: &str = "sessionID";
+  pub const WORKFLOW: &str = "workflow";
+  pub const EXPIRATION_TIME_UNIX: &str = "expirationTimeUnix";
+}
+
+// Audit log of account events. The partition key is e.g. "user#${userID}",
+// and the sort key starts with the RFC 3339 timestamp of the event, so that
+// events of a subject are sorted chronologically.
+pub mod audit_log_table {
+  pub const NAME: &str = "identity-audit-log";
+  pub const PARTITION_KEY: &str = "subject";
+  pub const SORT_KEY: &str = "eventID";
+  pub const EVENT_ID: &str = SORT_KEY;
+  pub const EVENT_TYPE: &str = "eventType";
+  pub const OUTCOME: &str = "outcome";
+  pub const TIMESTAMP: &str = "timestamp";
+  pub const DEVICE_ID: &str = "deviceID";
+  pub const AUTH_TYPE: &str = "authType";
+  pub const IP_ADDRESS: &str = "ipAddress";
+  pub const USER_AGENT: &str = "userAgent";
+  pub const EXPIRATION_TIME_UNIX: &str = "expirationTimeUnix";
+}
+
+// Account exports uploaded to the blob service. Blobs can be downloaded by
+// anyone who knows their hash, so export holders are revoked once they expire.
+// Rows are deleted after their holder is revoked, so they don't use a TTL.
+pub mod account_exports_table {
+  pub const NAME: &str = "identity-account-exports";
+  pub const PARTITION_KEY: &str = "userID";
+  pub const SORT_KEY: &str = "holder";
+  pub const USER_ID: &str = PARTITION_KEY;
+  pub const HOLDER: &str = SORT_KEY;
+  pub const BLOB_HASH: &str = "blobHash";
+  pub const HOLDER_EXPIRATION_TIME_UNIX: &str = "holderExpirationTimeUnix";
+}
+
+// Deletions of users, keyed by user ID. Pending deletions have a next attempt
+// time and are retried until all of their steps succeed. Completed ones are
+// kept as a record until they expire.
+pub mod user_deletions_table {
+  pub const NAME: &str = "identity-user-deletions";
+  pub const PARTITION_KEY: &str = "userID";
+  pub const USER_ID: &str = PARTITION_KEY;
+  pub const DEVICE_IDS: &str = "deviceIDs";
+  pub const PENDING_STEPS: &str = "pendingSteps";
+  pub const ATTEMPTS: &str = "attempts";
+  pub const LAST_ERROR: &str = "lastError";
+  pub const CREATED: &str = "created";
+  pub const NEXT_ATTEMPT_TIME_UNIX: &str = "nextAttemptTimeUnix";
+  pub const EXPIRATION_TIME_UNIX: &str = "expirationTimeUnix";
+}
+
 // One-time key constants for device info map
 pub const CONTENT_ONE_TIME_KEY: &str = "contentOneTimeKey";
 pub const NOTIF_ONE_TIME_KEY: &str = "notifOneTimeKey";
 
+// Authenticated service
+
+// Valid access tokens are cached for a short time to avoid a DynamoDB read
+// on every authenticated request. Revoked tokens are invalidated only on the
+// replica revoking them, so other replicas may accept them for up to this
+// long.
+pub const AUTH_CACHE_TTL_SECONDS: u64 = 5;
+pub const AUTH_CACHE_MAX_CAPACITY: u64 = 10_000;
+
+// Workflows in progress
+
+pub const WORKFLOW_IN_PROGRESS_TTL_SECONDS: u64 = 10;
+// Set to "true" to keep workflows in memory, which works only with a single
+// replica
+pub const IN_MEMORY_WORKFLOW_STORE: &str = "IN_MEMORY_WORKFLOW_STORE";
+
+// Set to "true" to read client addresses from X-Forwarded-For. Enable only
+// behind a load balancer which sets it.
+pub const TRUST_FORWARDED_FOR: &str = "TRUST_FORWARDED_FOR";
+
 // Tokio
 
 pub const MPSC_CHANNEL_BUFFER_CAPACITY: usize = 1;
@@ -112,6 +227,88 @@ pub const IDENTITY_SERVICE_SOCKET_ADDR: &str = "[::]:50054";
 // Token
 
 pub const ACCESS_TOKEN_LENGTH: usize = 512;
+pub const ACCESS_TOKEN_LIFETIME_DAYS: &str = "ACCESS_TOKEN_LIFETIME_DAYS";
+pub const DEFAULT_ACCESS_TOKEN_LIFETIME_DAYS: i64 = 30;
+// Expired tokens can still be refreshed for this long, so that devices
+// which were offline when their token expired aren't logged out
+pub const ACCESS_TOKEN_REFRESH_GRACE_PERIOD_DAYS: i64 = 7;
+
+// Login attempts
+
+pub const LOGIN_BACKOFF_THRESHOLD: &str = "LOGIN_BACKOFF_THRESHOLD";
+pub const DEFAULT_LOGIN_BACKOFF_THRESHOLD: u32 = 3;
+pub const LOGIN_BASE_BACKOFF_SECONDS: i64 = 1;
+pub const LOGIN_LOCKOUT_THRESHOLD: &str = "LOGIN_LOCKOUT_THRESHOLD";
+pub const DEFAULT_LOGIN_LOCKOUT_THRESHOLD: u32 = 10;
+pub const LOGIN_LOCKOUT_DURATION_MINUTES: &str =
+  "LOGIN_LOCKOUT_DURATION_MINUTES";
+pub const DEFAULT_LOGIN_LOCKOUT_DURATION_MINUTES: i64 = 15;
+
+// Username changes
+
+// Old usernames are held for their previous owner for this long. Set to 0 to
+// release them immediately.
+pub const USERNAME_GRACE_PERIOD_DAYS: &str = "USERNAME_GRACE_PERIOD_DAYS";
+pub const DEFAULT_USERNAME_GRACE_PERIOD_DAYS: i64 = 30;
+// New usernames are also held for their owner briefly, until the username
+// index reflects the change
+pub const USERNAME_CHANGE_CLAIM_MINUTES: i64 = 10;
+
+// User lookups
+
+// Lookups allowed per client IP address in each window. Batch lookups count
+// each requested user.
+pub const USER_LOOKUP_RATE_LIMIT: &str = "USER_LOOKUP_RATE_LIMIT";
+pub const DEFAULT_USER_LOOKUP_RATE_LIMIT: u32 = 300;
+pub const USER_LOOKUP_RATE_LIMIT_WINDOW_SECONDS: u64 = 60;
+pub const USER_LOOKUP_MAX_BATCH_SIZE: usize = 100;
+
+// Audit log
+
+// Events are deleted by DynamoDB TTL after this many days
+pub const AUDIT_LOG_RETENTION_DAYS: &str = "AUDIT_LOG_RETENTION_DAYS";
+pub const DEFAULT_AUDIT_LOG_RETENTION_DAYS: i64 = 90;
+pub const AUDIT_LOG_DEFAULT_PAGE_SIZE: u32 = 50;
+pub const AUDIT_LOG_MAX_PAGE_SIZE: u32 = 100;
+
+// Account data export
+
+// Exports larger than this are uploaded to the blob service instead of being
+// returned inline, to stay well below the gRPC message size limit
+pub const ACCOUNT_EXPORT_MAX_INLINE_BYTES: usize = 1024 * 1024;
+pub const ACCOUNT_EXPORT_BLOB_HOLDER_PREFIX: &str = "identity-export";
+// Uploaded exports can be downloaded for this long
+pub const ACCOUNT_EXPORT_LIFETIME_HOURS: i64 = 24;
+// Expired export holders are revoked this often
+pub const ACCOUNT_EXPORT_CLEANUP_INTERVAL_SECONDS: u64 = 600;
+
+// User deletion
+
+// Pending deletions are checked for due retries this often
+pub const USER_DELETION_RETRY_INTERVAL_SECONDS: u64 = 60;
+// A deletion being run is not retried by other replicas for this long
+pub const USER_DELETION_CLAIM_SECONDS: i64 = 300;
+pub const USER_DELETION_BASE_BACKOFF_SECONDS: i64 = 30;
+pub const USER_DELETION_MAX_BACKOFF_SECONDS: i64 = 3600;
+// Deletions still failing after this many attempts are logged as errors
+pub const USER_DELETION_ALERT_ATTEMPTS: u32 = 10;
+pub const USER_DELETION_STALLED_METRIC: &str = "UserDeletionStalled";
+// Completed deletions are kept as a record for this long
+pub const USER_DELETION_RECORD_RETENTION_DAYS: i64 = 30;
+
+// Two-factor authentication
+
+pub const TOTP_ISSUER: &str = "Comm";
+pub const TOTP_DIGITS: u32 = 6;
+pub const TOTP_STEP_SECONDS: i64 = 30;
+// Codes of this many steps before and after the current one are accepted
+pub const TOTP_ALLOWED_DRIFT_STEPS: i64 = 1;
+pub const TOTP_SECRET_LENGTH: usize = 20;
+pub const TOTP_RECOVERY_CODE_COUNT: usize = 10;
+// Base64 encoded 256-bit key used to encrypt TOTP secrets
+pub const TOTP_ENCRYPTION_KEY: &str = "TOTP_ENCRYPTION_KEY";
+// Time for entering a code after the password has been verified
+pub const TWO_FACTOR_LOGIN_TTL_SECONDS: u64 = 300;
 
 // Temporary config
 
@@ -123,6 +320,41 @@ pub const KEYSERVER_PUBLIC_KEY: &str = "KEYSERVER_PUBLIC_KEY";
 pub const NONCE_LENGTH: usize = 17;
 pub const NONCE_TTL_DURATION: i64 = 30;
 
+// Sign-In with Ethereum
+
+// Comma-separated lists of values allowed in SIWE messages. The defaults
+// include the web app in development, see lib/utils/siwe-utils.js.
+pub const SIWE_ALLOWED_DOMAINS: &str = "SIWE_ALLOWED_DOMAINS";
+pub const DEFAULT_SIWE_ALLOWED_DOMAINS: &[&str] =
+  &["comm.app", "web.comm.app", "localhost:3000"];
+pub const SIWE_ALLOWED_URIS: &str = "SIWE_ALLOWED_URIS";
+pub const DEFAULT_SIWE_ALLOWED_URIS: &[&str] = &[
+  "https://comm.app",
+  "https://web.comm.app",
+  "http://localhost:3000",
+];
+pub const SIWE_ALLOWED_CHAIN_IDS: &str = "SIWE_ALLOWED_CHAIN_IDS";
+pub const DEFAULT_SIWE_ALLOWED_CHAIN_IDS: &[&str] = &["1"];
+// Messages issued longer ago than this are rejected, even if not expired
+pub const SIWE_MAX_ISSUED_AT_AGE_MINUTES: &str =
+  "SIWE_MAX_ISSUED_AT_AGE_MINUTES";
+pub const DEFAULT_SIWE_MAX_ISSUED_AT_AGE_MINUTES: i64 = 10;
+// Set to "false" to accept messages without an expiration time
+pub const SIWE_REQUIRE_EXPIRATION_TIME: &str = "SIWE_REQUIRE_EXPIRATION_TIME";
+// Tolerated difference between the clocks of the client and the service
+pub const SIWE_ALLOWED_CLOCK_SKEW_SECONDS: i64 = 60;
+
+// Ethereum
+
+// JSON-RPC endpoint used to validate signatures of smart contract wallets
+// (EIP-1271) and to look up ENS names. Both are disabled if it isn't set.
+pub const ETHEREUM_RPC_URL: &str = "ETHEREUM_RPC_URL";
+// Set to "true" to store the primary ENS name of wallet users when they log in
+pub const ENS_REVERSE_RESOLUTION: &str = "ENS_REVERSE_RESOLUTION";
+pub const ENS_REGISTRY_ADDRESS: &str =
+  "0x00000000000C2E074eC69A0dFb2997BA6C7d2e1e";
+pub const ETHEREUM_RPC_TIMEOUT_SECONDS: u64 = 5;
+
 // LocalStack
 
 pub const LOCALSTACK_ENDPOINT: &str = "LOCALSTACK_ENDPOINT";
@@ -135,12 +367,28 @@ pub const OPAQUE_SERVER_SETUP: &str = "OPAQUE_SERVER_SETUP";
 pub const TUNNELBROKER_GRPC_ENDPOINT: &str = "TUNNELBROKER_GRPC_ENDPOINT";
 pub const DEFAULT_TUNNELBROKER_ENDPOINT: &str = "http://localhost:50051";
 
+// Blob service
+pub const BLOB_SERVICE_URL: &str = "BLOB_SERVICE_URL";
+pub const DEFAULT_BLOB_SERVICE_URL: &str = "http://localhost:50053";
+
+// Backup service
+pub const BACKUP_SERVICE_URL: &str = "BACKUP_SERVICE_URL";
+pub const DEFAULT_BACKUP_SERVICE_URL: &str = "http://localhost:50052";
+
 // X3DH key management
 
 // Threshold for requesting more one_time keys
-pub const ONE_TIME_KEY_MINIMUM_THRESHOLD: usize = 5;
+pub const ONE_TIME_KEY_MINIMUM_THRESHOLD: &str =
+  "ONE_TIME_KEY_MINIMUM_THRESHOLD";
+pub const DEFAULT_ONE_TIME_KEY_MINIMUM_THRESHOLD: usize = 5;
 // Number of keys to be refreshed when below the threshold
 pub const ONE_TIME_KEY_REFRESH_NUMBER: u32 = 5;
+// Time during which a device isn't asked for more keys again
+pub const ONE_TIME_KEY_REFRESH_INTERVAL_SECONDS: u64 = 60;
+// Names of structured log events, counted by log-based metrics
+pub const ONE_TIME_KEY_EXHAUSTED_METRIC: &str = "OneTimeKeyExhausted";
+pub const ONE_TIME_KEY_REFRESH_REQUESTED_METRIC: &str =
+  "OneTimeKeyRefreshRequested";
 
 // Minimum supported code versions
 
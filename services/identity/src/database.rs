@@ -1,52 +1,73 @@
-use constant_time_eq::constant_time_eq;
 use std::collections::{HashMap, HashSet};
 use std::str::FromStr;
 use std::sync::Arc;
 
 use crate::ddb_utils::{
-  create_one_time_key_partition_key, into_one_time_put_requests, OlmAccountType,
+  create_one_time_key_partition_key, into_one_time_delete_requests,
+  into_one_time_put_requests, OlmAccountType,
 };
 use crate::error::{consume_error, DBItemAttributeError, DBItemError, Error};
 use aws_config::SdkConfig;
-use aws_sdk_dynamodb::model::{AttributeValue, PutRequest, WriteRequest};
+use aws_sdk_dynamodb::model::{
+  AttributeValue, KeysAndAttributes, Put, PutRequest, ReturnValue, Select,
+  TransactWriteItem, Update, WriteRequest,
+};
 use aws_sdk_dynamodb::output::{
   DeleteItemOutput, GetItemOutput, PutItemOutput, QueryOutput,
 };
-use aws_sdk_dynamodb::{types::Blob, Client};
-use chrono::{DateTime, Utc};
+use aws_sdk_dynamodb::{types::Blob, Client, Error as DynamoDBError};
+use chrono::{DateTime, Duration, TimeZone, Utc};
 use serde::{Deserialize, Serialize};
 use tracing::{debug, error, info, warn};
 
+use crate::account_export::ExportBlob;
+use crate::audit_log::AuditEvent;
 use crate::client_service::{FlattenedDeviceKeyUpload, UserRegistrationInfo};
 use crate::config::CONFIG;
 use crate::constants::{
   ACCESS_TOKEN_SORT_KEY, ACCESS_TOKEN_TABLE,
   ACCESS_TOKEN_TABLE_AUTH_TYPE_ATTRIBUTE, ACCESS_TOKEN_TABLE_CREATED_ATTRIBUTE,
-  ACCESS_TOKEN_TABLE_PARTITION_KEY, ACCESS_TOKEN_TABLE_TOKEN_ATTRIBUTE,
-  ACCESS_TOKEN_TABLE_VALID_ATTRIBUTE, CONTENT_ONE_TIME_KEY, NONCE_TABLE,
-  NONCE_TABLE_CREATED_ATTRIBUTE, NONCE_TABLE_EXPIRATION_TIME_ATTRIBUTE,
+  ACCESS_TOKEN_TABLE_EXPIRATION_TIME_UNIX_ATTRIBUTE,
+  ACCESS_TOKEN_TABLE_EXPIRES_ATTRIBUTE, ACCESS_TOKEN_TABLE_PARTITION_KEY,
+  ACCESS_TOKEN_TABLE_TOKEN_ATTRIBUTE, ACCESS_TOKEN_TABLE_VALID_ATTRIBUTE,
+  BATCH_WRITE_BASE_BACKOFF_MILLIS, BATCH_WRITE_MAX_ATTEMPTS,
+  CONTENT_ONE_TIME_KEY, NONCE_TABLE, NONCE_TABLE_CREATED_ATTRIBUTE,
+  NONCE_TABLE_EXPIRATION_TIME_ATTRIBUTE,
   NONCE_TABLE_EXPIRATION_TIME_UNIX_ATTRIBUTE, NONCE_TABLE_PARTITION_KEY,
   NOTIF_ONE_TIME_KEY, RESERVED_USERNAMES_TABLE,
-  RESERVED_USERNAMES_TABLE_PARTITION_KEY, USERS_TABLE,
+  RESERVED_USERNAMES_TABLE_CREATION_TIME_ATTRIBUTE,
+  RESERVED_USERNAMES_TABLE_EXPIRATION_TIME_UNIX_ATTRIBUTE,
+  RESERVED_USERNAMES_TABLE_PARTITION_KEY,
+  RESERVED_USERNAMES_TABLE_USER_ID_ATTRIBUTE, USERNAME_CHANGE_CLAIM_MINUTES,
+  USERS_TABLE, USERS_TABLE_CREATION_TIME_ATTRIBUTE,
   USERS_TABLE_DEVICES_ATTRIBUTE,
+  USERS_TABLE_DEVICES_MAP_CODE_VERSION_ATTRIBUTE_NAME,
   USERS_TABLE_DEVICES_MAP_CONTENT_ONE_TIME_KEYS_ATTRIBUTE_NAME,
   USERS_TABLE_DEVICES_MAP_CONTENT_PREKEY_ATTRIBUTE_NAME,
   USERS_TABLE_DEVICES_MAP_CONTENT_PREKEY_SIGNATURE_ATTRIBUTE_NAME,
   USERS_TABLE_DEVICES_MAP_DEVICE_TYPE_ATTRIBUTE_NAME,
   USERS_TABLE_DEVICES_MAP_KEY_PAYLOAD_ATTRIBUTE_NAME,
   USERS_TABLE_DEVICES_MAP_KEY_PAYLOAD_SIGNATURE_ATTRIBUTE_NAME,
+  USERS_TABLE_DEVICES_MAP_LAST_LOGIN_ATTRIBUTE_NAME,
   USERS_TABLE_DEVICES_MAP_NOTIF_ONE_TIME_KEYS_ATTRIBUTE_NAME,
   USERS_TABLE_DEVICES_MAP_NOTIF_PREKEY_ATTRIBUTE_NAME,
   USERS_TABLE_DEVICES_MAP_NOTIF_PREKEY_SIGNATURE_ATTRIBUTE_NAME,
   USERS_TABLE_DEVICES_MAP_SOCIAL_PROOF_ATTRIBUTE_NAME,
-  USERS_TABLE_PARTITION_KEY, USERS_TABLE_REGISTRATION_ATTRIBUTE,
+  USERS_TABLE_ENS_NAME_ATTRIBUTE, USERS_TABLE_PARTITION_KEY,
+  USERS_TABLE_REGISTRATION_ATTRIBUTE, USERS_TABLE_TOTP_CONFIRMED_ATTRIBUTE,
+  USERS_TABLE_TOTP_LAST_USED_STEP_ATTRIBUTE,
+  USERS_TABLE_TOTP_RECOVERY_CODES_ATTRIBUTE, USERS_TABLE_TOTP_SECRET_ATTRIBUTE,
   USERS_TABLE_USERNAME_ATTRIBUTE, USERS_TABLE_USERNAME_INDEX,
   USERS_TABLE_WALLET_ADDRESS_ATTRIBUTE, USERS_TABLE_WALLET_ADDRESS_INDEX,
+  USER_DELETION_RECORD_RETENTION_DAYS,
 };
 use crate::error::{AttributeValueFromHashMap, FromAttributeValue};
 use crate::id::generate_uuid;
+use crate::login_attempts::LoginAttempts;
 use crate::nonce::NonceData;
+use crate::populate_db::User as KeyserverUser;
 use crate::token::{AccessTokenData, AuthType};
+use crate::user_deletion::UserDeletion;
 pub use grpc_clients::identity::DeviceType;
 
 #[derive(Serialize, Deserialize)]
@@ -107,6 +128,17 @@ pub struct OutboundKeys {
   pub notif_one_time_key: Option<String>,
 }
 
+/// Device of a user, as listed to the user
+pub struct UserDevice {
+  pub device_id: String,
+  pub device_type: String,
+  pub key_payload: String,
+  pub key_payload_signature: String,
+  // Missing for devices registered before it was recorded
+  pub last_login: Option<DateTime<Utc>>,
+  pub code_version: Option<u64>,
+}
+
 #[derive(Clone)]
 pub struct DatabaseClient {
   client: Arc<Client>,
@@ -190,6 +222,10 @@ impl DatabaseClient {
         USERS_TABLE_DEVICES_ATTRIBUTE.to_string(),
         AttributeValue::M(devices),
       ),
+      (
+        USERS_TABLE_CREATION_TIME_ATTRIBUTE.to_string(),
+        AttributeValue::S(Utc::now().to_rfc3339()),
+      ),
     ]);
 
     if let Some((username, password_file)) = username_and_password_file {
@@ -258,6 +294,35 @@ impl DatabaseClient {
       .await
   }
 
+  /// Sets the primary ENS name of a wallet user, or removes it if the user no
+  /// longer has one
+  pub async fn set_ens_name(
+    &self,
+    user_id: String,
+    ens_name: Option<String>,
+  ) -> Result<(), Error> {
+    let request = self
+      .client
+      .update_item()
+      .table_name(USERS_TABLE)
+      .key(USERS_TABLE_PARTITION_KEY, AttributeValue::S(user_id));
+    let request = match ens_name {
+      Some(ens_name) => request
+        .update_expression(format!(
+          "SET {} = :n",
+          USERS_TABLE_ENS_NAME_ATTRIBUTE
+        ))
+        .expression_attribute_values(":n", AttributeValue::S(ens_name)),
+      None => request.update_expression(format!(
+        "REMOVE {}",
+        USERS_TABLE_ENS_NAME_ATTRIBUTE
+      )),
+    };
+
+    request.send().await.map_err(|e| Error::AwsSdk(e.into()))?;
+    Ok(())
+  }
+
   pub async fn get_keyserver_keys_for_user(
     &self,
     user_id: &str,
@@ -370,7 +435,6 @@ impl DatabaseClient {
     account_type: OlmAccountType,
   ) -> Result<Option<String>, Error> {
     use crate::constants::one_time_keys_table as otk_table;
-    use crate::constants::ONE_TIME_KEY_MINIMUM_THRESHOLD;
 
     let query_result = self.get_one_time_keys(device_id, account_type).await?;
     let items = query_result.items();
@@ -381,7 +445,7 @@ impl DatabaseClient {
       tokio::spawn(async move {
         debug!("Attempting to request more keys for device: {}", &device_id);
         let result =
-          crate::tunnelbroker::send_refresh_keys_request(&device_id).await;
+          crate::tunnelbroker::request_one_time_keys_refresh(&device_id).await;
         consume_error(result);
       });
     }
@@ -389,12 +453,12 @@ impl DatabaseClient {
     // If no one-time keys exist, or if there aren't enough, request more.
     // Additionally, if no one-time keys exist, return early.
     let item_vec = if let Some(items_list) = items {
-      if items_list.len() < ONE_TIME_KEY_MINIMUM_THRESHOLD {
+      if items_list.len() < CONFIG.one_time_key_minimum_threshold {
         spawn_refresh_keys_task(device_id);
       }
       items_list
     } else {
-      debug!("Unable to find {:?} one-time key", account_type);
+      log_one_time_key_exhausted(device_id, account_type);
       spawn_refresh_keys_task(device_id);
       return Ok(None);
     };
@@ -439,10 +503,106 @@ impl DatabaseClient {
       }
     }
 
+    if result.is_none() {
+      log_one_time_key_exhausted(device_id, account_type);
+    }
+
     // Return deleted key
     Ok(result)
   }
 
+  /// Number of one-time keys of the device which weren't given out yet
+  pub async fn get_one_time_key_count(
+    &self,
+    device_id: &str,
+    account_type: OlmAccountType,
+  ) -> Result<u32, Error> {
+    use crate::constants::one_time_keys_table::*;
+
+    let partition_key =
+      create_one_time_key_partition_key(device_id, account_type);
+
+    let mut count = 0;
+    let mut exclusive_start_key = None;
+    // Queries are paginated, even when only counting items
+    loop {
+      let response = self
+        .client
+        .query()
+        .table_name(NAME)
+        .key_condition_expression(format!("{} = :pk", PARTITION_KEY))
+        .expression_attribute_values(
+          ":pk",
+          AttributeValue::S(partition_key.clone()),
+        )
+        .select(Select::Count)
+        .set_exclusive_start_key(exclusive_start_key)
+        .send()
+        .await
+        .map_err(|e| Error::AwsSdk(e.into()))?;
+
+      count += response.count() as u32;
+      exclusive_start_key = response.last_evaluated_key;
+      if exclusive_start_key.is_none() {
+        return Ok(count);
+      }
+    }
+  }
+
+  /// Removes all content and notification one-time keys of the device
+  pub async fn delete_one_time_keys(
+    &self,
+    device_id: &str,
+  ) -> Result<(), Error> {
+    use crate::constants::one_time_keys_table;
+
+    for account_type in [OlmAccountType::Content, OlmAccountType::Notification]
+    {
+      let partition_key =
+        create_one_time_key_partition_key(device_id, account_type);
+
+      let mut one_time_keys = Vec::new();
+      let mut exclusive_start_key = None;
+      loop {
+        let response = self
+          .client
+          .query()
+          .table_name(one_time_keys_table::NAME)
+          .key_condition_expression(format!(
+            "{} = :pk",
+            one_time_keys_table::PARTITION_KEY
+          ))
+          .expression_attribute_values(
+            ":pk",
+            AttributeValue::S(partition_key.clone()),
+          )
+          .set_exclusive_start_key(exclusive_start_key)
+          .send()
+          .await
+          .map_err(|e| Error::AwsSdk(e.into()))?;
+
+        for item in response.items.unwrap_or_default() {
+          let one_time_key =
+            item.get_string(one_time_keys_table::ONE_TIME_KEY)?;
+          one_time_keys.push(one_time_key.to_string());
+        }
+
+        exclusive_start_key = response.last_evaluated_key;
+        if exclusive_start_key.is_none() {
+          break;
+        }
+      }
+
+      let delete_requests =
+        into_one_time_delete_requests(device_id, one_time_keys, account_type);
+      self
+        .batch_write_with_retries(one_time_keys_table::NAME, delete_requests)
+        .await?;
+    }
+
+    Ok(())
+  }
+
   pub async fn get_one_time_keys(
     &self,
     device_id: &str,
@@ -613,13 +773,9 @@ impl DatabaseClient {
     user_id: String,
     device_id_key: String,
   ) -> Result<(), Error> {
+    // Map keys can't be passed as attribute values, only as attribute names
     let update_expression =
-      format!("REMOVE {}.{}", USERS_TABLE_DEVICES_ATTRIBUTE, ":deviceID");
-
-    let expression_attribute_values = HashMap::from([(
-      ":deviceID".to_string(),
-      AttributeValue::S(device_id_key),
-    )]);
+      format!("REMOVE {}.#deviceID", USERS_TABLE_DEVICES_ATTRIBUTE);
 
     self
       .client
@@ -627,7 +783,7 @@ impl DatabaseClient {
       .table_name(USERS_TABLE)
       .key(USERS_TABLE_PARTITION_KEY, AttributeValue::S(user_id))
       .update_expression(update_expression)
-      .set_expression_attribute_values(Some(expression_attribute_values))
+      .expression_attribute_names("#deviceID", device_id_key)
       .send()
       .await
       .map_err(|e| Error::AwsSdk(e.into()))?;
@@ -635,6 +791,64 @@ impl DatabaseClient {
     Ok(())
   }
 
+  /// Returns all devices of the user, or `None` if the user doesn't exist
+  pub async fn get_user_devices(
+    &self,
+    user_id: &str,
+  ) -> Result<Option<Vec<UserDevice>>, Error> {
+    let Some(user_info) = self.get_item_from_users_table(user_id).await?.item
+    else {
+      return Ok(None);
+    };
+
+    let Some(devices) = user_info.get(USERS_TABLE_DEVICES_ATTRIBUTE) else {
+      return Ok(Some(Vec::new()));
+    };
+
+    let mut user_devices = Vec::new();
+    for (device_id, device_info) in
+      devices.to_hashmap(USERS_TABLE_DEVICES_ATTRIBUTE)?
+    {
+      let device_info = device_info.to_hashmap(device_id)?;
+
+      let last_login = device_info
+        .get(USERS_TABLE_DEVICES_MAP_LAST_LOGIN_ATTRIBUTE_NAME)
+        .cloned()
+        .map(|attribute| {
+          parse_date_time_attribute(
+            USERS_TABLE_DEVICES_MAP_LAST_LOGIN_ATTRIBUTE_NAME,
+            Some(attribute),
+          )
+        })
+        .transpose()?;
+      let code_version = match device_info
+        .get(USERS_TABLE_DEVICES_MAP_CODE_VERSION_ATTRIBUTE_NAME)
+      {
+        Some(AttributeValue::N(version)) => version.parse().ok(),
+        _ => None,
+      };
+
+      user_devices.push(UserDevice {
+        device_id: device_id.to_string(),
+        device_type: device_info
+          .get_string(USERS_TABLE_DEVICES_MAP_DEVICE_TYPE_ATTRIBUTE_NAME)?
+          .to_string(),
+        key_payload: device_info
+          .get_string(USERS_TABLE_DEVICES_MAP_KEY_PAYLOAD_ATTRIBUTE_NAME)?
+          .to_string(),
+        key_payload_signature: device_info
+          .get_string(
+            USERS_TABLE_DEVICES_MAP_KEY_PAYLOAD_SIGNATURE_ATTRIBUTE_NAME,
+          )?
+          .to_string(),
+        last_login,
+        code_version,
+      });
+    }
+
+    Ok(Some(user_devices))
+  }
+
   pub async fn update_user_password(
     &self,
     user_id: String,
@@ -714,30 +928,17 @@ impl DatabaseClient {
       .await;
     match get_item_result {
       Ok(GetItemOutput {
-        item: Some(mut item),
-        ..
+        item: Some(item), ..
       }) => {
-        let created = parse_date_time_attribute(
-          ACCESS_TOKEN_TABLE_CREATED_ATTRIBUTE,
-          item.remove(ACCESS_TOKEN_TABLE_CREATED_ATTRIBUTE),
-        )?;
-        let auth_type = parse_auth_type_attribute(
-          item.remove(ACCESS_TOKEN_TABLE_AUTH_TYPE_ATTRIBUTE),
-        )?;
-        let valid = parse_valid_attribute(
-          item.remove(ACCESS_TOKEN_TABLE_VALID_ATTRIBUTE),
-        )?;
-        let access_token = parse_token_attribute(
-          item.remove(ACCESS_TOKEN_TABLE_TOKEN_ATTRIBUTE),
-        )?;
-        Ok(Some(AccessTokenData {
-          user_id,
-          signing_public_key,
-          access_token,
-          created,
-          auth_type,
-          valid,
-        }))
+        let is_legacy_token =
+          !item.contains_key(ACCESS_TOKEN_TABLE_EXPIRES_ATTRIBUTE);
+        let access_token_data = parse_access_token_item(item)?;
+        if is_legacy_token {
+          self
+            .backfill_access_token_expiration(&access_token_data)
+            .await?;
+        }
+        Ok(Some(access_token_data))
       }
       Ok(_) => {
         info!(
@@ -756,6 +957,110 @@ impl DatabaseClient {
     }
   }
 
+  /// Tokens issued before expiration was introduced are given a full
+  /// lifetime from when they're first read, so that existing sessions have
+  /// time to start refreshing their tokens
+  async fn backfill_access_token_expiration(
+    &self,
+    access_token_data: &AccessTokenData,
+  ) -> Result<(), Error> {
+    let primary_key = create_composite_primary_key(
+      (
+        ACCESS_TOKEN_TABLE_PARTITION_KEY.to_string(),
+        access_token_data.user_id.clone(),
+      ),
+      (
+        ACCESS_TOKEN_SORT_KEY.to_string(),
+        access_token_data.signing_public_key.clone(),
+      ),
+    );
+    let result = self
+      .client
+      .update_item()
+      .table_name(ACCESS_TOKEN_TABLE)
+      .set_key(Some(primary_key))
+      .update_expression("SET #expires = :expires, #ttl = :ttl")
+      .condition_expression(
+        "attribute_not_exists(#expires) AND #token = :token",
+      )
+      .expression_attribute_names(
+        "#expires",
+        ACCESS_TOKEN_TABLE_EXPIRES_ATTRIBUTE,
+      )
+      .expression_attribute_names(
+        "#ttl",
+        ACCESS_TOKEN_TABLE_EXPIRATION_TIME_UNIX_ATTRIBUTE,
+      )
+      .expression_attribute_names("#token", ACCESS_TOKEN_TABLE_TOKEN_ATTRIBUTE)
+      .expression_attribute_values(
+        ":expires",
+        AttributeValue::N(access_token_data.expires.timestamp().to_string()),
+      )
+      .expression_attribute_values(
+        ":ttl",
+        AttributeValue::N(
+          access_token_data
+            .refreshable_until()
+            .timestamp()
+            .to_string(),
+        ),
+      )
+      .expression_attribute_values(
+        ":token",
+        AttributeValue::S(access_token_data.access_token.clone()),
+      )
+      .send()
+      .await
+      .map_err(|e| Error::AwsSdk(e.into()));
+
+    match result {
+      // The token was backfilled or replaced concurrently. The expiration
+      // time read here differs from the stored one by at most the time
+      // between the reads.
+      Err(Error::AwsSdk(DynamoDBError::ConditionalCheckFailedException(_))) => {
+        Ok(())
+      }
+      result => result.map(|_| ()),
+    }
+  }
+
+  /// Returns tokens of all devices of the user, including expired and
+  /// revoked ones which weren't deleted yet
+  pub async fn get_access_tokens_for_user(
+    &self,
+    user_id: &str,
+  ) -> Result<Vec<AccessTokenData>, Error> {
+    let mut tokens = Vec::new();
+    let mut exclusive_start_key = None;
+
+    loop {
+      let response = self
+        .client
+        .query()
+        .table_name(ACCESS_TOKEN_TABLE)
+        .key_condition_expression("#userID = :userID")
+        .expression_attribute_names("#userID", ACCESS_TOKEN_TABLE_PARTITION_KEY)
+        .expression_attribute_values(
+          ":userID",
+          AttributeValue::S(user_id.to_string()),
+        )
+        .consistent_read(true)
+        .set_exclusive_start_key(exclusive_start_key)
+        .send()
+        .await
+        .map_err(|e| Error::AwsSdk(e.into()))?;
+
+      for item in response.items.unwrap_or_default() {
+        tokens.push(parse_access_token_item(item)?);
+      }
+
+      exclusive_start_key = response.last_evaluated_key;
+      if exclusive_start_key.is_none() {
+        return Ok(tokens);
+      }
+    }
+  }
+
   pub async fn verify_access_token(
     &self,
     user_id: String,
@@ -766,10 +1071,7 @@ impl DatabaseClient {
       .get_access_token_data(user_id, signing_public_key)
       .await?
       .map(|access_token_data| {
-        constant_time_eq(
-          access_token_data.access_token.as_bytes(),
-          access_token_to_verify.as_bytes(),
-        ) && access_token_data.is_valid()
+        access_token_data.verify(&access_token_to_verify)
       })
       .unwrap_or(false);
 
@@ -780,35 +1082,7 @@ impl DatabaseClient {
     &self,
     access_token_data: AccessTokenData,
   ) -> Result<PutItemOutput, Error> {
-    let item = HashMap::from([
-      (
-        ACCESS_TOKEN_TABLE_PARTITION_KEY.to_string(),
-        AttributeValue::S(access_token_data.user_id),
-      ),
-      (
-        ACCESS_TOKEN_SORT_KEY.to_string(),
-        AttributeValue::S(access_token_data.signing_public_key),
-      ),
-      (
-        ACCESS_TOKEN_TABLE_TOKEN_ATTRIBUTE.to_string(),
-        AttributeValue::S(access_token_data.access_token),
-      ),
-      (
-        ACCESS_TOKEN_TABLE_CREATED_ATTRIBUTE.to_string(),
-        AttributeValue::S(access_token_data.created.to_rfc3339()),
-      ),
-      (
-        ACCESS_TOKEN_TABLE_AUTH_TYPE_ATTRIBUTE.to_string(),
-        AttributeValue::S(match access_token_data.auth_type {
-          AuthType::Password => "password".to_string(),
-          AuthType::Wallet => "wallet".to_string(),
-        }),
-      ),
-      (
-        ACCESS_TOKEN_TABLE_VALID_ATTRIBUTE.to_string(),
-        AttributeValue::Bool(access_token_data.valid),
-      ),
-    ]);
+    let item = create_access_token_item(access_token_data);
     self
       .client
       .put_item()
@@ -819,6 +1093,40 @@ impl DatabaseClient {
       .map_err(|e| Error::AwsSdk(e.into()))
   }
 
+  /// Replaces the access token of a device, as long as it hasn't changed
+  /// since `previous_access_token` was read. Returns `false` if it did.
+  pub async fn replace_access_token_data(
+    &self,
+    previous_access_token: String,
+    access_token_data: AccessTokenData,
+  ) -> Result<bool, Error> {
+    let item = create_access_token_item(access_token_data);
+    let result = self
+      .client
+      .put_item()
+      .table_name(ACCESS_TOKEN_TABLE)
+      .set_item(Some(item))
+      .condition_expression("#token = :previous_token AND #valid = :valid")
+      .expression_attribute_names("#token", ACCESS_TOKEN_TABLE_TOKEN_ATTRIBUTE)
+      .expression_attribute_names("#valid", ACCESS_TOKEN_TABLE_VALID_ATTRIBUTE)
+      .expression_attribute_values(
+        ":previous_token",
+        AttributeValue::S(previous_access_token),
+      )
+      .expression_attribute_values(":valid", AttributeValue::Bool(true))
+      .send()
+      .await
+      .map_err(|e| Error::AwsSdk(e.into()));
+
+    match result {
+      Ok(_) => Ok(true),
+      Err(Error::AwsSdk(DynamoDBError::ConditionalCheckFailedException(_))) => {
+        Ok(false)
+      }
+      Err(e) => Err(e),
+    }
+  }
+
   pub async fn delete_access_token_data(
     &self,
     user_id: String,
@@ -1189,97 +1497,1483 @@ impl DatabaseClient {
     Ok(())
   }
 
-  pub async fn add_usernames_to_reserved_usernames_table(
+  pub async fn get_login_attempts(
     &self,
-    usernames: Vec<String>,
-  ) -> Result<(), Error> {
-    // A single call to BatchWriteItem can consist of up to 25 operations
-    for usernames_chunk in usernames.chunks(25) {
-      let write_requests = usernames_chunk
-        .iter()
-        .map(|username| {
-          let put_request = PutRequest::builder()
-            .item(
-              RESERVED_USERNAMES_TABLE_PARTITION_KEY,
-              AttributeValue::S(username.to_string()),
-            )
-            .build();
+    attempt_key: &str,
+  ) -> Result<Option<LoginAttempts>, Error> {
+    use crate::constants::login_attempts_table;
 
-          WriteRequest::builder().put_request(put_request).build()
-        })
-        .collect();
+    let get_response = self
+      .client
+      .get_item()
+      .table_name(login_attempts_table::NAME)
+      .key(
+        login_attempts_table::PARTITION_KEY,
+        AttributeValue::S(attempt_key.to_string()),
+      )
+      .consistent_read(true)
+      .send()
+      .await
+      .map_err(|e| Error::AwsSdk(e.into()))?;
 
-      self
-        .client
-        .batch_write_item()
-        .request_items(RESERVED_USERNAMES_TABLE, write_requests)
-        .send()
-        .await
-        .map_err(|e| Error::AwsSdk(e.into()))?;
+    let Some(mut item) = get_response.item else {
+      return Ok(None);
+    };
+
+    let failed_attempts_attribute =
+      item.remove(login_attempts_table::FAILED_ATTEMPTS);
+    let failed_attempts = match &failed_attempts_attribute {
+      Some(AttributeValue::N(count)) => count.parse().ok(),
+      _ => None,
     }
+    .ok_or_else(|| {
+      DBItemError::new(
+        login_attempts_table::FAILED_ATTEMPTS.to_string(),
+        failed_attempts_attribute,
+        DBItemAttributeError::IncorrectType,
+      )
+    })?;
 
-    info!("Batch write item to reserved usernames table succeeded");
+    let last_failure = parse_date_time_attribute(
+      login_attempts_table::LAST_FAILURE,
+      item.remove(login_attempts_table::LAST_FAILURE),
+    )?;
 
-    Ok(())
+    let locked_until = item
+      .remove(login_attempts_table::LOCKED_UNTIL)
+      .map(|attribute| {
+        parse_date_time_attribute(
+          login_attempts_table::LOCKED_UNTIL,
+          Some(attribute),
+        )
+      })
+      .transpose()?;
+
+    Ok(Some(LoginAttempts {
+      failed_attempts,
+      last_failure,
+      locked_until,
+    }))
   }
 
-  pub async fn delete_username_from_reserved_usernames_table(
+  /// Stores failed login attempts, as long as the number of failures hasn't
+  /// changed since `previous_failed_attempts` was read. Returns `false` if it
+  /// did.
+  pub async fn put_login_attempts(
     &self,
-    username: String,
-  ) -> Result<DeleteItemOutput, Error> {
-    debug!(
-      "Attempting to delete username {} from reserved usernames table",
-      username
-    );
+    attempt_key: &str,
+    login_attempts: &LoginAttempts,
+    previous_failed_attempts: Option<u32>,
+    expiration_time: DateTime<Utc>,
+  ) -> Result<bool, Error> {
+    use crate::constants::login_attempts_table;
 
-    match self
+    let mut item = HashMap::from([
+      (
+        login_attempts_table::PARTITION_KEY.to_string(),
+        AttributeValue::S(attempt_key.to_string()),
+      ),
+      (
+        login_attempts_table::FAILED_ATTEMPTS.to_string(),
+        AttributeValue::N(login_attempts.failed_attempts.to_string()),
+      ),
+      (
+        login_attempts_table::LAST_FAILURE.to_string(),
+        AttributeValue::S(login_attempts.last_failure.to_rfc3339()),
+      ),
+      (
+        login_attempts_table::EXPIRATION_TIME_UNIX.to_string(),
+        AttributeValue::N(expiration_time.timestamp().to_string()),
+      ),
+    ]);
+    if let Some(locked_until) = login_attempts.locked_until {
+      item.insert(
+        login_attempts_table::LOCKED_UNTIL.to_string(),
+        AttributeValue::S(locked_until.to_rfc3339()),
+      );
+    }
+
+    let request = self
       .client
-      .delete_item()
-      .table_name(RESERVED_USERNAMES_TABLE)
-      .key(
-        RESERVED_USERNAMES_TABLE_PARTITION_KEY,
-        AttributeValue::S(username.clone()),
-      )
-      .send()
-      .await
-    {
-      Ok(out) => {
-        info!(
-          "Username {} has been deleted from reserved usernames table",
-          username
-        );
-        Ok(out)
+      .put_item()
+      .table_name(login_attempts_table::NAME)
+      .set_item(Some(item))
+      .expression_attribute_names(
+        "#failed_attempts",
+        login_attempts_table::FAILED_ATTEMPTS,
+      );
+    let request = match previous_failed_attempts {
+      Some(count) => request
+        .condition_expression("#failed_attempts = :previous_failed_attempts")
+        .expression_attribute_values(
+          ":previous_failed_attempts",
+          AttributeValue::N(count.to_string()),
+        ),
+      None => {
+        request.condition_expression("attribute_not_exists(#failed_attempts)")
       }
-      Err(e) => {
-        error!("DynamoDB client failed to delete username {} from reserved usernames table", username);
-        Err(Error::AwsSdk(e.into()))
+    };
+
+    let result = request.send().await.map_err(|e| Error::AwsSdk(e.into()));
+
+    match result {
+      Ok(_) => Ok(true),
+      Err(Error::AwsSdk(DynamoDBError::ConditionalCheckFailedException(_))) => {
+        Ok(false)
       }
+      Err(e) => Err(e),
     }
   }
 
-  pub async fn username_in_reserved_usernames_table(
+  pub async fn delete_login_attempts(
     &self,
-    username: &str,
+    attempt_key: &str,
+  ) -> Result<(), Error> {
+    use crate::constants::login_attempts_table;
+
+    self
+      .client
+      .delete_item()
+      .table_name(login_attempts_table::NAME)
+      .key(
+        login_attempts_table::PARTITION_KEY,
+        AttributeValue::S(attempt_key.to_string()),
+      )
+      .send()
+      .await
+      .map_err(|e| Error::AwsSdk(e.into()))?;
+
+    Ok(())
+  }
+
+  /// Events are only ever added, never updated. Their IDs are unique, so
+  /// they are written in batches without conditions.
+  pub async fn put_audit_events(
+    &self,
+    events: Vec<(String, AuditEvent)>,
+    expiration_time: DateTime<Utc>,
+  ) -> Result<(), Error> {
+    use crate::constants::audit_log_table;
+
+    let requests: Vec<WriteRequest> = events
+      .into_iter()
+      .map(|(subject, event)| {
+        let item = create_audit_event_item(subject, event, expiration_time);
+        let put_request = PutRequest::builder().set_item(Some(item)).build();
+        WriteRequest::builder().put_request(put_request).build()
+      })
+      .collect();
+
+    self
+      .batch_write_with_retries(audit_log_table::NAME, requests)
+      .await
+  }
+
+  /// Returns events of the subject, newest first, and the ID of the last
+  /// returned event if there are more
+  pub async fn get_audit_events(
+    &self,
+    subject: &str,
+    limit: i32,
+    exclusive_start_event_id: Option<String>,
+  ) -> Result<(Vec<AuditEvent>, Option<String>), Error> {
+    use crate::constants::audit_log_table;
+
+    let exclusive_start_key = exclusive_start_event_id.map(|event_id| {
+      HashMap::from([
+        (
+          audit_log_table::PARTITION_KEY.to_string(),
+          AttributeValue::S(subject.to_string()),
+        ),
+        (
+          audit_log_table::SORT_KEY.to_string(),
+          AttributeValue::S(event_id),
+        ),
+      ])
+    });
+
+    let response = self
+      .client
+      .query()
+      .table_name(audit_log_table::NAME)
+      .key_condition_expression("#subject = :subject")
+      .expression_attribute_names("#subject", audit_log_table::PARTITION_KEY)
+      .expression_attribute_values(
+        ":subject",
+        AttributeValue::S(subject.to_string()),
+      )
+      .scan_index_forward(false)
+      .limit(limit)
+      .set_exclusive_start_key(exclusive_start_key)
+      .send()
+      .await
+      .map_err(|e| Error::AwsSdk(e.into()))?;
+
+    let events = response
+      .items
+      .unwrap_or_default()
+      .into_iter()
+      .map(parse_audit_event_item)
+      .collect::<Result<_, _>>()?;
+    let next_event_id =
+      response.last_evaluated_key.and_then(|mut key| {
+        match key.remove(audit_log_table::SORT_KEY) {
+          Some(AttributeValue::S(event_id)) => Some(event_id),
+          _ => None,
+        }
+      });
+
+    Ok((events, next_event_id))
+  }
+
+  pub async fn put_workflow_in_progress(
+    &self,
+    session_id: &str,
+    workflow: String,
+    expiration_time: DateTime<Utc>,
+  ) -> Result<(), Error> {
+    use crate::constants::workflows_in_progress_table;
+
+    let item = HashMap::from([
+      (
+        workflows_in_progress_table::PARTITION_KEY.to_string(),
+        AttributeValue::S(session_id.to_string()),
+      ),
+      (
+        workflows_in_progress_table::WORKFLOW.to_string(),
+        AttributeValue::S(workflow),
+      ),
+      (
+        workflows_in_progress_table::EXPIRATION_TIME_UNIX.to_string(),
+        AttributeValue::N(expiration_time.timestamp().to_string()),
+      ),
+    ]);
+    self
+      .client
+      .put_item()
+      .table_name(workflows_in_progress_table::NAME)
+      .set_item(Some(item))
+      .send()
+      .await
+      .map_err(|e| Error::AwsSdk(e.into()))?;
+
+    Ok(())
+  }
+
+  /// Deletes the workflow and returns it along with its expiration time
+  pub async fn take_workflow_in_progress(
+    &self,
+    session_id: &str,
+  ) -> Result<Option<(String, DateTime<Utc>)>, Error> {
+    use crate::constants::workflows_in_progress_table;
+
+    let response = self
+      .client
+      .delete_item()
+      .table_name(workflows_in_progress_table::NAME)
+      .key(
+        workflows_in_progress_table::PARTITION_KEY,
+        AttributeValue::S(session_id.to_string()),
+      )
+      .return_values(ReturnValue::AllOld)
+      .send()
+      .await
+      .map_err(|e| Error::AwsSdk(e.into()))?;
+
+    let Some(mut item) = response.attributes else {
+      return Ok(None);
+    };
+
+    let workflow = parse_string_attribute(
+      workflows_in_progress_table::WORKFLOW,
+      item.remove(workflows_in_progress_table::WORKFLOW),
+    )?;
+
+    let expiration_time_attribute =
+      item.remove(workflows_in_progress_table::EXPIRATION_TIME_UNIX);
+    let expiration_time = match &expiration_time_attribute {
+      Some(AttributeValue::N(timestamp)) => timestamp.parse().ok(),
+      _ => None,
+    }
+    .and_then(|timestamp| Utc.timestamp_opt(timestamp, 0).single())
+    .ok_or_else(|| {
+      DBItemError::new(
+        workflows_in_progress_table::EXPIRATION_TIME_UNIX.to_string(),
+        expiration_time_attribute,
+        DBItemAttributeError::IncorrectType,
+      )
+    })?;
+
+    Ok(Some((workflow, expiration_time)))
+  }
+
+  pub async fn put_export_blob(
+    &self,
+    export_blob: &ExportBlob,
+  ) -> Result<(), Error> {
+    use crate::constants::account_exports_table;
+
+    let item = HashMap::from([
+      (
+        account_exports_table::USER_ID.to_string(),
+        AttributeValue::S(export_blob.user_id.clone()),
+      ),
+      (
+        account_exports_table::HOLDER.to_string(),
+        AttributeValue::S(export_blob.holder.clone()),
+      ),
+      (
+        account_exports_table::BLOB_HASH.to_string(),
+        AttributeValue::S(export_blob.blob_hash.clone()),
+      ),
+      (
+        account_exports_table::HOLDER_EXPIRATION_TIME_UNIX.to_string(),
+        AttributeValue::N(export_blob.expiration_time.timestamp().to_string()),
+      ),
+    ]);
+    self
+      .client
+      .put_item()
+      .table_name(account_exports_table::NAME)
+      .set_item(Some(item))
+      .send()
+      .await
+      .map_err(|e| Error::AwsSdk(e.into()))?;
+
+    Ok(())
+  }
+
+  /// Returns export blobs whose holders expired at `now` or earlier
+  pub async fn get_expired_export_blobs(
+    &self,
+    now: DateTime<Utc>,
+  ) -> Result<Vec<ExportBlob>, Error> {
+    use crate::constants::account_exports_table;
+
+    let mut export_blobs = Vec::new();
+    let mut exclusive_start_key = None;
+
+    loop {
+      let response = self
+        .client
+        .scan()
+        .table_name(account_exports_table::NAME)
+        .filter_expression("#expiration <= :now")
+        .expression_attribute_names(
+          "#expiration",
+          account_exports_table::HOLDER_EXPIRATION_TIME_UNIX,
+        )
+        .expression_attribute_values(
+          ":now",
+          AttributeValue::N(now.timestamp().to_string()),
+        )
+        .set_exclusive_start_key(exclusive_start_key)
+        .send()
+        .await
+        .map_err(|e| Error::AwsSdk(e.into()))?;
+
+      for item in response.items.unwrap_or_default() {
+        export_blobs.push(parse_export_blob_item(item)?);
+      }
+
+      exclusive_start_key = response.last_evaluated_key;
+      if exclusive_start_key.is_none() {
+        return Ok(export_blobs);
+      }
+    }
+  }
+
+  pub async fn get_export_blobs_for_user(
+    &self,
+    user_id: &str,
+  ) -> Result<Vec<ExportBlob>, Error> {
+    use crate::constants::account_exports_table;
+
+    let mut export_blobs = Vec::new();
+    let mut exclusive_start_key = None;
+
+    loop {
+      let response = self
+        .client
+        .query()
+        .table_name(account_exports_table::NAME)
+        .key_condition_expression("#userID = :userID")
+        .expression_attribute_names(
+          "#userID",
+          account_exports_table::PARTITION_KEY,
+        )
+        .expression_attribute_values(
+          ":userID",
+          AttributeValue::S(user_id.to_string()),
+        )
+        .consistent_read(true)
+        .set_exclusive_start_key(exclusive_start_key)
+        .send()
+        .await
+        .map_err(|e| Error::AwsSdk(e.into()))?;
+
+      for item in response.items.unwrap_or_default() {
+        export_blobs.push(parse_export_blob_item(item)?);
+      }
+
+      exclusive_start_key = response.last_evaluated_key;
+      if exclusive_start_key.is_none() {
+        return Ok(export_blobs);
+      }
+    }
+  }
+
+  pub async fn delete_export_blob(
+    &self,
+    user_id: &str,
+    holder: &str,
+  ) -> Result<(), Error> {
+    use crate::constants::account_exports_table;
+
+    self
+      .client
+      .delete_item()
+      .table_name(account_exports_table::NAME)
+      .key(
+        account_exports_table::PARTITION_KEY,
+        AttributeValue::S(user_id.to_string()),
+      )
+      .key(
+        account_exports_table::SORT_KEY,
+        AttributeValue::S(holder.to_string()),
+      )
+      .send()
+      .await
+      .map_err(|e| Error::AwsSdk(e.into()))?;
+
+    Ok(())
+  }
+
+  /// Stores the deletion, replacing its previous state. Completed deletions
+  /// are kept as a record until the end of the retention period.
+  pub async fn put_user_deletion(
+    &self,
+    deletion: &UserDeletion,
+  ) -> Result<(), Error> {
+    use crate::constants::user_deletions_table;
+
+    let item = create_user_deletion_item(deletion);
+    self
+      .client
+      .put_item()
+      .table_name(user_deletions_table::NAME)
+      .set_item(Some(item))
+      .send()
+      .await
+      .map_err(|e| Error::AwsSdk(e.into()))?;
+
+    Ok(())
+  }
+
+  pub async fn delete_user_deletion(&self, user_id: &str) -> Result<(), Error> {
+    use crate::constants::user_deletions_table;
+
+    self
+      .client
+      .delete_item()
+      .table_name(user_deletions_table::NAME)
+      .key(
+        user_deletions_table::PARTITION_KEY,
+        AttributeValue::S(user_id.to_string()),
+      )
+      .send()
+      .await
+      .map_err(|e| Error::AwsSdk(e.into()))?;
+
+    Ok(())
+  }
+
+  /// Returns pending deletions whose next attempt is due
+  pub async fn get_due_user_deletions(
+    &self,
+    now: DateTime<Utc>,
+  ) -> Result<Vec<UserDeletion>, Error> {
+    use crate::constants::user_deletions_table;
+
+    let mut deletions = Vec::new();
+    let mut exclusive_start_key = None;
+
+    loop {
+      let response = self
+        .client
+        .scan()
+        .table_name(user_deletions_table::NAME)
+        .filter_expression("#next_attempt <= :now")
+        .expression_attribute_names(
+          "#next_attempt",
+          user_deletions_table::NEXT_ATTEMPT_TIME_UNIX,
+        )
+        .expression_attribute_values(
+          ":now",
+          AttributeValue::N(now.timestamp().to_string()),
+        )
+        .consistent_read(true)
+        .set_exclusive_start_key(exclusive_start_key)
+        .send()
+        .await
+        .map_err(|e| Error::AwsSdk(e.into()))?;
+
+      for item in response.items.unwrap_or_default() {
+        deletions.push(parse_user_deletion_item(item)?);
+      }
+
+      exclusive_start_key = response.last_evaluated_key;
+      if exclusive_start_key.is_none() {
+        return Ok(deletions);
+      }
+    }
+  }
+
+  /// Postpones the next attempt of the deletion to `claimed_until`, as long
+  /// as it hasn't changed since `previous_next_attempt_time` was read. Returns
+  /// `false` if it did, e.g. because another replica claimed it first.
+  pub async fn claim_user_deletion(
+    &self,
+    user_id: &str,
+    previous_next_attempt_time: DateTime<Utc>,
+    claimed_until: DateTime<Utc>,
+  ) -> Result<bool, Error> {
+    use crate::constants::user_deletions_table;
+
+    let result = self
+      .client
+      .update_item()
+      .table_name(user_deletions_table::NAME)
+      .key(
+        user_deletions_table::PARTITION_KEY,
+        AttributeValue::S(user_id.to_string()),
+      )
+      .update_expression("SET #next_attempt = :claimed_until")
+      .condition_expression("#next_attempt = :previous_next_attempt")
+      .expression_attribute_names(
+        "#next_attempt",
+        user_deletions_table::NEXT_ATTEMPT_TIME_UNIX,
+      )
+      .expression_attribute_values(
+        ":claimed_until",
+        AttributeValue::N(claimed_until.timestamp().to_string()),
+      )
+      .expression_attribute_values(
+        ":previous_next_attempt",
+        AttributeValue::N(previous_next_attempt_time.timestamp().to_string()),
+      )
+      .send()
+      .await
+      .map_err(|e| Error::AwsSdk(e.into()));
+
+    match result {
+      Ok(_) => Ok(true),
+      Err(Error::AwsSdk(DynamoDBError::ConditionalCheckFailedException(_))) => {
+        Ok(false)
+      }
+      Err(e) => Err(e),
+    }
+  }
+
+  pub async fn add_usernames_to_reserved_usernames_table(
+    &self,
+    usernames: Vec<String>,
+  ) -> Result<(), Error> {
+    // A single call to BatchWriteItem can consist of up to 25 operations
+    for usernames_chunk in usernames.chunks(25) {
+      let write_requests = usernames_chunk
+        .iter()
+        .map(|username| {
+          let put_request = PutRequest::builder()
+            .item(
+              RESERVED_USERNAMES_TABLE_PARTITION_KEY,
+              AttributeValue::S(username.to_string()),
+            )
+            .build();
+
+          WriteRequest::builder().put_request(put_request).build()
+        })
+        .collect();
+
+      self
+        .client
+        .batch_write_item()
+        .request_items(RESERVED_USERNAMES_TABLE, write_requests)
+        .send()
+        .await
+        .map_err(|e| Error::AwsSdk(e.into()))?;
+    }
+
+    info!("Batch write item to reserved usernames table succeeded");
+
+    Ok(())
+  }
+
+  pub async fn delete_username_from_reserved_usernames_table(
+    &self,
+    username: String,
+  ) -> Result<DeleteItemOutput, Error> {
+    debug!(
+      "Attempting to delete username {} from reserved usernames table",
+      username
+    );
+
+    match self
+      .client
+      .delete_item()
+      .table_name(RESERVED_USERNAMES_TABLE)
+      .key(
+        RESERVED_USERNAMES_TABLE_PARTITION_KEY,
+        AttributeValue::S(username.clone()),
+      )
+      .send()
+      .await
+    {
+      Ok(out) => {
+        info!(
+          "Username {} has been deleted from reserved usernames table",
+          username
+        );
+        Ok(out)
+      }
+      Err(e) => {
+        error!("DynamoDB client failed to delete username {} from reserved usernames table", username);
+        Err(Error::AwsSdk(e.into()))
+      }
+    }
+  }
+
+  pub async fn username_in_reserved_usernames_table(
+    &self,
+    username: &str,
+  ) -> Result<bool, Error> {
+    let reservation = self.get_username_reservation(username).await?;
+    Ok(reservation.is_some())
+  }
+
+  /// Returns `None` for usernames which aren't reserved, including ones held
+  /// after a username change whose grace period is over
+  pub async fn get_username_reservation(
+    &self,
+    username: &str,
+  ) -> Result<Option<UsernameReservation>, Error> {
+    let item = match self
+      .client
+      .get_item()
+      .table_name(RESERVED_USERNAMES_TABLE)
+      .key(
+        RESERVED_USERNAMES_TABLE_PARTITION_KEY.to_string(),
+        AttributeValue::S(username.to_string()),
+      )
+      .consistent_read(true)
+      .send()
+      .await
+    {
+      Ok(GetItemOutput {
+        item: Some(item), ..
+      }) => item,
+      Ok(_) => return Ok(None),
+      Err(e) => return Err(Error::AwsSdk(e.into())),
+    };
+
+    let reservation = UsernameReservation::try_from(item)?;
+    // TTL doesn't remove items immediately after they expire
+    if reservation
+      .expiration_time
+      .map_or(false, |expiration_time| expiration_time <= Utc::now())
+    {
+      return Ok(None);
+    }
+    Ok(Some(reservation))
+  }
+
+  /// Maps usernames of all password users to their user IDs
+  pub async fn get_user_ids_by_username(
+    &self,
+  ) -> Result<HashMap<String, String>, Error> {
+    let mut result = HashMap::new();
+    let mut exclusive_start_key = None;
+
+    loop {
+      let scan_output = self
+        .client
+        .scan()
+        .table_name(USERS_TABLE)
+        .projection_expression("#userID, #username")
+        .expression_attribute_names("#userID", USERS_TABLE_PARTITION_KEY)
+        .expression_attribute_names("#username", USERS_TABLE_USERNAME_ATTRIBUTE)
+        .set_exclusive_start_key(exclusive_start_key)
+        .send()
+        .await
+        .map_err(|e| Error::AwsSdk(e.into()))?;
+
+      for mut item in scan_output.items.unwrap_or_default() {
+        let Ok(username) = parse_string_attribute(
+          USERS_TABLE_USERNAME_ATTRIBUTE,
+          item.remove(USERS_TABLE_USERNAME_ATTRIBUTE),
+        ) else {
+          // Wallet users don't have usernames
+          continue;
+        };
+        let user_id = parse_string_attribute(
+          USERS_TABLE_PARTITION_KEY,
+          item.remove(USERS_TABLE_PARTITION_KEY),
+        )?;
+        result.insert(username, user_id);
+      }
+
+      exclusive_start_key = scan_output.last_evaluated_key;
+      if exclusive_start_key.is_none() {
+        return Ok(result);
+      }
+    }
+  }
+
+  /// Returns the reserved usernames among the given ones, with the user IDs
+  /// they are reserved for, if any
+  pub async fn get_reserved_username_user_ids(
+    &self,
+    usernames: Vec<String>,
+  ) -> Result<HashMap<String, Option<String>>, Error> {
+    let unique_usernames: HashSet<String> = usernames.into_iter().collect();
+    let unique_usernames: Vec<String> = unique_usernames.into_iter().collect();
+    let mut result = HashMap::new();
+
+    // A single call to BatchGetItem can retrieve up to 100 items
+    for usernames_chunk in unique_usernames.chunks(100) {
+      let keys = usernames_chunk
+        .iter()
+        .map(|username| {
+          create_simple_primary_key((
+            RESERVED_USERNAMES_TABLE_PARTITION_KEY.to_string(),
+            username.to_string(),
+          ))
+        })
+        .collect();
+      let mut request_items = Some(HashMap::from([(
+        RESERVED_USERNAMES_TABLE.to_string(),
+        KeysAndAttributes::builder()
+          .set_keys(Some(keys))
+          .consistent_read(true)
+          .build(),
+      )]));
+
+      while let Some(items) = request_items.filter(|items| !items.is_empty()) {
+        let output = self
+          .client
+          .batch_get_item()
+          .set_request_items(Some(items))
+          .send()
+          .await
+          .map_err(|e| Error::AwsSdk(e.into()))?;
+
+        let responses = output
+          .responses
+          .and_then(|mut responses| responses.remove(RESERVED_USERNAMES_TABLE))
+          .unwrap_or_default();
+        for mut item in responses {
+          let username = parse_string_attribute(
+            RESERVED_USERNAMES_TABLE_PARTITION_KEY,
+            item.remove(RESERVED_USERNAMES_TABLE_PARTITION_KEY),
+          )?;
+          let user_id = item
+            .remove(RESERVED_USERNAMES_TABLE_USER_ID_ATTRIBUTE)
+            .map(|attribute| {
+              parse_string_attribute(
+                RESERVED_USERNAMES_TABLE_USER_ID_ATTRIBUTE,
+                Some(attribute),
+              )
+            })
+            .transpose()?;
+          result.insert(username, user_id);
+        }
+
+        request_items = output.unprocessed_keys;
+      }
+    }
+
+    Ok(result)
+  }
+
+  /// Returns usernames or wallet addresses of the given users. Users which
+  /// don't exist are left out.
+  pub async fn get_user_identifiers(
+    &self,
+    user_ids: Vec<String>,
+  ) -> Result<HashMap<String, UserIdentifier>, Error> {
+    let unique_user_ids: HashSet<String> = user_ids.into_iter().collect();
+    let unique_user_ids: Vec<String> = unique_user_ids.into_iter().collect();
+    let mut result = HashMap::new();
+
+    // A single call to BatchGetItem can retrieve up to 100 items
+    for user_ids_chunk in unique_user_ids.chunks(100) {
+      let keys = user_ids_chunk
+        .iter()
+        .map(|user_id| {
+          create_simple_primary_key((
+            USERS_TABLE_PARTITION_KEY.to_string(),
+            user_id.to_string(),
+          ))
+        })
+        .collect();
+      let mut request_items = Some(HashMap::from([(
+        USERS_TABLE.to_string(),
+        KeysAndAttributes::builder()
+          .set_keys(Some(keys))
+          .projection_expression(format!(
+            "{}, {}, {}",
+            USERS_TABLE_PARTITION_KEY,
+            USERS_TABLE_USERNAME_ATTRIBUTE,
+            USERS_TABLE_WALLET_ADDRESS_ATTRIBUTE
+          ))
+          .build(),
+      )]));
+
+      while let Some(items) = request_items.filter(|items| !items.is_empty()) {
+        let output = self
+          .client
+          .batch_get_item()
+          .set_request_items(Some(items))
+          .send()
+          .await
+          .map_err(|e| Error::AwsSdk(e.into()))?;
+
+        let responses = output
+          .responses
+          .and_then(|mut responses| responses.remove(USERS_TABLE))
+          .unwrap_or_default();
+        for mut item in responses {
+          let user_id = parse_string_attribute(
+            USERS_TABLE_PARTITION_KEY,
+            item.remove(USERS_TABLE_PARTITION_KEY),
+          )?;
+          result.insert(user_id, UserIdentifier::try_from(item)?);
+        }
+
+        request_items = output.unprocessed_keys;
+      }
+    }
+
+    Ok(result)
+  }
+
+  /// Reserves usernames of keyserver users, storing their user IDs and
+  /// creation times. Existing reservations are overwritten.
+  pub async fn put_reserved_usernames_with_user_ids(
+    &self,
+    users: &[KeyserverUser],
+  ) -> Result<(), Error> {
+    let write_requests = users
+      .iter()
+      .map(|user| {
+        let mut put_request = PutRequest::builder()
+          .item(
+            RESERVED_USERNAMES_TABLE_PARTITION_KEY,
+            AttributeValue::S(user.username.clone()),
+          )
+          .item(
+            RESERVED_USERNAMES_TABLE_USER_ID_ATTRIBUTE,
+            AttributeValue::S(user.user_id()),
+          );
+        if let Some(creation_time) = user.creation_time() {
+          put_request = put_request.item(
+            RESERVED_USERNAMES_TABLE_CREATION_TIME_ATTRIBUTE,
+            AttributeValue::S(creation_time.to_rfc3339()),
+          );
+        }
+
+        WriteRequest::builder()
+          .put_request(put_request.build())
+          .build()
+      })
+      .collect();
+
+    self
+      .batch_write_with_retries(RESERVED_USERNAMES_TABLE, write_requests)
+      .await
+  }
+
+  /// Sends the write requests in batches, retrying unprocessed ones with
+  /// exponential backoff. Fails if some of them are still unprocessed after
+  /// `BATCH_WRITE_MAX_ATTEMPTS`.
+  async fn batch_write_with_retries(
+    &self,
+    table_name: &str,
+    write_requests: Vec<WriteRequest>,
+  ) -> Result<(), Error> {
+    // A single call to BatchWriteItem can consist of up to 25 operations
+    for requests in write_requests.chunks(25) {
+      let mut request_items =
+        Some(HashMap::from([(table_name.to_string(), requests.to_vec())]));
+
+      let mut attempt = 0;
+      while let Some(items) = request_items.filter(|items| !items.is_empty()) {
+        if attempt >= BATCH_WRITE_MAX_ATTEMPTS {
+          error!(
+            "Failed to write {} items to {} after {} attempts",
+            items.values().map(Vec::len).sum::<usize>(),
+            table_name,
+            attempt
+          );
+          return Err(Error::Status(tonic::Status::unavailable(
+            "unprocessed items remaining",
+          )));
+        }
+        if attempt > 0 {
+          // Unprocessed items are usually caused by throttling
+          let multiplier = 2_u64.pow(attempt - 1);
+          tokio::time::sleep(std::time::Duration::from_millis(
+            BATCH_WRITE_BASE_BACKOFF_MILLIS * multiplier,
+          ))
+          .await;
+        }
+        attempt += 1;
+
+        let output = self
+          .client
+          .batch_write_item()
+          .set_request_items(Some(items))
+          .send()
+          .await
+          .map_err(|e| Error::AwsSdk(e.into()))?;
+
+        request_items = output.unprocessed_items;
+      }
+    }
+
+    Ok(())
+  }
+
+  /// Returns `None` if the user doesn't exist
+  pub async fn get_user_record(
+    &self,
+    user_id: &str,
+  ) -> Result<Option<UserRecord>, Error> {
+    let Some(mut user) = self.get_item_from_users_table(user_id).await?.item
+    else {
+      return Ok(None);
+    };
+
+    let mut optional_attribute = |attribute_name: &str| {
+      user
+        .remove(attribute_name)
+        .map(|attribute| {
+          parse_string_attribute(attribute_name, Some(attribute))
+        })
+        .transpose()
+    };
+    let username = optional_attribute(USERS_TABLE_USERNAME_ATTRIBUTE)?;
+    let wallet_address =
+      optional_attribute(USERS_TABLE_WALLET_ADDRESS_ATTRIBUTE)?;
+    let ens_name = optional_attribute(USERS_TABLE_ENS_NAME_ATTRIBUTE)?;
+    let creation_time = user
+      .remove(USERS_TABLE_CREATION_TIME_ATTRIBUTE)
+      .map(|attribute| {
+        parse_date_time_attribute(
+          USERS_TABLE_CREATION_TIME_ATTRIBUTE,
+          Some(attribute),
+        )
+      })
+      .transpose()?;
+
+    Ok(Some(UserRecord {
+      user_id: user_id.to_string(),
+      username,
+      wallet_address,
+      ens_name,
+      creation_time,
+    }))
+  }
+
+  /// Returns `None` for unknown users and wallet users without a username
+  pub async fn get_username(
+    &self,
+    user_id: &str,
+  ) -> Result<Option<String>, Error> {
+    let Some(mut user) = self.get_item_from_users_table(user_id).await?.item
+    else {
+      return Ok(None);
+    };
+    let Some(username) = user.remove(USERS_TABLE_USERNAME_ATTRIBUTE) else {
+      return Ok(None);
+    };
+    let username =
+      parse_string_attribute(USERS_TABLE_USERNAME_ATTRIBUTE, Some(username))?;
+    Ok(Some(username))
+  }
+
+  /// Atomically replaces the username and password file of a user, and holds
+  /// the new username for them until the username index is updated. The old
+  /// username is held for them until `old_username_held_until`, if set.
+  ///
+  /// Returns `false` if the user's username has changed in the meantime or
+  /// the new username is held for someone else.
+  pub async fn change_username(
+    &self,
+    user_id: &str,
+    old_username: &str,
+    new_username: &str,
+    password_file: Vec<u8>,
+    old_username_held_until: Option<DateTime<Utc>>,
+    keyserver_ownership_proven: bool,
+  ) -> Result<bool, Error> {
+    let update_user = Update::builder()
+      .table_name(USERS_TABLE)
+      .key(
+        USERS_TABLE_PARTITION_KEY,
+        AttributeValue::S(user_id.to_string()),
+      )
+      .update_expression("SET #username = :new_username, #registration = :p")
+      .condition_expression("#username = :old_username")
+      .expression_attribute_names("#username", USERS_TABLE_USERNAME_ATTRIBUTE)
+      .expression_attribute_names(
+        "#registration",
+        USERS_TABLE_REGISTRATION_ATTRIBUTE,
+      )
+      .expression_attribute_values(
+        ":new_username",
+        AttributeValue::S(new_username.to_string()),
+      )
+      .expression_attribute_values(
+        ":old_username",
+        AttributeValue::S(old_username.to_string()),
+      )
+      .expression_attribute_values(
+        ":p",
+        AttributeValue::B(Blob::new(password_file)),
+      )
+      .build();
+
+    // Two users changing to the same username race for this item. Usernames
+    // reserved by the keyserver have neither a user ID nor an expiration, so
+    // they can only be claimed by users who proved they own them.
+    let mut claim_condition = "attribute_not_exists(#username) \
+      OR #userID = :user_id OR #expiration < :now"
+      .to_string();
+    if keyserver_ownership_proven {
+      claim_condition.push_str(
+        " OR (attribute_not_exists(#userID) \
+        AND attribute_not_exists(#expiration))",
+      );
+    }
+    let claim_expiration_time =
+      Utc::now() + Duration::minutes(USERNAME_CHANGE_CLAIM_MINUTES);
+    let claim_new_username = Put::builder()
+      .table_name(RESERVED_USERNAMES_TABLE)
+      .set_item(Some(create_username_reservation_item(
+        new_username,
+        user_id,
+        claim_expiration_time,
+      )))
+      .condition_expression(claim_condition)
+      .expression_attribute_names(
+        "#username",
+        RESERVED_USERNAMES_TABLE_PARTITION_KEY,
+      )
+      .expression_attribute_names(
+        "#userID",
+        RESERVED_USERNAMES_TABLE_USER_ID_ATTRIBUTE,
+      )
+      .expression_attribute_names(
+        "#expiration",
+        RESERVED_USERNAMES_TABLE_EXPIRATION_TIME_UNIX_ATTRIBUTE,
+      )
+      .expression_attribute_values(
+        ":user_id",
+        AttributeValue::S(user_id.to_string()),
+      )
+      .expression_attribute_values(
+        ":now",
+        AttributeValue::N(Utc::now().timestamp().to_string()),
+      )
+      .build();
+
+    let mut transact_items = vec![
+      TransactWriteItem::builder().update(update_user).build(),
+      TransactWriteItem::builder().put(claim_new_username).build(),
+    ];
+    if let Some(held_until) = old_username_held_until {
+      let hold_old_username = Put::builder()
+        .table_name(RESERVED_USERNAMES_TABLE)
+        .set_item(Some(create_username_reservation_item(
+          old_username,
+          user_id,
+          held_until,
+        )))
+        .build();
+      transact_items
+        .push(TransactWriteItem::builder().put(hold_old_username).build());
+    }
+
+    let result = self
+      .client
+      .transact_write_items()
+      .set_transact_items(Some(transact_items))
+      .send()
+      .await
+      .map_err(|e| Error::AwsSdk(e.into()));
+
+    match result {
+      Ok(_) => Ok(true),
+      Err(Error::AwsSdk(DynamoDBError::TransactionCanceledException(e))) => {
+        warn!("Username change of user {} was canceled: {}", user_id, e);
+        Ok(false)
+      }
+      Err(e) => Err(e),
+    }
+  }
+
+  /// Returns `None` if the user hasn't enrolled in two-factor authentication
+  pub async fn get_totp_state(
+    &self,
+    user_id: &str,
+  ) -> Result<Option<TotpState>, Error> {
+    let Some(mut user) = self.get_item_from_users_table(user_id).await?.item
+    else {
+      return Ok(None);
+    };
+    let Some(secret) = user.remove(USERS_TABLE_TOTP_SECRET_ATTRIBUTE) else {
+      return Ok(None);
+    };
+
+    let encrypted_secret = match secret {
+      AttributeValue::B(secret) => secret.into_inner(),
+      attribute => {
+        return Err(Error::Attribute(DBItemError::new(
+          USERS_TABLE_TOTP_SECRET_ATTRIBUTE.to_string(),
+          Some(attribute),
+          DBItemAttributeError::IncorrectType,
+        )))
+      }
+    };
+    let confirmed = match user.remove(USERS_TABLE_TOTP_CONFIRMED_ATTRIBUTE) {
+      Some(AttributeValue::Bool(confirmed)) => confirmed,
+      None => false,
+      attribute => {
+        return Err(Error::Attribute(DBItemError::new(
+          USERS_TABLE_TOTP_CONFIRMED_ATTRIBUTE.to_string(),
+          attribute,
+          DBItemAttributeError::IncorrectType,
+        )))
+      }
+    };
+    let recovery_code_hashes =
+      match user.remove(USERS_TABLE_TOTP_RECOVERY_CODES_ATTRIBUTE) {
+        Some(AttributeValue::Ss(hashes)) => hashes.into_iter().collect(),
+        None => HashSet::new(),
+        attribute => {
+          return Err(Error::Attribute(DBItemError::new(
+            USERS_TABLE_TOTP_RECOVERY_CODES_ATTRIBUTE.to_string(),
+            attribute,
+            DBItemAttributeError::IncorrectType,
+          )))
+        }
+      };
+
+    Ok(Some(TotpState {
+      encrypted_secret,
+      confirmed,
+      recovery_code_hashes,
+    }))
+  }
+
+  /// Stores a TOTP secret which is enabled only after it's confirmed. Returns
+  /// `false` if the user already has a confirmed secret.
+  pub async fn set_pending_totp_secret(
+    &self,
+    user_id: &str,
+    encrypted_secret: Vec<u8>,
   ) -> Result<bool, Error> {
-    match self
+    self
+      .update_totp_attributes(
+        user_id,
+        "SET #secret = :secret, #confirmed = :false \
+        REMOVE #recovery_codes, #last_used_step",
+        "attribute_exists(#userID) AND \
+        (attribute_not_exists(#confirmed) OR #confirmed = :false)",
+        HashMap::from([
+          (":secret", AttributeValue::B(Blob::new(encrypted_secret))),
+          (":false", AttributeValue::Bool(false)),
+        ]),
+      )
+      .await
+  }
+
+  /// Enables the pending TOTP secret. Returns `false` if it has been replaced
+  /// or confirmed in the meantime.
+  pub async fn confirm_totp(
+    &self,
+    user_id: &str,
+    encrypted_secret: Vec<u8>,
+    recovery_code_hashes: Vec<String>,
+    used_step: i64,
+  ) -> Result<bool, Error> {
+    self
+      .update_totp_attributes(
+        user_id,
+        "SET #confirmed = :true, #recovery_codes = :recovery_codes, \
+        #last_used_step = :step",
+        "#secret = :secret AND #confirmed = :false",
+        HashMap::from([
+          (":secret", AttributeValue::B(Blob::new(encrypted_secret))),
+          (":recovery_codes", AttributeValue::Ss(recovery_code_hashes)),
+          (":step", AttributeValue::N(used_step.to_string())),
+          (":true", AttributeValue::Bool(true)),
+          (":false", AttributeValue::Bool(false)),
+        ]),
+      )
+      .await
+  }
+
+  /// Marks a TOTP time step as used. Returns `false` if a code of this or a
+  /// later step has already been used.
+  pub async fn use_totp_step(
+    &self,
+    user_id: &str,
+    step: i64,
+  ) -> Result<bool, Error> {
+    self
+      .update_totp_attributes(
+        user_id,
+        "SET #last_used_step = :step",
+        "#confirmed = :true AND \
+        (attribute_not_exists(#last_used_step) OR #last_used_step < :step)",
+        HashMap::from([
+          (":step", AttributeValue::N(step.to_string())),
+          (":true", AttributeValue::Bool(true)),
+        ]),
+      )
+      .await
+  }
+
+  /// Removes a recovery code. Returns `false` if it has already been used.
+  pub async fn use_totp_recovery_code(
+    &self,
+    user_id: &str,
+    recovery_code_hash: &str,
+  ) -> Result<bool, Error> {
+    self
+      .update_totp_attributes(
+        user_id,
+        "DELETE #recovery_codes :recovery_codes",
+        "#confirmed = :true AND contains(#recovery_codes, :recovery_code)",
+        HashMap::from([
+          (
+            ":recovery_codes",
+            AttributeValue::Ss(vec![recovery_code_hash.to_string()]),
+          ),
+          (
+            ":recovery_code",
+            AttributeValue::S(recovery_code_hash.to_string()),
+          ),
+          (":true", AttributeValue::Bool(true)),
+        ]),
+      )
+      .await
+  }
+
+  pub async fn delete_totp(&self, user_id: &str) -> Result<(), Error> {
+    self
       .client
-      .get_item()
-      .table_name(RESERVED_USERNAMES_TABLE)
+      .update_item()
+      .table_name(USERS_TABLE)
       .key(
-        RESERVED_USERNAMES_TABLE_PARTITION_KEY.to_string(),
-        AttributeValue::S(username.to_string()),
+        USERS_TABLE_PARTITION_KEY,
+        AttributeValue::S(user_id.to_string()),
       )
-      .consistent_read(true)
+      .update_expression(
+        "REMOVE #secret, #confirmed, #recovery_codes, #last_used_step",
+      )
+      .set_expression_attribute_names(Some(totp_attribute_names()))
       .send()
       .await
-    {
-      Ok(GetItemOutput { item: Some(_), .. }) => Ok(true),
-      Ok(_) => Ok(false),
-      Err(e) => Err(Error::AwsSdk(e.into())),
+      .map_err(|e| Error::AwsSdk(e.into()))?;
+    Ok(())
+  }
+
+  /// Updates TOTP attributes of a user. Returns `false` if the condition
+  /// isn't met.
+  async fn update_totp_attributes(
+    &self,
+    user_id: &str,
+    update_expression: &str,
+    condition_expression: &str,
+    expression_attribute_values: HashMap<&str, AttributeValue>,
+  ) -> Result<bool, Error> {
+    // DynamoDB rejects unused attribute names
+    let mut expression_attribute_names = totp_attribute_names();
+    expression_attribute_names
+      .insert("#userID".to_string(), USERS_TABLE_PARTITION_KEY.to_string());
+    expression_attribute_names.retain(|name, _| {
+      update_expression.contains(name.as_str())
+        || condition_expression.contains(name.as_str())
+    });
+    let expression_attribute_values = expression_attribute_values
+      .into_iter()
+      .map(|(name, value)| (name.to_string(), value))
+      .collect();
+
+    let result = self
+      .client
+      .update_item()
+      .table_name(USERS_TABLE)
+      .key(
+        USERS_TABLE_PARTITION_KEY,
+        AttributeValue::S(user_id.to_string()),
+      )
+      .update_expression(update_expression)
+      .condition_expression(condition_expression)
+      .set_expression_attribute_names(Some(expression_attribute_names))
+      .set_expression_attribute_values(Some(expression_attribute_values))
+      .send()
+      .await
+      .map_err(|e| Error::AwsSdk(e.into()));
+
+    match result {
+      Ok(_) => Ok(true),
+      Err(Error::AwsSdk(DynamoDBError::ConditionalCheckFailedException(_))) => {
+        Ok(false)
+      }
+      Err(e) => Err(e),
+    }
+  }
+}
+
+#[derive(Clone, Debug)]
+pub struct TotpState {
+  // Encrypted with the TOTP encryption key
+  pub encrypted_secret: Vec<u8>,
+  // Two-factor authentication is enabled only once the secret is confirmed
+  pub confirmed: bool,
+  // SHA-256 hashes of unused recovery codes
+  pub recovery_code_hashes: HashSet<String>,
+}
+
+fn log_one_time_key_exhausted(device_id: &str, account_type: OlmAccountType) {
+  use crate::constants::ONE_TIME_KEY_EXHAUSTED_METRIC;
+
+  warn!(
+    metric = ONE_TIME_KEY_EXHAUSTED_METRIC,
+    device_id,
+    account_type = ?account_type,
+    "No one-time keys left for device"
+  );
+}
+
+fn totp_attribute_names() -> HashMap<String, String> {
+  HashMap::from([
+    (
+      "#secret".to_string(),
+      USERS_TABLE_TOTP_SECRET_ATTRIBUTE.to_string(),
+    ),
+    (
+      "#confirmed".to_string(),
+      USERS_TABLE_TOTP_CONFIRMED_ATTRIBUTE.to_string(),
+    ),
+    (
+      "#recovery_codes".to_string(),
+      USERS_TABLE_TOTP_RECOVERY_CODES_ATTRIBUTE.to_string(),
+    ),
+    (
+      "#last_used_step".to_string(),
+      USERS_TABLE_TOTP_LAST_USED_STEP_ATTRIBUTE.to_string(),
+    ),
+  ])
+}
+
+/// Account information of a user, without devices and credentials
+pub struct UserRecord {
+  pub user_id: String,
+  pub username: Option<String>,
+  pub wallet_address: Option<String>,
+  pub ens_name: Option<String>,
+  // Missing for users registered before it was recorded
+  pub creation_time: Option<DateTime<Utc>>,
+}
+
+#[derive(Clone, Debug)]
+pub struct UsernameReservation {
+  // Set for usernames imported from the keyserver and held after username
+  // changes
+  pub user_id: Option<String>,
+  // Set only for usernames held after username changes
+  pub expiration_time: Option<DateTime<Utc>>,
+}
+
+impl TryFrom<HashMap<String, AttributeValue>> for UsernameReservation {
+  type Error = DBItemError;
+
+  fn try_from(
+    mut item: HashMap<String, AttributeValue>,
+  ) -> Result<Self, Self::Error> {
+    let user_id = item
+      .remove(RESERVED_USERNAMES_TABLE_USER_ID_ATTRIBUTE)
+      .map(|attribute| {
+        parse_string_attribute(
+          RESERVED_USERNAMES_TABLE_USER_ID_ATTRIBUTE,
+          Some(attribute),
+        )
+      })
+      .transpose()?;
+    let expiration_time = parse_expiration_time_attribute(
+      RESERVED_USERNAMES_TABLE_EXPIRATION_TIME_UNIX_ATTRIBUTE,
+      item.remove(RESERVED_USERNAMES_TABLE_EXPIRATION_TIME_UNIX_ATTRIBUTE),
+    )?;
+
+    Ok(Self {
+      user_id,
+      expiration_time,
+    })
+  }
+}
+
+/// Username of a password user, or wallet address of a wallet user
+#[derive(Clone, Debug, PartialEq)]
+pub enum UserIdentifier {
+  Username(String),
+  WalletAddress(String),
+}
+
+impl TryFrom<HashMap<String, AttributeValue>> for UserIdentifier {
+  type Error = DBItemError;
+
+  fn try_from(
+    mut item: HashMap<String, AttributeValue>,
+  ) -> Result<Self, Self::Error> {
+    if let Some(username) = item.remove(USERS_TABLE_USERNAME_ATTRIBUTE) {
+      let username =
+        parse_string_attribute(USERS_TABLE_USERNAME_ATTRIBUTE, Some(username))?;
+      return Ok(UserIdentifier::Username(username));
     }
+    let wallet_address = parse_string_attribute(
+      USERS_TABLE_WALLET_ADDRESS_ATTRIBUTE,
+      item.remove(USERS_TABLE_WALLET_ADDRESS_ATTRIBUTE),
+    )?;
+    Ok(UserIdentifier::WalletAddress(wallet_address))
   }
 }
 
+fn create_username_reservation_item(
+  username: &str,
+  user_id: &str,
+  expiration_time: DateTime<Utc>,
+) -> HashMap<String, AttributeValue> {
+  HashMap::from([
+    (
+      RESERVED_USERNAMES_TABLE_PARTITION_KEY.to_string(),
+      AttributeValue::S(username.to_string()),
+    ),
+    (
+      RESERVED_USERNAMES_TABLE_USER_ID_ATTRIBUTE.to_string(),
+      AttributeValue::S(user_id.to_string()),
+    ),
+    (
+      RESERVED_USERNAMES_TABLE_EXPIRATION_TIME_UNIX_ATTRIBUTE.to_string(),
+      AttributeValue::N(expiration_time.timestamp().to_string()),
+    ),
+  ])
+}
+
 type AttributeName = String;
 pub type DeviceKeys = HashMap<String, String>;
 type Devices = HashMap<String, DeviceKeys>;
@@ -1299,6 +2993,420 @@ fn create_composite_primary_key(
   primary_key
 }
 
+fn create_access_token_item(
+  access_token_data: AccessTokenData,
+) -> HashMap<String, AttributeValue> {
+  HashMap::from([
+    (
+      ACCESS_TOKEN_TABLE_PARTITION_KEY.to_string(),
+      AttributeValue::S(access_token_data.user_id),
+    ),
+    (
+      ACCESS_TOKEN_SORT_KEY.to_string(),
+      AttributeValue::S(access_token_data.signing_public_key),
+    ),
+    (
+      ACCESS_TOKEN_TABLE_TOKEN_ATTRIBUTE.to_string(),
+      AttributeValue::S(access_token_data.access_token),
+    ),
+    (
+      ACCESS_TOKEN_TABLE_CREATED_ATTRIBUTE.to_string(),
+      AttributeValue::S(access_token_data.created.to_rfc3339()),
+    ),
+    (
+      ACCESS_TOKEN_TABLE_EXPIRES_ATTRIBUTE.to_string(),
+      AttributeValue::N(access_token_data.expires.timestamp().to_string()),
+    ),
+    (
+      ACCESS_TOKEN_TABLE_EXPIRATION_TIME_UNIX_ATTRIBUTE.to_string(),
+      AttributeValue::N(
+        access_token_data
+          .refreshable_until()
+          .timestamp()
+          .to_string(),
+      ),
+    ),
+    (
+      ACCESS_TOKEN_TABLE_AUTH_TYPE_ATTRIBUTE.to_string(),
+      AttributeValue::S(match access_token_data.auth_type {
+        AuthType::Password => "password".to_string(),
+        AuthType::Wallet => "wallet".to_string(),
+      }),
+    ),
+    (
+      ACCESS_TOKEN_TABLE_VALID_ATTRIBUTE.to_string(),
+      AttributeValue::Bool(access_token_data.valid),
+    ),
+  ])
+}
+
+fn parse_access_token_item(
+  mut item: HashMap<String, AttributeValue>,
+) -> Result<AccessTokenData, DBItemError> {
+  let user_id = parse_string_attribute(
+    ACCESS_TOKEN_TABLE_PARTITION_KEY,
+    item.remove(ACCESS_TOKEN_TABLE_PARTITION_KEY),
+  )?;
+  let signing_public_key = parse_string_attribute(
+    ACCESS_TOKEN_SORT_KEY,
+    item.remove(ACCESS_TOKEN_SORT_KEY),
+  )?;
+  let created = parse_date_time_attribute(
+    ACCESS_TOKEN_TABLE_CREATED_ATTRIBUTE,
+    item.remove(ACCESS_TOKEN_TABLE_CREATED_ATTRIBUTE),
+  )?;
+  let auth_type = parse_auth_type_attribute(
+    item.remove(ACCESS_TOKEN_TABLE_AUTH_TYPE_ATTRIBUTE),
+  )?;
+  let valid =
+    parse_valid_attribute(item.remove(ACCESS_TOKEN_TABLE_VALID_ATTRIBUTE))?;
+  let access_token =
+    parse_token_attribute(item.remove(ACCESS_TOKEN_TABLE_TOKEN_ATTRIBUTE))?;
+  // Tokens issued before expiration was introduced don't have the
+  // attribute. `get_access_token_data` stores the expiration time given to
+  // them here.
+  let expires = parse_expiration_time_attribute(
+    ACCESS_TOKEN_TABLE_EXPIRES_ATTRIBUTE,
+    item.remove(ACCESS_TOKEN_TABLE_EXPIRES_ATTRIBUTE),
+  )?
+  .unwrap_or_else(|| Utc::now() + CONFIG.access_token_lifetime);
+
+  Ok(AccessTokenData {
+    user_id,
+    signing_public_key,
+    access_token,
+    created,
+    expires,
+    auth_type,
+    valid,
+  })
+}
+
+fn create_audit_event_item(
+  subject: String,
+  event: AuditEvent,
+  expiration_time: DateTime<Utc>,
+) -> HashMap<String, AttributeValue> {
+  use crate::constants::audit_log_table;
+
+  let mut item = HashMap::from([
+    (
+      audit_log_table::PARTITION_KEY.to_string(),
+      AttributeValue::S(subject),
+    ),
+    (
+      audit_log_table::EVENT_ID.to_string(),
+      AttributeValue::S(event.event_id),
+    ),
+    (
+      audit_log_table::EVENT_TYPE.to_string(),
+      AttributeValue::S(event.event_type.as_str().to_string()),
+    ),
+    (
+      audit_log_table::OUTCOME.to_string(),
+      AttributeValue::S(event.outcome.as_str().to_string()),
+    ),
+    (
+      audit_log_table::TIMESTAMP.to_string(),
+      AttributeValue::S(event.timestamp.to_rfc3339()),
+    ),
+    (
+      audit_log_table::EXPIRATION_TIME_UNIX.to_string(),
+      AttributeValue::N(expiration_time.timestamp().to_string()),
+    ),
+  ]);
+
+  let auth_type = event.auth_type.map(|auth_type| match auth_type {
+    AuthType::Password => "password".to_string(),
+    AuthType::Wallet => "wallet".to_string(),
+  });
+  let optional_attributes = [
+    (audit_log_table::DEVICE_ID, event.device_id),
+    (audit_log_table::AUTH_TYPE, auth_type),
+    (audit_log_table::IP_ADDRESS, event.ip_address),
+    (audit_log_table::USER_AGENT, event.user_agent),
+  ];
+  for (attribute_name, value) in optional_attributes {
+    if let Some(value) = value {
+      item.insert(attribute_name.to_string(), AttributeValue::S(value));
+    }
+  }
+
+  item
+}
+
+fn parse_audit_event_item(
+  mut item: HashMap<String, AttributeValue>,
+) -> Result<AuditEvent, DBItemError> {
+  use crate::constants::audit_log_table;
+
+  let event_id = parse_string_attribute(
+    audit_log_table::EVENT_ID,
+    item.remove(audit_log_table::EVENT_ID),
+  )?;
+
+  let event_type_attribute = item.remove(audit_log_table::EVENT_TYPE);
+  let event_type = match &event_type_attribute {
+    Some(AttributeValue::S(event_type)) => event_type.parse().ok(),
+    _ => None,
+  }
+  .ok_or_else(|| {
+    DBItemError::new(
+      audit_log_table::EVENT_TYPE.to_string(),
+      event_type_attribute,
+      DBItemAttributeError::InvalidValue,
+    )
+  })?;
+
+  let outcome_attribute = item.remove(audit_log_table::OUTCOME);
+  let outcome = match &outcome_attribute {
+    Some(AttributeValue::S(outcome)) => outcome.parse().ok(),
+    _ => None,
+  }
+  .ok_or_else(|| {
+    DBItemError::new(
+      audit_log_table::OUTCOME.to_string(),
+      outcome_attribute,
+      DBItemAttributeError::InvalidValue,
+    )
+  })?;
+
+  let timestamp = parse_date_time_attribute(
+    audit_log_table::TIMESTAMP,
+    item.remove(audit_log_table::TIMESTAMP),
+  )?;
+
+  let mut optional_attribute =
+    |attribute_name| match item.remove(attribute_name) {
+      Some(AttributeValue::S(value)) => Some(value),
+      _ => None,
+    };
+  let device_id = optional_attribute(audit_log_table::DEVICE_ID);
+  let auth_type =
+    optional_attribute(audit_log_table::AUTH_TYPE).and_then(|auth_type| {
+      match auth_type.as_str() {
+        "password" => Some(AuthType::Password),
+        "wallet" => Some(AuthType::Wallet),
+        _ => None,
+      }
+    });
+  let ip_address = optional_attribute(audit_log_table::IP_ADDRESS);
+  let user_agent = optional_attribute(audit_log_table::USER_AGENT);
+
+  Ok(AuditEvent {
+    event_id,
+    event_type,
+    outcome,
+    timestamp,
+    device_id,
+    auth_type,
+    ip_address,
+    user_agent,
+  })
+}
+
+fn parse_export_blob_item(
+  mut item: HashMap<String, AttributeValue>,
+) -> Result<ExportBlob, DBItemError> {
+  use crate::constants::account_exports_table;
+
+  let user_id = parse_string_attribute(
+    account_exports_table::USER_ID,
+    item.remove(account_exports_table::USER_ID),
+  )?;
+  let holder = parse_string_attribute(
+    account_exports_table::HOLDER,
+    item.remove(account_exports_table::HOLDER),
+  )?;
+  let blob_hash = parse_string_attribute(
+    account_exports_table::BLOB_HASH,
+    item.remove(account_exports_table::BLOB_HASH),
+  )?;
+
+  let expiration_time_attribute =
+    item.remove(account_exports_table::HOLDER_EXPIRATION_TIME_UNIX);
+  let expiration_time = match &expiration_time_attribute {
+    Some(AttributeValue::N(timestamp)) => timestamp.parse().ok(),
+    _ => None,
+  }
+  .and_then(|timestamp| Utc.timestamp_opt(timestamp, 0).single())
+  .ok_or_else(|| {
+    DBItemError::new(
+      account_exports_table::HOLDER_EXPIRATION_TIME_UNIX.to_string(),
+      expiration_time_attribute,
+      DBItemAttributeError::IncorrectType,
+    )
+  })?;
+
+  Ok(ExportBlob {
+    user_id,
+    holder,
+    blob_hash,
+    expiration_time,
+  })
+}
+
+fn create_user_deletion_item(
+  deletion: &UserDeletion,
+) -> HashMap<String, AttributeValue> {
+  use crate::constants::user_deletions_table;
+
+  let string_list = |values: Vec<String>| {
+    AttributeValue::L(values.into_iter().map(AttributeValue::S).collect())
+  };
+  let pending_steps = deletion
+    .pending_steps
+    .iter()
+    .map(|step| step.as_str().to_string())
+    .collect();
+
+  let mut item = HashMap::from([
+    (
+      user_deletions_table::PARTITION_KEY.to_string(),
+      AttributeValue::S(deletion.user_id.clone()),
+    ),
+    (
+      user_deletions_table::DEVICE_IDS.to_string(),
+      string_list(deletion.device_ids.clone()),
+    ),
+    (
+      user_deletions_table::PENDING_STEPS.to_string(),
+      string_list(pending_steps),
+    ),
+    (
+      user_deletions_table::ATTEMPTS.to_string(),
+      AttributeValue::N(deletion.attempts.to_string()),
+    ),
+    (
+      user_deletions_table::CREATED.to_string(),
+      AttributeValue::S(deletion.created.to_rfc3339()),
+    ),
+  ]);
+
+  if let Some(last_error) = &deletion.last_error {
+    item.insert(
+      user_deletions_table::LAST_ERROR.to_string(),
+      AttributeValue::S(last_error.clone()),
+    );
+  }
+
+  // Only pending deletions are retried, only completed ones expire
+  match deletion.next_attempt_time {
+    Some(next_attempt_time) => item.insert(
+      user_deletions_table::NEXT_ATTEMPT_TIME_UNIX.to_string(),
+      AttributeValue::N(next_attempt_time.timestamp().to_string()),
+    ),
+    None => {
+      let expiration_time =
+        Utc::now() + Duration::days(USER_DELETION_RECORD_RETENTION_DAYS);
+      item.insert(
+        user_deletions_table::EXPIRATION_TIME_UNIX.to_string(),
+        AttributeValue::N(expiration_time.timestamp().to_string()),
+      )
+    }
+  };
+
+  item
+}
+
+fn parse_user_deletion_item(
+  mut item: HashMap<String, AttributeValue>,
+) -> Result<UserDeletion, DBItemError> {
+  use crate::constants::user_deletions_table;
+
+  let mut string_list = |attribute_name: &str| {
+    let attribute = item.remove(attribute_name);
+    match &attribute {
+      Some(AttributeValue::L(values)) => values
+        .iter()
+        .map(|value| match value {
+          AttributeValue::S(value) => Some(value.clone()),
+          _ => None,
+        })
+        .collect::<Option<Vec<_>>>(),
+      _ => None,
+    }
+    .ok_or_else(|| {
+      DBItemError::new(
+        attribute_name.to_string(),
+        attribute,
+        DBItemAttributeError::IncorrectType,
+      )
+    })
+  };
+
+  let device_ids = string_list(user_deletions_table::DEVICE_IDS)?;
+  let pending_steps = string_list(user_deletions_table::PENDING_STEPS)?
+    .iter()
+    .map(|step| {
+      step.parse().map_err(|_| {
+        DBItemError::new(
+          user_deletions_table::PENDING_STEPS.to_string(),
+          Some(AttributeValue::S(step.clone())),
+          DBItemAttributeError::InvalidValue,
+        )
+      })
+    })
+    .collect::<Result<_, _>>()?;
+
+  let user_id = parse_string_attribute(
+    user_deletions_table::USER_ID,
+    item.remove(user_deletions_table::USER_ID),
+  )?;
+
+  let attempts_attribute = item.remove(user_deletions_table::ATTEMPTS);
+  let attempts = match &attempts_attribute {
+    Some(AttributeValue::N(attempts)) => attempts.parse().ok(),
+    _ => None,
+  }
+  .ok_or_else(|| {
+    DBItemError::new(
+      user_deletions_table::ATTEMPTS.to_string(),
+      attempts_attribute,
+      DBItemAttributeError::IncorrectType,
+    )
+  })?;
+
+  let last_error = match item.remove(user_deletions_table::LAST_ERROR) {
+    Some(AttributeValue::S(last_error)) => Some(last_error),
+    _ => None,
+  };
+
+  let created = parse_date_time_attribute(
+    user_deletions_table::CREATED,
+    item.remove(user_deletions_table::CREATED),
+  )?;
+
+  let next_attempt_time_attribute =
+    item.remove(user_deletions_table::NEXT_ATTEMPT_TIME_UNIX);
+  let next_attempt_time = match &next_attempt_time_attribute {
+    Some(AttributeValue::N(timestamp)) => timestamp
+      .parse()
+      .ok()
+      .and_then(|timestamp| Utc.timestamp_opt(timestamp, 0).single())
+      .map(Some),
+    Some(_) => None,
+    None => Some(None),
+  }
+  .ok_or_else(|| {
+    DBItemError::new(
+      user_deletions_table::NEXT_ATTEMPT_TIME_UNIX.to_string(),
+      next_attempt_time_attribute,
+      DBItemAttributeError::IncorrectType,
+    )
+  })?;
+
+  Ok(UserDeletion {
+    user_id,
+    device_ids,
+    pending_steps,
+    attempts,
+    last_error,
+    created,
+    next_attempt_time,
+  })
+}
+
 fn parse_date_time_attribute(
   attribute_name: &str,
   attribute: Option<AttributeValue>,
@@ -1360,6 +3468,26 @@ fn parse_valid_attribute(
   }
 }
 
+fn parse_expiration_time_attribute(
+  attribute_name: &str,
+  attribute: Option<AttributeValue>,
+) -> Result<Option<DateTime<Utc>>, DBItemError> {
+  let timestamp = match &attribute {
+    Some(AttributeValue::N(timestamp)) => timestamp.parse().ok(),
+    Some(_) => None,
+    None => return Ok(None),
+  };
+
+  match timestamp.and_then(|t| Utc.timestamp_opt(t, 0).single()) {
+    Some(expiration_time) => Ok(Some(expiration_time)),
+    None => Err(DBItemError::new(
+      attribute_name.to_string(),
+      attribute,
+      DBItemAttributeError::IncorrectType,
+    )),
+  }
+}
+
 fn parse_token_attribute(
   attribute: Option<AttributeValue>,
 ) -> Result<String, DBItemError> {
@@ -1501,6 +3629,10 @@ fn create_device_info(
       USERS_TABLE_DEVICES_MAP_NOTIF_PREKEY_SIGNATURE_ATTRIBUTE_NAME.to_string(),
       AttributeValue::S(flattened_device_key_upload.notif_prekey_signature),
     ),
+    (
+      USERS_TABLE_DEVICES_MAP_LAST_LOGIN_ATTRIBUTE_NAME.to_string(),
+      AttributeValue::S(Utc::now().to_rfc3339()),
+    ),
   ]);
 
   if let Some(social_proof) = social_proof {
@@ -1510,6 +3642,13 @@ fn create_device_info(
     );
   }
 
+  if let Some(code_version) = flattened_device_key_upload.code_version {
+    device_info.insert(
+      USERS_TABLE_DEVICES_MAP_CODE_VERSION_ATTRIBUTE_NAME.to_string(),
+      AttributeValue::N(code_version.to_string()),
+    );
+  }
+
   device_info
 }
 
@@ -1575,4 +3714,72 @@ mod tests {
     let invalid_result = DeviceType::try_from(6);
     assert!(invalid_result.is_err());
   }
+
+  #[test]
+  fn test_username_reservation_from_item() {
+    let expiration_time = Utc.timestamp_opt(1_700_000_000, 0).unwrap();
+    let item =
+      create_username_reservation_item("alice", "user", expiration_time);
+    let reservation = UsernameReservation::try_from(item).unwrap();
+    assert_eq!(reservation.user_id.as_deref(), Some("user"));
+    assert_eq!(reservation.expiration_time, Some(expiration_time));
+
+    // Reserved by the keyserver
+    let item = HashMap::from([(
+      RESERVED_USERNAMES_TABLE_PARTITION_KEY.to_string(),
+      AttributeValue::S("bob".to_string()),
+    )]);
+    let reservation = UsernameReservation::try_from(item).unwrap();
+    assert_eq!(reservation.user_id, None);
+    assert_eq!(reservation.expiration_time, None);
+  }
+
+  #[test]
+  fn test_audit_event_item_round_trip() {
+    use crate::audit_log::{
+      AuditEventType, AuditOutcome, AuditSubject, RequestMetadata,
+    };
+
+    let request_metadata = RequestMetadata {
+      ip_address: Some("192.0.2.1".to_string()),
+      user_agent: None,
+    };
+    let event = AuditEvent {
+      device_id: Some("device".to_string()),
+      auth_type: Some(AuthType::Wallet),
+      ..AuditEvent::new(
+        AuditEventType::Login,
+        AuditOutcome::Failure,
+        &request_metadata,
+      )
+    };
+    let subject = AuditSubject::User("user".to_string()).to_string();
+
+    let item = create_audit_event_item(subject, event.clone(), Utc::now());
+    assert_eq!(parse_audit_event_item(item).unwrap(), event);
+  }
+
+  #[test]
+  fn test_user_identifier_from_item() {
+    let item = HashMap::from([(
+      USERS_TABLE_USERNAME_ATTRIBUTE.to_string(),
+      AttributeValue::S("alice".to_string()),
+    )]);
+    assert_eq!(
+      UserIdentifier::try_from(item).unwrap(),
+      UserIdentifier::Username("alice".to_string())
+    );
+
+    let address = "0x0000000000000000000000000000000000000001";
+    let item = HashMap::from([(
+      USERS_TABLE_WALLET_ADDRESS_ATTRIBUTE.to_string(),
+      AttributeValue::S(address.to_string()),
+    )]);
+    assert_eq!(
+      UserIdentifier::try_from(item).unwrap(),
+      UserIdentifier::WalletAddress(address.to_string())
+    );
+
+    assert!(UserIdentifier::try_from(HashMap::new()).is_err());
+  }
 }
@@ -1,4 +1,6 @@
-use aws_sdk_dynamodb::model::{AttributeValue, PutRequest, WriteRequest};
+use aws_sdk_dynamodb::model::{
+  AttributeValue, DeleteRequest, PutRequest, WriteRequest,
+};
 use std::collections::HashMap;
 use std::iter::IntoIterator;
 
@@ -56,3 +58,26 @@ where
     })
     .collect()
 }
+
+pub fn into_one_time_delete_requests(
+  device_id: &str,
+  one_time_keys: Vec<String>,
+  account_type: OlmAccountType,
+) -> Vec<WriteRequest> {
+  use crate::constants::one_time_keys_table::*;
+
+  let partition_key =
+    create_one_time_key_partition_key(device_id, account_type);
+  one_time_keys
+    .into_iter()
+    .map(|one_time_key| {
+      let delete_request = DeleteRequest::builder()
+        .key(PARTITION_KEY, AttributeValue::S(partition_key.clone()))
+        .key(SORT_KEY, AttributeValue::S(one_time_key))
+        .build();
+      WriteRequest::builder()
+        .delete_request(delete_request)
+        .build()
+    })
+    .collect()
+}
@@ -17,6 +17,8 @@ pub enum Error {
   Status(tonic::Status),
   #[display(...)]
   MissingItem,
+  #[display(...)]
+  Serde(serde_json::Error),
 }
 
 #[derive(Debug, derive_more::Error, derive_more::Constructor)]
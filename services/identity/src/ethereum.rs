@@ -0,0 +1,408 @@
+//! Minimal Ethereum JSON-RPC client, used to validate signatures of smart
+//! contract wallets (EIP-1271) and to look up primary ENS names. Only
+//! `eth_call` is needed, so calls are ABI-encoded by hand.
+
+use std::time::Duration;
+
+use serde_json::{json, Value};
+use sha3::{Digest, Keccak256};
+use tracing::warn;
+
+use crate::constants::{ENS_REGISTRY_ADDRESS, ETHEREUM_RPC_TIMEOUT_SECONDS};
+
+// bytes4(keccak256("isValidSignature(bytes32,bytes)")), which is also the
+// value returned for valid signatures
+const EIP_1271_MAGIC_VALUE: [u8; 4] = [0x16, 0x26, 0xba, 0x7e];
+// resolver(bytes32) of the ENS registry
+const RESOLVER_SELECTOR: [u8; 4] = [0x01, 0x78, 0xb8, 0xbf];
+// name(bytes32) of an ENS resolver
+const NAME_SELECTOR: [u8; 4] = [0x69, 0x1f, 0x34, 0x31];
+// addr(bytes32) of an ENS resolver
+const ADDR_SELECTOR: [u8; 4] = [0x3b, 0x3b, 0x57, 0xde];
+
+const WORD_LENGTH: usize = 32;
+
+#[derive(Debug, derive_more::Display, derive_more::From)]
+pub enum Error {
+  #[display(...)]
+  Http(reqwest::Error),
+  #[display(fmt = "JSON-RPC error: {}", _0)]
+  #[from(ignore)]
+  Rpc(Value),
+  #[display(fmt = "Invalid JSON-RPC response")]
+  InvalidResponse,
+}
+
+impl std::error::Error for Error {}
+
+#[derive(Clone)]
+pub struct EthereumClient {
+  http_client: reqwest::Client,
+  rpc_url: String,
+}
+
+impl EthereumClient {
+  pub fn new(rpc_url: String) -> Result<Self, Error> {
+    let http_client = reqwest::Client::builder()
+      .timeout(Duration::from_secs(ETHEREUM_RPC_TIMEOUT_SECONDS))
+      .build()?;
+    Ok(Self {
+      http_client,
+      rpc_url,
+    })
+  }
+
+  /// Calls a contract function with the given ABI-encoded input, returning
+  /// its ABI-encoded output
+  async fn call(&self, to: &[u8; 20], data: &[u8]) -> Result<Vec<u8>, Error> {
+    let request = json!({
+      "jsonrpc": "2.0",
+      "id": 1,
+      "method": "eth_call",
+      "params": [
+        { "to": to_hex(to), "data": to_hex(data) },
+        "latest"
+      ],
+    });
+    let response: Value = self
+      .http_client
+      .post(&self.rpc_url)
+      .json(&request)
+      .send()
+      .await?
+      .error_for_status()?
+      .json()
+      .await?;
+
+    if let Some(error) = response.get("error") {
+      return Err(Error::Rpc(error.clone()));
+    }
+    let result = response["result"].as_str().ok_or(Error::InvalidResponse)?;
+    hex::decode(result.trim_start_matches("0x"))
+      .map_err(|_| Error::InvalidResponse)
+  }
+
+  /// Checks a signature of a smart contract wallet, see EIP-1271. Returns
+  /// `false` for addresses without a contract.
+  pub async fn is_valid_contract_signature(
+    &self,
+    address: &[u8; 20],
+    hash: &[u8; 32],
+    signature: &[u8],
+  ) -> Result<bool, Error> {
+    let mut data = EIP_1271_MAGIC_VALUE.to_vec();
+    data.extend_from_slice(hash);
+    // Offset of the dynamic `bytes` argument, following the two head words
+    data.extend_from_slice(&encode_usize(2 * WORD_LENGTH));
+    data.extend_from_slice(&encode_usize(signature.len()));
+    data.extend_from_slice(signature);
+    data.resize(pad_to_word(data.len() - 4) + 4, 0);
+
+    let result = match self.call(address, &data).await {
+      Ok(result) => result,
+      // Contracts may revert instead of returning a different value
+      Err(Error::Rpc(error)) => {
+        warn!("isValidSignature call failed: {}", error);
+        return Ok(false);
+      }
+      Err(e) => return Err(e),
+    };
+    Ok(result.starts_with(&EIP_1271_MAGIC_VALUE))
+  }
+
+  /// Returns the primary ENS name of an address, only if the name resolves
+  /// back to the address
+  pub async fn lookup_ens_name(
+    &self,
+    address: &[u8; 20],
+  ) -> Result<Option<String>, Error> {
+    let reverse_node =
+      namehash(&format!("{}.addr.reverse", hex::encode(address)));
+    let Some(reverse_resolver) = self.get_resolver(&reverse_node).await? else {
+      return Ok(None);
+    };
+    let name_output = self
+      .call(
+        &reverse_resolver,
+        &encode_node_call(NAME_SELECTOR, &reverse_node),
+      )
+      .await?;
+    let name = decode_string(&name_output)?;
+    if name.is_empty() {
+      return Ok(None);
+    }
+
+    // Anyone can claim any name in their reverse record
+    let node = namehash(&name);
+    let Some(resolver) = self.get_resolver(&node).await? else {
+      return Ok(None);
+    };
+    let addr_output = self
+      .call(&resolver, &encode_node_call(ADDR_SELECTOR, &node))
+      .await?;
+    if decode_address(&addr_output)? != *address {
+      return Ok(None);
+    }
+
+    Ok(Some(name))
+  }
+
+  async fn get_resolver(
+    &self,
+    node: &[u8; 32],
+  ) -> Result<Option<[u8; 20]>, Error> {
+    let registry = parse_address(ENS_REGISTRY_ADDRESS)
+      .expect("ENS registry address should be valid");
+    let output = self
+      .call(&registry, &encode_node_call(RESOLVER_SELECTOR, node))
+      .await?;
+    let resolver = decode_address(&output)?;
+    Ok(Some(resolver).filter(|resolver| *resolver != [0; 20]))
+  }
+}
+
+/// See EIP-137
+pub fn namehash(name: &str) -> [u8; 32] {
+  let mut node = [0; 32];
+  for label in name.rsplit('.').filter(|label| !label.is_empty()) {
+    let label_hash = Keccak256::digest(label.as_bytes());
+    let mut hasher = Keccak256::new();
+    hasher.update(node);
+    hasher.update(label_hash);
+    node.copy_from_slice(&hasher.finalize());
+  }
+  node
+}
+
+pub fn parse_address(address: &str) -> Option<[u8; 20]> {
+  hex::decode(address.trim_start_matches("0x"))
+    .ok()?
+    .try_into()
+    .ok()
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+  format!("0x{}", hex::encode(bytes))
+}
+
+fn pad_to_word(length: usize) -> usize {
+  (length + WORD_LENGTH - 1) / WORD_LENGTH * WORD_LENGTH
+}
+
+fn encode_usize(value: usize) -> [u8; WORD_LENGTH] {
+  let mut word = [0; WORD_LENGTH];
+  word[WORD_LENGTH - 8..].copy_from_slice(&(value as u64).to_be_bytes());
+  word
+}
+
+fn encode_node_call(selector: [u8; 4], node: &[u8; 32]) -> Vec<u8> {
+  [&selector[..], node].concat()
+}
+
+fn decode_usize(word: &[u8]) -> Result<usize, Error> {
+  if word.len() != WORD_LENGTH {
+    return Err(Error::InvalidResponse);
+  }
+  let (high, low) = word.split_at(WORD_LENGTH - 8);
+  if high.iter().any(|byte| *byte != 0) {
+    return Err(Error::InvalidResponse);
+  }
+  let value = u64::from_be_bytes(low.try_into().unwrap());
+  usize::try_from(value).map_err(|_| Error::InvalidResponse)
+}
+
+fn decode_address(output: &[u8]) -> Result<[u8; 20], Error> {
+  let word = output.get(..WORD_LENGTH).ok_or(Error::InvalidResponse)?;
+  Ok(word[WORD_LENGTH - 20..].try_into().unwrap())
+}
+
+fn decode_string(output: &[u8]) -> Result<String, Error> {
+  let offset_word = output.get(..WORD_LENGTH).ok_or(Error::InvalidResponse)?;
+  let offset = decode_usize(offset_word)?;
+  // Offset and length come from the response, so they may overflow
+  let start = offset
+    .checked_add(WORD_LENGTH)
+    .ok_or(Error::InvalidResponse)?;
+  let length_word = output.get(offset..start).ok_or(Error::InvalidResponse)?;
+  let length = decode_usize(length_word)?;
+  let end = start.checked_add(length).ok_or(Error::InvalidResponse)?;
+  let bytes = output.get(start..end).ok_or(Error::InvalidResponse)?;
+  String::from_utf8(bytes.to_vec()).map_err(|_| Error::InvalidResponse)
+}
+
+#[cfg(test)]
+pub mod ethereum_tests {
+  use super::*;
+  use hyper::service::{make_service_fn, service_fn};
+  use hyper::{Body, Server};
+  use std::convert::Infallible;
+  use std::sync::Arc;
+
+  pub const WALLET: [u8; 20] = [0x11; 20];
+  const RESOLVER: [u8; 20] = [0x22; 20];
+
+  /// Handles `eth_call` requests with the given function, which receives the
+  /// `to` and `data` parameters and returns either the result or the error
+  pub type CallHandler =
+    Arc<dyn Fn(&str, &str) -> Result<String, Value> + Send + Sync>;
+
+  /// Starts a JSON-RPC server on a random port, returning its URL
+  pub async fn mock_rpc_server(handler: CallHandler) -> String {
+    let make_service = make_service_fn(move |_| {
+      let handler = handler.clone();
+      async move {
+        Ok::<_, Infallible>(service_fn(move |request| {
+          let handler = handler.clone();
+          async move {
+            let body = hyper::body::to_bytes(request.into_body()).await?;
+            let request: Value = serde_json::from_slice(&body).unwrap();
+            let call = &request["params"][0];
+            let response = match handler(
+              call["to"].as_str().unwrap(),
+              call["data"].as_str().unwrap(),
+            ) {
+              Ok(result) => {
+                json!({"jsonrpc": "2.0", "id": 1, "result": result})
+              }
+              Err(error) => json!({"jsonrpc": "2.0", "id": 1, "error": error}),
+            };
+            Ok::<_, hyper::Error>(hyper::Response::new(Body::from(
+              response.to_string(),
+            )))
+          }
+        }))
+      }
+    });
+    let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_service);
+    let url = format!("http://{}", server.local_addr());
+    tokio::spawn(server);
+    url
+  }
+
+  /// Mock wallet contract accepting only the signature `0xc0ffee`
+  pub fn wallet_contract(to: &str, data: &str) -> Result<String, Value> {
+    assert_eq!(to, to_hex(&WALLET));
+    if data.ends_with(&format!("03c0ffee{}", "00".repeat(29))) {
+      Ok(to_hex(&[&EIP_1271_MAGIC_VALUE[..], &[0; 28]].concat()))
+    } else {
+      Ok(to_hex(&[0; 32]))
+    }
+  }
+
+  fn encode_address(address: &[u8; 20]) -> String {
+    to_hex(&[&[0; 12][..], address].concat())
+  }
+
+  fn encode_string(value: &str) -> String {
+    let mut output = encode_usize(WORD_LENGTH).to_vec();
+    output.extend_from_slice(&encode_usize(value.len()));
+    output.extend_from_slice(value.as_bytes());
+    output.resize(pad_to_word(output.len()), 0);
+    to_hex(&output)
+  }
+
+  /// Mock ENS registry and resolver, where the reverse record of the wallet
+  /// is "wallet.eth", which resolves to `forward_address`
+  fn ens_handler(forward_address: [u8; 20]) -> CallHandler {
+    Arc::new(move |to, data| {
+      let data = hex::decode(&data[2..]).unwrap();
+      let (selector, node) = data.split_at(4);
+      let reverse_node =
+        namehash(&format!("{}.addr.reverse", hex::encode(WALLET)));
+      let node_is_known =
+        node == reverse_node || node == namehash("wallet.eth");
+
+      if to.eq_ignore_ascii_case(ENS_REGISTRY_ADDRESS) {
+        assert_eq!(selector, RESOLVER_SELECTOR);
+        let resolver = if node_is_known { RESOLVER } else { [0; 20] };
+        return Ok(encode_address(&resolver));
+      }
+      assert_eq!(to, to_hex(&RESOLVER));
+      match selector {
+        s if s == NAME_SELECTOR && node == reverse_node => {
+          Ok(encode_string("wallet.eth"))
+        }
+        s if s == ADDR_SELECTOR && node == namehash("wallet.eth") => {
+          Ok(encode_address(&forward_address))
+        }
+        _ => Err(json!({"code": 3, "message": "execution reverted"})),
+      }
+    })
+  }
+
+  #[test]
+  fn test_namehash() {
+    // Test vectors from EIP-137
+    assert_eq!(namehash(""), [0; 32]);
+    assert_eq!(
+      hex::encode(namehash("eth")),
+      "93cdeb708b7545dc668eb9280176169d1c33cfd8ed6f04690a0bcc88a93fc4ae"
+    );
+    assert_eq!(
+      hex::encode(namehash("foo.eth")),
+      "de9b09fd7c5f901e23a3f19fecc54828e9c848539801e86591bd9801b019f84f"
+    );
+  }
+
+  #[test]
+  fn test_decode_string() {
+    let output = hex::decode(&encode_string("wallet.eth")[2..]).unwrap();
+    assert_eq!(decode_string(&output).unwrap(), "wallet.eth");
+    assert!(decode_string(&output[..40]).is_err());
+
+    let mut overflowing_offset = output.clone();
+    overflowing_offset[24..32].copy_from_slice(&[0xff; 8]);
+    assert!(decode_string(&overflowing_offset).is_err());
+
+    let mut overflowing_length = output;
+    overflowing_length[56..64].copy_from_slice(&[0xff; 8]);
+    assert!(decode_string(&overflowing_length).is_err());
+  }
+
+  #[tokio::test]
+  async fn test_contract_signature() {
+    let url = mock_rpc_server(Arc::new(wallet_contract)).await;
+    let client = EthereumClient::new(url).unwrap();
+
+    let hash = [0xab; 32];
+    assert!(client
+      .is_valid_contract_signature(&WALLET, &hash, &[0xc0, 0xff, 0xee])
+      .await
+      .unwrap());
+    assert!(!client
+      .is_valid_contract_signature(&WALLET, &hash, &[0xde, 0xad])
+      .await
+      .unwrap());
+  }
+
+  #[tokio::test]
+  async fn test_reverted_contract_signature() {
+    let url = mock_rpc_server(Arc::new(|_, _| {
+      Err(json!({"code": 3, "message": "execution reverted"}))
+    }))
+    .await;
+    let client = EthereumClient::new(url).unwrap();
+
+    assert!(!client
+      .is_valid_contract_signature(&WALLET, &[0; 32], &[0; 65])
+      .await
+      .unwrap());
+  }
+
+  #[tokio::test]
+  async fn test_lookup_ens_name() {
+    let url = mock_rpc_server(ens_handler(WALLET)).await;
+    let client = EthereumClient::new(url).unwrap();
+    assert_eq!(
+      client.lookup_ens_name(&WALLET).await.unwrap(),
+      Some("wallet.eth".to_string())
+    );
+    // No reverse record
+    assert_eq!(client.lookup_ens_name(&[0x33; 20]).await.unwrap(), None);
+
+    // The name resolves to a different address
+    let url = mock_rpc_server(ens_handler([0x44; 20])).await;
+    let client = EthereumClient::new(url).unwrap();
+    assert_eq!(client.lookup_ens_name(&WALLET).await.unwrap(), None);
+  }
+}
@@ -0,0 +1,260 @@
+//! Authentication of requests to the authenticated gRPC service. Requests
+//! carry `user_id`, `device_id` and `access_token` metadata, which are
+//! verified against the access token table before the request reaches the
+//! service. The verified identity is then available to handlers through
+//! request extensions, see [`AuthenticatedDevice`].
+
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use moka::future::Cache;
+use tonic::body::BoxBody;
+use tonic::codegen::{http, BoxFuture, Service};
+use tonic::server::NamedService;
+use tonic::{Request, Status};
+use tower::Layer;
+use tracing::{debug, error};
+
+use crate::client_service::handle_db_error;
+use crate::constants::{AUTH_CACHE_MAX_CAPACITY, AUTH_CACHE_TTL_SECONDS};
+use crate::database::DatabaseClient;
+
+/// Identity of the device making an authenticated request
+#[derive(Clone, Debug, PartialEq)]
+pub struct AuthenticatedDevice {
+  pub user_id: String,
+  pub device_id: String,
+}
+
+impl AuthenticatedDevice {
+  /// Fails only if the request didn't go through [`AuthLayer`]
+  pub fn from_request<T>(request: &Request<T>) -> Result<Self, Status> {
+    request
+      .extensions()
+      .get::<AuthenticatedDevice>()
+      .cloned()
+      .ok_or_else(|| Status::unauthenticated("Missing credentials"))
+  }
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+struct Credentials {
+  user_id: String,
+  device_id: String,
+  access_token: String,
+}
+
+fn get_header_value(headers: &http::HeaderMap, key: &str) -> Option<String> {
+  let raw_value = headers.get(key)?;
+  raw_value.to_str().ok().map(|s| s.to_string())
+}
+
+fn get_credentials(headers: &http::HeaderMap) -> Option<Credentials> {
+  Some(Credentials {
+    user_id: get_header_value(headers, "user_id")?,
+    device_id: get_header_value(headers, "device_id")?,
+    access_token: get_header_value(headers, "access_token")?,
+  })
+}
+
+/// Valid credentials, shared with the services so that they can invalidate
+/// credentials of revoked tokens. Other replicas keep accepting them until
+/// their cache entries expire.
+#[derive(Clone)]
+pub struct AuthCache(Cache<Credentials, ()>);
+
+impl AuthCache {
+  pub fn new() -> Self {
+    let cache = Cache::builder()
+      .max_capacity(AUTH_CACHE_MAX_CAPACITY)
+      .time_to_live(Duration::from_secs(AUTH_CACHE_TTL_SECONDS))
+      .support_invalidation_closures()
+      .build();
+    Self(cache)
+  }
+
+  /// Invalidates credentials of the device, or of all devices of the user if
+  /// `device_id` is not provided
+  pub fn invalidate(&self, user_id: &str, device_id: Option<&str>) {
+    let user_id = user_id.to_string();
+    let device_id = device_id.map(str::to_string);
+    let result = self.0.invalidate_entries_if(move |credentials, _| {
+      credentials.user_id == user_id
+        && device_id
+          .as_ref()
+          .map_or(true, |device_id| &credentials.device_id == device_id)
+    });
+    if let Err(e) = result {
+      error!("Unable to invalidate cached credentials: {:?}", e);
+    }
+  }
+}
+
+/// Only valid credentials are cached, see [`AuthCache`]
+#[derive(Clone)]
+pub struct AuthLayer {
+  db_client: DatabaseClient,
+  cache: AuthCache,
+}
+
+impl AuthLayer {
+  pub fn new(db_client: DatabaseClient, cache: AuthCache) -> Self {
+    Self { db_client, cache }
+  }
+
+  async fn authenticate(
+    &self,
+    headers: &http::HeaderMap,
+  ) -> Result<AuthenticatedDevice, Status> {
+    let credentials = get_credentials(headers)
+      .ok_or_else(|| Status::unauthenticated("Missing credentials"))?;
+    let device = AuthenticatedDevice {
+      user_id: credentials.user_id.clone(),
+      device_id: credentials.device_id.clone(),
+    };
+
+    if self.cache.0.get(&credentials).is_some() {
+      return Ok(device);
+    }
+
+    debug!("Verifying access token of device {}", credentials.device_id);
+    let valid_token = self
+      .db_client
+      .verify_access_token(
+        credentials.user_id.clone(),
+        credentials.device_id.clone(),
+        credentials.access_token.clone(),
+      )
+      .await
+      .map_err(handle_db_error)?;
+    if !valid_token {
+      return Err(Status::aborted("Bad Credentials"));
+    }
+
+    self.cache.0.insert(credentials, ()).await;
+    Ok(device)
+  }
+}
+
+impl<S> Layer<S> for AuthLayer {
+  type Service = AuthService<S>;
+
+  fn layer(&self, inner: S) -> Self::Service {
+    AuthService {
+      inner,
+      layer: self.clone(),
+    }
+  }
+}
+
+#[derive(Clone)]
+pub struct AuthService<S> {
+  inner: S,
+  layer: AuthLayer,
+}
+
+impl<S, B> Service<http::Request<B>> for AuthService<S>
+where
+  S: Service<http::Request<B>, Response = http::Response<BoxBody>>
+    + Clone
+    + Send
+    + 'static,
+  S::Future: Send,
+  B: Send + 'static,
+{
+  type Response = S::Response;
+  type Error = S::Error;
+  type Future = BoxFuture<Self::Response, Self::Error>;
+
+  fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), S::Error>> {
+    self.inner.poll_ready(cx)
+  }
+
+  fn call(&mut self, mut request: http::Request<B>) -> Self::Future {
+    // The service which was polled ready is used for this request, and the
+    // clone is left for the next one
+    let clone = self.inner.clone();
+    let mut inner = std::mem::replace(&mut self.inner, clone);
+    let layer = self.layer.clone();
+
+    Box::pin(async move {
+      match layer.authenticate(request.headers()).await {
+        Ok(device) => {
+          request.extensions_mut().insert(device);
+          inner.call(request).await
+        }
+        Err(status) => Ok(status.to_http()),
+      }
+    })
+  }
+}
+
+impl<S: NamedService> NamedService for AuthService<S> {
+  const NAME: &'static str = S::NAME;
+}
+
+#[cfg(test)]
+mod auth_layer_tests {
+  use super::*;
+
+  #[test]
+  fn test_get_credentials() {
+    let mut headers = http::HeaderMap::new();
+    headers.insert("user_id", "user".parse().unwrap());
+    headers.insert("device_id", "device".parse().unwrap());
+    assert_eq!(get_credentials(&headers), None);
+
+    headers.insert("access_token", "token".parse().unwrap());
+    assert_eq!(
+      get_credentials(&headers),
+      Some(Credentials {
+        user_id: "user".to_string(),
+        device_id: "device".to_string(),
+        access_token: "token".to_string(),
+      })
+    );
+  }
+
+  #[test]
+  fn test_authenticated_device_from_extensions() {
+    let mut request = Request::new(());
+    assert_eq!(
+      AuthenticatedDevice::from_request(&request)
+        .unwrap_err()
+        .code(),
+      tonic::Code::Unauthenticated
+    );
+
+    let device = AuthenticatedDevice {
+      user_id: "user".to_string(),
+      device_id: "device".to_string(),
+    };
+    request.extensions_mut().insert(device.clone());
+    assert_eq!(AuthenticatedDevice::from_request(&request).unwrap(), device);
+  }
+
+  #[tokio::test]
+  async fn test_cache_invalidation() {
+    let credentials = |user_id: &str, device_id: &str| Credentials {
+      user_id: user_id.to_string(),
+      device_id: device_id.to_string(),
+      access_token: "token".to_string(),
+    };
+    let cache = AuthCache::new();
+    for (user_id, device_id) in [
+      ("user", "device1"),
+      ("user", "device2"),
+      ("other", "device1"),
+    ] {
+      cache.0.insert(credentials(user_id, device_id), ()).await;
+    }
+
+    cache.invalidate("user", Some("device1"));
+    assert!(cache.0.get(&credentials("user", "device1")).is_none());
+    assert!(cache.0.get(&credentials("user", "device2")).is_some());
+
+    cache.invalidate("user", None);
+    assert!(cache.0.get(&credentials("user", "device2")).is_none());
+    assert!(cache.0.get(&credentials("other", "device1")).is_some());
+  }
+}
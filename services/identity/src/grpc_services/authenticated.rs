@@ -1,6 +1,41 @@
+use std::str::FromStr;
+use std::sync::Arc;
+
+use chrono::Utc;
+use comm_opaque2::grpc::protocol_error_to_grpc_status;
+use comm_services_lib::auth::{AuthService, UserIdentity};
+use comm_services_lib::blob::client::BlobServiceClient;
+
 use crate::{
-  client_service::handle_db_error, database::DatabaseClient,
-  grpc_services::shared::get_value,
+  account_export::{export_account_data, upload_export},
+  audit_log::{
+    record_audit_event, AuditEvent, AuditEventType, AuditOutcome, AuditSubject,
+    RequestMetadata,
+  },
+  client_service::{handle_db_error, UsernameChangeState, WorkflowInProgress},
+  config::CONFIG,
+  constants::{
+    ACCOUNT_EXPORT_MAX_INLINE_BYTES, AUDIT_LOG_DEFAULT_PAGE_SIZE,
+    AUDIT_LOG_MAX_PAGE_SIZE,
+  },
+  database::{DatabaseClient, DeviceType},
+  ddb_utils::OlmAccountType,
+  error::Error as DBError,
+  grpc_services::{
+    auth_layer::{AuthCache, AuthenticatedDevice},
+    shared::get_value,
+  },
+  login_attempts::{ensure_login_allowed, record_failed_login, AttemptKey},
+  reserved_users::validate_account_ownership_message_and_get_user_id,
+  signature::verify_prekey_signatures,
+  siwe::is_valid_ethereum_address,
+  token::AuthType,
+  totp::{
+    decrypt_secret, encrypt_secret, generate_recovery_codes, generate_secret,
+    hash_recovery_code, otpauth_uri, verify_second_factor, verify_totp_code,
+  },
+  tunnelbroker::{invalidate_access_tokens, purge_device_queue},
+  workflow_store::WorkflowStore,
 };
 use tonic::{Request, Response, Status};
 
@@ -14,8 +49,14 @@ pub mod auth_proto {
   tonic::include_proto!("identity.authenticated");
 }
 use auth_proto::{
-  identity_client_service_server::IdentityClientService, KeyserverKeysResponse,
-  OutboundKeyInfo, OutboundKeysForUserRequest, RefreshUserPreKeysRequest,
+  identity_client_service_server::IdentityClientService,
+  ChangeUsernameFinishRequest, ChangeUsernameStartRequest,
+  ChangeUsernameStartResponse, ConfirmTotpRequest, ConfirmTotpResponse,
+  DeviceInfo, DeviceListResponse, DeviceOneTimeKeyCounts, DisableTotpRequest,
+  EnrollTotpResponse, ExportAccountDataRequest, ExportAccountDataResponse,
+  ExportBlobInfo, GetAuditLogRequest, GetAuditLogResponse,
+  KeyserverKeysResponse, OneTimeKeyCountsResponse, OutboundKeyInfo,
+  OutboundKeysForUserRequest, RefreshUserPreKeysRequest, RemoveDeviceRequest,
   UploadOneTimeKeysRequest,
 };
 use client::{Empty, IdentityKeyInfo};
@@ -24,58 +65,81 @@ use tracing::debug;
 #[derive(derive_more::Constructor)]
 pub struct AuthenticatedService {
   db_client: DatabaseClient,
+  workflow_store: Arc<dyn WorkflowStore>,
+  blob_client: BlobServiceClient,
+  auth_service: AuthService,
+  auth_cache: AuthCache,
 }
 
-fn get_auth_info(req: &Request<()>) -> Option<(String, String, String)> {
-  debug!("Retrieving auth info for request: {:?}", req);
-
-  let user_id = get_value(req, "user_id")?;
-  let device_id = get_value(req, "device_id")?;
-  let access_token = get_value(req, "access_token")?;
-
-  Some((user_id, device_id, access_token))
-}
-
-pub fn auth_interceptor(
-  req: Request<()>,
-  db_client: &DatabaseClient,
-) -> Result<Request<()>, Status> {
-  debug!("Intercepting request to check auth info: {:?}", req);
-
-  let (user_id, device_id, access_token) = get_auth_info(&req)
-    .ok_or_else(|| Status::unauthenticated("Missing credentials"))?;
+impl AuthenticatedService {
+  /// Usernames reserved on Ashoat's keyserver can be taken only by their
+  /// owner, who proves ownership with a keyserver-signed message. Usernames
+  /// held after a username change can be taken back by their previous owner.
+  /// Returns `true` if ownership was proven with a keyserver-signed message.
+  async fn ensure_username_available(
+    &self,
+    user_id: &str,
+    username: &str,
+    keyserver_message: Option<(&str, &str)>,
+  ) -> Result<bool, Status> {
+    if CONFIG.reserved_usernames.contains(username)
+      || is_valid_ethereum_address(username)
+    {
+      return Err(Status::invalid_argument("username reserved"));
+    }
 
-  let handle = tokio::runtime::Handle::current();
-  let new_db_client = db_client.clone();
+    let username_taken = self
+      .db_client
+      .username_taken(username.to_string())
+      .await
+      .map_err(handle_db_error)?;
+    if username_taken {
+      return Err(Status::already_exists("username already exists"));
+    }
 
-  // This function cannot be `async`, yet must call the async db call
-  // Force tokio to resolve future in current thread without an explicit .await
-  let valid_token = tokio::task::block_in_place(move || {
-    handle
-      .block_on(new_db_client.verify_access_token(
-        user_id,
-        device_id,
-        access_token,
-      ))
-      .map_err(handle_db_error)
-  })?;
+    let Some(reservation) = self
+      .db_client
+      .get_username_reservation(username)
+      .await
+      .map_err(handle_db_error)?
+    else {
+      return Ok(false);
+    };
+    if reservation.user_id.as_deref() == Some(user_id) {
+      return Ok(false);
+    }
 
-  if !valid_token {
-    return Err(Status::aborted("Bad Credentials"));
+    let (message, signature) = keyserver_message
+      .ok_or_else(|| Status::already_exists("username already exists"))?;
+    let owner_user_id = validate_account_ownership_message_and_get_user_id(
+      username, message, signature,
+    )?;
+    if owner_user_id != user_id {
+      return Err(Status::permission_denied("username reserved"));
+    }
+    Ok(true)
   }
 
-  Ok(req)
-}
-
-pub fn get_user_and_device_id<T>(
-  request: &Request<T>,
-) -> Result<(String, String), Status> {
-  let user_id = get_value(request, "user_id")
-    .ok_or_else(|| Status::unauthenticated("Missing user_id field"))?;
-  let device_id = get_value(request, "device_id")
-    .ok_or_else(|| Status::unauthenticated("Missing device_id field"))?;
-
-  Ok((user_id, device_id))
+  /// Records an event performed by the authenticated device
+  async fn record_device_event(
+    &self,
+    user_id: &str,
+    device_id: &str,
+    event_type: AuditEventType,
+    outcome: AuditOutcome,
+    request_metadata: &RequestMetadata,
+  ) {
+    let event = AuditEvent {
+      device_id: Some(device_id.to_string()),
+      ..AuditEvent::new(event_type, outcome, request_metadata)
+    };
+    record_audit_event(
+      &self.db_client,
+      AuditSubject::User(user_id.to_string()),
+      event,
+    )
+    .await;
+  }
 }
 
 #[tonic::async_trait]
@@ -84,7 +148,8 @@ impl IdentityClientService for AuthenticatedService {
     &self,
     request: Request<RefreshUserPreKeysRequest>,
   ) -> Result<Response<Empty>, Status> {
-    let (user_id, device_id) = get_user_and_device_id(&request)?;
+    let AuthenticatedDevice { user_id, device_id } =
+      AuthenticatedDevice::from_request(&request)?;
     let message = request.into_inner();
 
     debug!("Refreshing prekeys for user: {}", user_id);
@@ -96,6 +161,25 @@ impl IdentityClientService for AuthenticatedService {
       .new_notif_pre_keys
       .ok_or_else(|| Status::invalid_argument("Missing notification keys"))?;
 
+    let device = self
+      .db_client
+      .get_user_devices(&user_id)
+      .await
+      .map_err(handle_db_error)?
+      .and_then(|devices| {
+        devices
+          .into_iter()
+          .find(|device| device.device_id == device_id)
+      })
+      .ok_or_else(|| Status::not_found("device not found"))?;
+    verify_prekey_signatures(
+      &device.key_payload,
+      &content_keys.pre_key,
+      &content_keys.pre_key_signature,
+      &notif_keys.pre_key,
+      &notif_keys.pre_key_signature,
+    )?;
+
     self
       .db_client
       .set_prekey(
@@ -153,7 +237,8 @@ impl IdentityClientService for AuthenticatedService {
     &self,
     request: tonic::Request<UploadOneTimeKeysRequest>,
   ) -> Result<tonic::Response<Empty>, tonic::Status> {
-    let (user_id, device_id) = get_user_and_device_id(&request)?;
+    let AuthenticatedDevice { user_id, device_id } =
+      AuthenticatedDevice::from_request(&request)?;
     let message = request.into_inner();
 
     debug!("Attempting to update one time keys for user: {}", user_id);
@@ -169,4 +254,574 @@ impl IdentityClientService for AuthenticatedService {
 
     Ok(tonic::Response::new(Empty {}))
   }
+
+  async fn get_one_time_key_counts(
+    &self,
+    request: Request<Empty>,
+  ) -> Result<Response<OneTimeKeyCountsResponse>, Status> {
+    let AuthenticatedDevice { user_id, .. } =
+      AuthenticatedDevice::from_request(&request)?;
+
+    let user_devices = self
+      .db_client
+      .get_user_devices(&user_id)
+      .await
+      .map_err(handle_db_error)?
+      .ok_or_else(|| Status::not_found("user not found"))?;
+
+    let mut devices = Vec::with_capacity(user_devices.len());
+    for device in user_devices {
+      let content_one_time_keys = self
+        .db_client
+        .get_one_time_key_count(&device.device_id, OlmAccountType::Content)
+        .await
+        .map_err(handle_db_error)?;
+      let notif_one_time_keys = self
+        .db_client
+        .get_one_time_key_count(&device.device_id, OlmAccountType::Notification)
+        .await
+        .map_err(handle_db_error)?;
+      devices.push(DeviceOneTimeKeyCounts {
+        device_id: device.device_id,
+        content_one_time_keys,
+        notif_one_time_keys,
+      });
+    }
+
+    Ok(Response::new(OneTimeKeyCountsResponse {
+      devices,
+      minimum_threshold: CONFIG.one_time_key_minimum_threshold as u32,
+    }))
+  }
+
+  async fn get_device_list(
+    &self,
+    request: Request<Empty>,
+  ) -> Result<Response<DeviceListResponse>, Status> {
+    let AuthenticatedDevice { user_id, .. } =
+      AuthenticatedDevice::from_request(&request)?;
+
+    let devices = self
+      .db_client
+      .get_user_devices(&user_id)
+      .await
+      .map_err(handle_db_error)?
+      .ok_or_else(|| Status::not_found("user not found"))?
+      .into_iter()
+      .map(|device| {
+        let device_type = DeviceType::from_str(&device.device_type)
+          .map_err(|_| Status::failed_precondition("unexpected error"))?;
+        Ok(DeviceInfo {
+          device_id: device.device_id,
+          device_type: device_type.into(),
+          key_payload: device.key_payload,
+          key_payload_signature: device.key_payload_signature,
+          last_login: device.last_login.map(|time| time.to_rfc3339()),
+          code_version: device.code_version,
+        })
+      })
+      .collect::<Result<_, Status>>()?;
+
+    Ok(Response::new(DeviceListResponse { devices }))
+  }
+
+  async fn remove_device(
+    &self,
+    request: Request<RemoveDeviceRequest>,
+  ) -> Result<Response<Empty>, Status> {
+    let AuthenticatedDevice { user_id, device_id } =
+      AuthenticatedDevice::from_request(&request)?;
+    let request_metadata = RequestMetadata::from_request(&request);
+    let device_to_remove = request.into_inner().device_id;
+
+    if device_to_remove == device_id {
+      return Err(Status::invalid_argument(
+        "calling device should log out instead",
+      ));
+    }
+
+    let is_user_device = self
+      .db_client
+      .get_user_devices(&user_id)
+      .await
+      .map_err(handle_db_error)?
+      .unwrap_or_default()
+      .iter()
+      .any(|device| device.device_id == device_to_remove);
+    if !is_user_device {
+      return Err(Status::not_found("device not found"));
+    }
+
+    debug!("Removing device {} of user {}", device_to_remove, user_id);
+    // The device is removed from the device list last, so that if any step
+    // fails, the request can be retried and clean up the rest
+    let map_error = |e: DBError| match e {
+      DBError::Status(status) => status,
+      e => handle_db_error(e),
+    };
+    self
+      .db_client
+      .delete_access_token_data(user_id.clone(), device_to_remove.clone())
+      .await
+      .map_err(map_error)?;
+    self
+      .auth_cache
+      .invalidate(&user_id, Some(&device_to_remove));
+    invalidate_access_tokens(
+      &self.auth_service,
+      &user_id,
+      Some(&device_to_remove),
+    )
+    .await
+    .map_err(map_error)?;
+    self
+      .db_client
+      .delete_one_time_keys(&device_to_remove)
+      .await
+      .map_err(map_error)?;
+    purge_device_queue(&self.auth_service, &device_to_remove)
+      .await
+      .map_err(map_error)?;
+    self
+      .db_client
+      .remove_device_from_users_table(user_id.clone(), device_to_remove.clone())
+      .await
+      .map_err(map_error)?;
+
+    self
+      .record_device_event(
+        &user_id,
+        &device_id,
+        AuditEventType::DeviceRemoval,
+        AuditOutcome::Success,
+        &request_metadata,
+      )
+      .await;
+
+    Ok(Response::new(Empty {}))
+  }
+
+  async fn change_username_start(
+    &self,
+    request: Request<ChangeUsernameStartRequest>,
+  ) -> Result<Response<ChangeUsernameStartResponse>, Status> {
+    let AuthenticatedDevice { user_id, .. } =
+      AuthenticatedDevice::from_request(&request)?;
+    let message = request.into_inner();
+
+    let old_username = self
+      .db_client
+      .get_username(&user_id)
+      .await
+      .map_err(handle_db_error)?
+      .ok_or_else(|| Status::failed_precondition("user has no username"))?;
+    if message.new_username == old_username {
+      return Err(Status::invalid_argument("username unchanged"));
+    }
+
+    let keyserver_message = message
+      .keyserver_message
+      .as_deref()
+      .zip(message.keyserver_signature.as_deref());
+    let keyserver_ownership_proven = self
+      .ensure_username_available(
+        &user_id,
+        &message.new_username,
+        keyserver_message,
+      )
+      .await?;
+
+    let server_registration = comm_opaque2::server::Registration::new();
+    let server_message = server_registration
+      .start(
+        &CONFIG.server_setup,
+        &message.opaque_registration_request,
+        message.new_username.as_bytes(),
+      )
+      .map_err(protocol_error_to_grpc_status)?;
+
+    let username_change_state = UsernameChangeState {
+      user_id,
+      old_username,
+      new_username: message.new_username,
+      keyserver_ownership_proven,
+    };
+    let session_id = self
+      .workflow_store
+      .insert(WorkflowInProgress::UsernameChange(username_change_state))
+      .await
+      .map_err(handle_db_error)?;
+
+    Ok(Response::new(ChangeUsernameStartResponse {
+      session_id,
+      opaque_registration_response: server_message,
+    }))
+  }
+
+  async fn change_username_finish(
+    &self,
+    request: Request<ChangeUsernameFinishRequest>,
+  ) -> Result<Response<Empty>, Status> {
+    let AuthenticatedDevice { user_id, device_id } =
+      AuthenticatedDevice::from_request(&request)?;
+    let request_metadata = RequestMetadata::from_request(&request);
+    let message = request.into_inner();
+
+    let Some(WorkflowInProgress::UsernameChange(state)) = self
+      .workflow_store
+      .take(&message.session_id)
+      .await
+      .map_err(handle_db_error)?
+    else {
+      return Err(Status::not_found("session not found"));
+    };
+    if state.user_id != user_id {
+      return Err(Status::not_found("session not found"));
+    }
+
+    let server_registration = comm_opaque2::server::Registration::new();
+    let password_file = server_registration
+      .finish(&message.opaque_registration_upload)
+      .map_err(protocol_error_to_grpc_status)?;
+
+    // The username could have been registered since the workflow started
+    let username_taken = self
+      .db_client
+      .username_taken(state.new_username.clone())
+      .await
+      .map_err(handle_db_error)?;
+    if username_taken {
+      return Err(Status::already_exists("username already exists"));
+    }
+
+    let old_username_held_until = CONFIG
+      .username_grace_period
+      .map(|grace_period| Utc::now() + grace_period);
+    let changed = self
+      .db_client
+      .change_username(
+        &user_id,
+        &state.old_username,
+        &state.new_username,
+        password_file,
+        old_username_held_until,
+        state.keyserver_ownership_proven,
+      )
+      .await
+      .map_err(handle_db_error)?;
+    if !changed {
+      return Err(Status::already_exists("username already exists"));
+    }
+
+    debug!(
+      "Changed username of user {} from {} to {}",
+      user_id, state.old_username, state.new_username
+    );
+    self
+      .record_device_event(
+        &user_id,
+        &device_id,
+        AuditEventType::UsernameChange,
+        AuditOutcome::Success,
+        &request_metadata,
+      )
+      .await;
+    Ok(Response::new(Empty {}))
+  }
+
+  async fn enroll_totp(
+    &self,
+    request: Request<Empty>,
+  ) -> Result<Response<EnrollTotpResponse>, Status> {
+    let AuthenticatedDevice { user_id, .. } =
+      AuthenticatedDevice::from_request(&request)?;
+
+    // Only password users log in with credentials that a second factor
+    // could protect
+    let username = self
+      .db_client
+      .get_username(&user_id)
+      .await
+      .map_err(handle_db_error)?
+      .ok_or_else(|| Status::failed_precondition("user has no username"))?;
+
+    let secret = generate_secret();
+    let enrolled = self
+      .db_client
+      .set_pending_totp_secret(&user_id, encrypt_secret(&secret)?)
+      .await
+      .map_err(handle_db_error)?;
+    if !enrolled {
+      return Err(Status::already_exists("two-factor authentication enabled"));
+    }
+
+    Ok(Response::new(EnrollTotpResponse {
+      otpauth_uri: otpauth_uri(&secret, &username),
+      secret: data_encoding::BASE32_NOPAD.encode(&secret),
+    }))
+  }
+
+  async fn confirm_totp(
+    &self,
+    request: Request<ConfirmTotpRequest>,
+  ) -> Result<Response<ConfirmTotpResponse>, Status> {
+    let AuthenticatedDevice { user_id, device_id } =
+      AuthenticatedDevice::from_request(&request)?;
+    let request_metadata = RequestMetadata::from_request(&request);
+    let message = request.into_inner();
+
+    let totp_state = self
+      .db_client
+      .get_totp_state(&user_id)
+      .await
+      .map_err(handle_db_error)?
+      .filter(|totp_state| !totp_state.confirmed)
+      .ok_or_else(|| Status::failed_precondition("no pending enrollment"))?;
+
+    let secret = decrypt_secret(&totp_state.encrypted_secret)?;
+    let Some(step) = verify_totp_code(&secret, &message.code, Utc::now())
+    else {
+      return Err(Status::permission_denied("invalid code"));
+    };
+
+    let recovery_codes = generate_recovery_codes();
+    let recovery_code_hashes = recovery_codes
+      .iter()
+      .map(|code| hash_recovery_code(code))
+      .collect();
+    // Fails if the enrollment was restarted or confirmed in the meantime
+    let confirmed = self
+      .db_client
+      .confirm_totp(
+        &user_id,
+        totp_state.encrypted_secret,
+        recovery_code_hashes,
+        step,
+      )
+      .await
+      .map_err(handle_db_error)?;
+    if !confirmed {
+      return Err(Status::aborted("enrollment changed"));
+    }
+
+    debug!("Enabled two-factor authentication for user {}", user_id);
+    self
+      .record_device_event(
+        &user_id,
+        &device_id,
+        AuditEventType::TwoFactorEnabled,
+        AuditOutcome::Success,
+        &request_metadata,
+      )
+      .await;
+    Ok(Response::new(ConfirmTotpResponse { recovery_codes }))
+  }
+
+  async fn disable_totp(
+    &self,
+    request: Request<DisableTotpRequest>,
+  ) -> Result<Response<Empty>, Status> {
+    let AuthenticatedDevice { user_id, device_id } =
+      AuthenticatedDevice::from_request(&request)?;
+    let request_metadata = RequestMetadata::from_request(&request);
+    let message = request.into_inner();
+
+    let totp_state = self
+      .db_client
+      .get_totp_state(&user_id)
+      .await
+      .map_err(handle_db_error)?
+      .filter(|totp_state| totp_state.confirmed)
+      .ok_or_else(|| {
+        Status::failed_precondition("two-factor authentication disabled")
+      })?;
+
+    // Codes are limited like second factors of logins, so that a stolen
+    // device can't guess them
+    let username = self
+      .db_client
+      .get_username(&user_id)
+      .await
+      .map_err(handle_db_error)?
+      .ok_or_else(|| Status::failed_precondition("user has no username"))?;
+    let mut attempt_keys = vec![
+      AttemptKey::Username(username),
+      AttemptKey::Device(device_id.clone()),
+    ];
+    attempt_keys
+      .extend(request_metadata.ip_address.clone().map(AttemptKey::Ip));
+    ensure_login_allowed(&self.db_client, &attempt_keys).await?;
+
+    let code_valid = verify_second_factor(
+      &self.db_client,
+      &user_id,
+      &totp_state,
+      &message.code,
+    )
+    .await?;
+    if !code_valid {
+      // Someone with access to a device tried to disable the second factor
+      record_failed_login(&self.db_client, &attempt_keys).await;
+      self
+        .record_device_event(
+          &user_id,
+          &device_id,
+          AuditEventType::TwoFactorDisabled,
+          AuditOutcome::Failure,
+          &request_metadata,
+        )
+        .await;
+      return Err(Status::permission_denied("invalid code"));
+    }
+
+    self
+      .db_client
+      .delete_totp(&user_id)
+      .await
+      .map_err(handle_db_error)?;
+
+    debug!("Disabled two-factor authentication for user {}", user_id);
+    self
+      .record_device_event(
+        &user_id,
+        &device_id,
+        AuditEventType::TwoFactorDisabled,
+        AuditOutcome::Success,
+        &request_metadata,
+      )
+      .await;
+    Ok(Response::new(Empty {}))
+  }
+
+  async fn get_audit_log(
+    &self,
+    request: Request<GetAuditLogRequest>,
+  ) -> Result<Response<GetAuditLogResponse>, Status> {
+    let AuthenticatedDevice { user_id, .. } =
+      AuthenticatedDevice::from_request(&request)?;
+    let message = request.into_inner();
+
+    let limit = message
+      .limit
+      .unwrap_or(AUDIT_LOG_DEFAULT_PAGE_SIZE)
+      .clamp(1, AUDIT_LOG_MAX_PAGE_SIZE);
+    let (events, next_page_token) = self
+      .db_client
+      .get_audit_events(
+        &AuditSubject::User(user_id).to_string(),
+        limit as i32,
+        message.page_token,
+      )
+      .await
+      .map_err(handle_db_error)?;
+
+    let events = events
+      .into_iter()
+      .filter_map(audit_event_to_proto)
+      .collect();
+    Ok(Response::new(GetAuditLogResponse {
+      events,
+      next_page_token,
+    }))
+  }
+
+  async fn export_account_data(
+    &self,
+    request: Request<ExportAccountDataRequest>,
+  ) -> Result<Response<ExportAccountDataResponse>, Status> {
+    use auth_proto::export_account_data_response::Export;
+
+    let AuthenticatedDevice { user_id, device_id } =
+      AuthenticatedDevice::from_request(&request)?;
+    let request_metadata = RequestMetadata::from_request(&request);
+    // Verified by the auth layer
+    let access_token = get_value(&request, "access_token")
+      .ok_or_else(|| Status::unauthenticated("Missing credentials"))?;
+    let message = request.into_inner();
+
+    let export = export_account_data(&self.db_client, &user_id)
+      .await
+      .map_err(handle_db_error)?
+      .ok_or_else(|| Status::not_found("user not found"))?;
+    let export_json =
+      serde_json::to_string(&export).map_err(|e| handle_db_error(e.into()))?;
+
+    let response_export = if message.upload_to_blob
+      || export_json.len() > ACCOUNT_EXPORT_MAX_INLINE_BYTES
+    {
+      let user_identity = UserIdentity {
+        user_id: user_id.clone(),
+        device_id: device_id.clone(),
+        access_token,
+      };
+      let export_blob = upload_export(
+        &self.db_client,
+        &self.blob_client,
+        user_identity,
+        export_json,
+      )
+      .await
+      .map_err(|e| match e {
+        DBError::Status(status) => status,
+        e => handle_db_error(e),
+      })?;
+      Export::BlobInfo(ExportBlobInfo {
+        blob_hash: export_blob.blob_hash,
+        holder: export_blob.holder,
+      })
+    } else {
+      Export::ExportJson(export_json)
+    };
+
+    self
+      .record_device_event(
+        &user_id,
+        &device_id,
+        AuditEventType::DataExport,
+        AuditOutcome::Success,
+        &request_metadata,
+      )
+      .await;
+    Ok(Response::new(ExportAccountDataResponse {
+      export: Some(response_export),
+    }))
+  }
+}
+
+/// Returns `None` for events which aren't logged for users
+fn audit_event_to_proto(event: AuditEvent) -> Option<auth_proto::AuditEvent> {
+  use auth_proto::{AuditEventOutcome, AuditEventType as ProtoEventType};
+
+  let event_type = match event.event_type {
+    AuditEventType::Registration => ProtoEventType::Registration,
+    AuditEventType::Login => ProtoEventType::Login,
+    AuditEventType::PasswordUpdate => ProtoEventType::PasswordUpdate,
+    AuditEventType::Logout => ProtoEventType::Logout,
+    AuditEventType::DeviceRemoval => ProtoEventType::DeviceRemoval,
+    AuditEventType::UsernameChange => ProtoEventType::UsernameChange,
+    AuditEventType::TwoFactorEnabled => ProtoEventType::TwoFactorEnabled,
+    AuditEventType::TwoFactorDisabled => ProtoEventType::TwoFactorDisabled,
+    AuditEventType::UserDeletion => ProtoEventType::UserDeletion,
+    AuditEventType::DataExport => ProtoEventType::DataExport,
+    AuditEventType::ReservedUsernameAdded
+    | AuditEventType::ReservedUsernameRemoved => return None,
+  };
+  let outcome = match event.outcome {
+    AuditOutcome::Success => AuditEventOutcome::Success,
+    AuditOutcome::Failure => AuditEventOutcome::Failure,
+  };
+  let auth_type = event.auth_type.map(|auth_type| match auth_type {
+    AuthType::Password => "password".to_string(),
+    AuthType::Wallet => "wallet".to_string(),
+  });
+
+  Some(auth_proto::AuditEvent {
+    event_type: event_type.into(),
+    outcome: outcome.into(),
+    timestamp: event.timestamp.to_rfc3339(),
+    device_id: event.device_id,
+    auth_type,
+    ip_address: event.ip_address,
+    user_agent: event.user_agent,
+  })
 }
@@ -1,2 +1,3 @@
+pub mod auth_layer;
 pub mod authenticated;
 pub mod shared;
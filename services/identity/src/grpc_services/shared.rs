@@ -2,6 +2,7 @@ use grpc_clients::error::unsupported_version;
 use tonic::{Request, Status};
 use tracing::debug;
 
+use crate::config::CONFIG;
 use crate::constants::MIN_SUPPORTED_NATIVE_VERSION;
 
 pub fn version_interceptor(req: Request<()>) -> Result<Request<()>, Status> {
@@ -21,13 +22,56 @@ pub fn version_interceptor(req: Request<()>) -> Result<Request<()>, Status> {
 fn get_version_info(req: &Request<()>) -> Option<(u64, String)> {
   debug!("Retrieving version info for request: {:?}", req);
 
-  let code_version: u64 = get_value(req, "code_version")?.parse().ok()?;
+  let code_version = get_code_version(req)?;
   let device_type = get_value(req, "device_type")?;
 
   Some((code_version, device_type))
 }
 
+pub fn get_code_version<T>(req: &Request<T>) -> Option<u64> {
+  get_value(req, "code_version")?.parse().ok()
+}
+
 pub fn get_value<T>(req: &Request<T>, key: &str) -> Option<String> {
   let raw_value = req.metadata().get(key)?;
   raw_value.to_str().ok().map(|s| s.to_string())
 }
+
+/// Behind a load balancer, the client address is the last one appended to
+/// X-Forwarded-For. Clients can set the header themselves, so it's only
+/// trusted if `TRUST_FORWARDED_FOR` is enabled.
+pub fn get_client_ip<T>(req: &Request<T>) -> Option<String> {
+  client_ip(req, CONFIG.trust_forwarded_for)
+}
+
+fn client_ip<T>(req: &Request<T>, trust_forwarded_for: bool) -> Option<String> {
+  let forwarded_ip = get_value(req, "x-forwarded-for")
+    .filter(|_| trust_forwarded_for)
+    .and_then(|value| {
+      value
+        .rsplit(',')
+        .next()
+        .map(str::trim)
+        .filter(|ip| !ip.is_empty())
+        .map(str::to_string)
+    });
+
+  forwarded_ip.or_else(|| req.remote_addr().map(|addr| addr.ip().to_string()))
+}
+
+#[cfg(test)]
+mod shared_tests {
+  use super::*;
+
+  #[test]
+  fn test_client_ip() {
+    let mut request = Request::new(());
+    request
+      .metadata_mut()
+      .insert("x-forwarded-for", "10.0.0.1, 192.0.2.1".parse().unwrap());
+
+    assert_eq!(client_ip(&request, true).as_deref(), Some("192.0.2.1"));
+    // Without a connection there's no remote address to fall back to
+    assert_eq!(client_ip(&request, false), None);
+  }
+}
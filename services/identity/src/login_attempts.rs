@@ -0,0 +1,322 @@
+//! Protection against brute-forcing logins. Failed attempts are counted per
+//! username, wallet address, device and IP address. After a few failures,
+//! further attempts are delayed with exponential backoff, and after too many
+//! the key is temporarily locked out. Counters are stored in DynamoDB, so that
+//! all replicas enforce the same limits.
+
+use std::fmt;
+
+use chrono::{DateTime, Duration, Utc};
+use tonic::{metadata::MetadataValue, Status};
+use tracing::warn;
+
+use crate::client_service::handle_db_error;
+use crate::config::CONFIG;
+use crate::database::DatabaseClient;
+use crate::error::{consume_error, Error};
+
+// Concurrent failures of the same key are resolved by retrying
+const MAX_RECORD_RETRIES: usize = 3;
+
+#[derive(Clone)]
+pub enum AttemptKey {
+  Username(String),
+  WalletAddress(String),
+  Device(String),
+  Ip(String),
+}
+
+impl fmt::Display for AttemptKey {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      AttemptKey::Username(username) => write!(f, "username#{}", username),
+      AttemptKey::WalletAddress(address) => write!(f, "wallet#{}", address),
+      AttemptKey::Device(device_id) => write!(f, "device#{}", device_id),
+      AttemptKey::Ip(ip) => write!(f, "ip#{}", ip),
+    }
+  }
+}
+
+#[derive(Clone, Debug)]
+pub struct LoginAttemptPolicy {
+  // Number of failures allowed before backoff kicks in
+  pub backoff_threshold: u32,
+  // Delay after the first failure past the threshold, doubled for each
+  // subsequent one
+  pub base_backoff: Duration,
+  // Number of failures after which the key is locked out
+  pub lockout_threshold: u32,
+  // Duration of a lockout. Failures older than this are forgotten.
+  pub lockout_duration: Duration,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct LoginAttempts {
+  pub failed_attempts: u32,
+  pub last_failure: DateTime<Utc>,
+  pub locked_until: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum LoginDenied {
+  Backoff(Duration),
+  Locked(Duration),
+}
+
+impl LoginAttempts {
+  /// Stale records are treated as missing. DynamoDB TTL deletes them
+  /// eventually, but not immediately.
+  pub fn is_stale(
+    &self,
+    now: DateTime<Utc>,
+    policy: &LoginAttemptPolicy,
+  ) -> bool {
+    now >= self.expiration_time(policy)
+  }
+
+  /// Time after which the record can be deleted
+  pub fn expiration_time(&self, policy: &LoginAttemptPolicy) -> DateTime<Utc> {
+    let forgotten = self.last_failure + policy.lockout_duration;
+    match self.locked_until {
+      Some(locked_until) if locked_until > forgotten => locked_until,
+      _ => forgotten,
+    }
+  }
+
+  /// Returns why a login attempt is not allowed right now, if it isn't
+  pub fn check(
+    &self,
+    now: DateTime<Utc>,
+    policy: &LoginAttemptPolicy,
+  ) -> Option<LoginDenied> {
+    if let Some(locked_until) = self.locked_until {
+      if locked_until > now {
+        return Some(LoginDenied::Locked(locked_until - now));
+      }
+    }
+
+    if self.failed_attempts < policy.backoff_threshold {
+      return None;
+    }
+    let exponent = (self.failed_attempts - policy.backoff_threshold).min(16);
+    let backoff =
+      (policy.base_backoff * 2_i32.pow(exponent)).min(policy.lockout_duration);
+    let retry_time = self.last_failure + backoff;
+    if retry_time > now {
+      return Some(LoginDenied::Backoff(retry_time - now));
+    }
+    None
+  }
+
+  /// Returns the state after another failed attempt
+  pub fn with_failure(
+    previous: Option<&LoginAttempts>,
+    now: DateTime<Utc>,
+    policy: &LoginAttemptPolicy,
+  ) -> Self {
+    let previous_failures = previous
+      .filter(|attempts| !attempts.is_stale(now, policy))
+      .map_or(0, |attempts| attempts.failed_attempts);
+    let failed_attempts = previous_failures + 1;
+
+    let locked_until = if failed_attempts >= policy.lockout_threshold {
+      Some(now + policy.lockout_duration)
+    } else {
+      None
+    };
+
+    LoginAttempts {
+      failed_attempts,
+      last_failure: now,
+      locked_until,
+    }
+  }
+}
+
+impl From<LoginDenied> for Status {
+  fn from(denied: LoginDenied) -> Self {
+    let (mut status, retry_after) = match denied {
+      LoginDenied::Backoff(retry_after) => (
+        Status::resource_exhausted("too many failed login attempts"),
+        retry_after,
+      ),
+      LoginDenied::Locked(retry_after) => {
+        (Status::permission_denied("login locked"), retry_after)
+      }
+    };
+
+    // Rounded up, so that retrying after this time succeeds
+    let seconds = (retry_after.num_milliseconds() + 999) / 1000;
+    if let Ok(value) = MetadataValue::try_from(seconds.to_string()) {
+      status.metadata_mut().insert("retry-after", value);
+    }
+    status
+  }
+}
+
+/// Fails with `resource_exhausted` during backoff and `permission_denied`
+/// during lockout. Both carry a `retry-after` metadata value in seconds.
+pub async fn ensure_login_allowed(
+  db_client: &DatabaseClient,
+  keys: &[AttemptKey],
+) -> Result<(), Status> {
+  let now = Utc::now();
+  let policy = &CONFIG.login_attempt_policy;
+
+  for key in keys {
+    let attempts = db_client
+      .get_login_attempts(&key.to_string())
+      .await
+      .map_err(handle_db_error)?;
+    if let Some(denied) =
+      attempts.and_then(|attempts| attempts.check(now, policy))
+    {
+      warn!("Login denied for {}: {:?}", key, denied);
+      return Err(denied.into());
+    }
+  }
+  Ok(())
+}
+
+/// Failing to record an attempt doesn't fail the login request
+pub async fn record_failed_login(
+  db_client: &DatabaseClient,
+  keys: &[AttemptKey],
+) {
+  for key in keys {
+    let result = record_failure(db_client, &key.to_string()).await;
+    consume_error(result);
+  }
+}
+
+async fn record_failure(
+  db_client: &DatabaseClient,
+  key: &str,
+) -> Result<(), Error> {
+  let policy = &CONFIG.login_attempt_policy;
+
+  for _ in 0..MAX_RECORD_RETRIES {
+    let previous = db_client.get_login_attempts(key).await?;
+    let attempts =
+      LoginAttempts::with_failure(previous.as_ref(), Utc::now(), policy);
+    let expiration_time = attempts.expiration_time(policy);
+
+    let updated = db_client
+      .put_login_attempts(
+        key,
+        &attempts,
+        previous.map(|attempts| attempts.failed_attempts),
+        expiration_time,
+      )
+      .await?;
+    if updated {
+      return Ok(());
+    }
+  }
+  warn!(
+    "Failed to record login attempt for {} due to contention",
+    key
+  );
+  Ok(())
+}
+
+/// Called after a successful login. IP address keys shouldn't be reset, since
+/// a login with e.g. a throwaway wallet would let the address keep guessing
+/// passwords of other users; their failures expire on their own.
+pub async fn reset_failed_logins(
+  db_client: &DatabaseClient,
+  keys: &[AttemptKey],
+) {
+  for key in keys {
+    let result = db_client.delete_login_attempts(&key.to_string()).await;
+    consume_error(result);
+  }
+}
+
+#[cfg(test)]
+mod login_attempts_tests {
+  use super::*;
+
+  fn policy() -> LoginAttemptPolicy {
+    LoginAttemptPolicy {
+      backoff_threshold: 3,
+      base_backoff: Duration::seconds(1),
+      lockout_threshold: 10,
+      lockout_duration: Duration::minutes(15),
+    }
+  }
+
+  fn fail_times(count: u32, now: DateTime<Utc>) -> LoginAttempts {
+    let mut attempts = None;
+    for _ in 0..count {
+      attempts = Some(LoginAttempts::with_failure(
+        attempts.as_ref(),
+        now,
+        &policy(),
+      ));
+    }
+    attempts.unwrap()
+  }
+
+  #[test]
+  fn test_attempts_below_backoff_threshold() {
+    let now = Utc::now();
+    let attempts = fail_times(2, now);
+    assert_eq!(attempts.failed_attempts, 2);
+    assert_eq!(attempts.check(now, &policy()), None);
+  }
+
+  #[test]
+  fn test_exponential_backoff() {
+    let now = Utc::now();
+    let attempts = fail_times(3, now);
+    assert_eq!(
+      attempts.check(now, &policy()),
+      Some(LoginDenied::Backoff(Duration::seconds(1)))
+    );
+    assert_eq!(attempts.check(now + Duration::seconds(1), &policy()), None);
+
+    let attempts = fail_times(5, now);
+    assert_eq!(
+      attempts.check(now + Duration::seconds(1), &policy()),
+      Some(LoginDenied::Backoff(Duration::seconds(3)))
+    );
+  }
+
+  #[test]
+  fn test_lockout() {
+    let now = Utc::now();
+    let attempts = fail_times(10, now);
+    assert_eq!(attempts.locked_until, Some(now + Duration::minutes(15)));
+    assert_eq!(
+      attempts.check(now + Duration::minutes(5), &policy()),
+      Some(LoginDenied::Locked(Duration::minutes(10)))
+    );
+    assert_eq!(attempts.check(now + Duration::minutes(15), &policy()), None);
+  }
+
+  #[test]
+  fn test_stale_attempts_are_forgotten() {
+    let now = Utc::now();
+    let attempts = fail_times(9, now);
+    let later = now + Duration::minutes(15);
+    assert!(attempts.is_stale(later, &policy()));
+
+    let attempts =
+      LoginAttempts::with_failure(Some(&attempts), later, &policy());
+    assert_eq!(attempts.failed_attempts, 1);
+    assert_eq!(attempts.locked_until, None);
+  }
+
+  #[test]
+  fn test_denied_status() {
+    let status: Status =
+      LoginDenied::Backoff(Duration::milliseconds(1500)).into();
+    assert_eq!(status.code(), tonic::Code::ResourceExhausted);
+    assert_eq!(status.metadata().get("retry-after").unwrap(), "2");
+
+    let status: Status = LoginDenied::Locked(Duration::minutes(1)).into();
+    assert_eq!(status.code(), tonic::Code::PermissionDenied);
+    assert_eq!(status.metadata().get("retry-after").unwrap(), "60");
+  }
+}
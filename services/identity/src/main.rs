@@ -1,35 +1,60 @@
+use std::path::Path;
+use std::sync::Arc;
 use std::time::Duration;
 
 use clap::{Parser, Subcommand};
+use comm_services_lib::auth::AuthService;
+use comm_services_lib::blob::client::BlobServiceClient;
 use database::DatabaseClient;
-use moka::future::Cache;
 use tonic::transport::Server;
+use tower::Layer;
 
+mod account_export;
+mod audit_log;
+mod backup;
 mod client_service;
 mod config;
 pub mod constants;
 mod database;
 pub mod ddb_utils;
 pub mod error;
+mod ethereum;
 mod grpc_services;
 mod grpc_utils;
 mod id;
 mod keygen;
+mod login_attempts;
 mod nonce;
+mod populate_db;
+mod rate_limiter;
 mod reserved_users;
+mod signature;
 mod siwe;
 mod token;
+mod totp;
 mod tunnelbroker;
+mod user_deletion;
+mod workflow_store;
 
-use config::load_config;
-use constants::{IDENTITY_SERVICE_SOCKET_ADDR, SECRETS_DIRECTORY};
+use config::{load_config, CONFIG};
+use constants::{
+  IDENTITY_SERVICE_SOCKET_ADDR, SECRETS_DIRECTORY,
+  USER_LOOKUP_RATE_LIMIT_WINDOW_SECONDS,
+};
+use ethereum::EthereumClient;
 use keygen::generate_and_persist_keypair;
+use rate_limiter::RateLimiter;
 use tracing::{self, info, Level};
 use tracing_subscriber::EnvFilter;
+use user_deletion::UserDeletionWorker;
 
 use client_service::{ClientService, IdentityClientServiceServer};
+use grpc_services::auth_layer::{AuthCache, AuthLayer};
 use grpc_services::authenticated::auth_proto::identity_client_service_server::IdentityClientServiceServer as AuthServer;
 use grpc_services::authenticated::AuthenticatedService;
+use workflow_store::{
+  DynamoDBWorkflowStore, InMemoryWorkflowStore, WorkflowStore,
+};
 
 #[derive(Parser)]
 #[clap(author, version, about, long_about = None)]
@@ -49,8 +74,20 @@ enum Commands {
     #[clap(default_value_t = String::from(SECRETS_DIRECTORY))]
     dir: String,
   },
-  /// Populates the `identity-users` table in DynamoDB from MySQL
-  PopulateDB,
+  /// Reserves usernames of keyserver users in DynamoDB, using a JSON or CSV
+  /// export of the keyserver `users` table
+  PopulateDB {
+    /// Path to the export. Files with the `.csv` extension are read as CSV,
+    /// other files as a JSON array.
+    #[clap(short, long)]
+    input: String,
+    /// Number of users imported per DynamoDB batch
+    #[clap(short, long, default_value_t = populate_db::MAX_BATCH_SIZE)]
+    batch_size: usize,
+    /// Path to write the import summary with conflicts to, as JSON
+    #[clap(long)]
+    conflicts_output: Option<String>,
+  },
 }
 
 #[tokio::main]
@@ -73,22 +110,74 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
       let addr = IDENTITY_SERVICE_SOCKET_ADDR.parse()?;
       let aws_config = aws_config::from_env().region("us-east-2").load().await;
       let database_client = DatabaseClient::new(&aws_config);
-      let workflow_cache = Cache::builder()
-        .time_to_live(Duration::from_secs(10))
-        .build();
-      let inner_client_service =
-        ClientService::new(database_client.clone(), workflow_cache);
+      let workflow_store: Arc<dyn WorkflowStore> =
+        if CONFIG.in_memory_workflow_store {
+          Arc::new(InMemoryWorkflowStore::new())
+        } else {
+          Arc::new(DynamoDBWorkflowStore::new(database_client.clone()))
+        };
+      let ethereum_client = CONFIG
+        .ethereum_rpc_url
+        .clone()
+        .map(EthereumClient::new)
+        .transpose()?;
+      let lookup_rate_limiter = RateLimiter::new(
+        CONFIG.user_lookup_rate_limit,
+        Duration::from_secs(USER_LOOKUP_RATE_LIMIT_WINDOW_SECONDS),
+      );
+      // The auth service is built with the AWS SDK version of
+      // comm-services-lib. Identity only uses it to get the services token,
+      // so it points at itself for access token verification.
+      let mut services_aws_config_loader =
+        comm_services_lib::aws_config::from_env().region("us-east-2");
+      if let Some(endpoint) = &CONFIG.localstack_endpoint {
+        services_aws_config_loader =
+          services_aws_config_loader.endpoint_url(endpoint);
+      }
+      let services_auth_service = AuthService::new(
+        &services_aws_config_loader.load().await,
+        format!("http://{}", IDENTITY_SERVICE_SOCKET_ADDR),
+      );
+      let blob_client =
+        BlobServiceClient::new(CONFIG.blob_service_url.parse()?);
+      tokio::spawn(account_export::run_export_cleanup_loop(
+        database_client.clone(),
+        blob_client.clone(),
+        services_auth_service.clone(),
+      ));
+      let user_deletion_worker = UserDeletionWorker::new(
+        database_client.clone(),
+        services_auth_service.clone(),
+        blob_client.clone(),
+      );
+      tokio::spawn(user_deletion_worker.clone().run_retry_loop());
+      let auth_cache = AuthCache::new();
+      let inner_client_service = ClientService::new(
+        database_client.clone(),
+        workflow_store.clone(),
+        ethereum_client,
+        lookup_rate_limiter,
+        user_deletion_worker,
+        services_auth_service.clone(),
+        auth_cache.clone(),
+      );
       let client_service = IdentityClientServiceServer::with_interceptor(
         inner_client_service,
         grpc_services::shared::version_interceptor,
       );
-      let inner_auth_service =
-        AuthenticatedService::new(database_client.clone());
-      let auth_service =
-        AuthServer::with_interceptor(inner_auth_service, move |req| {
-          grpc_services::authenticated::auth_interceptor(req, &database_client)
-            .and_then(grpc_services::shared::version_interceptor)
-        });
+      let inner_auth_service = AuthenticatedService::new(
+        database_client.clone(),
+        workflow_store,
+        blob_client,
+        services_auth_service,
+        auth_cache.clone(),
+      );
+      let auth_service = AuthLayer::new(database_client, auth_cache).layer(
+        AuthServer::with_interceptor(
+          inner_auth_service,
+          grpc_services::shared::version_interceptor,
+        ),
+      );
 
       info!("Listening to gRPC traffic on {}", addr);
       Server::builder()
@@ -98,7 +187,32 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .serve(addr)
         .await?;
     }
-    Commands::PopulateDB => unimplemented!(),
+    Commands::PopulateDB {
+      input,
+      batch_size,
+      conflicts_output,
+    } => {
+      load_config();
+      let aws_config = aws_config::from_env().region("us-east-2").load().await;
+      let database_client = DatabaseClient::new(&aws_config);
+
+      let users = populate_db::read_users(Path::new(input))?;
+      let summary =
+        populate_db::import_users(&database_client, users, *batch_size).await?;
+      info!(
+        "Imported {} users: {} reserved, {} already reserved, {} already \
+        registered, {} conflicts",
+        summary.read,
+        summary.reserved,
+        summary.already_reserved,
+        summary.already_registered,
+        summary.conflicts.len()
+      );
+
+      if let Some(path) = conflicts_output {
+        std::fs::write(path, serde_json::to_string_pretty(&summary)?)?;
+      }
+    }
   }
 
   Ok(())
@@ -1,5 +1,352 @@
-struct User {
-  id: i64,
-  username: String,
-  creation_time: i64,
+//! Imports users exported from a legacy keyserver. Their usernames are added
+//! to the reserved usernames table along with their keyserver user IDs, so
+//! that they can later claim them with a signed keyserver message.
+//!
+//! The import is idempotent: usernames already reserved for the same user, or
+//! already registered by them, are skipped. Usernames reserved for or
+//! registered by a different user are reported as conflicts and left as is.
+
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+use chrono::{DateTime, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::config::CONFIG;
+use crate::database::DatabaseClient;
+
+// A single call to BatchWriteItem can consist of up to 25 operations
+pub const MAX_BATCH_SIZE: usize = 25;
+
+#[derive(Debug, derive_more::Display, derive_more::From)]
+pub enum Error {
+  #[display(...)]
+  Io(std::io::Error),
+  #[display(...)]
+  Json(serde_json::Error),
+  #[display(...)]
+  Csv(csv::Error),
+  #[display(...)]
+  Database(crate::error::Error),
+  #[display(fmt = "Invalid input: {}", _0)]
+  #[from(ignore)]
+  InvalidInput(String),
+}
+
+impl std::error::Error for Error {}
+
+/// A row of the keyserver `users` table
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct User {
+  pub id: i64,
+  pub username: String,
+  // Milliseconds since the Unix epoch
+  pub creation_time: i64,
+}
+
+impl User {
+  pub fn user_id(&self) -> String {
+    self.id.to_string()
+  }
+
+  pub fn creation_time(&self) -> Option<DateTime<Utc>> {
+    Utc.timestamp_millis_opt(self.creation_time).single()
+  }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ConflictReason {
+  // Registered with identity by a different user
+  UsernameRegistered { user_id: String },
+  // Reserved for a different user, e.g. by a previous import
+  UsernameReserved { user_id: String },
+  // Another user with the same username appears earlier in the input
+  DuplicateUsername { user_id: String },
+  // Reserved by identity itself
+  UsernameNotAllowed,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Conflict {
+  pub user: User,
+  pub reason: ConflictReason,
+}
+
+#[derive(Debug, Default, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportSummary {
+  pub read: usize,
+  pub reserved: usize,
+  pub already_reserved: usize,
+  pub already_registered: usize,
+  pub conflicts: Vec<Conflict>,
+}
+
+/// Reads a JSON array of users, or a CSV file with a header row if the
+/// extension is `.csv`
+pub fn read_users(path: &Path) -> Result<Vec<User>, Error> {
+  let file = File::open(path)?;
+  let is_csv = path
+    .extension()
+    .map_or(false, |extension| extension.eq_ignore_ascii_case("csv"));
+
+  if is_csv {
+    parse_csv_users(file)
+  } else {
+    Ok(serde_json::from_reader(BufReader::new(file))?)
+  }
+}
+
+fn parse_csv_users(reader: impl std::io::Read) -> Result<Vec<User>, Error> {
+  let mut csv_reader = csv::ReaderBuilder::new()
+    .trim(csv::Trim::All)
+    .from_reader(reader);
+  let users = csv_reader
+    .deserialize()
+    .collect::<Result<Vec<User>, csv::Error>>()?;
+  Ok(users)
+}
+
+/// What to do with users of a batch, given the current state of the tables
+#[derive(Debug, Default, PartialEq)]
+struct BatchPlan {
+  to_reserve: Vec<User>,
+  already_reserved: usize,
+  already_registered: usize,
+  conflicts: Vec<Conflict>,
+}
+
+fn plan_batch(
+  users: &[User],
+  registered: &HashMap<String, String>,
+  reserved: &HashMap<String, Option<String>>,
+  seen: &mut HashMap<String, String>,
+  not_allowed: &HashSet<String>,
+) -> BatchPlan {
+  let mut plan = BatchPlan::default();
+
+  for user in users {
+    let user_id = user.user_id();
+    let conflict = |reason| Conflict {
+      user: user.clone(),
+      reason,
+    };
+
+    if let Some(previous_user_id) = seen.get(&user.username) {
+      if previous_user_id != &user_id {
+        plan
+          .conflicts
+          .push(conflict(ConflictReason::DuplicateUsername {
+            user_id: previous_user_id.clone(),
+          }));
+      }
+      continue;
+    }
+    seen.insert(user.username.clone(), user_id.clone());
+
+    if not_allowed.contains(&user.username) {
+      plan
+        .conflicts
+        .push(conflict(ConflictReason::UsernameNotAllowed));
+      continue;
+    }
+
+    match registered.get(&user.username) {
+      Some(registered_user_id) if registered_user_id == &user_id => {
+        plan.already_registered += 1;
+        continue;
+      }
+      Some(registered_user_id) => {
+        plan
+          .conflicts
+          .push(conflict(ConflictReason::UsernameRegistered {
+            user_id: registered_user_id.clone(),
+          }));
+        continue;
+      }
+      None => (),
+    }
+
+    match reserved.get(&user.username) {
+      Some(Some(reserved_user_id)) if reserved_user_id == &user_id => {
+        plan.already_reserved += 1;
+      }
+      Some(Some(reserved_user_id)) => {
+        plan
+          .conflicts
+          .push(conflict(ConflictReason::UsernameReserved {
+            user_id: reserved_user_id.clone(),
+          }));
+      }
+      // Reserved without a user ID by the keyserver cron job, so it's
+      // updated with the ID
+      Some(None) | None => plan.to_reserve.push(user.clone()),
+    }
+  }
+
+  plan
+}
+
+pub async fn import_users(
+  db_client: &DatabaseClient,
+  users: Vec<User>,
+  batch_size: usize,
+) -> Result<ImportSummary, Error> {
+  if batch_size == 0 || batch_size > MAX_BATCH_SIZE {
+    return Err(Error::InvalidInput(format!(
+      "batch size must be between 1 and {}",
+      MAX_BATCH_SIZE
+    )));
+  }
+  for user in &users {
+    if user.username.is_empty() || user.creation_time().is_none() {
+      return Err(Error::InvalidInput(format!("{:?}", user)));
+    }
+  }
+
+  let registered = db_client.get_user_ids_by_username().await?;
+  let mut seen = HashMap::new();
+  let mut summary = ImportSummary {
+    read: users.len(),
+    ..Default::default()
+  };
+
+  for (batch_number, batch) in users.chunks(batch_size).enumerate() {
+    let usernames = batch.iter().map(|user| user.username.clone()).collect();
+    let reserved = db_client.get_reserved_username_user_ids(usernames).await?;
+
+    let plan = plan_batch(
+      batch,
+      &registered,
+      &reserved,
+      &mut seen,
+      &CONFIG.reserved_usernames,
+    );
+
+    db_client
+      .put_reserved_usernames_with_user_ids(&plan.to_reserve)
+      .await?;
+
+    for conflict in &plan.conflicts {
+      warn!(
+        "Conflict importing {:?}: {:?}",
+        conflict.user, conflict.reason
+      );
+    }
+    info!(
+      "Imported batch {}: {} reserved, {} skipped, {} conflicts",
+      batch_number + 1,
+      plan.to_reserve.len(),
+      plan.already_reserved + plan.already_registered,
+      plan.conflicts.len()
+    );
+
+    summary.reserved += plan.to_reserve.len();
+    summary.already_reserved += plan.already_reserved;
+    summary.already_registered += plan.already_registered;
+    summary.conflicts.extend(plan.conflicts);
+  }
+
+  Ok(summary)
+}
+
+#[cfg(test)]
+mod populate_db_tests {
+  use super::*;
+
+  fn user(id: i64, username: &str) -> User {
+    User {
+      id,
+      username: username.to_string(),
+      creation_time: 1_600_000_000_000,
+    }
+  }
+
+  #[test]
+  fn test_parse_users() {
+    let csv = "id,username,creation_time\n1, alice ,1600000000000\n2,bob,1600000000001\n";
+    let users = parse_csv_users(csv.as_bytes()).unwrap();
+    assert_eq!(
+      users,
+      vec![user(1, "alice"), {
+        let mut bob = user(2, "bob");
+        bob.creation_time += 1;
+        bob
+      }]
+    );
+
+    let json =
+      r#"[{"id": 1, "username": "alice", "creation_time": 1600000000000}]"#;
+    let users: Vec<User> = serde_json::from_str(json).unwrap();
+    assert_eq!(users, vec![user(1, "alice")]);
+  }
+
+  #[test]
+  fn test_plan_batch() {
+    let users = vec![
+      user(1, "new"),
+      user(2, "migrated"),
+      user(3, "taken"),
+      user(4, "imported"),
+      user(5, "claimed"),
+      user(6, "cron"),
+      user(7, "new"),
+      user(8, "comm"),
+    ];
+    let registered = HashMap::from([
+      ("migrated".to_string(), "2".to_string()),
+      ("taken".to_string(), "other".to_string()),
+    ]);
+    let reserved = HashMap::from([
+      ("imported".to_string(), Some("4".to_string())),
+      ("claimed".to_string(), Some("other".to_string())),
+      ("cron".to_string(), None),
+    ]);
+    let not_allowed = HashSet::from(["comm".to_string()]);
+
+    let plan = plan_batch(
+      &users,
+      &registered,
+      &reserved,
+      &mut HashMap::new(),
+      &not_allowed,
+    );
+
+    assert_eq!(plan.to_reserve, vec![user(1, "new"), user(6, "cron")]);
+    assert_eq!(plan.already_registered, 1);
+    assert_eq!(plan.already_reserved, 1);
+    let reasons: Vec<_> = plan
+      .conflicts
+      .into_iter()
+      .map(|conflict| (conflict.user.id, conflict.reason))
+      .collect();
+    assert_eq!(
+      reasons,
+      vec![
+        (
+          3,
+          ConflictReason::UsernameRegistered {
+            user_id: "other".to_string()
+          }
+        ),
+        (
+          5,
+          ConflictReason::UsernameReserved {
+            user_id: "other".to_string()
+          }
+        ),
+        (
+          7,
+          ConflictReason::DuplicateUsername {
+            user_id: "1".to_string()
+          }
+        ),
+        (8, ConflictReason::UsernameNotAllowed),
+      ]
+    );
+  }
 }
@@ -0,0 +1,104 @@
+//! Fixed-window rate limiting of unauthenticated requests, e.g. user lookups,
+//! per client IP address. Counters are kept in memory, so each replica
+//! enforces the limit separately.
+
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use moka::future::Cache;
+use tonic::{metadata::MetadataValue, Status};
+use tracing::warn;
+
+// Used for requests whose client address is unknown
+const UNKNOWN_CLIENT_KEY: &str = "unknown";
+
+struct Window {
+  start: Instant,
+  count: AtomicU32,
+}
+
+#[derive(Clone)]
+pub struct RateLimiter {
+  windows: Cache<String, Arc<Window>>,
+  max_cost_per_window: u32,
+  window_duration: Duration,
+}
+
+impl RateLimiter {
+  pub fn new(max_cost_per_window: u32, window_duration: Duration) -> Self {
+    RateLimiter {
+      // Windows are reset when their entries expire
+      windows: Cache::builder().time_to_live(window_duration).build(),
+      max_cost_per_window,
+      window_duration,
+    }
+  }
+
+  /// Fails with `resource_exhausted` if the client exceeded its limit in the
+  /// current window, with a `retry-after` metadata value in seconds. Requests
+  /// which do several lookups can have a cost higher than 1.
+  pub async fn check(
+    &self,
+    client_ip: Option<&str>,
+    cost: u32,
+  ) -> Result<(), Status> {
+    let key = client_ip.unwrap_or(UNKNOWN_CLIENT_KEY);
+    let window = self
+      .windows
+      .get_with(key.to_string(), async {
+        Arc::new(Window {
+          start: Instant::now(),
+          count: AtomicU32::new(0),
+        })
+      })
+      .await;
+
+    let previous_count = window.count.fetch_add(cost, Ordering::Relaxed);
+    if previous_count.saturating_add(cost) <= self.max_cost_per_window {
+      return Ok(());
+    }
+
+    warn!("Rate limit exceeded for {}", key);
+    let retry_after = self
+      .window_duration
+      .saturating_sub(window.start.elapsed())
+      .as_secs()
+      + 1;
+    let mut status = Status::resource_exhausted("too many requests");
+    if let Ok(value) = MetadataValue::try_from(retry_after.to_string()) {
+      status.metadata_mut().insert("retry-after", value);
+    }
+    Err(status)
+  }
+}
+
+#[cfg(test)]
+mod rate_limiter_tests {
+  use super::*;
+
+  #[tokio::test]
+  async fn test_rate_limit_per_client() {
+    let rate_limiter = RateLimiter::new(3, Duration::from_secs(60));
+
+    assert!(rate_limiter.check(Some("192.0.2.1"), 1).await.is_ok());
+    assert!(rate_limiter.check(Some("192.0.2.1"), 2).await.is_ok());
+    let status = rate_limiter.check(Some("192.0.2.1"), 1).await.unwrap_err();
+    assert_eq!(status.code(), tonic::Code::ResourceExhausted);
+    assert_eq!(status.metadata().get("retry-after").unwrap(), "60");
+
+    // Other clients have their own limits
+    assert!(rate_limiter.check(Some("192.0.2.2"), 3).await.is_ok());
+    assert!(rate_limiter.check(None, 4).await.is_err());
+  }
+
+  #[tokio::test]
+  async fn test_rate_limit_window_expires() {
+    let rate_limiter = RateLimiter::new(1, Duration::from_millis(100));
+
+    assert!(rate_limiter.check(Some("192.0.2.1"), 1).await.is_ok());
+    assert!(rate_limiter.check(Some("192.0.2.1"), 1).await.is_err());
+    tokio::time::sleep(Duration::from_millis(150)).await;
+    assert!(rate_limiter.check(Some("192.0.2.1"), 1).await.is_ok());
+  }
+}
@@ -0,0 +1,173 @@
+use std::str::FromStr;
+
+use base64::{engine::general_purpose, Engine as _};
+use ed25519_dalek::{PublicKey, Signature, Verifier};
+use tonic::Status;
+
+use crate::client_service::FlattenedDeviceKeyUpload;
+use crate::database::KeyPayload;
+
+/// Verifies an Olm signature. Keys and signatures are unpadded base64.
+fn verify_signature(
+  signing_public_key: &str,
+  message: &[u8],
+  signature: &str,
+) -> Result<(), Status> {
+  let public_key_bytes = general_purpose::STANDARD_NO_PAD
+    .decode(signing_public_key)
+    .map_err(|_| Status::invalid_argument("malformed signing key"))?;
+  let public_key = PublicKey::from_bytes(&public_key_bytes)
+    .map_err(|_| Status::invalid_argument("malformed signing key"))?;
+
+  let signature_bytes = general_purpose::STANDARD_NO_PAD
+    .decode(signature)
+    .map_err(|_| Status::invalid_argument("signature invalid"))?;
+  let signature = Signature::from_bytes(&signature_bytes)
+    .map_err(|_| Status::invalid_argument("signature invalid"))?;
+
+  public_key
+    .verify(message, &signature)
+    .map_err(|_| Status::invalid_argument("signature invalid"))
+}
+
+/// The key payload is signed as uploaded by the primary identity key, which
+/// is also the device ID
+pub fn verify_key_payload_signature(
+  device_id: &str,
+  key_payload: &str,
+  key_payload_signature: &str,
+) -> Result<(), Status> {
+  verify_signature(device_id, key_payload.as_bytes(), key_payload_signature)
+}
+
+/// Olm signs the raw curve25519 prekey, which is uploaded as base64
+pub fn verify_prekey_signature(
+  signing_public_key: &str,
+  prekey: &str,
+  prekey_signature: &str,
+) -> Result<(), Status> {
+  let prekey_bytes = general_purpose::STANDARD_NO_PAD
+    .decode(prekey)
+    .map_err(|_| Status::invalid_argument("malformed prekey"))?;
+
+  verify_signature(signing_public_key, &prekey_bytes, prekey_signature)
+}
+
+/// Verifies prekeys of both Olm accounts of a device. The content prekey is
+/// signed by the primary identity key and the notif prekey by the
+/// notification identity key.
+pub fn verify_prekey_signatures(
+  key_payload: &str,
+  content_prekey: &str,
+  content_prekey_signature: &str,
+  notif_prekey: &str,
+  notif_prekey_signature: &str,
+) -> Result<(), Status> {
+  let key_info = KeyPayload::from_str(key_payload)
+    .map_err(|_| Status::invalid_argument("malformed payload"))?;
+
+  verify_prekey_signature(
+    &key_info.primary_identity_public_keys.ed25519,
+    content_prekey,
+    content_prekey_signature,
+  )?;
+  verify_prekey_signature(
+    &key_info.notification_identity_public_keys.ed25519,
+    notif_prekey,
+    notif_prekey_signature,
+  )
+}
+
+pub fn verify_device_key_upload(
+  device_key_upload: &FlattenedDeviceKeyUpload,
+) -> Result<(), Status> {
+  verify_key_payload_signature(
+    &device_key_upload.device_id_key,
+    &device_key_upload.key_payload,
+    &device_key_upload.key_payload_signature,
+  )?;
+  verify_prekey_signatures(
+    &device_key_upload.key_payload,
+    &device_key_upload.content_prekey,
+    &device_key_upload.content_prekey_signature,
+    &device_key_upload.notif_prekey,
+    &device_key_upload.notif_prekey_signature,
+  )
+}
+
+#[cfg(test)]
+mod signature_tests {
+  use super::*;
+  use ed25519_dalek::{Keypair, SecretKey, Signer};
+
+  fn keypair(seed: u8) -> Keypair {
+    let secret = SecretKey::from_bytes(&[seed; 32]).unwrap();
+    let public = PublicKey::from(&secret);
+    Keypair { secret, public }
+  }
+
+  fn encode(bytes: &[u8]) -> String {
+    general_purpose::STANDARD_NO_PAD.encode(bytes)
+  }
+
+  fn sign(keypair: &Keypair, message: &[u8]) -> String {
+    encode(&keypair.sign(message).to_bytes())
+  }
+
+  fn key_payload(content: &Keypair, notif: &Keypair) -> String {
+    format!(
+      r#"{{"notificationIdentityPublicKeys":{{"curve25519":"a","ed25519":"{}"}},"primaryIdentityPublicKeys":{{"curve25519":"b","ed25519":"{}"}}}}"#,
+      encode(notif.public.as_bytes()),
+      encode(content.public.as_bytes()),
+    )
+  }
+
+  #[test]
+  fn test_key_payload_signature() {
+    let content = keypair(1);
+    let device_id = encode(content.public.as_bytes());
+    let payload = key_payload(&content, &keypair(2));
+    let signature = sign(&content, payload.as_bytes());
+
+    assert!(
+      verify_key_payload_signature(&device_id, &payload, &signature).is_ok()
+    );
+
+    let other_signature = sign(&keypair(3), payload.as_bytes());
+    let result =
+      verify_key_payload_signature(&device_id, &payload, &other_signature);
+    assert_eq!(result.unwrap_err().code(), tonic::Code::InvalidArgument);
+
+    let result =
+      verify_key_payload_signature(&device_id, &payload, "not a signature");
+    assert_eq!(result.unwrap_err().code(), tonic::Code::InvalidArgument);
+  }
+
+  #[test]
+  fn test_prekey_signatures() {
+    let content = keypair(1);
+    let notif = keypair(2);
+    let payload = key_payload(&content, &notif);
+    let content_prekey = [4; 32];
+    let notif_prekey = [5; 32];
+
+    assert!(verify_prekey_signatures(
+      &payload,
+      &encode(&content_prekey),
+      &sign(&content, &content_prekey),
+      &encode(&notif_prekey),
+      &sign(&notif, &notif_prekey),
+    )
+    .is_ok());
+
+    // Notif prekey signed by the content account
+    let result = verify_prekey_signatures(
+      &payload,
+      &encode(&content_prekey),
+      &sign(&content, &content_prekey),
+      &encode(&notif_prekey),
+      &sign(&content, &notif_prekey),
+    );
+    assert_eq!(result.unwrap_err().code(), tonic::Code::InvalidArgument);
+  }
+}
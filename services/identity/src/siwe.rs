@@ -1,35 +1,172 @@
-use chrono::Utc;
+use std::collections::HashSet;
+
+use chrono::{DateTime, Duration, Utc};
 use regex::Regex;
 use siwe::Message;
 use tonic::Status;
 use tracing::error;
 
-pub fn parse_and_verify_siwe_message(
+use crate::config::CONFIG;
+use crate::ethereum::EthereumClient;
+
+/// Restrictions on SIWE messages, on top of the signature being valid. They
+/// ensure that messages signed for other sites can't be used to log in.
+#[derive(Clone, Debug)]
+pub struct SiwePolicy {
+  // Domains requesting the signature, e.g. "web.comm.app"
+  pub allowed_domains: HashSet<String>,
+  // URIs of the signed resource, e.g. "https://web.comm.app"
+  pub allowed_uris: HashSet<String>,
+  pub allowed_chain_ids: HashSet<u64>,
+  // Messages issued longer ago than this are rejected, even if not expired
+  pub max_issued_at_age: Duration,
+  pub require_expiration_time: bool,
+  // Tolerated difference between the clocks of the client and the service
+  pub allowed_clock_skew: Duration,
+}
+
+#[derive(Debug, PartialEq, Eq, derive_more::Display)]
+pub enum PolicyViolation {
+  #[display(fmt = "domain not allowed")]
+  DomainNotAllowed,
+  #[display(fmt = "uri not allowed")]
+  UriNotAllowed,
+  #[display(fmt = "chain id not allowed")]
+  ChainIdNotAllowed,
+  #[display(fmt = "message issued in the future")]
+  IssuedInFuture,
+  #[display(fmt = "message issued too long ago")]
+  IssuedTooLongAgo,
+  #[display(fmt = "missing expiration time")]
+  MissingExpirationTime,
+  #[display(fmt = "message expired")]
+  Expired,
+  #[display(fmt = "message not yet valid")]
+  NotYetValid,
+}
+
+impl SiwePolicy {
+  pub fn check(
+    &self,
+    message: &Message,
+    now: DateTime<Utc>,
+  ) -> Result<(), PolicyViolation> {
+    if !self.allowed_domains.contains(message.domain.as_str()) {
+      return Err(PolicyViolation::DomainNotAllowed);
+    }
+    if !self.allowed_uris.contains(message.uri.as_str()) {
+      return Err(PolicyViolation::UriNotAllowed);
+    }
+    if !self.allowed_chain_ids.contains(&message.chain_id) {
+      return Err(PolicyViolation::ChainIdNotAllowed);
+    }
+
+    let issued_at = message.issued_at.as_ref();
+    if *issued_at > now + self.allowed_clock_skew {
+      return Err(PolicyViolation::IssuedInFuture);
+    }
+    if *issued_at < now - self.max_issued_at_age - self.allowed_clock_skew {
+      return Err(PolicyViolation::IssuedTooLongAgo);
+    }
+
+    match &message.expiration_time {
+      Some(expiration_time)
+        if *expiration_time.as_ref() < now - self.allowed_clock_skew =>
+      {
+        return Err(PolicyViolation::Expired);
+      }
+      None if self.require_expiration_time => {
+        return Err(PolicyViolation::MissingExpirationTime);
+      }
+      _ => (),
+    }
+    if let Some(not_before) = &message.not_before {
+      if *not_before.as_ref() > now + self.allowed_clock_skew {
+        return Err(PolicyViolation::NotYetValid);
+      }
+    }
+
+    Ok(())
+  }
+}
+
+/// Signatures which aren't valid ECDSA signatures of the address are checked
+/// with EIP-1271 if an Ethereum client is given, to support smart contract
+/// wallets
+pub async fn parse_and_verify_siwe_message(
   siwe_message: &str,
   siwe_signature: &str,
+  ethereum_client: Option<&EthereumClient>,
+) -> Result<Message, Status> {
+  verify_siwe_message(
+    siwe_message,
+    siwe_signature,
+    &CONFIG.siwe_policy,
+    ethereum_client,
+    Utc::now(),
+  )
+  .await
+}
+
+async fn verify_siwe_message(
+  siwe_message: &str,
+  siwe_signature: &str,
+  policy: &SiwePolicy,
+  ethereum_client: Option<&EthereumClient>,
+  now: DateTime<Utc>,
 ) -> Result<Message, Status> {
   let siwe_message: Message = siwe_message.parse().map_err(|e| {
     error!("Failed to parse SIWE message: {}", e);
     Status::invalid_argument("invalid message")
   })?;
 
-  let decoded_signature = hex::decode(siwe_signature.trim_start_matches("0x"))
+  policy.check(&siwe_message, now).map_err(|violation| {
+    error!("SIWE message rejected by policy: {}", violation);
+    Status::invalid_argument(violation.to_string())
+  })?;
+
+  let signature = hex::decode(siwe_signature.trim_start_matches("0x"))
     .map_err(|e| {
       error!("Failed to decode SIWE signature: {}", e);
       Status::invalid_argument("invalid signature")
     })?;
 
-  let signature = decoded_signature.try_into().map_err(|e| {
-    error!("Conversion to SIWE signature failed: {:?}", e);
+  // Time constraints were checked by the policy, allowing for clock skew
+  let ecdsa_result = <[u8; 65]>::try_from(signature.as_slice())
+    .map_err(|_| "not a 65-byte ECDSA signature".to_string())
+    .and_then(|ecdsa_signature| {
+      siwe_message
+        .verify_eip191(&ecdsa_signature)
+        .map_err(|e| e.to_string())
+    });
+  let ecdsa_error = match ecdsa_result {
+    Ok(_) => return Ok(siwe_message),
+    Err(e) => e,
+  };
+
+  let Some(ethereum_client) = ethereum_client else {
+    error!("Signature verification failed: {}", ecdsa_error);
+    return Err(Status::unauthenticated("message not authenticated"));
+  };
+  let hash = siwe_message.eip191_hash().map_err(|e| {
+    error!("Failed to hash SIWE message: {}", e);
     Status::invalid_argument("invalid message")
   })?;
-
-  siwe_message
-    .verify(signature, None, None, Some(&Utc::now()))
+  let contract_signature_valid = ethereum_client
+    .is_valid_contract_signature(&siwe_message.address, &hash, &signature)
+    .await
     .map_err(|e| {
-      error!("Signature verification failed: {}", e);
-      Status::unauthenticated("message not authenticated")
+      error!("Failed to validate contract wallet signature: {}", e);
+      Status::unavailable("unable to verify signature")
     })?;
+  if !contract_signature_valid {
+    error!(
+      "Signature verification failed: {}, and not a valid contract wallet \
+      signature",
+      ecdsa_error
+    );
+    return Err(Status::unauthenticated("message not authenticated"));
+  }
 
   Ok(siwe_message)
 }
@@ -42,43 +179,235 @@ pub fn is_valid_ethereum_address(candidate: &str) -> bool {
 #[cfg(test)]
 mod tests {
   use super::*;
+  use crate::ethereum::ethereum_tests::{
+    mock_rpc_server, wallet_contract, WALLET,
+  };
+  use std::sync::Arc;
+  use tonic::Code;
+
+  fn invalid_signature() -> String {
+    format!("0x{}", "00".repeat(65))
+  }
+
+  fn policy() -> SiwePolicy {
+    SiwePolicy {
+      allowed_domains: HashSet::from(["web.comm.app".to_string()]),
+      allowed_uris: HashSet::from(["https://web.comm.app".to_string()]),
+      allowed_chain_ids: HashSet::from([1]),
+      max_issued_at_age: Duration::minutes(10),
+      require_expiration_time: true,
+      allowed_clock_skew: Duration::seconds(60),
+    }
+  }
+
+  fn now() -> DateTime<Utc> {
+    "2023-08-01T12:00:00Z".parse().unwrap()
+  }
+
+  /// A message issued a minute ago, which expires in 10 minutes
+  fn message() -> Message {
+    let issued_at = now() - Duration::minutes(1);
+    let expiration_time = now() + Duration::minutes(10);
+    format!(
+      "web.comm.app wants you to sign in with your Ethereum account:\n\
+      {}\n\n\
+      Device IdPubKey: key\n\n\
+      URI: https://web.comm.app\n\
+      Version: 1\n\
+      Chain ID: 1\n\
+      Nonce: abcdefghijklmnopq\n\
+      Issued At: {}\n\
+      Expiration Time: {}",
+      siwe::eip55(&WALLET),
+      issued_at.to_rfc3339(),
+      expiration_time.to_rfc3339()
+    )
+    .parse()
+    .unwrap()
+  }
+
+  #[test]
+  fn test_policy_accepts_valid_message() {
+    assert_eq!(policy().check(&message(), now()), Ok(()));
+
+    let mut message = message();
+    message.expiration_time = None;
+    let policy = SiwePolicy {
+      require_expiration_time: false,
+      ..policy()
+    };
+    assert_eq!(policy.check(&message, now()), Ok(()));
+  }
+
+  #[test]
+  fn test_policy_rejects_domain() {
+    let mut message = message();
+    message.domain = "evil.com".parse().unwrap();
+    assert_eq!(
+      policy().check(&message, now()),
+      Err(PolicyViolation::DomainNotAllowed)
+    );
+  }
 
   #[test]
-  fn test_valid_ethereum_address() {
-    assert!(is_valid_ethereum_address(
-      "0x1234567890123456789012345678901234567890"
-    ),);
-    assert!(is_valid_ethereum_address(
-      "0xABCDEF123456789012345678901234567890ABCD"
-    ));
-    assert!(is_valid_ethereum_address(
-      "0xabcdef123456789012345678901234567890abcd"
-    ));
+  fn test_policy_rejects_uri() {
+    let mut message = message();
+    message.uri = "https://evil.com".try_into().unwrap();
+    assert_eq!(
+      policy().check(&message, now()),
+      Err(PolicyViolation::UriNotAllowed)
+    );
   }
 
   #[test]
-  fn test_invalid_ethereum_address() {
-    // Shorter than 42 characters
+  fn test_policy_rejects_chain_id() {
+    let mut message = message();
+    message.chain_id = 5;
     assert_eq!(
-      is_valid_ethereum_address("0x12345678901234567890123456789012345678"),
-      false
+      policy().check(&message, now()),
+      Err(PolicyViolation::ChainIdNotAllowed)
     );
-    // Longer than 42 characters
+  }
+
+  #[test]
+  fn test_policy_rejects_future_issued_at() {
+    let mut message = message();
+    message.issued_at = (now() + Duration::minutes(2)).into();
     assert_eq!(
-      is_valid_ethereum_address("0x123456789012345678901234567890123456789012"),
-      false
+      policy().check(&message, now()),
+      Err(PolicyViolation::IssuedInFuture)
     );
-    // Missing 0x prefix
+  }
+
+  #[test]
+  fn test_policy_rejects_old_issued_at() {
+    let mut message = message();
+    message.issued_at = (now() - Duration::minutes(12)).into();
+    assert_eq!(
+      policy().check(&message, now()),
+      Err(PolicyViolation::IssuedTooLongAgo)
+    );
+  }
+
+  #[test]
+  fn test_policy_rejects_missing_expiration_time() {
+    let mut message = message();
+    message.expiration_time = None;
+    assert_eq!(
+      policy().check(&message, now()),
+      Err(PolicyViolation::MissingExpirationTime)
+    );
+  }
+
+  #[test]
+  fn test_policy_rejects_expired_message() {
+    let mut message = message();
+    message.expiration_time = Some((now() - Duration::minutes(2)).into());
     assert_eq!(
-      is_valid_ethereum_address("1234567890123456789012345678901234567890"),
-      false
+      policy().check(&message, now()),
+      Err(PolicyViolation::Expired)
     );
-    // Contains invalid characters
+  }
+
+  #[test]
+  fn test_policy_allows_clock_skew() {
+    let mut message = message();
+    message.issued_at = (now() - Duration::seconds(630)).into();
+    message.expiration_time = Some((now() - Duration::seconds(30)).into());
+    message.not_before = Some((now() + Duration::seconds(30)).into());
+    assert_eq!(policy().check(&message, now()), Ok(()));
+  }
+
+  #[test]
+  fn test_policy_rejects_not_yet_valid_message() {
+    let mut message = message();
+    message.not_before = Some((now() + Duration::minutes(2)).into());
     assert_eq!(
-      is_valid_ethereum_address("0x1234567890GHIJKL9012345678901234567890"),
-      false
+      policy().check(&message, now()),
+      Err(PolicyViolation::NotYetValid)
     );
-    // Empty string
-    assert_eq!(is_valid_ethereum_address(""), false);
+  }
+
+  #[tokio::test]
+  async fn test_verify_rejects_unparsable_message() {
+    let status = verify_siwe_message(
+      "not a message",
+      &invalid_signature(),
+      &policy(),
+      None,
+      now(),
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(status.code(), Code::InvalidArgument);
+    assert_eq!(status.message(), "invalid message");
+  }
+
+  #[tokio::test]
+  async fn test_verify_rejects_policy_violation() {
+    let mut message = message();
+    message.chain_id = 5;
+    let status = verify_siwe_message(
+      &message.to_string(),
+      &invalid_signature(),
+      &policy(),
+      None,
+      now(),
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(status.code(), Code::InvalidArgument);
+    assert_eq!(status.message(), "chain id not allowed");
+  }
+
+  #[tokio::test]
+  async fn test_verify_rejects_malformed_signature() {
+    let message = message().to_string();
+    let status = verify_siwe_message(&message, "0xzz", &policy(), None, now())
+      .await
+      .unwrap_err();
+    assert_eq!(status.code(), Code::InvalidArgument);
+    assert_eq!(status.message(), "invalid signature");
+  }
+
+  #[tokio::test]
+  async fn test_verify_rejects_invalid_signature() {
+    let message = message().to_string();
+    for signature in [invalid_signature(), "0xc0ffee".to_string()] {
+      let status =
+        verify_siwe_message(&message, &signature, &policy(), None, now())
+          .await
+          .unwrap_err();
+      assert_eq!(status.code(), Code::Unauthenticated);
+    }
+  }
+
+  #[tokio::test]
+  async fn test_verify_contract_wallet_signature() {
+    let url = mock_rpc_server(Arc::new(wallet_contract)).await;
+    let ethereum_client = EthereumClient::new(url).unwrap();
+    let message = message().to_string();
+
+    let verified_message = verify_siwe_message(
+      &message,
+      "0xc0ffee",
+      &policy(),
+      Some(&ethereum_client),
+      now(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(verified_message.address, WALLET);
+
+    let status = verify_siwe_message(
+      &message,
+      &invalid_signature(),
+      &policy(),
+      Some(&ethereum_client),
+      now(),
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(status.code(), Code::Unauthenticated);
   }
 }
@@ -1,12 +1,16 @@
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, Duration, Utc};
+use constant_time_eq::constant_time_eq;
 use rand::{
   distributions::{Alphanumeric, DistString},
   CryptoRng, Rng,
 };
 
-use crate::constants::ACCESS_TOKEN_LENGTH;
+use crate::config::CONFIG;
+use crate::constants::{
+  ACCESS_TOKEN_LENGTH, ACCESS_TOKEN_REFRESH_GRACE_PERIOD_DAYS,
+};
 
-#[derive(Clone, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, PartialEq)]
 pub enum AuthType {
   Password,
   Wallet,
@@ -18,6 +22,7 @@ pub struct AccessTokenData {
   pub signing_public_key: String,
   pub access_token: String,
   pub created: DateTime<Utc>,
+  pub expires: DateTime<Utc>,
   pub auth_type: AuthType,
   pub valid: bool,
 }
@@ -29,17 +34,98 @@ impl AccessTokenData {
     auth_type: AuthType,
     rng: &mut (impl Rng + CryptoRng),
   ) -> Self {
+    let created = Utc::now();
     AccessTokenData {
       user_id,
       signing_public_key,
       access_token: Alphanumeric.sample_string(rng, ACCESS_TOKEN_LENGTH),
-      created: Utc::now(),
+      created,
+      expires: created + CONFIG.access_token_lifetime,
       auth_type,
       valid: true,
     }
   }
 
+  /// Creates a new token for the same device, which replaces this one
+  pub fn rotate(&self, rng: &mut (impl Rng + CryptoRng)) -> Self {
+    Self::new(
+      self.user_id.clone(),
+      self.signing_public_key.clone(),
+      self.auth_type.clone(),
+      rng,
+    )
+  }
+
   pub fn is_valid(&self) -> bool {
-    self.valid
+    self.valid && Utc::now() < self.expires
+  }
+
+  /// Time until which the token can be refreshed, even if it has expired
+  pub fn refreshable_until(&self) -> DateTime<Utc> {
+    self.expires + Duration::days(ACCESS_TOKEN_REFRESH_GRACE_PERIOD_DAYS)
+  }
+
+  /// Checks if the token matches this one and is still valid
+  pub fn verify(&self, access_token: &str) -> bool {
+    constant_time_eq(self.access_token.as_bytes(), access_token.as_bytes())
+      && self.is_valid()
+  }
+
+  /// Like `verify`, but also accepts tokens which expired less than the
+  /// grace period ago
+  pub fn verify_for_refresh(&self, access_token: &str) -> bool {
+    constant_time_eq(self.access_token.as_bytes(), access_token.as_bytes())
+      && self.valid
+      && Utc::now() < self.refreshable_until()
+  }
+}
+
+#[cfg(test)]
+mod token_tests {
+  use super::*;
+
+  fn token_data(expires: DateTime<Utc>) -> AccessTokenData {
+    AccessTokenData {
+      user_id: "user".to_string(),
+      signing_public_key: "device".to_string(),
+      access_token: "token".to_string(),
+      created: Utc::now() - Duration::days(1),
+      expires,
+      auth_type: AuthType::Password,
+      valid: true,
+    }
+  }
+
+  #[test]
+  fn test_verify_token() {
+    let token = token_data(Utc::now() + Duration::days(1));
+    assert!(token.verify("token"));
+    assert!(!token.verify("other token"));
+  }
+
+  #[test]
+  fn test_expired_token() {
+    let token = token_data(Utc::now() - Duration::seconds(1));
+    assert!(!token.is_valid());
+    assert!(!token.verify("token"));
+    assert!(token.verify_for_refresh("token"));
+    assert!(!token.verify_for_refresh("other token"));
+  }
+
+  #[test]
+  fn test_token_past_grace_period() {
+    let expires = Utc::now()
+      - Duration::days(ACCESS_TOKEN_REFRESH_GRACE_PERIOD_DAYS)
+      - Duration::seconds(1);
+    let token = token_data(expires);
+    assert!(!token.verify_for_refresh("token"));
+  }
+
+  #[test]
+  fn test_invalidated_token() {
+    let mut token = token_data(Utc::now() + Duration::days(1));
+    token.valid = false;
+    assert!(!token.verify("token"));
+    assert!(!token.verify_for_refresh("token"));
   }
 }
@@ -0,0 +1,250 @@
+//! Two-factor authentication of password users with time-based one-time
+//! passwords (RFC 6238), as generated by authenticator apps. TOTP secrets are
+//! stored encrypted, and recovery codes are stored hashed.
+
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use chrono::{DateTime, Utc};
+use constant_time_eq::constant_time_eq;
+use data_encoding::BASE32_NOPAD;
+use hmac::{Hmac, Mac};
+use rand::{distributions::Alphanumeric, Rng};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use tonic::Status;
+use tracing::{error, warn};
+
+use crate::client_service::handle_db_error;
+use crate::config::CONFIG;
+use crate::constants::{
+  TOTP_ALLOWED_DRIFT_STEPS, TOTP_DIGITS, TOTP_ISSUER, TOTP_RECOVERY_CODE_COUNT,
+  TOTP_SECRET_LENGTH, TOTP_STEP_SECONDS,
+};
+use crate::database::{DatabaseClient, TotpState};
+
+const NONCE_LENGTH: usize = 12;
+// Length of each of the two groups of a recovery code
+const RECOVERY_CODE_GROUP_LENGTH: usize = 5;
+
+pub fn generate_secret() -> Vec<u8> {
+  let mut secret = vec![0; TOTP_SECRET_LENGTH];
+  rand::thread_rng().fill(&mut secret[..]);
+  secret
+}
+
+/// URI understood by authenticator apps, usually shown as a QR code
+pub fn otpauth_uri(secret: &[u8], username: &str) -> String {
+  let label = format!("{}:{}", TOTP_ISSUER, username);
+  format!(
+    "otpauth://totp/{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+    percent_encode(&label),
+    BASE32_NOPAD.encode(secret),
+    percent_encode(TOTP_ISSUER),
+    TOTP_DIGITS,
+    TOTP_STEP_SECONDS
+  )
+}
+
+fn percent_encode(value: &str) -> String {
+  value
+    .bytes()
+    .map(|byte| match byte {
+      b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+        (byte as char).to_string()
+      }
+      _ => format!("%{:02X}", byte),
+    })
+    .collect()
+}
+
+pub fn time_step(time: DateTime<Utc>) -> i64 {
+  time.timestamp().div_euclid(TOTP_STEP_SECONDS)
+}
+
+fn hotp(secret: &[u8], counter: u64) -> String {
+  let mut mac = <Hmac<Sha1> as Mac>::new_from_slice(secret)
+    .expect("HMAC accepts any key length");
+  mac.update(&counter.to_be_bytes());
+  let hash = mac.finalize().into_bytes();
+
+  // Dynamic truncation, see RFC 4226 section 5.3
+  let offset = (hash[hash.len() - 1] & 0xf) as usize;
+  let binary = u32::from_be_bytes([
+    hash[offset] & 0x7f,
+    hash[offset + 1],
+    hash[offset + 2],
+    hash[offset + 3],
+  ]);
+  let code = binary % 10_u32.pow(TOTP_DIGITS);
+  format!("{:0width$}", code, width = TOTP_DIGITS as usize)
+}
+
+pub fn totp_code(secret: &[u8], step: i64) -> String {
+  hotp(secret, step as u64)
+}
+
+/// Returns the time step of the code, if it's valid at the given time. Codes
+/// of adjacent steps are accepted to allow for clock drift.
+pub fn verify_totp_code(
+  secret: &[u8],
+  code: &str,
+  time: DateTime<Utc>,
+) -> Option<i64> {
+  let current_step = time_step(time);
+  (current_step - TOTP_ALLOWED_DRIFT_STEPS
+    ..=current_step + TOTP_ALLOWED_DRIFT_STEPS)
+    .find(|step| {
+      constant_time_eq(totp_code(secret, *step).as_bytes(), code.as_bytes())
+    })
+}
+
+/// Recovery codes are formatted as `xxxxx-xxxxx`
+pub fn generate_recovery_codes() -> Vec<String> {
+  let mut rng = rand::thread_rng();
+  (0..TOTP_RECOVERY_CODE_COUNT)
+    .map(|_| {
+      let code: String = (&mut rng)
+        .sample_iter(&Alphanumeric)
+        .take(2 * RECOVERY_CODE_GROUP_LENGTH)
+        .map(|c| char::from(c).to_ascii_lowercase())
+        .collect();
+      let (first, second) = code.split_at(RECOVERY_CODE_GROUP_LENGTH);
+      format!("{}-{}", first, second)
+    })
+    .collect()
+}
+
+/// Recovery codes are case insensitive and can be entered without the dash
+pub fn hash_recovery_code(code: &str) -> String {
+  let normalized: String = code
+    .chars()
+    .filter(char::is_ascii_alphanumeric)
+    .map(|c| c.to_ascii_lowercase())
+    .collect();
+  hex::encode(Sha256::digest(normalized.as_bytes()))
+}
+
+fn encryption_key() -> Result<&'static Key<Aes256Gcm>, Status> {
+  CONFIG.totp_encryption_key.as_ref().ok_or_else(|| {
+    error!("TOTP encryption key is not configured");
+    Status::failed_precondition("two-factor authentication unavailable")
+  })
+}
+
+/// Returns the sealed secret in the following format: nonce || ciphertext
+pub fn encrypt_secret(secret: &[u8]) -> Result<Vec<u8>, Status> {
+  let cipher = Aes256Gcm::new(encryption_key()?);
+  let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+  let ciphertext = cipher
+    .encrypt(&nonce, secret)
+    .map_err(|_| Status::internal("unexpected error"))?;
+  Ok([nonce.as_slice(), &ciphertext].concat())
+}
+
+pub fn decrypt_secret(encrypted_secret: &[u8]) -> Result<Vec<u8>, Status> {
+  if encrypted_secret.len() < NONCE_LENGTH {
+    return Err(Status::internal("unexpected error"));
+  }
+  let cipher = Aes256Gcm::new(encryption_key()?);
+  let (nonce, ciphertext) = encrypted_secret.split_at(NONCE_LENGTH);
+  cipher
+    .decrypt(Nonce::from_slice(nonce), ciphertext)
+    .map_err(|_| {
+      error!("Failed to decrypt TOTP secret");
+      Status::internal("unexpected error")
+    })
+}
+
+/// Checks a TOTP code or a recovery code of a user with two-factor
+/// authentication enabled. Each code can be used only once.
+pub async fn verify_second_factor(
+  db_client: &DatabaseClient,
+  user_id: &str,
+  totp_state: &TotpState,
+  code: &str,
+) -> Result<bool, Status> {
+  // Recovery codes don't depend on the secret, so they're checked first
+  let code_hash = hash_recovery_code(code);
+  if totp_state.recovery_code_hashes.contains(&code_hash) {
+    return db_client
+      .use_totp_recovery_code(user_id, &code_hash)
+      .await
+      .map_err(handle_db_error);
+  }
+
+  let secret = decrypt_secret(&totp_state.encrypted_secret)?;
+  let Some(step) = verify_totp_code(&secret, code, Utc::now()) else {
+    return Ok(false);
+  };
+  let unused = db_client
+    .use_totp_step(user_id, step)
+    .await
+    .map_err(handle_db_error)?;
+  if !unused {
+    warn!("Reused TOTP code for user {}", user_id);
+  }
+  Ok(unused)
+}
+
+#[cfg(test)]
+mod totp_tests {
+  use super::*;
+  use chrono::TimeZone;
+
+  // Secret of the RFC 6238 SHA1 test vectors
+  const RFC_SECRET: &[u8] = b"12345678901234567890";
+
+  #[test]
+  fn test_rfc_6238_vectors() {
+    // The RFC lists 8 digit codes, of which the last 6 digits are used
+    let vectors = [
+      (59, "287082"),
+      (1111111109, "081804"),
+      (1111111111, "050471"),
+      (1234567890, "005924"),
+      (2000000000, "279037"),
+    ];
+    for (timestamp, expected_code) in vectors {
+      let time = Utc.timestamp_opt(timestamp, 0).unwrap();
+      assert_eq!(totp_code(RFC_SECRET, time_step(time)), expected_code);
+    }
+  }
+
+  #[test]
+  fn test_verify_totp_code() {
+    let time = Utc.timestamp_opt(1111111109, 0).unwrap();
+    let step = time_step(time);
+
+    assert_eq!(verify_totp_code(RFC_SECRET, "081804", time), Some(step));
+    // Code of the previous step
+    let code = totp_code(RFC_SECRET, step - 1);
+    assert_eq!(verify_totp_code(RFC_SECRET, &code, time), Some(step - 1));
+    // Code of two steps ago
+    let code = totp_code(RFC_SECRET, step - 2);
+    assert_eq!(verify_totp_code(RFC_SECRET, &code, time), None);
+    assert_eq!(verify_totp_code(RFC_SECRET, "81804", time), None);
+  }
+
+  #[test]
+  fn test_otpauth_uri() {
+    assert_eq!(
+      otpauth_uri(RFC_SECRET, "ashoat"),
+      "otpauth://totp/Comm%3Aashoat?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ\
+      &issuer=Comm&algorithm=SHA1&digits=6&period=30"
+    );
+  }
+
+  #[test]
+  fn test_recovery_codes() {
+    let codes = generate_recovery_codes();
+    assert_eq!(codes.len(), TOTP_RECOVERY_CODE_COUNT);
+    assert!(codes.iter().all(|code| code.len() == 11));
+
+    let code = &codes[0];
+    assert_eq!(
+      hash_recovery_code(code),
+      hash_recovery_code(&code.to_uppercase().replace('-', ""))
+    );
+    assert_ne!(hash_recovery_code(code), hash_recovery_code(&codes[1]));
+  }
+}
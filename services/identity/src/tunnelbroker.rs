@@ -1,16 +1,35 @@
+use std::time::Duration;
+
 use crate::config::CONFIG;
+use crate::constants::{
+  ONE_TIME_KEY_REFRESH_INTERVAL_SECONDS, ONE_TIME_KEY_REFRESH_REQUESTED_METRIC,
+};
+use comm_services_lib::auth::{AuthService, AuthorizationCredential};
+use grpc_clients::tunnelbroker::create_authenticated_tunnelbroker_client as shared_authenticated_tb_client;
 use grpc_clients::tunnelbroker::create_tunnelbroker_client as shared_tb_client;
+use grpc_clients::tunnelbroker::invalidate_access_tokens as shared_invalidate;
 use grpc_clients::tunnelbroker::protos;
+use grpc_clients::tunnelbroker::purge_device_queue as shared_purge_queue;
+use grpc_clients::tunnelbroker::AuthenticatedTunnelbrokerClient;
+use moka::future::Cache;
+use once_cell::sync::Lazy;
 use protos::tunnelbroker_service_client::TunnelbrokerServiceClient;
 use protos::{Empty, MessageToDevice};
 use tonic::transport::Channel;
 use tonic::Response;
 use tonic::Status;
-use tracing::error;
+use tracing::{debug, error, info};
 use tunnelbroker_messages as messages;
 
 use crate::error::Error;
 
+// Devices which were recently asked for more one-time keys
+static RECENT_REFRESH_REQUESTS: Lazy<Cache<String, ()>> = Lazy::new(|| {
+  Cache::builder()
+    .time_to_live(Duration::from_secs(ONE_TIME_KEY_REFRESH_INTERVAL_SECONDS))
+    .build()
+});
+
 pub async fn create_tunnelbroker_client(
 ) -> Result<TunnelbrokerServiceClient<Channel>, Error> {
   shared_tb_client(&CONFIG.tunnelbroker_endpoint)
@@ -21,7 +40,68 @@ pub async fn create_tunnelbroker_client(
     })
 }
 
-pub async fn send_refresh_keys_request(
+/// Creates a client authenticated with the services token, which Tunnelbroker
+/// requires for RPCs not meant to be called by devices
+async fn create_authenticated_tunnelbroker_client(
+  auth_service: &AuthService,
+) -> Result<AuthenticatedTunnelbrokerClient, Error> {
+  let services_token =
+    auth_service.get_services_token().await.map_err(|e| {
+      error!("Unable to get services token: {:?}", e);
+      Error::Status(Status::unavailable(format!("{}", e)))
+    })?;
+  let authorization_token =
+    AuthorizationCredential::ServicesToken(services_token)
+      .as_authorization_token()?;
+
+  shared_authenticated_tb_client(
+    &CONFIG.tunnelbroker_endpoint,
+    authorization_token,
+  )
+  .await
+  .map_err(|e| {
+    error!("Unable able to connect to tunnelbroker: {:?}", e);
+    Error::Status(Status::invalid_argument(format!("{}", e)))
+  })
+}
+
+/// Asks the device for more one-time keys, unless it was already asked within
+/// the last `ONE_TIME_KEY_REFRESH_INTERVAL_SECONDS`
+pub async fn request_one_time_keys_refresh(
+  device_id: &str,
+) -> Result<(), Error> {
+  if !mark_refresh_requested(&RECENT_REFRESH_REQUESTS, device_id).await {
+    debug!("Device {} was already asked for more keys", device_id);
+    return Ok(());
+  }
+
+  info!(
+    metric = ONE_TIME_KEY_REFRESH_REQUESTED_METRIC,
+    device_id, "Requesting more one-time keys"
+  );
+  let result = send_refresh_keys_request(device_id).await;
+  if result.is_err() {
+    // Retry with the next key request instead of waiting for the interval
+    RECENT_REFRESH_REQUESTS
+      .invalidate(&device_id.to_string())
+      .await;
+  }
+  result.map(|_| ())
+}
+
+/// Returns false if the device was already marked
+async fn mark_refresh_requested(
+  recent_requests: &Cache<String, ()>,
+  device_id: &str,
+) -> bool {
+  let mut is_new_request = false;
+  recent_requests
+    .get_with(device_id.to_string(), async { is_new_request = true })
+    .await;
+  is_new_request
+}
+
+async fn send_refresh_keys_request(
   device_id: &str,
 ) -> Result<Response<Empty>, Error> {
   use crate::constants::ONE_TIME_KEY_REFRESH_NUMBER;
@@ -46,3 +126,64 @@ pub async fn send_refresh_keys_request(
       .await?,
   )
 }
+
+/// Tells Tunnelbroker to stop accepting access tokens which were revoked
+pub async fn invalidate_access_tokens(
+  auth_service: &AuthService,
+  user_id: &str,
+  device_id: Option<&str>,
+) -> Result<(), Error> {
+  let mut tunnelbroker_client =
+    create_authenticated_tunnelbroker_client(auth_service).await?;
+
+  shared_invalidate(&mut tunnelbroker_client, user_id, device_id)
+    .await
+    .map_err(|e| {
+      error!(
+        "Unable to invalidate access tokens in tunnelbroker: {:?}",
+        e
+      );
+      Error::Status(Status::unavailable(format!("{}", e)))
+    })
+}
+
+/// Deletes messages which weren't delivered to the device yet
+pub async fn purge_device_queue(
+  auth_service: &AuthService,
+  device_id: &str,
+) -> Result<(), Error> {
+  let mut tunnelbroker_client =
+    create_authenticated_tunnelbroker_client(auth_service).await?;
+
+  shared_purge_queue(&mut tunnelbroker_client, device_id)
+    .await
+    .map(|deleted_messages_count| {
+      debug!(
+        "Purged {} undelivered messages of device {}",
+        deleted_messages_count, device_id
+      );
+    })
+    .map_err(|e| {
+      error!("Unable to purge device queue in tunnelbroker: {:?}", e);
+      Error::Status(Status::unavailable(format!("{}", e)))
+    })
+}
+
+#[cfg(test)]
+mod tunnelbroker_tests {
+  use super::*;
+
+  #[tokio::test]
+  async fn test_refresh_requests_are_deduplicated() {
+    let recent_requests = Cache::builder()
+      .time_to_live(Duration::from_millis(100))
+      .build();
+
+    assert!(mark_refresh_requested(&recent_requests, "device1").await);
+    assert!(!mark_refresh_requested(&recent_requests, "device1").await);
+    assert!(mark_refresh_requested(&recent_requests, "device2").await);
+
+    tokio::time::sleep(Duration::from_millis(150)).await;
+    assert!(mark_refresh_requested(&recent_requests, "device1").await);
+  }
+}
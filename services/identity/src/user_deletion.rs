@@ -0,0 +1,332 @@
+//! Removal of everything that outlives a deleted user's row in the users
+//! table. Each deletion is stored with its pending steps, so that steps which
+//! fail, e.g. because another service is down, are retried with backoff until
+//! they succeed, even across restarts.
+
+use std::str::FromStr;
+
+use chrono::{DateTime, Duration, Utc};
+use comm_services_lib::auth::AuthService;
+use comm_services_lib::blob::client::BlobServiceClient;
+use tracing::{error, info, warn};
+
+use crate::account_export::{revoke_export_blobs, services_blob_client};
+use crate::constants::{
+  USER_DELETION_ALERT_ATTEMPTS, USER_DELETION_BASE_BACKOFF_SECONDS,
+  USER_DELETION_CLAIM_SECONDS, USER_DELETION_MAX_BACKOFF_SECONDS,
+  USER_DELETION_RETRY_INTERVAL_SECONDS, USER_DELETION_STALLED_METRIC,
+};
+use crate::database::DatabaseClient;
+use crate::error::{consume_error, Error};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DeletionStep {
+  AccessTokens,
+  OneTimeKeys,
+  TunnelbrokerQueues,
+  Backups,
+  AccountExports,
+}
+
+impl DeletionStep {
+  pub const ALL: [DeletionStep; 5] = [
+    DeletionStep::AccessTokens,
+    DeletionStep::OneTimeKeys,
+    DeletionStep::TunnelbrokerQueues,
+    DeletionStep::Backups,
+    DeletionStep::AccountExports,
+  ];
+
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      DeletionStep::AccessTokens => "accessTokens",
+      DeletionStep::OneTimeKeys => "oneTimeKeys",
+      DeletionStep::TunnelbrokerQueues => "tunnelbrokerQueues",
+      DeletionStep::Backups => "backups",
+      DeletionStep::AccountExports => "accountExports",
+    }
+  }
+}
+
+impl FromStr for DeletionStep {
+  type Err = ();
+
+  /// Parses the value returned by `DeletionStep::as_str()`
+  fn from_str(value: &str) -> Result<Self, Self::Err> {
+    match value {
+      "accessTokens" => Ok(DeletionStep::AccessTokens),
+      "oneTimeKeys" => Ok(DeletionStep::OneTimeKeys),
+      "tunnelbrokerQueues" => Ok(DeletionStep::TunnelbrokerQueues),
+      "backups" => Ok(DeletionStep::Backups),
+      "accountExports" => Ok(DeletionStep::AccountExports),
+      _ => Err(()),
+    }
+  }
+}
+
+#[derive(Clone, Debug)]
+pub struct UserDeletion {
+  pub user_id: String,
+  // Read before the user's row is deleted, since it holds the devices
+  pub device_ids: Vec<String>,
+  pub pending_steps: Vec<DeletionStep>,
+  pub attempts: u32,
+  pub last_error: Option<String>,
+  pub created: DateTime<Utc>,
+  // Not set once all steps succeeded
+  pub next_attempt_time: Option<DateTime<Utc>>,
+}
+
+/// Delay before the next attempt, after `attempts` failed ones
+fn retry_backoff(attempts: u32) -> Duration {
+  let multiplier = 2_i64.saturating_pow(attempts.saturating_sub(1));
+  let seconds = USER_DELETION_BASE_BACKOFF_SECONDS
+    .saturating_mul(multiplier)
+    .min(USER_DELETION_MAX_BACKOFF_SECONDS);
+  Duration::seconds(seconds)
+}
+
+#[derive(Clone)]
+pub struct UserDeletionWorker {
+  db_client: DatabaseClient,
+  auth_service: AuthService,
+  blob_client: BlobServiceClient,
+  http_client: reqwest::Client,
+}
+
+impl UserDeletionWorker {
+  pub fn new(
+    db_client: DatabaseClient,
+    auth_service: AuthService,
+    blob_client: BlobServiceClient,
+  ) -> Self {
+    UserDeletionWorker {
+      db_client,
+      auth_service,
+      blob_client,
+      http_client: reqwest::Client::new(),
+    }
+  }
+
+  /// Stores the deletion with all steps pending. It's claimed until
+  /// `USER_DELETION_CLAIM_SECONDS` from now, so it's only retried if the
+  /// first attempt doesn't finish by then.
+  pub async fn start(
+    &self,
+    user_id: String,
+    device_ids: Vec<String>,
+  ) -> Result<UserDeletion, Error> {
+    let now = Utc::now();
+    let deletion = UserDeletion {
+      user_id,
+      device_ids,
+      pending_steps: DeletionStep::ALL.to_vec(),
+      attempts: 0,
+      last_error: None,
+      created: now,
+      next_attempt_time: Some(
+        now + Duration::seconds(USER_DELETION_CLAIM_SECONDS),
+      ),
+    };
+    self.db_client.put_user_deletion(&deletion).await?;
+
+    Ok(deletion)
+  }
+
+  /// Removes the deletion of a user who wasn't deleted after all
+  pub async fn abort(&self, user_id: &str) -> Result<(), Error> {
+    self.db_client.delete_user_deletion(user_id).await
+  }
+
+  /// Runs the first attempt without delaying the response
+  pub fn spawn_run(&self, deletion: UserDeletion) {
+    let worker = self.clone();
+    tokio::spawn(async move {
+      let result = worker.run(deletion).await;
+      consume_error(result);
+    });
+  }
+
+  /// Retries due deletions every `USER_DELETION_RETRY_INTERVAL_SECONDS`
+  pub async fn run_retry_loop(self) {
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(
+      USER_DELETION_RETRY_INTERVAL_SECONDS,
+    ));
+    loop {
+      interval.tick().await;
+      let result = self.retry_due_deletions().await;
+      consume_error(result);
+    }
+  }
+
+  async fn retry_due_deletions(&self) -> Result<(), Error> {
+    let now = Utc::now();
+    for deletion in self.db_client.get_due_user_deletions(now).await? {
+      let Some(next_attempt_time) = deletion.next_attempt_time else {
+        continue;
+      };
+      let claimed_until = now + Duration::seconds(USER_DELETION_CLAIM_SECONDS);
+      let claimed = self
+        .db_client
+        .claim_user_deletion(
+          &deletion.user_id,
+          next_attempt_time,
+          claimed_until,
+        )
+        .await?;
+      if !claimed {
+        // Another replica is retrying it
+        continue;
+      }
+
+      self.run(deletion).await?;
+    }
+
+    Ok(())
+  }
+
+  /// Runs all pending steps and stores which of them are still pending
+  async fn run(&self, mut deletion: UserDeletion) -> Result<(), Error> {
+    // The deletion is stored before the user's row is deleted, so if deleting
+    // the row failed, none of the user's other data may be removed
+    let user_exists = self
+      .db_client
+      .get_item_from_users_table(&deletion.user_id)
+      .await?
+      .item
+      .is_some();
+    if user_exists {
+      warn!(
+        "User {} still exists, aborting their deletion",
+        deletion.user_id
+      );
+      return self.abort(&deletion.user_id).await;
+    }
+
+    let mut pending_steps = Vec::new();
+    let mut last_error = None;
+    for step in std::mem::take(&mut deletion.pending_steps) {
+      if let Err(e) = self.run_step(step, &deletion).await {
+        warn!(
+          "Step {} of deleting user {} failed: {}",
+          step.as_str(),
+          deletion.user_id,
+          e
+        );
+        pending_steps.push(step);
+        last_error = Some(format!("{}: {}", step.as_str(), e));
+      }
+    }
+
+    deletion.pending_steps = pending_steps;
+    deletion.attempts += 1;
+    if deletion.pending_steps.is_empty() {
+      info!("User {} has been fully deleted", deletion.user_id);
+      deletion.next_attempt_time = None;
+    } else {
+      if deletion.attempts >= USER_DELETION_ALERT_ATTEMPTS {
+        error!(
+          metric = USER_DELETION_STALLED_METRIC,
+          user_id = deletion.user_id.as_str(),
+          attempts = deletion.attempts,
+          "Deleting user is still failing"
+        );
+      }
+      deletion.last_error = last_error;
+      deletion.next_attempt_time =
+        Some(Utc::now() + retry_backoff(deletion.attempts));
+    }
+
+    self.db_client.put_user_deletion(&deletion).await
+  }
+
+  async fn run_step(
+    &self,
+    step: DeletionStep,
+    deletion: &UserDeletion,
+  ) -> Result<(), Error> {
+    let user_id = &deletion.user_id;
+    match step {
+      DeletionStep::AccessTokens => {
+        for token in self.db_client.get_access_tokens_for_user(user_id).await? {
+          self
+            .db_client
+            .delete_access_token_data(
+              user_id.to_string(),
+              token.signing_public_key,
+            )
+            .await?;
+        }
+        crate::tunnelbroker::invalidate_access_tokens(
+          &self.auth_service,
+          user_id,
+          None,
+        )
+        .await
+      }
+      DeletionStep::OneTimeKeys => {
+        for device_id in &deletion.device_ids {
+          self.db_client.delete_one_time_keys(device_id).await?;
+        }
+        Ok(())
+      }
+      DeletionStep::TunnelbrokerQueues => {
+        for device_id in &deletion.device_ids {
+          crate::tunnelbroker::purge_device_queue(
+            &self.auth_service,
+            device_id,
+          )
+          .await?;
+        }
+        Ok(())
+      }
+      DeletionStep::Backups => {
+        crate::backup::delete_user_data(
+          &self.auth_service,
+          &self.http_client,
+          user_id,
+        )
+        .await
+      }
+      DeletionStep::AccountExports => {
+        let export_blobs =
+          self.db_client.get_export_blobs_for_user(user_id).await?;
+        if export_blobs.is_empty() {
+          return Ok(());
+        }
+        let blob_client =
+          services_blob_client(&self.blob_client, &self.auth_service).await?;
+        revoke_export_blobs(&self.db_client, &blob_client, export_blobs).await
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod user_deletion_tests {
+  use super::*;
+
+  #[test]
+  fn test_step_round_trip() {
+    for step in DeletionStep::ALL {
+      assert_eq!(step.as_str().parse(), Ok(step));
+    }
+    assert_eq!("unknown".parse::<DeletionStep>(), Err(()));
+  }
+
+  #[test]
+  fn test_retry_backoff() {
+    assert_eq!(
+      retry_backoff(1),
+      Duration::seconds(USER_DELETION_BASE_BACKOFF_SECONDS)
+    );
+    assert_eq!(
+      retry_backoff(2),
+      Duration::seconds(USER_DELETION_BASE_BACKOFF_SECONDS * 2)
+    );
+    assert_eq!(
+      retry_backoff(100),
+      Duration::seconds(USER_DELETION_MAX_BACKOFF_SECONDS)
+    );
+  }
+}
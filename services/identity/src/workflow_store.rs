@@ -0,0 +1,248 @@
+//! Storage for the state of multi-step workflows (registration, login and
+//! password update) between their `*_start` and `*_finish` requests. With the
+//! DynamoDB store, a workflow can be finished by a different replica than the
+//! one which started it.
+
+use std::time::{Duration, Instant};
+
+use chrono::Utc;
+use moka::future::Cache;
+
+use crate::client_service::WorkflowInProgress;
+use crate::constants::{
+  TWO_FACTOR_LOGIN_TTL_SECONDS, WORKFLOW_IN_PROGRESS_TTL_SECONDS,
+};
+use crate::database::DatabaseClient;
+use crate::error::Error;
+use crate::id::generate_uuid;
+
+#[tonic::async_trait]
+pub trait WorkflowStore: Send + Sync {
+  /// Stores the workflow and returns its session ID
+  async fn insert(&self, workflow: WorkflowInProgress)
+    -> Result<String, Error>;
+
+  /// Removes and returns the workflow, so that a session can be finished only
+  /// once. Returns `None` for unknown and expired sessions.
+  async fn take(
+    &self,
+    session_id: &str,
+  ) -> Result<Option<WorkflowInProgress>, Error>;
+}
+
+/// Works only with a single replica
+pub struct InMemoryWorkflowStore {
+  // Entries are kept for the longest workflow TTL, and expire according to
+  // their own TTL
+  cache: Cache<String, (WorkflowInProgress, Instant)>,
+}
+
+impl InMemoryWorkflowStore {
+  pub fn new() -> Self {
+    let max_time_to_live =
+      WORKFLOW_IN_PROGRESS_TTL_SECONDS.max(TWO_FACTOR_LOGIN_TTL_SECONDS);
+    let cache = Cache::builder()
+      .time_to_live(Duration::from_secs(max_time_to_live))
+      .build();
+    Self { cache }
+  }
+}
+
+#[tonic::async_trait]
+impl WorkflowStore for InMemoryWorkflowStore {
+  async fn insert(
+    &self,
+    workflow: WorkflowInProgress,
+  ) -> Result<String, Error> {
+    let session_id = generate_uuid();
+    let expiration_time = Instant::now() + workflow.time_to_live();
+    self
+      .cache
+      .insert(session_id.clone(), (workflow, expiration_time))
+      .await;
+    Ok(session_id)
+  }
+
+  async fn take(
+    &self,
+    session_id: &str,
+  ) -> Result<Option<WorkflowInProgress>, Error> {
+    let entry = self.cache.get(session_id);
+    self.cache.invalidate(session_id).await;
+    Ok(
+      entry
+        .filter(|(_, expiration_time)| *expiration_time > Instant::now())
+        .map(|(workflow, _)| workflow),
+    )
+  }
+}
+
+/// Workflows are stored as JSON, with the DynamoDB TTL removing abandoned
+/// ones
+pub struct DynamoDBWorkflowStore {
+  db_client: DatabaseClient,
+}
+
+impl DynamoDBWorkflowStore {
+  pub fn new(db_client: DatabaseClient) -> Self {
+    Self { db_client }
+  }
+}
+
+#[tonic::async_trait]
+impl WorkflowStore for DynamoDBWorkflowStore {
+  async fn insert(
+    &self,
+    workflow: WorkflowInProgress,
+  ) -> Result<String, Error> {
+    let session_id = generate_uuid();
+    let workflow_json = serde_json::to_string(&workflow)?;
+    let expiration_time = Utc::now()
+      + chrono::Duration::from_std(workflow.time_to_live())
+        .expect("Workflow TTL out of range");
+
+    self
+      .db_client
+      .put_workflow_in_progress(&session_id, workflow_json, expiration_time)
+      .await?;
+    Ok(session_id)
+  }
+
+  async fn take(
+    &self,
+    session_id: &str,
+  ) -> Result<Option<WorkflowInProgress>, Error> {
+    let Some((workflow_json, expiration_time)) =
+      self.db_client.take_workflow_in_progress(session_id).await?
+    else {
+      return Ok(None);
+    };
+
+    // TTL doesn't remove items immediately after they expire
+    if expiration_time <= Utc::now() {
+      return Ok(None);
+    }
+    Ok(Some(serde_json::from_str(&workflow_json)?))
+  }
+}
+
+/// Serializes the OPAQUE server login state as base64
+pub(crate) mod opaque_server_login_serde {
+  use base64::{engine::general_purpose, Engine as _};
+  use comm_opaque2::server::Login;
+  use serde::{de::Error as _, ser::Error as _};
+  use serde::{Deserialize, Deserializer, Serializer};
+
+  pub fn serialize<S: Serializer>(
+    login: &Login,
+    serializer: S,
+  ) -> Result<S::Ok, S::Error> {
+    let state = login
+      .serialize()
+      .ok_or_else(|| S::Error::custom("login was not started"))?;
+    serializer.serialize_str(&general_purpose::STANDARD.encode(state))
+  }
+
+  pub fn deserialize<'de, D: Deserializer<'de>>(
+    deserializer: D,
+  ) -> Result<Login, D::Error> {
+    let encoded_state = String::deserialize(deserializer)?;
+    let state = general_purpose::STANDARD
+      .decode(encoded_state)
+      .map_err(D::Error::custom)?;
+    Login::deserialize(&state).map_err(|e| D::Error::custom(e.to_string()))
+  }
+}
+
+/// Serializes device types as their protobuf values
+pub(crate) mod device_type_serde {
+  use serde::{de::Error as _, Deserialize, Deserializer, Serializer};
+
+  use crate::database::DeviceType;
+
+  pub fn serialize<S: Serializer>(
+    device_type: &DeviceType,
+    serializer: S,
+  ) -> Result<S::Ok, S::Error> {
+    serializer.serialize_i32(*device_type as i32)
+  }
+
+  pub fn deserialize<'de, D: Deserializer<'de>>(
+    deserializer: D,
+  ) -> Result<DeviceType, D::Error> {
+    let value = i32::deserialize(deserializer)?;
+    DeviceType::try_from(value).map_err(D::Error::custom)
+  }
+}
+
+#[cfg(test)]
+mod workflow_store_tests {
+  use super::*;
+  use crate::client_service::{
+    FlattenedDeviceKeyUpload, UpdateState, UserRegistrationInfo,
+  };
+  use crate::database::DeviceType;
+
+  fn registration_workflow() -> WorkflowInProgress {
+    WorkflowInProgress::Registration(Box::new(UserRegistrationInfo {
+      username: "alice".to_string(),
+      flattened_device_key_upload: FlattenedDeviceKeyUpload {
+        device_id_key: "device".to_string(),
+        key_payload: "payload".to_string(),
+        key_payload_signature: "signature".to_string(),
+        content_prekey: "content_prekey".to_string(),
+        content_prekey_signature: "content_signature".to_string(),
+        content_one_time_keys: vec!["a".to_string()],
+        notif_prekey: "notif_prekey".to_string(),
+        notif_prekey_signature: "notif_signature".to_string(),
+        notif_one_time_keys: vec!["b".to_string()],
+        device_type: DeviceType::Ios,
+        code_version: Some(270),
+      },
+      user_id: None,
+    }))
+  }
+
+  #[test]
+  fn test_workflow_serialization() {
+    let json = serde_json::to_string(&registration_workflow()).unwrap();
+    let WorkflowInProgress::Registration(registration) =
+      serde_json::from_str(&json).unwrap()
+    else {
+      panic!("Deserialized a different workflow");
+    };
+    assert_eq!(registration.username, "alice");
+    let device_key_upload = registration.flattened_device_key_upload;
+    assert_eq!(device_key_upload.device_type, DeviceType::Ios);
+    assert_eq!(device_key_upload.code_version, Some(270));
+    assert_eq!(device_key_upload.notif_one_time_keys, vec!["b"]);
+
+    let update = WorkflowInProgress::Update(UpdateState {
+      user_id: "user".to_string(),
+    });
+    let json = serde_json::to_string(&update).unwrap();
+    assert!(matches!(
+      serde_json::from_str(&json).unwrap(),
+      WorkflowInProgress::Update(UpdateState { user_id }) if user_id == "user"
+    ));
+  }
+
+  #[test]
+  fn test_unstarted_login_is_not_serialized() {
+    let login = comm_opaque2::server::Login::new();
+    let mut serializer = serde_json::Serializer::new(Vec::new());
+    assert!(
+      opaque_server_login_serde::serialize(&login, &mut serializer).is_err()
+    );
+  }
+
+  #[tokio::test]
+  async fn test_in_memory_session_taken_once() {
+    let store = InMemoryWorkflowStore::new();
+    let session_id = store.insert(registration_workflow()).await.unwrap();
+
+    assert!(store.take(&session_id).await.unwrap().is_some());
+    assert!(store.take(&session_id).await.unwrap().is_none());
+    assert!(store.take("unknown").await.unwrap().is_none());
+  }
+}
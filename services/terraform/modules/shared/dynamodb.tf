@@ -96,6 +96,28 @@ resource "aws_dynamodb_table" "tunnelbroker-undelivered-messages" {
   }
 }
 
+resource "aws_dynamodb_table" "tunnelbroker-device-presence" {
+  name         = "tunnelbroker-device-presence"
+  hash_key     = "deviceID"
+  billing_mode = "PAY_PER_REQUEST"
+
+  attribute {
+    name = "deviceID"
+    type = "S"
+  }
+
+  attribute {
+    name = "userID"
+    type = "S"
+  }
+
+  global_secondary_index {
+    name            = "userID-index"
+    hash_key        = "userID"
+    projection_type = "ALL"
+  }
+}
+
 resource "aws_dynamodb_table" "identity-users" {
   name         = "identity-users"
   hash_key     = "userID"
@@ -191,6 +213,11 @@ resource "aws_dynamodb_table" "identity-tokens" {
     name = "signingPublicKey"
     type = "S"
   }
+
+  ttl {
+    attribute_name = "expirationTimeUnix"
+    enabled        = true
+  }
 }
 
 resource "aws_dynamodb_table" "identity-nonces" {
@@ -218,6 +245,12 @@ resource "aws_dynamodb_table" "identity-reserved-usernames" {
     name = "username"
     type = "S"
   }
+
+  # Set on usernames held after a username change
+  ttl {
+    attribute_name = "expirationTimeUnix"
+    enabled        = true
+  }
 }
 
 resource "aws_dynamodb_table" "identity-one-time-keys" {
@@ -237,6 +270,93 @@ resource "aws_dynamodb_table" "identity-one-time-keys" {
   }
 }
 
+resource "aws_dynamodb_table" "identity-login-attempts" {
+  name         = "identity-login-attempts"
+  hash_key     = "attemptKey"
+  billing_mode = "PAY_PER_REQUEST"
+
+  attribute {
+    name = "attemptKey"
+    type = "S"
+  }
+
+  ttl {
+    attribute_name = "expirationTimeUnix"
+    enabled        = true
+  }
+}
+
+resource "aws_dynamodb_table" "identity-workflows-in-progress" {
+  name         = "identity-workflows-in-progress"
+  hash_key     = "sessionID"
+  billing_mode = "PAY_PER_REQUEST"
+
+  attribute {
+    name = "sessionID"
+    type = "S"
+  }
+
+  ttl {
+    attribute_name = "expirationTimeUnix"
+    enabled        = true
+  }
+}
+
+resource "aws_dynamodb_table" "identity-audit-log" {
+  name         = "identity-audit-log"
+  hash_key     = "subject"
+  range_key    = "eventID"
+  billing_mode = "PAY_PER_REQUEST"
+
+  attribute {
+    name = "subject"
+    type = "S"
+  }
+
+  attribute {
+    name = "eventID"
+    type = "S"
+  }
+
+  ttl {
+    attribute_name = "expirationTimeUnix"
+    enabled        = true
+  }
+}
+
+resource "aws_dynamodb_table" "identity-account-exports" {
+  name         = "identity-account-exports"
+  hash_key     = "userID"
+  range_key    = "holder"
+  billing_mode = "PAY_PER_REQUEST"
+
+  attribute {
+    name = "userID"
+    type = "S"
+  }
+
+  attribute {
+    name = "holder"
+    type = "S"
+  }
+}
+
+resource "aws_dynamodb_table" "identity-user-deletions" {
+  name         = "identity-user-deletions"
+  hash_key     = "userID"
+  billing_mode = "PAY_PER_REQUEST"
+
+  attribute {
+    name = "userID"
+    type = "S"
+  }
+
+  ttl {
+    attribute_name = "expirationTimeUnix"
+    enabled        = true
+  }
+}
+
 resource "aws_dynamodb_table" "feature-flags" {
   name         = "feature-flags"
   hash_key     = "platform"
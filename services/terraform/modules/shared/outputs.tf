@@ -4,6 +4,7 @@ locals {
     aws_dynamodb_table.backup-service-backup,
     aws_dynamodb_table.reports-service-reports,
     aws_dynamodb_table.tunnelbroker-undelivered-messages,
+    aws_dynamodb_table.tunnelbroker-device-presence,
   ]
 }
 
@@ -103,11 +103,34 @@ resource "aws_iam_role" "services_ddb_full_access" {
 
   managed_policy_arns = [
     aws_iam_policy.allow_ecs_exec.arn,
+    aws_iam_policy.read_services_token.arn,
     "arn:aws:iam::aws:policy/AmazonDynamoDBFullAccess",
     "arn:aws:iam::aws:policy/AmazonS3FullAccess",
   ]
 }
 
+# Services token IAM
+# Lets services authenticate their requests to other services, and verify
+# requests authenticated with the token
+data "aws_iam_policy_document" "read_services_token" {
+  statement {
+    sid    = "ServicesTokenReadAccess"
+    effect = "Allow"
+    actions = [
+      "secretsmanager:DescribeSecret",
+      "secretsmanager:GetSecretValue",
+    ]
+    resources = [
+      module.shared.services_token_id
+    ]
+  }
+}
+resource "aws_iam_policy" "read_services_token" {
+  name        = "services-token-read-access"
+  policy      = data.aws_iam_policy_document.read_services_token.json
+  description = "Allows reading the service-to-service access token"
+}
+
 # Feature Flags IAM
 data "aws_iam_policy_document" "read_feature_flags" {
   statement {
@@ -163,7 +186,8 @@ resource "aws_iam_role" "backup_service" {
 
   managed_policy_arns = [
     aws_iam_policy.allow_ecs_exec.arn,
-    aws_iam_policy.manage_backup_ddb.arn
+    aws_iam_policy.manage_backup_ddb.arn,
+    aws_iam_policy.read_services_token.arn
   ]
 }
 
@@ -4,6 +4,14 @@ locals {
   backup_service_server_image        = "commapp/backup-server:${local.backup_service_image_tag}"
   backup_service_container_http_port = 50052
   backup_service_domain_name         = "backup.${local.root_domain}"
+
+  # HTTP port configuration for ECS Service Connect
+  backup_sc_port_name = "backup-service-ecs-http"
+  backup_sc_dns_name  = "backup-service"
+
+  # URL accessible by other services in the same Service Connect namespace
+  # This renders to 'http://backup-service:50052'
+  backup_local_url = "http://${local.backup_sc_dns_name}:${local.backup_service_container_http_port}"
 }
 
 resource "aws_ecs_task_definition" "backup_service" {
@@ -15,6 +23,7 @@ resource "aws_ecs_task_definition" "backup_service" {
       essential = true
       portMappings = [
         {
+          name          = local.backup_sc_port_name
           containerPort = local.backup_service_container_http_port
           protocol      = "tcp"
           appProtocol   = "http"
@@ -71,6 +80,17 @@ resource "aws_ecs_service" "backup_service" {
   service_connect_configuration {
     # to be able to reach Blob service by DNS name
     enabled = true
+
+    # Expose Backup service to other services in the cluster, e.g. identity
+    # deleting backups of deleted users
+    service {
+      discovery_name = local.backup_sc_dns_name
+      port_name      = local.backup_sc_port_name
+      client_alias {
+        port     = local.backup_service_container_http_port
+        dns_name = local.backup_sc_dns_name
+      }
+    }
   }
 
   # HTTP
@@ -17,12 +17,17 @@ locals {
   identity_service_domain_name      = "identity.${local.root_domain}"
 
   opaque_server_setup_secret_name = "identity/ServerSetup"
+  totp_encryption_key_secret_name = "identity/TotpEncryptionKey"
 }
 
 data "aws_secretsmanager_secret" "identity_server_setup" {
   name = local.opaque_server_setup_secret_name
 }
 
+data "aws_secretsmanager_secret" "identity_totp_encryption_key" {
+  name = local.totp_encryption_key_secret_name
+}
+
 resource "aws_ecs_task_definition" "identity_service" {
   family = "identity-service-task-def"
   container_definitions = jsonencode([
@@ -46,6 +51,22 @@ resource "aws_ecs_task_definition" "identity_service" {
         {
           name  = "KEYSERVER_PUBLIC_KEY"
           value = nonsensitive(local.secrets["keyserverPublicKey"])
+        },
+        {
+          # Clients connect through the application load balancer, which
+          # appends their address to X-Forwarded-For
+          name  = "TRUST_FORWARDED_FOR"
+          value = "true"
+        },
+        {
+          # Account exports are uploaded to, and revoked in, Blob service
+          name  = "BLOB_SERVICE_URL"
+          value = local.blob_local_url
+        },
+        {
+          # Backups of deleted users are deleted in Backup service
+          name  = "BACKUP_SERVICE_URL"
+          value = local.backup_local_url
         }
       ]
       secrets = [
@@ -53,6 +74,10 @@ resource "aws_ecs_task_definition" "identity_service" {
           # This is exposed as an environment variable in the container
           name      = "OPAQUE_SERVER_SETUP"
           valueFrom = data.aws_secretsmanager_secret.identity_server_setup.arn
+        },
+        {
+          name      = "TOTP_ENCRYPTION_KEY"
+          valueFrom = data.aws_secretsmanager_secret.identity_totp_encryption_key.arn
         }
       ]
       logConfiguration = {
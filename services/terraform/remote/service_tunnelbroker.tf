@@ -79,6 +79,10 @@ resource "aws_ecs_task_definition" "tunnelbroker" {
         {
           name  = "COMM_TUNNELBROKER_IDENTITY_ENDPOINT",
           value = local.identity_local_url
+        },
+        {
+          name  = "COMM_TUNNELBROKER_TRUST_FORWARDED_FOR",
+          value = "true"
         }
       ]
       logConfiguration = {
@@ -0,0 +1,467 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use futures_util::StreamExt;
+use lapin::acker::Acker;
+use lapin::options::{
+  BasicAckOptions, BasicCancelOptions, BasicConsumeOptions, BasicNackOptions,
+  BasicPublishOptions, BasicQosOptions, QueueDeclareOptions,
+  QueueDeleteOptions, QueuePurgeOptions,
+};
+use lapin::protocol::{AMQPErrorKind, AMQPSoftError};
+use lapin::types::FieldTable;
+use lapin::{uri::AMQPUri, BasicProperties, Connection, ConnectionProperties};
+use tokio::sync::{mpsc, Notify};
+use tokio::time::{interval, sleep};
+use tracing::{debug, error, info, warn};
+
+use super::{
+  BrokerConsumer, BrokerDelivery, BrokerError, DeliveryAcker, MessageBroker,
+};
+use crate::constants::{
+  MAX_RMQ_MSG_PRIORITY, RMQ_CONNECTION_CHECK_INTERVAL,
+  RMQ_CONSUMER_BUFFER_SIZE, RMQ_CONSUMER_TAG, RMQ_PREFETCH_COUNT,
+  RMQ_RECONNECT_MAX_DELAY, RMQ_RECONNECT_MIN_DELAY,
+};
+use crate::CONFIG;
+
+pub async fn connect() -> Result<Connection, lapin::Error> {
+  let mut amqp_uri = CONFIG
+    .amqp_uri
+    .parse::<AMQPUri>()
+    .expect("Invalid AMQP URI");
+
+  // Allow set / override credentials using env vars
+  if let Some(amqp_user) = from_env("AMQP_USERNAME") {
+    amqp_uri.authority.userinfo.username = amqp_user;
+  }
+  if let Some(amqp_pass) = from_env("AMQP_PASSWORD") {
+    amqp_uri.authority.userinfo.password = amqp_pass;
+  }
+
+  let conn =
+    Connection::connect_uri(amqp_uri, ConnectionProperties::default()).await?;
+
+  info!("Connected to AMQP endpoint: {}", &CONFIG.amqp_uri);
+  Ok(conn)
+}
+
+fn from_env(var_name: &str) -> Option<String> {
+  std::env::var(var_name).ok().filter(|s| !s.is_empty())
+}
+
+/// Acknowledges deliveries on the channel of their consumer. If the channel
+/// is closed in the meantime, the message is already back in the queue.
+struct AmqpAcker(Acker);
+
+#[tonic::async_trait]
+impl DeliveryAcker for AmqpAcker {
+  async fn ack(&self) -> Result<(), BrokerError> {
+    self.0.ack(BasicAckOptions::default()).await?;
+    Ok(())
+  }
+
+  async fn reject(&self) -> Result<(), BrokerError> {
+    let options = BasicNackOptions {
+      requeue: true,
+      ..Default::default()
+    };
+    self.0.nack(options).await?;
+    Ok(())
+  }
+}
+
+type DeliverySender = mpsc::Sender<Result<BrokerDelivery, BrokerError>>;
+
+struct AmqpConnection {
+  connection: Connection,
+  // Channel used for declaring queues and publishing messages. Recreated if
+  // the server closes it.
+  channel: lapin::Channel,
+}
+
+impl AmqpConnection {
+  async fn channel(&mut self) -> Result<lapin::Channel, BrokerError> {
+    if !self.channel.status().connected() {
+      debug!("Recreating closed AMQP channel");
+      self.channel = self.connection.create_channel().await?;
+    }
+    Ok(self.channel.clone())
+  }
+}
+
+/// Consumer which outlives the AMQP connection. Its deliveries are forwarded
+/// from the current AMQP consumer, which is recreated after reconnecting.
+struct ManagedConsumer {
+  device_id: String,
+  deliveries: DeliverySender,
+  // Channel of the current AMQP consumer, if subscribed
+  channel: Option<lapin::Channel>,
+}
+
+struct AmqpState {
+  // Not set while disconnected
+  connection: tokio::sync::Mutex<Option<AmqpConnection>>,
+  // Consumer tag -> consumer
+  consumers: Mutex<HashMap<String, ManagedConsumer>>,
+  healthy: AtomicBool,
+  next_consumer_id: AtomicU64,
+}
+
+/// RabbitMQ backed broker. The connection is maintained in the background:
+/// when it's lost, the broker reconnects with backoff, re-declares queues
+/// of active consumers and subscribes them again. Each consumer gets its
+/// own channel, which is closed when the consumer is cancelled.
+pub struct AmqpBroker {
+  state: Arc<AmqpState>,
+}
+
+impl AmqpBroker {
+  /// Creates the broker and starts connecting in the background. Operations
+  /// fail with `BrokerError::Unavailable` until the connection is established.
+  pub async fn connect() -> Self {
+    let state = Arc::new(AmqpState {
+      connection: tokio::sync::Mutex::new(None),
+      consumers: Mutex::new(HashMap::new()),
+      healthy: AtomicBool::new(false),
+      next_consumer_id: AtomicU64::new(0),
+    });
+
+    tokio::spawn(maintain_connection(state.clone()));
+    Self { state }
+  }
+
+  async fn channel(&self) -> Result<lapin::Channel, BrokerError> {
+    let mut connection = self.state.connection.lock().await;
+    match connection.as_mut() {
+      Some(connection) => connection.channel().await,
+      None => Err(BrokerError::Unavailable),
+    }
+  }
+}
+
+/// Keeps the connection alive. Runs for the whole lifetime of the process.
+async fn maintain_connection(state: Arc<AmqpState>) {
+  let mut reconnect_delay = RMQ_RECONNECT_MIN_DELAY;
+
+  loop {
+    let connection_lost = Arc::new(Notify::new());
+    let connection = match connect().await {
+      Ok(connection) => connection,
+      Err(e) => {
+        warn!(
+          "Failed to connect to AMQP endpoint: {}. Retrying in {:?}",
+          e, reconnect_delay
+        );
+        sleep(reconnect_delay).await;
+        reconnect_delay = (reconnect_delay * 2).min(RMQ_RECONNECT_MAX_DELAY);
+        continue;
+      }
+    };
+    reconnect_delay = RMQ_RECONNECT_MIN_DELAY;
+
+    let on_error_notify = connection_lost.clone();
+    connection.on_error(move |e| {
+      error!("AMQP connection error: {}", e);
+      on_error_notify.notify_one();
+    });
+
+    let channel = match connection.create_channel().await {
+      Ok(channel) => channel,
+      Err(e) => {
+        error!("Unable to create AMQP channel: {}", e);
+        sleep(reconnect_delay).await;
+        continue;
+      }
+    };
+
+    {
+      let mut current = state.connection.lock().await;
+      let current = current.insert(AmqpConnection {
+        connection,
+        channel,
+      });
+      resubscribe_consumers(&state, current).await;
+      state.healthy.store(true, Ordering::Relaxed);
+    }
+
+    wait_for_connection_loss(&state, &connection_lost).await;
+
+    warn!("Lost connection to AMQP endpoint, reconnecting");
+    state.healthy.store(false, Ordering::Relaxed);
+    state.connection.lock().await.take();
+    for consumer in state
+      .consumers
+      .lock()
+      .expect("Consumers lock poisoned")
+      .values_mut()
+    {
+      consumer.channel = None;
+    }
+  }
+}
+
+/// Completes once the connection is reported as failed or found closed
+async fn wait_for_connection_loss(state: &AmqpState, connection_lost: &Notify) {
+  let mut check_interval = interval(RMQ_CONNECTION_CHECK_INTERVAL);
+  loop {
+    tokio::select! {
+      _ = connection_lost.notified() => return,
+      _ = check_interval.tick() => {
+        let connection = state.connection.lock().await;
+        let connected = connection
+          .as_ref()
+          .map(|current| current.connection.status().connected())
+          .unwrap_or_default();
+        if !connected {
+          return;
+        }
+      }
+    }
+  }
+}
+
+/// Subscribes all registered consumers to the new connection
+async fn resubscribe_consumers(
+  state: &AmqpState,
+  current: &mut AmqpConnection,
+) {
+  let consumers: Vec<(String, String, DeliverySender)> = state
+    .consumers
+    .lock()
+    .expect("Consumers lock poisoned")
+    .iter()
+    .map(|(tag, consumer)| {
+      (
+        tag.clone(),
+        consumer.device_id.clone(),
+        consumer.deliveries.clone(),
+      )
+    })
+    .collect();
+
+  if !consumers.is_empty() {
+    info!("Resubscribing {} AMQP consumers", consumers.len());
+  }
+
+  for (tag, device_id, deliveries) in consumers {
+    let result = async {
+      declare_queue(&current.channel().await?, &device_id).await?;
+      subscribe(&current.connection, &tag, &device_id, deliveries).await
+    }
+    .await;
+
+    match result {
+      Ok(channel) => {
+        if let Some(consumer) = state
+          .consumers
+          .lock()
+          .expect("Consumers lock poisoned")
+          .get_mut(&tag)
+        {
+          consumer.channel = Some(channel);
+        }
+      }
+      Err(e) => error!("Failed to resubscribe consumer {}: {}", tag, e),
+    }
+  }
+}
+
+async fn declare_queue(
+  channel: &lapin::Channel,
+  device_id: &str,
+) -> Result<(), BrokerError> {
+  let mut args = FieldTable::default();
+  args.insert("x-max-priority".into(), MAX_RMQ_MSG_PRIORITY.into());
+  channel
+    .queue_declare(device_id, QueueDeclareOptions::default(), args)
+    .await?;
+  Ok(())
+}
+
+/// Starts an AMQP consumer on a new channel and forwards its deliveries.
+/// Returns the channel of the consumer.
+async fn subscribe(
+  connection: &Connection,
+  tag: &str,
+  device_id: &str,
+  deliveries: DeliverySender,
+) -> Result<lapin::Channel, BrokerError> {
+  let channel = connection.create_channel().await?;
+  // Limits messages waiting for acknowledgement
+  channel
+    .basic_qos(RMQ_PREFETCH_COUNT, BasicQosOptions::default())
+    .await?;
+  let mut consumer = channel
+    .basic_consume(
+      device_id,
+      tag,
+      BasicConsumeOptions::default(),
+      FieldTable::default(),
+    )
+    .await?;
+
+  let tag = tag.to_string();
+  tokio::spawn(async move {
+    while let Some(delivery) = consumer.next().await {
+      let delivery = match delivery {
+        Ok(delivery) => BrokerDelivery::new(
+          delivery.data,
+          Box::new(AmqpAcker(delivery.acker)),
+        ),
+        Err(e) => {
+          // Consumer is subscribed again after reconnecting
+          debug!("AMQP consumer {} stopped: {}", tag, e);
+          return;
+        }
+      };
+      if deliveries.send(Ok(delivery)).await.is_err() {
+        return;
+      }
+    }
+  });
+
+  Ok(channel)
+}
+
+#[tonic::async_trait]
+impl MessageBroker for AmqpBroker {
+  async fn declare_device_queue(
+    &self,
+    device_id: &str,
+  ) -> Result<(), BrokerError> {
+    declare_queue(&self.channel().await?, device_id).await
+  }
+
+  async fn publish(
+    &self,
+    device_id: &str,
+    payload: &[u8],
+    priority: u8,
+  ) -> Result<(), BrokerError> {
+    self
+      .channel()
+      .await?
+      .basic_publish(
+        "",
+        device_id,
+        BasicPublishOptions::default(),
+        payload,
+        BasicProperties::default().with_priority(priority),
+      )
+      .await?;
+    Ok(())
+  }
+
+  async fn consume(
+    &self,
+    device_id: &str,
+  ) -> Result<BrokerConsumer, BrokerError> {
+    let consumer_id =
+      self.state.next_consumer_id.fetch_add(1, Ordering::Relaxed);
+    let tag = format!("{}:{}", RMQ_CONSUMER_TAG, consumer_id);
+    let (sender, receiver) = mpsc::channel(RMQ_CONSUMER_BUFFER_SIZE);
+
+    // Holding the connection lock prevents resubscribing the consumer twice
+    let connection = self.state.connection.lock().await;
+    let Some(current) = connection.as_ref() else {
+      return Err(BrokerError::Unavailable);
+    };
+    let channel =
+      subscribe(&current.connection, &tag, device_id, sender.clone()).await?;
+
+    self
+      .state
+      .consumers
+      .lock()
+      .expect("Consumers lock poisoned")
+      .insert(
+        tag.clone(),
+        ManagedConsumer {
+          device_id: device_id.to_string(),
+          deliveries: sender,
+          channel: Some(channel),
+        },
+      );
+
+    // Ends once the consumer is cancelled and its forwarder stops
+    let deliveries =
+      futures_util::stream::unfold(receiver, |mut receiver| async move {
+        receiver.recv().await.map(|delivery| (delivery, receiver))
+      });
+    Ok(BrokerConsumer::new(tag, Box::pin(deliveries)))
+  }
+
+  async fn cancel(&self, consumer_tag: &str) -> Result<(), BrokerError> {
+    let consumer = self
+      .state
+      .consumers
+      .lock()
+      .expect("Consumers lock poisoned")
+      .remove(consumer_tag)
+      .ok_or(BrokerError::ConsumerNotFound)?;
+
+    // Not subscribed while disconnected, nothing to cancel
+    let Some(channel) = consumer.channel else {
+      return Ok(());
+    };
+
+    channel
+      .basic_cancel(consumer_tag, BasicCancelOptions::default())
+      .await?;
+    if let Err(e) = channel.close(200, "Consumer cancelled").await {
+      debug!("Failed to close consumer channel: {}", e);
+    }
+    Ok(())
+  }
+
+  async fn purge_device_queue(
+    &self,
+    device_id: &str,
+  ) -> Result<u32, BrokerError> {
+    // The server closes the channel if the queue doesn't exist, so
+    // a temporary channel is used to keep the shared one open
+    let channel = {
+      let connection = self.state.connection.lock().await;
+      let Some(current) = connection.as_ref() else {
+        return Err(BrokerError::Unavailable);
+      };
+      current.connection.create_channel().await?
+    };
+    let result = channel
+      .queue_purge(device_id, QueuePurgeOptions::default())
+      .await;
+
+    match result {
+      Ok(purged) => {
+        if let Err(e) = channel.close(200, "Queue purged").await {
+          debug!("Failed to close purge channel: {}", e);
+        }
+        Ok(purged)
+      }
+      Err(lapin::Error::ProtocolError(e))
+        if matches!(e.kind(), AMQPErrorKind::Soft(AMQPSoftError::NOTFOUND)) =>
+      {
+        Ok(0)
+      }
+      Err(e) => Err(e.into()),
+    }
+  }
+
+  async fn delete_device_queue(
+    &self,
+    device_id: &str,
+  ) -> Result<(), BrokerError> {
+    self
+      .channel()
+      .await?
+      .queue_delete(device_id, QueueDeleteOptions::default())
+      .await?;
+    Ok(())
+  }
+
+  fn is_healthy(&self) -> bool {
+    self.state.healthy.load(Ordering::Relaxed)
+  }
+}
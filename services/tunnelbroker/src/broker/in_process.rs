@@ -0,0 +1,411 @@
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering as AtomicOrdering};
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::Notify;
+
+use super::{
+  BrokerConsumer, BrokerDelivery, BrokerError, DeliveryAcker, MessageBroker,
+};
+use crate::constants::MAX_RMQ_MSG_PRIORITY;
+
+#[derive(Clone)]
+struct QueuedMessage {
+  priority: u8,
+  // Messages with equal priority are delivered in publish order
+  sequence: u64,
+  data: Vec<u8>,
+}
+
+impl Ord for QueuedMessage {
+  fn cmp(&self, other: &Self) -> Ordering {
+    self
+      .priority
+      .cmp(&other.priority)
+      .then_with(|| other.sequence.cmp(&self.sequence))
+  }
+}
+
+impl PartialOrd for QueuedMessage {
+  fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+    Some(self.cmp(other))
+  }
+}
+
+impl PartialEq for QueuedMessage {
+  fn eq(&self, other: &Self) -> bool {
+    self.cmp(other) == Ordering::Equal
+  }
+}
+
+impl Eq for QueuedMessage {}
+
+#[derive(Default)]
+struct DeviceQueue {
+  messages: BinaryHeap<QueuedMessage>,
+  // Tag of the active consumer. A queue has at most one consumer, a new one
+  // replaces the previous.
+  consumer: Option<String>,
+  // Sequence -> delivered message waiting for acknowledgement, along with
+  // the tag of the consumer which received it
+  unacked: HashMap<u64, (String, QueuedMessage)>,
+  notify: Arc<Notify>,
+}
+
+impl DeviceQueue {
+  /// Returns unacknowledged messages of the consumer to the queue
+  fn requeue_unacked(&mut self, consumer_tag: &str) {
+    let sequences: Vec<u64> = self
+      .unacked
+      .iter()
+      .filter(|(_, (consumer, _))| consumer == consumer_tag)
+      .map(|(sequence, _)| *sequence)
+      .collect();
+
+    for sequence in sequences {
+      if let Some((_, message)) = self.unacked.remove(&sequence) {
+        self.messages.push(message);
+      }
+    }
+  }
+
+  /// Wakes up consumers waiting for messages, including one that is just
+  /// about to start waiting
+  fn wake_consumers(&self) {
+    self.notify.notify_waiters();
+    self.notify.notify_one();
+  }
+}
+
+type Queues = Arc<Mutex<HashMap<String, DeviceQueue>>>;
+
+/// Broker keeping device queues in memory of the current process.
+/// Suitable for single-node deployments and tests.
+#[derive(Default)]
+pub struct InProcessBroker {
+  queues: Queues,
+  next_sequence: AtomicU64,
+  next_consumer_id: AtomicU64,
+}
+
+impl InProcessBroker {
+  pub fn new() -> Self {
+    Self::default()
+  }
+}
+
+struct InProcessAcker {
+  queues: Queues,
+  device_id: String,
+  sequence: u64,
+}
+
+impl InProcessAcker {
+  fn settle(&self, requeue: bool) {
+    let mut queues = self.queues.lock().expect("Queues lock poisoned");
+    let Some(queue) = queues.get_mut(&self.device_id) else {
+      return;
+    };
+    // Already settled or returned to the queue after the consumer was
+    // cancelled
+    let Some((_, message)) = queue.unacked.remove(&self.sequence) else {
+      return;
+    };
+
+    if requeue {
+      queue.messages.push(message);
+      queue.wake_consumers();
+    }
+  }
+}
+
+#[tonic::async_trait]
+impl DeliveryAcker for InProcessAcker {
+  async fn ack(&self) -> Result<(), BrokerError> {
+    self.settle(false);
+    Ok(())
+  }
+
+  async fn reject(&self) -> Result<(), BrokerError> {
+    self.settle(true);
+    Ok(())
+  }
+}
+
+enum NextDelivery {
+  Ready(u64, Vec<u8>),
+  Wait(Arc<Notify>),
+  Finished,
+}
+
+fn next_delivery(queues: &Queues, device_id: &str, tag: &str) -> NextDelivery {
+  let mut queues = queues.lock().expect("Queues lock poisoned");
+  let Some(queue) = queues.get_mut(device_id) else {
+    return NextDelivery::Finished;
+  };
+  if queue.consumer.as_deref() != Some(tag) {
+    return NextDelivery::Finished;
+  }
+
+  match queue.messages.pop() {
+    Some(message) => {
+      let (sequence, data) = (message.sequence, message.data.clone());
+      queue.unacked.insert(sequence, (tag.to_string(), message));
+      NextDelivery::Ready(sequence, data)
+    }
+    None => NextDelivery::Wait(queue.notify.clone()),
+  }
+}
+
+#[tonic::async_trait]
+impl MessageBroker for InProcessBroker {
+  async fn declare_device_queue(
+    &self,
+    device_id: &str,
+  ) -> Result<(), BrokerError> {
+    self
+      .queues
+      .lock()
+      .expect("Queues lock poisoned")
+      .entry(device_id.to_string())
+      .or_default();
+    Ok(())
+  }
+
+  async fn publish(
+    &self,
+    device_id: &str,
+    payload: &[u8],
+    priority: u8,
+  ) -> Result<(), BrokerError> {
+    let mut queues = self.queues.lock().expect("Queues lock poisoned");
+    let Some(queue) = queues.get_mut(device_id) else {
+      return Ok(());
+    };
+
+    queue.messages.push(QueuedMessage {
+      priority: priority.min(MAX_RMQ_MSG_PRIORITY),
+      sequence: self.next_sequence.fetch_add(1, AtomicOrdering::Relaxed),
+      data: payload.to_vec(),
+    });
+    queue.notify.notify_one();
+    Ok(())
+  }
+
+  async fn consume(
+    &self,
+    device_id: &str,
+  ) -> Result<BrokerConsumer, BrokerError> {
+    let consumer_id =
+      self.next_consumer_id.fetch_add(1, AtomicOrdering::Relaxed);
+    let tag = format!("{}:{}", device_id, consumer_id);
+
+    {
+      let mut queues = self.queues.lock().expect("Queues lock poisoned");
+      let queue = queues.entry(device_id.to_string()).or_default();
+      if let Some(previous) = queue.consumer.take() {
+        queue.requeue_unacked(&previous);
+      }
+      queue.consumer = Some(tag.clone());
+      // Wake up the previous consumer so that its stream ends
+      queue.wake_consumers();
+    }
+
+    let state = (self.queues.clone(), device_id.to_string(), tag.clone());
+    let deliveries =
+      futures_util::stream::unfold(state, |(queues, device_id, tag)| async {
+        loop {
+          match next_delivery(&queues, &device_id, &tag) {
+            NextDelivery::Ready(sequence, data) => {
+              let acker = InProcessAcker {
+                queues: queues.clone(),
+                device_id: device_id.clone(),
+                sequence,
+              };
+              let delivery = Ok(BrokerDelivery::new(data, Box::new(acker)));
+              return Some((delivery, (queues, device_id, tag)));
+            }
+            NextDelivery::Wait(notify) => notify.notified().await,
+            NextDelivery::Finished => return None,
+          }
+        }
+      });
+
+    Ok(BrokerConsumer::new(tag, Box::pin(deliveries)))
+  }
+
+  async fn cancel(&self, consumer_tag: &str) -> Result<(), BrokerError> {
+    let mut queues = self.queues.lock().expect("Queues lock poisoned");
+    let queue = queues
+      .values_mut()
+      .find(|queue| queue.consumer.as_deref() == Some(consumer_tag))
+      .ok_or(BrokerError::ConsumerNotFound)?;
+
+    queue.consumer = None;
+    queue.requeue_unacked(consumer_tag);
+    queue.wake_consumers();
+    Ok(())
+  }
+
+  async fn purge_device_queue(
+    &self,
+    device_id: &str,
+  ) -> Result<u32, BrokerError> {
+    let mut queues = self.queues.lock().expect("Queues lock poisoned");
+    let purged = queues
+      .get_mut(device_id)
+      .map(|queue| std::mem::take(&mut queue.messages).len())
+      .unwrap_or_default();
+    Ok(purged as u32)
+  }
+
+  async fn delete_device_queue(
+    &self,
+    device_id: &str,
+  ) -> Result<(), BrokerError> {
+    let removed = self
+      .queues
+      .lock()
+      .expect("Queues lock poisoned")
+      .remove(device_id);
+
+    if let Some(queue) = removed {
+      queue.wake_consumers();
+    }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod in_process_broker_tests {
+  use super::*;
+
+  async fn next_payload(consumer: &mut BrokerConsumer) -> Vec<u8> {
+    consumer
+      .next()
+      .await
+      .expect("Consumer stream ended")
+      .expect("Delivery failed")
+      .data
+  }
+
+  #[tokio::test]
+  async fn test_delivery_order() {
+    let broker = InProcessBroker::new();
+    broker.declare_device_queue("device").await.unwrap();
+
+    broker.publish("device", b"first", 1).await.unwrap();
+    broker.publish("device", b"second", 1).await.unwrap();
+    broker.publish("device", b"priority", 10).await.unwrap();
+
+    let mut consumer = broker.consume("device").await.unwrap();
+    assert_eq!(next_payload(&mut consumer).await, b"priority");
+    assert_eq!(next_payload(&mut consumer).await, b"first");
+    assert_eq!(next_payload(&mut consumer).await, b"second");
+  }
+
+  #[tokio::test]
+  async fn test_publish_while_consuming() {
+    let broker = Arc::new(InProcessBroker::new());
+    broker.declare_device_queue("device").await.unwrap();
+    let mut consumer = broker.consume("device").await.unwrap();
+
+    let publisher = broker.clone();
+    tokio::spawn(async move {
+      publisher.publish("device", b"message", 1).await.unwrap();
+    });
+
+    assert_eq!(next_payload(&mut consumer).await, b"message");
+  }
+
+  #[tokio::test]
+  async fn test_publish_to_undeclared_queue() {
+    let broker = InProcessBroker::new();
+    broker.publish("device", b"dropped", 1).await.unwrap();
+
+    broker.declare_device_queue("device").await.unwrap();
+    broker.publish("device", b"kept", 1).await.unwrap();
+
+    let mut consumer = broker.consume("device").await.unwrap();
+    assert_eq!(next_payload(&mut consumer).await, b"kept");
+  }
+
+  #[tokio::test]
+  async fn test_cancel_ends_stream() {
+    let broker = InProcessBroker::new();
+    broker.declare_device_queue("device").await.unwrap();
+    let mut consumer = broker.consume("device").await.unwrap();
+
+    broker.cancel(consumer.tag()).await.unwrap();
+    assert!(consumer.next().await.is_none());
+    assert!(broker.cancel(consumer.tag()).await.is_err());
+  }
+
+  #[tokio::test]
+  async fn test_reject_redelivers() {
+    let broker = InProcessBroker::new();
+    broker.declare_device_queue("device").await.unwrap();
+    broker.publish("device", b"message", 1).await.unwrap();
+
+    let mut consumer = broker.consume("device").await.unwrap();
+    let delivery = consumer.next().await.unwrap().unwrap();
+    delivery.reject().await.unwrap();
+
+    let delivery = consumer.next().await.unwrap().unwrap();
+    assert_eq!(delivery.data, b"message");
+    delivery.ack().await.unwrap();
+    // Settling twice has no effect
+    delivery.reject().await.unwrap();
+
+    broker.publish("device", b"next", 1).await.unwrap();
+    assert_eq!(next_payload(&mut consumer).await, b"next");
+  }
+
+  #[tokio::test]
+  async fn test_unacked_requeued_for_new_consumer() {
+    let broker = InProcessBroker::new();
+    broker.declare_device_queue("device").await.unwrap();
+    broker.publish("device", b"unacked", 1).await.unwrap();
+
+    let mut consumer = broker.consume("device").await.unwrap();
+    assert_eq!(next_payload(&mut consumer).await, b"unacked");
+    broker.cancel(consumer.tag()).await.unwrap();
+
+    let mut consumer = broker.consume("device").await.unwrap();
+    let delivery = consumer.next().await.unwrap().unwrap();
+    assert_eq!(delivery.data, b"unacked");
+  }
+
+  #[tokio::test]
+  async fn test_purge_queue() {
+    let broker = InProcessBroker::new();
+    assert_eq!(broker.purge_device_queue("device").await.unwrap(), 0);
+
+    broker.declare_device_queue("device").await.unwrap();
+    broker.publish("device", b"first", 1).await.unwrap();
+    broker.publish("device", b"second", 1).await.unwrap();
+    let mut consumer = broker.consume("device").await.unwrap();
+    assert_eq!(broker.purge_device_queue("device").await.unwrap(), 2);
+
+    // The consumer is kept and receives messages published afterwards
+    broker.publish("device", b"new", 1).await.unwrap();
+    assert_eq!(next_payload(&mut consumer).await, b"new");
+  }
+
+  #[tokio::test]
+  async fn test_delete_queue() {
+    let broker = InProcessBroker::new();
+    broker.declare_device_queue("device").await.unwrap();
+    broker.publish("device", b"deleted", 1).await.unwrap();
+    let mut consumer = broker.consume("device").await.unwrap();
+
+    broker.delete_device_queue("device").await.unwrap();
+    assert!(consumer.next().await.is_none());
+
+    broker.declare_device_queue("device").await.unwrap();
+    broker.publish("device", b"new", 1).await.unwrap();
+    let mut consumer = broker.consume("device").await.unwrap();
+    assert_eq!(next_payload(&mut consumer).await, b"new");
+  }
+}
@@ -0,0 +1,145 @@
+//! Message broker used to deliver messages to devices connected to
+//! Tunnelbroker. Every device has its own queue, which is consumed by the
+//! websocket session of that device.
+//!
+//! Deliveries have to be acknowledged explicitly. Messages which are neither
+//! acknowledged nor rejected are returned to the queue once their consumer
+//! is cancelled.
+
+pub mod amqp;
+pub mod in_process;
+
+use std::pin::Pin;
+use std::sync::Arc;
+
+use futures_util::{Stream, StreamExt};
+use tracing::info;
+
+use crate::config::{BrokerType, CONFIG};
+
+pub use self::amqp::AmqpBroker;
+pub use self::in_process::InProcessBroker;
+
+#[derive(
+  Debug, derive_more::Display, derive_more::From, derive_more::Error,
+)]
+pub enum BrokerError {
+  AmqpError(lapin::Error),
+  #[display(fmt = "Consumer not found")]
+  ConsumerNotFound,
+  #[display(fmt = "Broker unavailable")]
+  Unavailable,
+}
+
+/// Settles a single delivery with the broker
+#[tonic::async_trait]
+pub trait DeliveryAcker: Send + Sync {
+  /// Removes the message from the queue
+  async fn ack(&self) -> Result<(), BrokerError>;
+
+  /// Returns the message to the queue, so that it's delivered again
+  async fn reject(&self) -> Result<(), BrokerError>;
+}
+
+/// Message received from a device queue
+pub struct BrokerDelivery {
+  pub data: Vec<u8>,
+  acker: Box<dyn DeliveryAcker>,
+}
+
+impl BrokerDelivery {
+  pub fn new(data: Vec<u8>, acker: Box<dyn DeliveryAcker>) -> Self {
+    Self { data, acker }
+  }
+
+  pub async fn ack(&self) -> Result<(), BrokerError> {
+    self.acker.ack().await
+  }
+
+  pub async fn reject(&self) -> Result<(), BrokerError> {
+    self.acker.reject().await
+  }
+}
+
+pub type DeliveryStream = Pin<
+  Box<dyn Stream<Item = Result<BrokerDelivery, BrokerError>> + Send + Sync>,
+>;
+
+/// Stream of messages from a device queue
+pub struct BrokerConsumer {
+  tag: String,
+  deliveries: DeliveryStream,
+}
+
+impl BrokerConsumer {
+  pub fn new(tag: String, deliveries: DeliveryStream) -> Self {
+    Self { tag, deliveries }
+  }
+
+  pub fn tag(&self) -> &str {
+    &self.tag
+  }
+
+  pub async fn next(&mut self) -> Option<Result<BrokerDelivery, BrokerError>> {
+    self.deliveries.next().await
+  }
+}
+
+#[tonic::async_trait]
+pub trait MessageBroker: Send + Sync {
+  /// Creates the device queue if it doesn't exist yet
+  async fn declare_device_queue(
+    &self,
+    device_id: &str,
+  ) -> Result<(), BrokerError>;
+
+  /// Publishes a message to the device queue. Messages with higher priority
+  /// are delivered first. If the queue doesn't exist, the message is dropped.
+  async fn publish(
+    &self,
+    device_id: &str,
+    payload: &[u8],
+    priority: u8,
+  ) -> Result<(), BrokerError>;
+
+  /// Starts consuming messages from the device queue
+  async fn consume(
+    &self,
+    device_id: &str,
+  ) -> Result<BrokerConsumer, BrokerError>;
+
+  /// Stops the consumer with given tag. Its stream ends afterwards.
+  async fn cancel(&self, consumer_tag: &str) -> Result<(), BrokerError>;
+
+  /// Removes all messages from the device queue, keeping the queue and its
+  /// consumer. Returns the number of removed messages. Purging a queue which
+  /// doesn't exist is not an error.
+  async fn purge_device_queue(
+    &self,
+    device_id: &str,
+  ) -> Result<u32, BrokerError>;
+
+  /// Deletes the device queue along with all messages in it
+  async fn delete_device_queue(
+    &self,
+    device_id: &str,
+  ) -> Result<(), BrokerError>;
+
+  /// Whether the broker is able to deliver messages
+  fn is_healthy(&self) -> bool {
+    true
+  }
+}
+
+pub type Broker = Arc<dyn MessageBroker>;
+
+/// Creates the message broker selected in `AppConfig`
+pub async fn create_broker() -> Broker {
+  match CONFIG.message_broker {
+    BrokerType::Amqp => Arc::new(AmqpBroker::connect().await),
+    BrokerType::InProcess => {
+      info!("Using in-process message broker");
+      Arc::new(InProcessBroker::new())
+    }
+  }
+}
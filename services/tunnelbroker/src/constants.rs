@@ -5,12 +5,56 @@ pub const GRPC_SERVER_PORT: u16 = 50051;
 pub const GRPC_KEEP_ALIVE_PING_INTERVAL: Duration = Duration::from_secs(3);
 pub const GRPC_KEEP_ALIVE_PING_TIMEOUT: Duration = Duration::from_secs(10);
 
+// Maximum number of messages in a single SendMessagesToDevices request
+pub const GRPC_MAX_BATCH_MESSAGES: usize = 100;
+
+// Successful access token verifications are cached for a short time, so
+// that reconnecting devices don't need to be verified by identity again.
+// Revoked tokens are invalidated through the InvalidateAccessTokens RPC, but
+// only on the instance handling it, so other instances may keep accepting a
+// revoked token for up to this long.
+pub const IDENTITY_TOKEN_CACHE_TTL: Duration = Duration::from_secs(5);
+pub const IDENTITY_TOKEN_CACHE_MAX_ENTRIES: usize = 100_000;
+
+// Number of tracked keys after which rate limiters forget keys with full
+// buckets, at most once per interval
+pub const RATE_LIMITER_CLEANUP_THRESHOLD: usize = 10_000;
+pub const RATE_LIMITER_CLEANUP_INTERVAL: Duration = Duration::from_secs(10);
+
 pub const SOCKET_HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(3);
 
+// How often an open connection refreshes the `lastHeartbeat` of its device
+// presence record, and after how long without a refresh the record is
+// considered stale (e.g. the instance holding the connection crashed).
+pub const PRESENCE_REFRESH_INTERVAL: Duration = Duration::from_secs(10);
+pub const PRESENCE_STALE_THRESHOLD: Duration = Duration::from_secs(30);
+
+// Graceful shutdown deadline, after which remaining connections are dropped.
+// Devices are asked to reconnect after a random delay up to the given value.
+pub const DEFAULT_SHUTDOWN_TIMEOUT_SECS: u64 = 30;
+pub const SHUTDOWN_MAX_RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
 pub const MAX_RMQ_MSG_PRIORITY: u8 = 10;
 pub const DDB_RMQ_MSG_PRIORITY: u8 = 10;
 pub const CLIENT_RMQ_MSG_PRIORITY: u8 = 1;
 pub const RMQ_CONSUMER_TAG: &str = "tunnelbroker";
+pub const RMQ_CONSUMER_BUFFER_SIZE: usize = 32;
+// Maximum number of messages delivered to a device but not yet confirmed
+pub const RMQ_PREFETCH_COUNT: u16 = 256;
+// Number of confirmed message IDs remembered by a session to skip
+// duplicate deliveries
+pub const MAX_CONFIRMED_MESSAGE_IDS: usize = 1000;
+
+// Backoff of reconnect attempts after the AMQP connection is lost, and how
+// often the connection is checked in case the failure wasn't reported
+pub const RMQ_RECONNECT_MIN_DELAY: Duration = Duration::from_secs(1);
+pub const RMQ_RECONNECT_MAX_DELAY: Duration = Duration::from_secs(30);
+pub const RMQ_CONNECTION_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+// DynamoDB limit of items in a single BatchWriteItem request
+pub const DDB_BATCH_WRITE_MAX_ITEMS: usize = 25;
+pub const DDB_BATCH_WRITE_MAX_ATTEMPTS: u32 = 3;
+pub const DDB_BATCH_WRITE_RETRY_DELAY: Duration = Duration::from_millis(100);
 
 pub const LOG_LEVEL_ENV_VAR: &str =
   tracing_subscriber::filter::EnvFilter::DEFAULT_ENV;
@@ -27,6 +71,11 @@ pub mod dynamodb {
   //      Timestamp is needed to order the messages correctly to the device.
   //      Timestamp format is ISO 8601 to handle lexicographical sorting.
   //    - clientMessageID: Message ID generated on client using UUID Version 4.
+  // - senderDeviceID: Set only if the sender requested a delivery receipt.
+  //   Device to which MessageDelivered is sent once the message is confirmed.
+  // - messageType: Set only for messages generated by Tunnelbroker (currently
+  //   "MessageDelivered"). Payload of such messages is sent to the device as
+  //   is, instead of being wrapped in MessageToDevice.
   pub mod undelivered_messages {
     pub const TABLE_NAME: &str = "tunnelbroker-undelivered-messages";
     pub const PARTITION_KEY: &str = "deviceID";
@@ -34,5 +83,34 @@ pub mod dynamodb {
     pub const PAYLOAD: &str = "payload";
     pub const MESSAGE_ID: &str = "messageID";
     pub const SORT_KEY: &str = "messageID";
+    pub const SENDER_DEVICE_ID: &str = "senderDeviceID";
+    pub const MESSAGE_TYPE: &str = "messageType";
+
+    pub const MESSAGE_TYPE_DELIVERED: &str = "MessageDelivered";
+  }
+
+  // This table holds websocket connection status of devices. An item exists
+  // only while the device has an open connection to some Tunnelbroker
+  // instance.
+  //
+  // - deviceID (primary key): The public key of a device's olm identity key
+  // - userID: ID of the user owning the device
+  // - instanceID: ID of the Tunnelbroker instance holding the connection
+  // - connectedSince: RFC 3339 timestamp of when the connection was opened
+  // - lastHeartbeat: RFC 3339 timestamp of the last time the connection was
+  //   confirmed to be alive
+  // - presenceSubscription: Whether the device wants to receive presence
+  //   updates about other devices of the same user
+  pub mod device_presence {
+    pub const TABLE_NAME: &str = "tunnelbroker-device-presence";
+    pub const PARTITION_KEY: &str = "deviceID";
+    pub const DEVICE_ID: &str = "deviceID";
+    pub const USER_ID: &str = "userID";
+    pub const INSTANCE_ID: &str = "instanceID";
+    pub const CONNECTED_SINCE: &str = "connectedSince";
+    pub const LAST_HEARTBEAT: &str = "lastHeartbeat";
+    pub const PRESENCE_SUBSCRIPTION: &str = "presenceSubscription";
+
+    pub const USER_ID_INDEX: &str = "userID-index";
   }
 }
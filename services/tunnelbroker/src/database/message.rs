@@ -4,7 +4,7 @@ use aws_sdk_dynamodb::types::AttributeValue;
 use tunnelbroker_messages::MessageToDevice;
 
 use crate::constants::dynamodb::undelivered_messages::{
-  DEVICE_ID, MESSAGE_ID, PAYLOAD,
+  DEVICE_ID, MESSAGE_ID, MESSAGE_TYPE, PAYLOAD,
 };
 
 #[derive(Debug, derive_more::Display, derive_more::Error)]
@@ -48,3 +48,23 @@ impl MessageToDeviceExt for MessageToDevice {
     })
   }
 }
+
+/// Serializes a persisted message into the form in which it is sent
+/// to the device
+pub fn serialize_persisted_message(
+  hashmap: HashMap<String, AttributeValue>,
+) -> Result<String, MessageErrors> {
+  // Messages generated by Tunnelbroker are persisted already serialized
+  if hashmap.contains_key(MESSAGE_TYPE) {
+    let payload = hashmap
+      .get(PAYLOAD)
+      .ok_or(MessageErrors::SerializationError)?
+      .as_s()
+      .map_err(|_| MessageErrors::SerializationError)?;
+    return Ok(payload.to_string());
+  }
+
+  let message_to_device = MessageToDevice::from_hashmap(hashmap)?;
+  serde_json::to_string(&message_to_device)
+    .map_err(|_| MessageErrors::SerializationError)
+}
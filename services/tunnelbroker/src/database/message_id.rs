@@ -18,6 +18,14 @@ impl MessageID {
       client_message_id,
     }
   }
+
+  pub fn timestamp(&self) -> &DateTime<Utc> {
+    &self.timestamp
+  }
+
+  pub fn client_message_id(&self) -> &str {
+    &self.client_message_id
+  }
 }
 
 impl TryFrom<String> for MessageID {
@@ -5,20 +5,27 @@ use aws_sdk_dynamodb::operation::delete_item::{
 };
 use aws_sdk_dynamodb::operation::put_item::PutItemError;
 use aws_sdk_dynamodb::operation::query::QueryError;
+use aws_sdk_dynamodb::types::ReturnValue;
 use aws_sdk_dynamodb::{types::AttributeValue, Client};
 use std::collections::HashMap;
 use std::sync::Arc;
 use tracing::{debug, error};
+use tunnelbroker_messages::MessageDelivered;
 
 use crate::constants::dynamodb::undelivered_messages::{
-  PARTITION_KEY, PAYLOAD, SORT_KEY, TABLE_NAME,
+  MESSAGE_TYPE, MESSAGE_TYPE_DELIVERED, PARTITION_KEY, PAYLOAD,
+  SENDER_DEVICE_ID, SORT_KEY, TABLE_NAME,
 };
 
 pub mod message;
 pub mod message_id;
+pub mod presence;
+pub mod queue;
 
 use crate::database::message_id::MessageID;
 pub use message::*;
+pub use presence::DevicePresence;
+pub use queue::QueueStats;
 
 #[derive(Clone)]
 pub struct DatabaseClient {
@@ -51,6 +58,7 @@ impl DatabaseClient {
     device_id: &str,
     payload: &str,
     client_message_id: &str,
+    sender_device_id: Option<&str>,
   ) -> Result<String, SdkError<PutItemError>> {
     let message_id: String =
       MessageID::new(client_message_id.to_string()).into();
@@ -59,7 +67,7 @@ impl DatabaseClient {
     let payload_av = AttributeValue::S(payload.to_string());
     let message_id_av = AttributeValue::S(message_id.clone());
 
-    let request = self
+    let mut request = self
       .client
       .put_item()
       .table_name(TABLE_NAME)
@@ -67,12 +75,66 @@ impl DatabaseClient {
       .item(SORT_KEY, message_id_av)
       .item(PAYLOAD, payload_av);
 
+    if let Some(sender_device_id) = sender_device_id {
+      request = request.item(
+        SENDER_DEVICE_ID,
+        AttributeValue::S(sender_device_id.to_string()),
+      );
+    }
+
     debug!("Persisting message to device: {}", &device_id);
 
     request.send().await?;
     Ok(message_id)
   }
 
+  /// Persists a receipt informing the sender that the recipient confirmed
+  /// the message with given ID
+  pub async fn persist_delivery_receipt(
+    &self,
+    sender_device_id: &str,
+    recipient_device_id: &str,
+    message_id: &str,
+  ) -> Result<MessageDelivered, SdkError<PutItemError>> {
+    let client_message_id = MessageID::try_from(message_id.to_string())
+      .map(|id| id.client_message_id().to_string())
+      .unwrap_or_else(|_| message_id.to_string());
+
+    let receipt_id: String =
+      MessageID::new(uuid::Uuid::new_v4().to_string()).into();
+    let receipt = MessageDelivered {
+      message_id: receipt_id.clone(),
+      client_message_id,
+      device_id: recipient_device_id.to_string(),
+    };
+    let payload = serde_json::to_string(&receipt)
+      .expect("Failed to serialize delivery receipt");
+
+    debug!(
+      "Persisting delivery receipt to device: {}",
+      sender_device_id
+    );
+
+    self
+      .client
+      .put_item()
+      .table_name(TABLE_NAME)
+      .item(
+        PARTITION_KEY,
+        AttributeValue::S(sender_device_id.to_string()),
+      )
+      .item(SORT_KEY, AttributeValue::S(receipt_id))
+      .item(PAYLOAD, AttributeValue::S(payload))
+      .item(
+        MESSAGE_TYPE,
+        AttributeValue::S(MESSAGE_TYPE_DELIVERED.to_string()),
+      )
+      .send()
+      .await?;
+
+    Ok(receipt)
+  }
+
   pub async fn retrieve_messages(
     &self,
     device_id: &str,
@@ -122,6 +184,7 @@ impl DatabaseClient {
       .delete_item()
       .table_name(TABLE_NAME)
       .set_key(Some(key))
+      .return_values(ReturnValue::AllOld)
       .send()
       .await
   }
@@ -0,0 +1,234 @@
+use std::collections::HashMap;
+
+use aws_sdk_dynamodb::error::SdkError;
+use aws_sdk_dynamodb::operation::delete_item::DeleteItemError;
+use aws_sdk_dynamodb::operation::get_item::GetItemError;
+use aws_sdk_dynamodb::operation::put_item::PutItemError;
+use aws_sdk_dynamodb::operation::query::QueryError;
+use aws_sdk_dynamodb::operation::update_item::UpdateItemError;
+use aws_sdk_dynamodb::types::AttributeValue;
+use chrono::{DateTime, Utc};
+use tracing::debug;
+
+use crate::config::INSTANCE_ID;
+use crate::constants::dynamodb::device_presence::{
+  CONNECTED_SINCE, DEVICE_ID, INSTANCE_ID as INSTANCE_ID_ATTR, LAST_HEARTBEAT,
+  PARTITION_KEY, PRESENCE_SUBSCRIPTION, TABLE_NAME, USER_ID, USER_ID_INDEX,
+};
+use crate::constants::PRESENCE_STALE_THRESHOLD;
+
+use super::{DatabaseClient, MessageErrors};
+
+#[derive(Debug, Clone)]
+pub struct DevicePresence {
+  pub device_id: String,
+  pub user_id: String,
+  pub instance_id: String,
+  pub connected_since: DateTime<Utc>,
+  pub last_heartbeat: DateTime<Utc>,
+  pub presence_subscription: bool,
+}
+
+impl DevicePresence {
+  /// A presence record is stale if the connection hasn't been confirmed to be
+  /// alive for a while, e.g. because the instance holding it crashed
+  pub fn is_stale(&self) -> bool {
+    let threshold = chrono::Duration::from_std(PRESENCE_STALE_THRESHOLD)
+      .expect("Presence threshold out of range");
+    Utc::now() - self.last_heartbeat > threshold
+  }
+
+  fn from_hashmap(
+    mut hashmap: HashMap<String, AttributeValue>,
+  ) -> Result<Self, MessageErrors> {
+    let mut take_string = |attr: &str| -> Result<String, MessageErrors> {
+      match hashmap.remove(attr) {
+        Some(AttributeValue::S(value)) => Ok(value),
+        _ => Err(MessageErrors::SerializationError),
+      }
+    };
+
+    let device_id = take_string(DEVICE_ID)?;
+    let user_id = take_string(USER_ID)?;
+    let instance_id = take_string(INSTANCE_ID_ATTR)?;
+    let connected_since = parse_timestamp(&take_string(CONNECTED_SINCE)?)?;
+    let last_heartbeat = parse_timestamp(&take_string(LAST_HEARTBEAT)?)?;
+    let presence_subscription = matches!(
+      hashmap.get(PRESENCE_SUBSCRIPTION),
+      Some(AttributeValue::Bool(true))
+    );
+
+    Ok(DevicePresence {
+      device_id,
+      user_id,
+      instance_id,
+      connected_since,
+      last_heartbeat,
+      presence_subscription,
+    })
+  }
+}
+
+fn parse_timestamp(value: &str) -> Result<DateTime<Utc>, MessageErrors> {
+  DateTime::parse_from_rfc3339(value)
+    .map(|timestamp| timestamp.with_timezone(&Utc))
+    .map_err(|_| MessageErrors::SerializationError)
+}
+
+impl DatabaseClient {
+  /// Records that the device has an open connection to this instance.
+  /// Returns the `connectedSince` timestamp, which identifies the connection
+  /// when it is later unregistered.
+  pub async fn set_device_connected(
+    &self,
+    device_id: &str,
+    user_id: &str,
+  ) -> Result<String, SdkError<PutItemError>> {
+    let now = Utc::now().to_rfc3339();
+    debug!("Registering presence of device: {}", device_id);
+
+    self
+      .client
+      .put_item()
+      .table_name(TABLE_NAME)
+      .item(PARTITION_KEY, AttributeValue::S(device_id.to_string()))
+      .item(USER_ID, AttributeValue::S(user_id.to_string()))
+      .item(INSTANCE_ID_ATTR, AttributeValue::S(INSTANCE_ID.to_string()))
+      .item(CONNECTED_SINCE, AttributeValue::S(now.clone()))
+      .item(LAST_HEARTBEAT, AttributeValue::S(now.clone()))
+      .item(PRESENCE_SUBSCRIPTION, AttributeValue::Bool(false))
+      .send()
+      .await?;
+
+    Ok(now)
+  }
+
+  /// Updates `lastHeartbeat` of the device presence. Fails if the connection
+  /// has since been taken over by another instance.
+  pub async fn update_device_heartbeat(
+    &self,
+    device_id: &str,
+  ) -> Result<(), SdkError<UpdateItemError>> {
+    self
+      .client
+      .update_item()
+      .table_name(TABLE_NAME)
+      .key(PARTITION_KEY, AttributeValue::S(device_id.to_string()))
+      .update_expression("SET #heartbeat = :now")
+      .condition_expression("#instance = :instance")
+      .expression_attribute_names("#heartbeat", LAST_HEARTBEAT)
+      .expression_attribute_names("#instance", INSTANCE_ID_ATTR)
+      .expression_attribute_values(
+        ":now",
+        AttributeValue::S(Utc::now().to_rfc3339()),
+      )
+      .expression_attribute_values(
+        ":instance",
+        AttributeValue::S(INSTANCE_ID.to_string()),
+      )
+      .send()
+      .await?;
+
+    Ok(())
+  }
+
+  pub async fn set_presence_subscription(
+    &self,
+    device_id: &str,
+    subscribe: bool,
+  ) -> Result<(), SdkError<UpdateItemError>> {
+    self
+      .client
+      .update_item()
+      .table_name(TABLE_NAME)
+      .key(PARTITION_KEY, AttributeValue::S(device_id.to_string()))
+      .update_expression("SET #subscription = :subscribe")
+      .condition_expression("attribute_exists(#device)")
+      .expression_attribute_names("#subscription", PRESENCE_SUBSCRIPTION)
+      .expression_attribute_names("#device", DEVICE_ID)
+      .expression_attribute_values(
+        ":subscribe",
+        AttributeValue::Bool(subscribe),
+      )
+      .send()
+      .await?;
+
+    Ok(())
+  }
+
+  /// Removes the device presence, unless the device has already opened
+  /// a newer connection (on this or any other instance).
+  pub async fn set_device_disconnected(
+    &self,
+    device_id: &str,
+    connected_since: &str,
+  ) -> Result<(), SdkError<DeleteItemError>> {
+    debug!("Unregistering presence of device: {}", device_id);
+
+    self
+      .client
+      .delete_item()
+      .table_name(TABLE_NAME)
+      .key(PARTITION_KEY, AttributeValue::S(device_id.to_string()))
+      .condition_expression("#instance = :instance AND #since = :since")
+      .expression_attribute_names("#instance", INSTANCE_ID_ATTR)
+      .expression_attribute_names("#since", CONNECTED_SINCE)
+      .expression_attribute_values(
+        ":instance",
+        AttributeValue::S(INSTANCE_ID.to_string()),
+      )
+      .expression_attribute_values(
+        ":since",
+        AttributeValue::S(connected_since.to_string()),
+      )
+      .send()
+      .await?;
+
+    Ok(())
+  }
+
+  pub async fn get_device_presence(
+    &self,
+    device_id: &str,
+  ) -> Result<Option<DevicePresence>, SdkError<GetItemError>> {
+    let response = self
+      .client
+      .get_item()
+      .table_name(TABLE_NAME)
+      .key(PARTITION_KEY, AttributeValue::S(device_id.to_string()))
+      .consistent_read(true)
+      .send()
+      .await?;
+
+    let presence = response.item.and_then(|item| {
+      DevicePresence::from_hashmap(item)
+        .map_err(|e| debug!("Invalid presence item for {}: {}", device_id, e))
+        .ok()
+    });
+    Ok(presence)
+  }
+
+  /// Returns presence records of all connected devices of the user
+  pub async fn get_user_devices_presence(
+    &self,
+    user_id: &str,
+  ) -> Result<Vec<DevicePresence>, SdkError<QueryError>> {
+    let response = self
+      .client
+      .query()
+      .table_name(TABLE_NAME)
+      .index_name(USER_ID_INDEX)
+      .key_condition_expression(format!("{} = :u", USER_ID))
+      .expression_attribute_values(":u", AttributeValue::S(user_id.to_string()))
+      .send()
+      .await?;
+
+    let presences = response
+      .items
+      .unwrap_or_default()
+      .into_iter()
+      .filter_map(|item| DevicePresence::from_hashmap(item).ok())
+      .collect();
+    Ok(presences)
+  }
+}
@@ -0,0 +1,201 @@
+use std::collections::HashMap;
+
+use aws_sdk_dynamodb::error::SdkError;
+use aws_sdk_dynamodb::operation::batch_write_item::BatchWriteItemError;
+use aws_sdk_dynamodb::operation::query::QueryError;
+use aws_sdk_dynamodb::types::{
+  AttributeValue, DeleteRequest, Select, WriteRequest,
+};
+use chrono::{DateTime, Utc};
+use tokio::time::sleep;
+use tracing::{debug, warn};
+
+use crate::constants::dynamodb::undelivered_messages::{
+  MESSAGE_ID, PARTITION_KEY, SORT_KEY, TABLE_NAME,
+};
+use crate::constants::{
+  DDB_BATCH_WRITE_MAX_ATTEMPTS, DDB_BATCH_WRITE_MAX_ITEMS,
+  DDB_BATCH_WRITE_RETRY_DELAY,
+};
+
+use super::message_id::MessageID;
+use super::DatabaseClient;
+
+/// Summary of messages waiting to be delivered to a device
+#[derive(Debug, Default)]
+pub struct QueueStats {
+  pub undelivered_messages_count: u64,
+  pub oldest_message_timestamp: Option<DateTime<Utc>>,
+}
+
+impl DatabaseClient {
+  pub async fn get_queue_stats(
+    &self,
+    device_id: &str,
+  ) -> Result<QueueStats, SdkError<QueryError>> {
+    let mut stats = QueueStats::default();
+
+    let mut last_evaluated_key = None;
+    loop {
+      let response = self
+        .client
+        .query()
+        .table_name(TABLE_NAME)
+        .key_condition_expression(format!("{} = :u", PARTITION_KEY))
+        .expression_attribute_values(
+          ":u",
+          AttributeValue::S(device_id.to_string()),
+        )
+        .select(Select::Count)
+        .set_exclusive_start_key(last_evaluated_key)
+        .send()
+        .await?;
+
+      stats.undelivered_messages_count += response.count as u64;
+      last_evaluated_key = response.last_evaluated_key;
+      if last_evaluated_key.is_none() {
+        break;
+      }
+    }
+
+    if stats.undelivered_messages_count == 0 {
+      return Ok(stats);
+    }
+
+    // Messages are sorted by messageID, which starts with a timestamp
+    let response = self
+      .client
+      .query()
+      .table_name(TABLE_NAME)
+      .key_condition_expression(format!("{} = :u", PARTITION_KEY))
+      .expression_attribute_values(
+        ":u",
+        AttributeValue::S(device_id.to_string()),
+      )
+      .projection_expression(MESSAGE_ID)
+      .limit(1)
+      .consistent_read(true)
+      .send()
+      .await?;
+
+    stats.oldest_message_timestamp = response
+      .items
+      .and_then(|mut items| items.pop())
+      .and_then(|mut item| item.remove(MESSAGE_ID))
+      .and_then(|message_id| match message_id {
+        AttributeValue::S(message_id) => MessageID::try_from(message_id).ok(),
+        _ => None,
+      })
+      .map(|message_id| *message_id.timestamp());
+
+    Ok(stats)
+  }
+
+  /// Returns IDs of all messages waiting to be delivered to the device
+  pub async fn retrieve_message_ids(
+    &self,
+    device_id: &str,
+  ) -> Result<Vec<String>, SdkError<QueryError>> {
+    let mut message_ids = Vec::new();
+
+    let mut last_evaluated_key = None;
+    loop {
+      let response = self
+        .client
+        .query()
+        .table_name(TABLE_NAME)
+        .key_condition_expression(format!("{} = :u", PARTITION_KEY))
+        .expression_attribute_values(
+          ":u",
+          AttributeValue::S(device_id.to_string()),
+        )
+        .projection_expression(MESSAGE_ID)
+        .set_exclusive_start_key(last_evaluated_key)
+        .send()
+        .await?;
+
+      let items = response.items.unwrap_or_default();
+      message_ids.extend(items.into_iter().filter_map(|mut item| {
+        match item.remove(MESSAGE_ID) {
+          Some(AttributeValue::S(message_id)) => Some(message_id),
+          _ => None,
+        }
+      }));
+
+      last_evaluated_key = response.last_evaluated_key;
+      if last_evaluated_key.is_none() {
+        break;
+      }
+    }
+
+    Ok(message_ids)
+  }
+
+  /// Deletes given messages of the device in batches. Returns the number of
+  /// deleted messages.
+  pub async fn delete_messages(
+    &self,
+    device_id: &str,
+    message_ids: &[String],
+  ) -> Result<u64, SdkError<BatchWriteItemError>> {
+    debug!(
+      "Deleting {} messages for device: {}",
+      message_ids.len(),
+      device_id
+    );
+
+    let mut deleted_count = 0;
+    for chunk in message_ids.chunks(DDB_BATCH_WRITE_MAX_ITEMS) {
+      let mut requests: Vec<WriteRequest> = chunk
+        .iter()
+        .map(|message_id| {
+          let key = HashMap::from([
+            (
+              PARTITION_KEY.to_string(),
+              AttributeValue::S(device_id.to_string()),
+            ),
+            (
+              SORT_KEY.to_string(),
+              AttributeValue::S(message_id.to_string()),
+            ),
+          ]);
+          WriteRequest::builder()
+            .delete_request(DeleteRequest::builder().set_key(Some(key)).build())
+            .build()
+        })
+        .collect();
+
+      let mut attempt = 0;
+      while !requests.is_empty() && attempt < DDB_BATCH_WRITE_MAX_ATTEMPTS {
+        if attempt > 0 {
+          // Unprocessed items are usually caused by throttling
+          sleep(DDB_BATCH_WRITE_RETRY_DELAY * attempt).await;
+        }
+        attempt += 1;
+        let requested_count = requests.len();
+        let response = self
+          .client
+          .batch_write_item()
+          .request_items(TABLE_NAME, requests)
+          .send()
+          .await?;
+
+        requests = response
+          .unprocessed_items
+          .and_then(|mut items| items.remove(TABLE_NAME))
+          .unwrap_or_default();
+        deleted_count += (requested_count - requests.len()) as u64;
+      }
+
+      if !requests.is_empty() {
+        warn!(
+          "Failed to delete {} messages for device: {}",
+          requests.len(),
+          device_id
+        );
+      }
+    }
+
+    Ok(deleted_count)
+  }
+}
@@ -15,5 +15,7 @@ pub enum Error {
   #[display(...)]
   LapinError(lapin::Error),
   #[display(...)]
+  BrokerError(crate::broker::BrokerError),
+  #[display(...)]
   SerdeError(serde_json::Error),
 }
@@ -2,48 +2,99 @@ mod proto {
   tonic::include_proto!("tunnelbroker");
 }
 
-use lapin::{options::BasicPublishOptions, BasicProperties};
+use comm_services_lib::auth::{AuthService, AuthorizationCredential};
+use futures_util::future::join_all;
 use proto::tunnelbroker_service_server::{
   TunnelbrokerService, TunnelbrokerServiceServer,
 };
 use proto::Empty;
 use tonic::transport::Server;
-use tracing::debug;
+use tracing::{debug, error, warn};
 use tunnelbroker_messages::MessageToDevice;
 
-use crate::constants::CLIENT_RMQ_MSG_PRIORITY;
+use crate::broker::{Broker, BrokerError};
+use crate::constants::{CLIENT_RMQ_MSG_PRIORITY, GRPC_MAX_BATCH_MESSAGES};
 use crate::database::{handle_ddb_error, DatabaseClient};
+use crate::identity;
+use crate::shutdown::ShutdownHandle;
 use crate::{constants, CONFIG};
 
 struct TunnelbrokerGRPC {
   client: DatabaseClient,
-  amqp_channel: lapin::Channel,
+  broker: Broker,
+  auth_service: AuthService,
 }
 
-pub fn handle_amqp_error(error: lapin::Error) -> tonic::Status {
+pub fn handle_broker_error(error: BrokerError) -> tonic::Status {
   match error {
-    lapin::Error::SerialisationError(_) | lapin::Error::ParsingError(_) => {
-      tonic::Status::invalid_argument("Invalid argument")
-    }
+    BrokerError::AmqpError(
+      lapin::Error::SerialisationError(_) | lapin::Error::ParsingError(_),
+    ) => tonic::Status::invalid_argument("Invalid argument"),
+    BrokerError::Unavailable => tonic::Status::unavailable("please retry"),
     _ => tonic::Status::internal("Internal Error"),
   }
 }
 
-#[tonic::async_trait]
-impl TunnelbrokerService for TunnelbrokerGRPC {
-  async fn send_message_to_device(
+/// Returns the services token from the authorization header, if present
+fn services_token_credential(
+  metadata: &tonic::metadata::MetadataMap,
+) -> Option<AuthorizationCredential> {
+  let credential = metadata
+    .get("authorization")?
+    .to_str()
+    .ok()?
+    .strip_prefix("Bearer ")?
+    .parse()
+    .ok()?;
+  match credential {
+    AuthorizationCredential::ServicesToken(_) => Some(credential),
+    AuthorizationCredential::UserToken(_) => None,
+  }
+}
+
+impl TunnelbrokerGRPC {
+  /// Rejects requests which don't come from other services. Used by RPCs
+  /// which aren't meant to be called by devices.
+  async fn verify_services_token<T>(
     &self,
-    request: tonic::Request<proto::MessageToDevice>,
-  ) -> Result<tonic::Response<proto::Empty>, tonic::Status> {
-    let message = request.into_inner();
+    request: &tonic::Request<T>,
+  ) -> Result<(), tonic::Status> {
+    let Some(credential) = services_token_credential(request.metadata()) else {
+      return Err(tonic::Status::unauthenticated("missing services token"));
+    };
 
+    let token_valid = self
+      .auth_service
+      .verify_auth_credential(&credential)
+      .await
+      .map_err(|e| {
+        error!("Unable to verify services token: {:?}", e);
+        tonic::Status::unavailable("please retry")
+      })?;
+    if !token_valid {
+      return Err(tonic::Status::unauthenticated("invalid services token"));
+    }
+
+    Ok(())
+  }
+
+  /// Persists the message and publishes it to the device queue
+  async fn send_message(
+    &self,
+    message: proto::MessageToDevice,
+  ) -> Result<(), tonic::Status> {
     debug!("Received message for {}", &message.device_id);
 
     let client_message_id = uuid::Uuid::new_v4().to_string();
 
     let message_id = self
       .client
-      .persist_message(&message.device_id, &message.payload, &client_message_id)
+      .persist_message(
+        &message.device_id,
+        &message.payload,
+        &client_message_id,
+        None,
+      )
       .await
       .map_err(handle_ddb_error)?;
 
@@ -57,43 +108,254 @@ impl TunnelbrokerService for TunnelbrokerGRPC {
       .map_err(|_| tonic::Status::invalid_argument("Invalid argument"))?;
 
     self
-      .amqp_channel
-      .basic_publish(
-        "",
+      .broker
+      .publish(
         &message.device_id,
-        BasicPublishOptions::default(),
         serialized_message.as_bytes(),
-        BasicProperties::default().with_priority(CLIENT_RMQ_MSG_PRIORITY),
+        CLIENT_RMQ_MSG_PRIORITY,
       )
       .await
-      .map_err(handle_amqp_error)?;
+      .map_err(handle_broker_error)?;
+
+    Ok(())
+  }
+}
+
+#[tonic::async_trait]
+impl TunnelbrokerService for TunnelbrokerGRPC {
+  async fn send_message_to_device(
+    &self,
+    request: tonic::Request<proto::MessageToDevice>,
+  ) -> Result<tonic::Response<proto::Empty>, tonic::Status> {
+    self.send_message(request.into_inner()).await?;
 
     let response = tonic::Response::new(Empty {});
     Ok(response)
   }
+
+  async fn send_messages_to_devices(
+    &self,
+    request: tonic::Request<proto::MessagesToDevices>,
+  ) -> Result<
+    tonic::Response<proto::SendMessagesToDevicesResponse>,
+    tonic::Status,
+  > {
+    self.verify_services_token(&request).await?;
+    let messages = request.into_inner().messages;
+
+    debug!("Received batch of {} messages", messages.len());
+
+    if messages.len() > GRPC_MAX_BATCH_MESSAGES {
+      return Err(tonic::Status::invalid_argument(format!(
+        "Too many messages, maximum is {}",
+        GRPC_MAX_BATCH_MESSAGES
+      )));
+    }
+
+    let results = join_all(messages.into_iter().enumerate().map(
+      |(index, message)| async move {
+        let device_id = message.device_id.clone();
+        self.send_message(message).await.map_err(|status| {
+          proto::MessageSendFailure {
+            index: index as u32,
+            device_id,
+            error: status.message().to_string(),
+          }
+        })
+      },
+    ))
+    .await;
+
+    let failures: Vec<_> =
+      results.into_iter().filter_map(Result::err).collect();
+    if !failures.is_empty() {
+      warn!("Failed to send {} messages from batch", failures.len());
+    }
+
+    let response = proto::SendMessagesToDevicesResponse { failures };
+    Ok(tonic::Response::new(response))
+  }
+
+  async fn get_device_queue_stats(
+    &self,
+    request: tonic::Request<proto::DeviceQueueRequest>,
+  ) -> Result<tonic::Response<proto::DeviceQueueStats>, tonic::Status> {
+    self.verify_services_token(&request).await?;
+    let device_id = request.into_inner().device_id;
+
+    debug!("Received queue stats request for {}", &device_id);
+
+    let stats = self
+      .client
+      .get_queue_stats(&device_id)
+      .await
+      .map_err(handle_ddb_error)?;
+
+    let response = proto::DeviceQueueStats {
+      undelivered_messages_count: stats.undelivered_messages_count,
+      oldest_message_timestamp: stats
+        .oldest_message_timestamp
+        .map(|timestamp| timestamp.to_rfc3339()),
+    };
+    Ok(tonic::Response::new(response))
+  }
+
+  async fn purge_device_queue(
+    &self,
+    request: tonic::Request<proto::DeviceQueueRequest>,
+  ) -> Result<tonic::Response<proto::PurgeDeviceQueueResponse>, tonic::Status>
+  {
+    self.verify_services_token(&request).await?;
+    let device_id = request.into_inner().device_id;
+
+    debug!("Received queue purge request for {}", &device_id);
+
+    // Messages are removed from the broker first, so that a connected device
+    // doesn't receive a message which is no longer persisted
+    let purged_count = self
+      .broker
+      .purge_device_queue(&device_id)
+      .await
+      .map_err(handle_broker_error)?;
+    debug!("Purged {} messages from broker queue", purged_count);
+
+    let message_ids = self
+      .client
+      .retrieve_message_ids(&device_id)
+      .await
+      .map_err(handle_ddb_error)?;
+    let deleted_messages_count = self
+      .client
+      .delete_messages(&device_id, &message_ids)
+      .await
+      .map_err(handle_ddb_error)?;
+
+    let response = proto::PurgeDeviceQueueResponse {
+      deleted_messages_count,
+    };
+    Ok(tonic::Response::new(response))
+  }
+
+  async fn get_device_presence(
+    &self,
+    request: tonic::Request<proto::DevicePresenceRequest>,
+  ) -> Result<tonic::Response<proto::DevicePresenceResponse>, tonic::Status> {
+    self.verify_services_token(&request).await?;
+    let device_id = request.into_inner().device_id;
+
+    debug!("Received presence request for {}", &device_id);
+
+    let presence = self
+      .client
+      .get_device_presence(&device_id)
+      .await
+      .map_err(handle_ddb_error)?
+      .filter(|presence| !presence.is_stale());
+
+    let response = match presence {
+      Some(presence) => proto::DevicePresenceResponse {
+        connected: true,
+        instance_id: Some(presence.instance_id),
+        connected_since: Some(presence.connected_since.to_rfc3339()),
+        last_heartbeat: Some(presence.last_heartbeat.to_rfc3339()),
+      },
+      None => proto::DevicePresenceResponse {
+        connected: false,
+        instance_id: None,
+        connected_since: None,
+        last_heartbeat: None,
+      },
+    };
+
+    Ok(tonic::Response::new(response))
+  }
+
+  async fn invalidate_access_tokens(
+    &self,
+    request: tonic::Request<proto::InvalidateAccessTokensRequest>,
+  ) -> Result<tonic::Response<proto::Empty>, tonic::Status> {
+    self.verify_services_token(&request).await?;
+    let message = request.into_inner();
+
+    debug!("Invalidating access tokens of user: {}", &message.user_id);
+    identity::invalidate_access_tokens(
+      &message.user_id,
+      message.device_id.as_deref(),
+    );
+
+    Ok(tonic::Response::new(Empty {}))
+  }
 }
 
 pub async fn run_server(
   client: DatabaseClient,
-  ampq_connection: &lapin::Connection,
+  broker: Broker,
+  auth_service: AuthService,
+  shutdown: ShutdownHandle,
 ) -> Result<(), tonic::transport::Error> {
   let addr = format!("[::]:{}", CONFIG.grpc_port)
     .parse()
     .expect("Unable to parse gRPC address");
 
-  let amqp_channel = ampq_connection
-    .create_channel()
-    .await
-    .expect("Unable to create amqp channel");
+  // The handle is kept until the server finishes in-flight requests
+  let mut shutdown_signal = shutdown.clone();
 
   tracing::info!("gRPC server listening on {}", &addr);
-  Server::builder()
+  let result = Server::builder()
     .http2_keepalive_interval(Some(constants::GRPC_KEEP_ALIVE_PING_INTERVAL))
     .http2_keepalive_timeout(Some(constants::GRPC_KEEP_ALIVE_PING_TIMEOUT))
     .add_service(TunnelbrokerServiceServer::new(TunnelbrokerGRPC {
       client,
-      amqp_channel,
+      broker,
+      auth_service,
     }))
-    .serve(addr)
-    .await
+    .serve_with_shutdown(addr, async move {
+      shutdown_signal.wait().await;
+      tracing::info!("Stopping gRPC server");
+    })
+    .await;
+
+  drop(shutdown);
+  result
+}
+
+#[cfg(test)]
+mod grpc_tests {
+  use super::*;
+  use comm_services_lib::auth::{ServicesAuthToken, UserIdentity};
+  use tonic::metadata::MetadataMap;
+
+  fn metadata_with_bearer(token: &str) -> MetadataMap {
+    let mut metadata = MetadataMap::new();
+    let bearer = format!("Bearer {}", token);
+    metadata.insert("authorization", bearer.parse().unwrap());
+    metadata
+  }
+
+  #[test]
+  fn test_services_token_credential() {
+    let services_token = ServicesAuthToken::new("secret".to_string());
+    let bearer = services_token.as_authorization_token().unwrap();
+    assert_eq!(
+      services_token_credential(&metadata_with_bearer(&bearer)),
+      Some(AuthorizationCredential::ServicesToken(services_token))
+    );
+
+    let user_identity = UserIdentity {
+      user_id: "user".to_string(),
+      access_token: "token".to_string(),
+      device_id: "device".to_string(),
+    };
+    let bearer = user_identity.as_authorization_token().unwrap();
+    assert_eq!(
+      services_token_credential(&metadata_with_bearer(&bearer)),
+      None
+    );
+
+    assert_eq!(
+      services_token_credential(&metadata_with_bearer("???")),
+      None
+    );
+    assert_eq!(services_token_credential(&MetadataMap::new()), None);
+  }
 }
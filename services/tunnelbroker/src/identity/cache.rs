@@ -0,0 +1,165 @@
+//! Short-lived cache of successful access token verifications. Devices
+//! reconnecting in bulk (e.g. after a deploy) are verified without calling
+//! the identity service again.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use sha2::{Digest, Sha256};
+use tokio::time::Instant;
+use tracing::debug;
+
+#[derive(Hash, PartialEq, Eq)]
+struct CacheKey {
+  user_id: String,
+  device_id: String,
+  // Tokens aren't kept in memory in plain text
+  token_hash: [u8; 32],
+}
+
+impl CacheKey {
+  fn new(user_id: &str, device_id: &str, access_token: &str) -> Self {
+    Self {
+      user_id: user_id.to_string(),
+      device_id: device_id.to_string(),
+      token_hash: Sha256::digest(access_token.as_bytes()).into(),
+    }
+  }
+}
+
+pub struct TokenCache {
+  ttl: Duration,
+  max_entries: usize,
+  // Key -> expiration time of the entry
+  entries: Mutex<HashMap<CacheKey, Instant>>,
+}
+
+impl TokenCache {
+  pub fn new(ttl: Duration, max_entries: usize) -> Self {
+    Self {
+      ttl,
+      max_entries,
+      entries: Mutex::new(HashMap::new()),
+    }
+  }
+
+  /// Returns true if the token was verified recently
+  pub fn contains(
+    &self,
+    user_id: &str,
+    device_id: &str,
+    access_token: &str,
+  ) -> bool {
+    let key = CacheKey::new(user_id, device_id, access_token);
+    let mut entries = self.entries.lock().expect("Token cache lock poisoned");
+
+    match entries.get(&key) {
+      Some(expires_at) if *expires_at > Instant::now() => true,
+      Some(_) => {
+        entries.remove(&key);
+        false
+      }
+      None => false,
+    }
+  }
+
+  /// Records a successful verification of the token
+  pub fn insert(&self, user_id: &str, device_id: &str, access_token: &str) {
+    let key = CacheKey::new(user_id, device_id, access_token);
+    let now = Instant::now();
+    let mut entries = self.entries.lock().expect("Token cache lock poisoned");
+
+    if entries.len() >= self.max_entries {
+      entries.retain(|_, expires_at| *expires_at > now);
+    }
+    if entries.len() >= self.max_entries {
+      debug!("Token cache is full, skipping verification result");
+      return;
+    }
+
+    entries.insert(key, now + self.ttl);
+  }
+
+  /// Removes cached verifications of all tokens of the device, or of all
+  /// devices of the user if `device_id` is not provided
+  pub fn invalidate(&self, user_id: &str, device_id: Option<&str>) {
+    let mut entries = self.entries.lock().expect("Token cache lock poisoned");
+    entries.retain(|key, _| {
+      key.user_id != user_id
+        || device_id.is_some_and(|device_id| key.device_id != device_id)
+    });
+  }
+}
+
+#[cfg(test)]
+mod token_cache_tests {
+  use super::*;
+
+  const TTL: Duration = Duration::from_secs(60);
+
+  #[tokio::test]
+  async fn test_cached_token() {
+    let cache = TokenCache::new(TTL, 10);
+    assert!(!cache.contains("user", "device", "token"));
+
+    cache.insert("user", "device", "token");
+    assert!(cache.contains("user", "device", "token"));
+    assert!(!cache.contains("user", "device", "other token"));
+    assert!(!cache.contains("user", "other device", "token"));
+    assert!(!cache.contains("other user", "device", "token"));
+  }
+
+  #[tokio::test(start_paused = true)]
+  async fn test_expiration() {
+    let cache = TokenCache::new(TTL, 10);
+    cache.insert("user", "device", "token");
+
+    tokio::time::advance(TTL - Duration::from_secs(1)).await;
+    assert!(cache.contains("user", "device", "token"));
+
+    tokio::time::advance(Duration::from_secs(1)).await;
+    assert!(!cache.contains("user", "device", "token"));
+  }
+
+  #[tokio::test]
+  async fn test_invalidate_device() {
+    let cache = TokenCache::new(TTL, 10);
+    cache.insert("user", "device", "token");
+    cache.insert("user", "other device", "token");
+
+    cache.invalidate("user", Some("device"));
+    assert!(!cache.contains("user", "device", "token"));
+    assert!(cache.contains("user", "other device", "token"));
+  }
+
+  #[tokio::test]
+  async fn test_invalidate_user() {
+    let cache = TokenCache::new(TTL, 10);
+    cache.insert("user", "device", "token");
+    cache.insert("user", "other device", "token");
+    cache.insert("other user", "device", "token");
+
+    cache.invalidate("user", None);
+    assert!(!cache.contains("user", "device", "token"));
+    assert!(!cache.contains("user", "other device", "token"));
+    assert!(cache.contains("other user", "device", "token"));
+  }
+
+  #[tokio::test(start_paused = true)]
+  async fn test_max_entries() {
+    let cache = TokenCache::new(TTL, 2);
+    cache.insert("user", "first", "token");
+    cache.insert("user", "second", "token");
+
+    // Full cache doesn't accept new entries
+    cache.insert("user", "third", "token");
+    assert!(!cache.contains("user", "third", "token"));
+    assert!(cache.contains("user", "first", "token"));
+
+    // Expired entries are removed to make space
+    tokio::time::advance(TTL).await;
+    cache.insert("user", "third", "token");
+    assert!(cache.contains("user", "third", "token"));
+  }
+}
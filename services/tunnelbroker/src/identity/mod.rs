@@ -1,10 +1,23 @@
+pub mod cache;
+
+use client_proto::identity_client_service_client::IdentityClientServiceClient;
 use client_proto::VerifyUserAccessTokenRequest;
 use grpc_clients::identity;
+use grpc_clients::identity::shared::CodeVersionLayer;
+use grpc_clients::tonic::codegen::InterceptedService;
+use grpc_clients::tonic::transport::Channel;
 use grpc_clients::tonic::Request;
 use identity::get_unauthenticated_client;
 use identity::protos::unauthenticated as client_proto;
+use once_cell::sync::Lazy;
+use tokio::sync::OnceCell;
+use tracing::debug;
 
+use self::cache::TokenCache;
 use crate::config::CONFIG;
+use crate::constants::{
+  IDENTITY_TOKEN_CACHE_MAX_ENTRIES, IDENTITY_TOKEN_CACHE_TTL,
+};
 use crate::error::Error;
 
 // Identity service gRPC clients require a code version and device type.
@@ -13,18 +26,43 @@ use crate::error::Error;
 const PLACEHOLDER_CODE_VERSION: u64 = 0;
 const DEVICE_TYPE: &str = "service";
 
+type IdentityClient =
+  IdentityClientServiceClient<InterceptedService<Channel, CodeVersionLayer>>;
+
+/// Client shared by all sessions. Connected on first use, the underlying
+/// channel reconnects automatically afterwards.
+static IDENTITY_CLIENT: Lazy<OnceCell<IdentityClient>> =
+  Lazy::new(OnceCell::new);
+
+static TOKEN_CACHE: Lazy<TokenCache> = Lazy::new(|| {
+  TokenCache::new(IDENTITY_TOKEN_CACHE_TTL, IDENTITY_TOKEN_CACHE_MAX_ENTRIES)
+});
+
+async fn identity_client() -> Result<IdentityClient, Error> {
+  let client = IDENTITY_CLIENT
+    .get_or_try_init(|| {
+      get_unauthenticated_client(
+        &CONFIG.identity_endpoint,
+        PLACEHOLDER_CODE_VERSION,
+        DEVICE_TYPE.to_string(),
+      )
+    })
+    .await?;
+  Ok(client.clone())
+}
+
 /// Returns true if access token is valid
 pub async fn verify_user_access_token(
   user_id: &str,
   device_id: &str,
   access_token: &str,
 ) -> Result<bool, Error> {
-  let mut grpc_client = get_unauthenticated_client(
-    &CONFIG.identity_endpoint,
-    PLACEHOLDER_CODE_VERSION,
-    DEVICE_TYPE.to_string(),
-  )
-  .await?;
+  if TOKEN_CACHE.contains(user_id, device_id, access_token) {
+    debug!("Using cached token verification for device: {}", device_id);
+    return Ok(true);
+  }
+
+  let mut grpc_client = identity_client().await?;
   let message = VerifyUserAccessTokenRequest {
     user_id: user_id.to_string(),
     signing_public_key: device_id.to_string(),
@@ -33,5 +71,17 @@ pub async fn verify_user_access_token(
 
   let request = Request::new(message);
   let response = grpc_client.verify_user_access_token(request).await?;
-  Ok(response.into_inner().token_valid)
+  let token_valid = response.into_inner().token_valid;
+
+  // Only successful verifications are cached, so that a device can connect
+  // as soon as it obtains a valid token
+  if token_valid {
+    TOKEN_CACHE.insert(user_id, device_id, access_token);
+  }
+  Ok(token_valid)
+}
+
+/// Forgets cached verifications of tokens revoked by the identity service
+pub fn invalidate_access_tokens(user_id: &str, device_id: Option<&str>) {
+  TOKEN_CACHE.invalidate(user_id, device_id);
 }
@@ -1,14 +1,18 @@
-pub mod amqp;
+pub mod broker;
 pub mod config;
 pub mod constants;
 pub mod database;
 pub mod error;
 pub mod grpc;
 pub mod identity;
+pub mod rate_limit;
+pub mod shutdown;
 pub mod websockets;
 
 use anyhow::{anyhow, Result};
+use comm_services_lib::auth::AuthService;
 use config::CONFIG;
+use std::time::Duration;
 use tracing::{self, Level};
 use tracing_subscriber::EnvFilter;
 
@@ -26,18 +30,42 @@ async fn main() -> Result<()> {
   config::parse_cmdline_args()?;
   let aws_config = config::load_aws_config().await;
   let db_client = database::DatabaseClient::new(&aws_config);
-  let amqp_connection = amqp::connect().await;
+  let broker = broker::create_broker().await;
+  let auth_service =
+    AuthService::new(&aws_config, CONFIG.identity_endpoint.clone());
 
-  let grpc_server = grpc::run_server(db_client.clone(), &amqp_connection);
-  let websocket_server =
-    websockets::run_server(db_client.clone(), &amqp_connection);
+  let shutdown = shutdown::Shutdown::new();
 
-  tokio::select! {
-    Ok(_) = grpc_server => { Ok(()) },
-    Ok(_) = websocket_server => { Ok(()) },
-    else => {
-      tracing::error!("A grpc or websocket server crashed.");
+  let mut grpc_server = tokio::spawn(grpc::run_server(
+    db_client.clone(),
+    broker.clone(),
+    auth_service,
+    shutdown.handle(),
+  ));
+  let mut websocket_server = tokio::spawn(websockets::run_server(
+    db_client.clone(),
+    broker,
+    shutdown.handle(),
+  ));
+
+  let result = tokio::select! {
+    _ = shutdown::wait_for_signal() => Ok(()),
+    _ = &mut grpc_server => Err(anyhow!("A grpc or websocket server crashed.")),
+    _ = &mut websocket_server => {
       Err(anyhow!("A grpc or websocket server crashed."))
     }
+  };
+  if let Err(e) = &result {
+    tracing::error!("{}", e);
+  }
+
+  tracing::info!("Shutting down, waiting for open connections to close");
+  let deadline = Duration::from_secs(CONFIG.shutdown_timeout);
+  if shutdown.drain(deadline).await {
+    tracing::info!("All connections closed");
+  } else {
+    tracing::warn!("Shutdown deadline exceeded, dropping open connections");
   }
+
+  result
 }
@@ -0,0 +1,235 @@
+//! Limits of messages sent by devices and of websocket connections opened
+//! from a single IP address. Limits are enforced by each Tunnelbroker
+//! instance separately.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use once_cell::sync::Lazy;
+use tokio::time::Instant;
+
+use crate::config::CONFIG;
+use crate::constants::{
+  RATE_LIMITER_CLEANUP_INTERVAL, RATE_LIMITER_CLEANUP_THRESHOLD,
+};
+
+/// Limits messages sent by a device
+pub static SENDER_RATE_LIMITER: Lazy<RateLimiter> = Lazy::new(|| {
+  RateLimiter::new(CONFIG.sender_burst_limit, CONFIG.sender_rate_limit)
+});
+
+/// Limits messages sent to a device, regardless of the sender
+pub static RECIPIENT_RATE_LIMITER: Lazy<RateLimiter> = Lazy::new(|| {
+  RateLimiter::new(CONFIG.recipient_burst_limit, CONFIG.recipient_rate_limit)
+});
+
+struct TokenBucket {
+  tokens: f64,
+  last_refill: Instant,
+}
+
+struct Buckets {
+  entries: HashMap<String, TokenBucket>,
+  last_cleanup: Instant,
+}
+
+/// Token bucket rate limiter. Each key has a bucket of `capacity` tokens,
+/// refilled at `refill_rate` tokens per second. A request takes one token.
+pub struct RateLimiter {
+  capacity: f64,
+  refill_rate: f64,
+  buckets: Mutex<Buckets>,
+}
+
+impl RateLimiter {
+  pub fn new(capacity: u32, refill_rate: u32) -> Self {
+    Self {
+      capacity: capacity.into(),
+      refill_rate: refill_rate.into(),
+      buckets: Mutex::new(Buckets {
+        entries: HashMap::new(),
+        last_cleanup: Instant::now(),
+      }),
+    }
+  }
+
+  /// Takes a token from the bucket of the key. If there are no tokens left,
+  /// returns the time after which the request can be retried.
+  pub fn check(&self, key: &str) -> Result<(), Duration> {
+    let now = Instant::now();
+    let mut buckets = self.buckets.lock().expect("Rate limiter lock poisoned");
+
+    // Cleanup is linear in the number of keys, so it runs at most once per
+    // interval instead of on every check
+    if buckets.entries.len() > RATE_LIMITER_CLEANUP_THRESHOLD
+      && now.duration_since(buckets.last_cleanup)
+        >= RATE_LIMITER_CLEANUP_INTERVAL
+    {
+      // Full buckets are the same as missing ones
+      buckets
+        .entries
+        .retain(|_, bucket| self.refilled_tokens(bucket, now) < self.capacity);
+      buckets.last_cleanup = now;
+    }
+
+    let bucket =
+      buckets
+        .entries
+        .entry(key.to_string())
+        .or_insert(TokenBucket {
+          tokens: self.capacity,
+          last_refill: now,
+        });
+    bucket.tokens = self.refilled_tokens(bucket, now);
+    bucket.last_refill = now;
+
+    if bucket.tokens >= 1.0 {
+      bucket.tokens -= 1.0;
+      return Ok(());
+    }
+
+    if self.refill_rate <= 0.0 {
+      return Err(Duration::MAX);
+    }
+    let missing_tokens = 1.0 - bucket.tokens;
+    Err(Duration::from_secs_f64(missing_tokens / self.refill_rate))
+  }
+
+  fn refilled_tokens(&self, bucket: &TokenBucket, now: Instant) -> f64 {
+    let elapsed = now.duration_since(bucket.last_refill).as_secs_f64();
+    (bucket.tokens + elapsed * self.refill_rate).min(self.capacity)
+  }
+}
+
+/// Limits concurrent connections from a single IP address
+#[derive(Clone)]
+pub struct ConnectionLimiter {
+  max_connections: usize,
+  connections: Arc<Mutex<HashMap<IpAddr, usize>>>,
+}
+
+/// Counts as an open connection until dropped
+pub struct ConnectionPermit {
+  ip: IpAddr,
+  connections: Arc<Mutex<HashMap<IpAddr, usize>>>,
+}
+
+impl ConnectionLimiter {
+  pub fn new(max_connections: usize) -> Self {
+    Self {
+      max_connections,
+      connections: Arc::new(Mutex::new(HashMap::new())),
+    }
+  }
+
+  /// Returns `None` if there are too many connections from the address
+  pub fn try_acquire(&self, ip: IpAddr) -> Option<ConnectionPermit> {
+    let mut connections = self
+      .connections
+      .lock()
+      .expect("Connection limiter lock poisoned");
+
+    let count = connections.entry(ip).or_default();
+    if *count >= self.max_connections {
+      return None;
+    }
+    *count += 1;
+
+    Some(ConnectionPermit {
+      ip,
+      connections: self.connections.clone(),
+    })
+  }
+}
+
+impl Drop for ConnectionPermit {
+  fn drop(&mut self) {
+    let mut connections = self
+      .connections
+      .lock()
+      .expect("Connection limiter lock poisoned");
+
+    if let Some(count) = connections.get_mut(&self.ip) {
+      *count -= 1;
+      if *count == 0 {
+        connections.remove(&self.ip);
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod rate_limit_tests {
+  use super::*;
+
+  #[tokio::test(start_paused = true)]
+  async fn test_burst_and_refill() {
+    let limiter = RateLimiter::new(2, 1);
+    assert!(limiter.check("device").is_ok());
+    assert!(limiter.check("device").is_ok());
+
+    let retry_after = limiter.check("device").unwrap_err();
+    assert_eq!(retry_after, Duration::from_secs(1));
+
+    tokio::time::advance(Duration::from_millis(500)).await;
+    let retry_after = limiter.check("device").unwrap_err();
+    assert_eq!(retry_after, Duration::from_millis(500));
+
+    tokio::time::advance(Duration::from_millis(500)).await;
+    assert!(limiter.check("device").is_ok());
+    assert!(limiter.check("device").is_err());
+  }
+
+  #[tokio::test(start_paused = true)]
+  async fn test_refill_capped_at_capacity() {
+    let limiter = RateLimiter::new(2, 1);
+    tokio::time::advance(Duration::from_secs(10)).await;
+
+    assert!(limiter.check("device").is_ok());
+    assert!(limiter.check("device").is_ok());
+    assert!(limiter.check("device").is_err());
+  }
+
+  #[tokio::test(start_paused = true)]
+  async fn test_separate_keys() {
+    let limiter = RateLimiter::new(1, 1);
+    assert!(limiter.check("first").is_ok());
+    assert!(limiter.check("first").is_err());
+    assert!(limiter.check("second").is_ok());
+  }
+
+  #[tokio::test(start_paused = true)]
+  async fn test_cleanup_interval() {
+    let limiter = RateLimiter::new(1, 1);
+    for i in 0..=RATE_LIMITER_CLEANUP_THRESHOLD {
+      assert!(limiter.check(&i.to_string()).is_ok());
+    }
+    let tracked_keys = || limiter.buckets.lock().unwrap().entries.len();
+
+    // All buckets are full again, but the last cleanup was too recent
+    tokio::time::advance(RATE_LIMITER_CLEANUP_INTERVAL / 2).await;
+    assert!(limiter.check("device").is_ok());
+    assert_eq!(tracked_keys(), RATE_LIMITER_CLEANUP_THRESHOLD + 2);
+
+    tokio::time::advance(RATE_LIMITER_CLEANUP_INTERVAL / 2).await;
+    assert!(limiter.check("other device").is_ok());
+    assert_eq!(tracked_keys(), 1);
+  }
+
+  #[test]
+  fn test_connection_limit() {
+    let limiter = ConnectionLimiter::new(2);
+    let ip: IpAddr = "192.0.2.1".parse().unwrap();
+    let other_ip: IpAddr = "192.0.2.2".parse().unwrap();
+
+    let first = limiter.try_acquire(ip).unwrap();
+    let _second = limiter.try_acquire(ip).unwrap();
+    assert!(limiter.try_acquire(ip).is_none());
+    assert!(limiter.try_acquire(other_ip).is_some());
+
+    drop(first);
+    assert!(limiter.try_acquire(ip).is_some());
+  }
+}
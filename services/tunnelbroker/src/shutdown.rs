@@ -0,0 +1,124 @@
+//! Graceful shutdown of Tunnelbroker. When a shutdown is triggered, servers
+//! stop accepting new connections and open websocket sessions ask their
+//! devices to reconnect to another instance. The process exits once all
+//! handles are dropped or the deadline passes.
+
+use std::time::Duration;
+
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::{mpsc, watch};
+use tracing::info;
+
+/// Controls the shutdown. Owned by `main()`.
+pub struct Shutdown {
+  trigger: watch::Sender<bool>,
+  handle: ShutdownHandle,
+  // Receives `None` once all handles are dropped
+  drained: mpsc::Receiver<()>,
+}
+
+/// Notifies its owner about the shutdown. Shutdown isn't finished until all
+/// handles are dropped, so tasks that need to clean up before the process
+/// exits should hold one.
+#[derive(Clone)]
+pub struct ShutdownHandle {
+  signal: watch::Receiver<bool>,
+  _drain_guard: mpsc::Sender<()>,
+}
+
+impl Shutdown {
+  pub fn new() -> Self {
+    let (trigger, signal) = watch::channel(false);
+    let (drain_guard, drained) = mpsc::channel(1);
+
+    Self {
+      trigger,
+      handle: ShutdownHandle {
+        signal,
+        _drain_guard: drain_guard,
+      },
+      drained,
+    }
+  }
+
+  pub fn handle(&self) -> ShutdownHandle {
+    self.handle.clone()
+  }
+
+  /// Notifies all handles about the shutdown and waits until they are
+  /// dropped. Returns `false` if they weren't dropped before the deadline.
+  pub async fn drain(self, deadline: Duration) -> bool {
+    let Self {
+      trigger,
+      handle,
+      mut drained,
+    } = self;
+
+    // Fails only if there are no handles left, which is fine
+    let _ = trigger.send(true);
+    drop(handle);
+
+    tokio::time::timeout(deadline, drained.recv()).await.is_ok()
+  }
+}
+
+impl Default for Shutdown {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl ShutdownHandle {
+  pub fn is_shutting_down(&self) -> bool {
+    *self.signal.borrow()
+  }
+
+  /// Completes once the shutdown is triggered
+  pub async fn wait(&mut self) {
+    while !*self.signal.borrow_and_update() {
+      if self.signal.changed().await.is_err() {
+        // Shutdown controller was dropped, so the process is exiting anyway
+        return;
+      }
+    }
+  }
+}
+
+/// Completes when the process receives SIGTERM or SIGINT
+pub async fn wait_for_signal() {
+  let mut sigterm =
+    signal(SignalKind::terminate()).expect("Unable to listen for SIGTERM");
+
+  tokio::select! {
+    _ = sigterm.recv() => info!("Received SIGTERM"),
+    _ = tokio::signal::ctrl_c() => info!("Received SIGINT"),
+  }
+}
+
+#[cfg(test)]
+mod shutdown_tests {
+  use super::*;
+
+  #[tokio::test]
+  async fn test_drain_waits_for_handles() {
+    let shutdown = Shutdown::new();
+    let mut handle = shutdown.handle();
+    assert!(!handle.is_shutting_down());
+
+    tokio::spawn(async move {
+      handle.wait().await;
+      assert!(handle.is_shutting_down());
+      // Dropping the handle finishes the drain
+    });
+
+    assert!(shutdown.drain(Duration::from_secs(5)).await);
+  }
+
+  #[tokio::test]
+  async fn test_drain_deadline() {
+    let shutdown = Shutdown::new();
+    let _handle = shutdown.handle();
+
+    assert!(!shutdown.drain(Duration::from_millis(10)).await);
+  }
+}
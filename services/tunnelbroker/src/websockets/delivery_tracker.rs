@@ -0,0 +1,128 @@
+//! Tracks messages sent to a device during a websocket session. Broker
+//! deliveries are acknowledged only after the device confirms them with
+//! MessageReceiveConfirmation, and a message which is delivered more than
+//! once (e.g. republished from DynamoDB or redelivered after the broker
+//! reconnected) is sent to the device only once.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use crate::broker::BrokerDelivery;
+use crate::constants::MAX_CONFIRMED_MESSAGE_IDS;
+
+/// Returns the ID of a message received from the broker. Messages without
+/// an ID (e.g. presence updates) are never confirmed by the device.
+pub fn delivered_message_id(data: &[u8]) -> Option<String> {
+  let message = serde_json::from_slice::<serde_json::Value>(data).ok()?;
+  message
+    .get("messageID")
+    .and_then(|message_id| message_id.as_str())
+    .map(str::to_string)
+}
+
+#[derive(Default)]
+pub struct DeliveryTracker {
+  // Message ID -> delivery waiting for confirmation from the device
+  pending: HashMap<String, BrokerDelivery>,
+  // Recently confirmed message IDs, oldest first
+  confirmed: VecDeque<String>,
+  confirmed_ids: HashSet<String>,
+}
+
+impl DeliveryTracker {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Whether the message was already sent to the device in this session
+  pub fn is_duplicate(&self, message_id: &str) -> bool {
+    self.pending.contains_key(message_id)
+      || self.confirmed_ids.contains(message_id)
+  }
+
+  /// Records that the message was sent to the device
+  pub fn track(&mut self, message_id: String, delivery: BrokerDelivery) {
+    self.pending.insert(message_id, delivery);
+  }
+
+  /// Records that the device confirmed the message. Returns its delivery,
+  /// which should be acknowledged.
+  pub fn confirm(&mut self, message_id: &str) -> Option<BrokerDelivery> {
+    if self.confirmed_ids.insert(message_id.to_string()) {
+      self.confirmed.push_back(message_id.to_string());
+      if self.confirmed.len() > MAX_CONFIRMED_MESSAGE_IDS {
+        if let Some(oldest) = self.confirmed.pop_front() {
+          self.confirmed_ids.remove(&oldest);
+        }
+      }
+    }
+
+    self.pending.remove(message_id)
+  }
+
+  pub fn pending_count(&self) -> usize {
+    self.pending.len()
+  }
+}
+
+#[cfg(test)]
+mod delivery_tracker_tests {
+  use super::*;
+  use crate::broker::{InProcessBroker, MessageBroker};
+
+  async fn create_delivery(broker: &InProcessBroker) -> BrokerDelivery {
+    broker.declare_device_queue("device").await.unwrap();
+    broker.publish("device", b"message", 1).await.unwrap();
+    let mut consumer = broker.consume("device").await.unwrap();
+    consumer.next().await.unwrap().unwrap()
+  }
+
+  #[test]
+  fn test_delivered_message_id() {
+    let message = r#"{
+      "type": "MessageToDevice",
+      "deviceID": "alice",
+      "payload": "message",
+      "messageID": "id123"
+    }"#;
+    assert_eq!(
+      delivered_message_id(message.as_bytes()),
+      Some("id123".to_string())
+    );
+
+    let presence_update = r#"{
+      "type": "DevicePresenceUpdate",
+      "deviceID": "alice",
+      "connected": false
+    }"#;
+    assert_eq!(delivered_message_id(presence_update.as_bytes()), None);
+  }
+
+  #[tokio::test]
+  async fn test_duplicates() {
+    let broker = InProcessBroker::new();
+    let mut tracker = DeliveryTracker::new();
+    assert!(!tracker.is_duplicate("id"));
+
+    tracker.track("id".to_string(), create_delivery(&broker).await);
+    assert!(tracker.is_duplicate("id"));
+    assert_eq!(tracker.pending_count(), 1);
+
+    assert!(tracker.confirm("id").is_some());
+    assert_eq!(tracker.pending_count(), 0);
+    // Confirmed messages are still recognized
+    assert!(tracker.is_duplicate("id"));
+    assert!(tracker.confirm("id").is_none());
+  }
+
+  #[test]
+  fn test_confirmed_ids_limit() {
+    let mut tracker = DeliveryTracker::new();
+    for i in 0..=MAX_CONFIRMED_MESSAGE_IDS {
+      tracker.confirm(&i.to_string());
+    }
+
+    assert!(!tracker.is_duplicate("0"));
+    assert!(tracker.is_duplicate("1"));
+    assert!(tracker.is_duplicate(&MAX_CONFIRMED_MESSAGE_IDS.to_string()));
+  }
+}
@@ -1,8 +1,12 @@
+pub mod delivery_tracker;
 pub mod session;
 
+use crate::broker::Broker;
 use crate::constants::SOCKET_HEARTBEAT_TIMEOUT;
 use crate::database::DatabaseClient;
-use crate::websockets::session::{initialize_amqp, SessionError};
+use crate::rate_limit::ConnectionLimiter;
+use crate::shutdown::ShutdownHandle;
+use crate::websockets::session::{initialize_broker, SessionError};
 use crate::CONFIG;
 use futures_util::stream::SplitSink;
 use futures_util::{SinkExt, StreamExt};
@@ -13,7 +17,7 @@ use hyper_tungstenite::HyperWebsocket;
 use hyper_tungstenite::WebSocketStream;
 use std::env;
 use std::future::Future;
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
 use std::pin::Pin;
 use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::net::TcpListener;
@@ -38,8 +42,29 @@ use self::session::WebsocketSession;
 /// It also handles regular HTTP requests (currently health check)
 struct WebsocketService {
   addr: SocketAddr,
-  channel: lapin::Channel,
+  broker: Broker,
   db_client: DatabaseClient,
+  shutdown: ShutdownHandle,
+  connection_limiter: ConnectionLimiter,
+}
+
+/// Returns the address of the client. Behind a load balancer, the peer
+/// address is the load balancer's, and the client address is the last one
+/// appended to X-Forwarded-For.
+fn client_ip(req: &Request<Body>, peer_addr: SocketAddr) -> IpAddr {
+  if !CONFIG.trust_forwarded_for {
+    return peer_addr.ip();
+  }
+
+  req
+    .headers()
+    .get_all("x-forwarded-for")
+    .iter()
+    .filter_map(|value| value.to_str().ok())
+    .flat_map(|value| value.split(','))
+    .last()
+    .and_then(|ip| ip.trim().parse().ok())
+    .unwrap_or_else(|| peer_addr.ip())
 }
 
 impl hyper::service::Service<Request<Body>> for WebsocketService {
@@ -60,16 +85,29 @@ impl hyper::service::Service<Request<Body>> for WebsocketService {
   fn call(&mut self, mut req: Request<Body>) -> Self::Future {
     let addr = self.addr;
     let db_client = self.db_client.clone();
-    let channel = self.channel.clone();
+    let broker = self.broker.clone();
+    let shutdown = self.shutdown.clone();
+    let connection_limiter = self.connection_limiter.clone();
 
     let future = async move {
       // Check if the request is a websocket upgrade request.
       if hyper_tungstenite::is_upgrade_request(&req) {
+        let ip = client_ip(&req, addr);
+        let Some(permit) = connection_limiter.try_acquire(ip) else {
+          info!("Too many WebSocket connections from: {}", ip);
+          let response = Response::builder()
+            .status(StatusCode::TOO_MANY_REQUESTS)
+            .body(Body::from("Too many connections"))?;
+          return Ok(response);
+        };
+
         let (response, websocket) = hyper_tungstenite::upgrade(&mut req, None)?;
 
         // Spawn a task to handle the websocket connection.
         tokio::spawn(async move {
-          accept_connection(websocket, addr, db_client, channel).await;
+          accept_connection(websocket, addr, db_client, broker, shutdown).await;
+          // Connection is counted until the session ends
+          drop(permit);
         });
 
         // Return the response so the spawned future can continue.
@@ -84,6 +122,14 @@ impl hyper::service::Service<Request<Body>> for WebsocketService {
 
       // A simple router for regular HTTP requests
       let response = match req.uri().path() {
+        // Load balancer should stop routing to an instance which is
+        // shutting down
+        "/health" if shutdown.is_shutting_down() => Response::builder()
+          .status(StatusCode::SERVICE_UNAVAILABLE)
+          .body(Body::from("Shutting down"))?,
+        "/health" if !broker.is_healthy() => Response::builder()
+          .status(StatusCode::SERVICE_UNAVAILABLE)
+          .body(Body::from("Message broker unavailable"))?,
         "/health" => Response::new(Body::from("OK")),
         _ => Response::builder()
           .status(StatusCode::NOT_FOUND)
@@ -97,7 +143,8 @@ impl hyper::service::Service<Request<Body>> for WebsocketService {
 
 pub async fn run_server(
   db_client: DatabaseClient,
-  amqp_connection: &lapin::Connection,
+  broker: Broker,
+  mut shutdown: ShutdownHandle,
 ) -> Result<(), BoxedError> {
   let addr = env::var("COMM_TUNNELBROKER_WEBSOCKET_ADDR")
     .unwrap_or_else(|_| format!("0.0.0.0:{}", &CONFIG.http_port));
@@ -109,24 +156,47 @@ pub async fn run_server(
   http.http1_only(true);
   http.http1_keep_alive(true);
 
-  while let Ok((stream, addr)) = listener.accept().await {
-    let channel = amqp_connection
-      .create_channel()
-      .await
-      .expect("Failed to create AMQP channel");
+  let connection_limiter =
+    ConnectionLimiter::new(CONFIG.max_connections_per_ip);
+
+  loop {
+    let (stream, addr) = tokio::select! {
+      accepted = listener.accept() => match accepted {
+        Ok(accepted) => accepted,
+        Err(_) => break,
+      },
+      _ = shutdown.wait() => {
+        info!("Stopped accepting WebSocket connections");
+        break;
+      }
+    };
+
     let connection = http
       .serve_connection(
         stream,
         WebsocketService {
-          channel,
+          broker: broker.clone(),
           db_client: db_client.clone(),
           addr,
+          shutdown: shutdown.clone(),
+          connection_limiter: connection_limiter.clone(),
         },
       )
       .with_upgrades();
 
+    let mut connection_shutdown = shutdown.clone();
     tokio::spawn(async move {
-      if let Err(err) = connection.await {
+      tokio::pin!(connection);
+      let result = tokio::select! {
+        result = connection.as_mut() => result,
+        _ = connection_shutdown.wait() => {
+          // Finishes the current request and closes idle keep-alive
+          // connections. Upgraded websockets are handled by their sessions.
+          connection.as_mut().graceful_shutdown();
+          connection.await
+        }
+      };
+      if let Err(err) = result {
         error!("Error serving HTTP/WebSocket connection: {:?}", err);
       }
     });
@@ -163,7 +233,8 @@ async fn accept_connection(
   hyper_ws: HyperWebsocket,
   addr: SocketAddr,
   db_client: DatabaseClient,
-  amqp_channel: lapin::Channel,
+  broker: Broker,
+  mut shutdown: ShutdownHandle,
 ) {
   debug!("Incoming connection from: {}", addr);
 
@@ -183,7 +254,7 @@ async fn accept_connection(
   // We don't know the identity of the device until it sends the session
   // request over the websocket connection
   let mut session = if let Some(Ok(first_msg)) = incoming.next().await {
-    match initiate_session(outgoing, first_msg, db_client, amqp_channel).await {
+    match initiate_session(outgoing, first_msg, db_client, broker).await {
       Ok(mut session) => {
         let response =
           tunnelbroker_messages::ConnectionInitializationResponse {
@@ -194,6 +265,7 @@ async fn accept_connection(
         session
           .send_message_to_device(Message::Text(serialized_response))
           .await;
+        session.register_presence().await;
         session
       }
       Err((err, outgoing)) => {
@@ -216,12 +288,8 @@ async fn accept_connection(
   loop {
     debug!("Polling for messages from: {}", addr);
     tokio::select! {
-      Some(Ok(delivery)) = session.next_amqp_message() => {
-        if let Ok(message) = std::str::from_utf8(&delivery.data) {
-          session.send_message_to_device(Message::Text(message.to_string())).await;
-        } else {
-          error!("Invalid payload");
-        }
+      Some(Ok(delivery)) = session.next_broker_message() => {
+        session.handle_broker_delivery(delivery).await;
       },
       device_message = incoming.next() => {
         let message: Message = match device_message {
@@ -246,6 +314,7 @@ async fn accept_connection(
           Message::Text(msg) => {
             got_heartbeat_response = true;
             ping_timeout = Box::pin(tokio::time::sleep(SOCKET_HEARTBEAT_TIMEOUT));
+            session.refresh_presence().await;
 
             let Some(message_status) = session.handle_websocket_frame_from_device(msg).await else {
               continue;
@@ -270,6 +339,11 @@ async fn accept_connection(
           }
         }
       },
+      _ = shutdown.wait() => {
+        info!("Draining connection to: {}", addr);
+        session.drain().await;
+        break;
+      },
       _ = &mut ping_timeout => {
         if !got_heartbeat_response {
           error!("Connection to {} died", addr);
@@ -296,18 +370,18 @@ async fn initiate_session<S: AsyncRead + AsyncWrite + Unpin>(
   outgoing: SplitSink<WebSocketStream<S>, Message>,
   frame: Message,
   db_client: DatabaseClient,
-  amqp_channel: lapin::Channel,
+  broker: Broker,
 ) -> Result<WebsocketSession<S>, ErrorWithStreamHandle<S>> {
   let initialized_session =
-    initialize_amqp(db_client.clone(), frame, &amqp_channel).await;
+    initialize_broker(db_client.clone(), frame, &broker).await;
 
   match initialized_session {
-    Ok((device_info, amqp_consumer)) => Ok(WebsocketSession::new(
+    Ok((device_info, consumer)) => Ok(WebsocketSession::new(
       outgoing,
       db_client,
       device_info,
-      amqp_channel,
-      amqp_consumer,
+      broker,
+      consumer,
     )),
     Err(e) => Err((e, outgoing)),
   }
@@ -1,35 +1,38 @@
+use crate::broker::{Broker, BrokerConsumer, BrokerDelivery, BrokerError};
 use crate::constants::{
-  CLIENT_RMQ_MSG_PRIORITY, DDB_RMQ_MSG_PRIORITY, MAX_RMQ_MSG_PRIORITY,
-  RMQ_CONSUMER_TAG,
+  CLIENT_RMQ_MSG_PRIORITY, DDB_RMQ_MSG_PRIORITY, PRESENCE_REFRESH_INTERVAL,
+  SHUTDOWN_MAX_RECONNECT_DELAY,
+};
+use crate::rate_limit::{RECIPIENT_RATE_LIMITER, SENDER_RATE_LIMITER};
+use crate::websockets::delivery_tracker::{
+  delivered_message_id, DeliveryTracker,
 };
 use aws_sdk_dynamodb::error::SdkError;
 use aws_sdk_dynamodb::operation::put_item::PutItemError;
 use derive_more;
 use futures_util::stream::SplitSink;
 use futures_util::SinkExt;
-use futures_util::StreamExt;
 use hyper_tungstenite::{tungstenite::Message, WebSocketStream};
-use lapin::message::Delivery;
-use lapin::options::{
-  BasicCancelOptions, BasicConsumeOptions, BasicPublishOptions,
-  QueueDeclareOptions, QueueDeleteOptions,
-};
-use lapin::types::FieldTable;
-use lapin::BasicProperties;
+use std::time::Duration;
 use tokio::io::AsyncRead;
 use tokio::io::AsyncWrite;
+use tokio::time::Instant;
 use tracing::{debug, error, info};
 use tunnelbroker_messages::{
   message_to_device_request_status::Failure,
-  message_to_device_request_status::MessageSentStatus, session::DeviceTypes,
-  Heartbeat, MessageToDevice, MessageToDeviceRequest, Messages,
+  message_to_device_request_status::MessageSentStatus,
+  message_to_device_request_status::Throttled, session::DeviceTypes,
+  DevicePresenceUpdate, Heartbeat, MessageToDevice, MessageToDeviceRequest,
+  Messages, ServerShutdown,
 };
 
-use crate::database::{self, DatabaseClient, MessageToDeviceExt};
+use crate::constants::dynamodb::undelivered_messages::SENDER_DEVICE_ID;
+use crate::database::{self, DatabaseClient};
 use crate::identity;
 
 pub struct DeviceInfo {
   pub device_id: String,
+  pub user_id: String,
   pub notify_token: Option<String>,
   pub device_type: DeviceTypes,
   pub device_app_version: Option<String>,
@@ -40,9 +43,16 @@ pub struct WebsocketSession<S> {
   tx: SplitSink<WebSocketStream<S>, Message>,
   db_client: DatabaseClient,
   pub device_info: DeviceInfo,
-  amqp_channel: lapin::Channel,
-  // Stream of messages from AMQP endpoint
-  amqp_consumer: lapin::Consumer,
+  broker: Broker,
+  // Stream of messages from the device queue
+  consumer: BrokerConsumer,
+  // Cleared once the consumer is cancelled
+  consuming: bool,
+  // Messages sent to the device, waiting for confirmation
+  deliveries: DeliveryTracker,
+  // Identifies the presence record of this connection, set once registered
+  connected_since: Option<String>,
+  last_presence_refresh: Instant,
 }
 
 #[derive(
@@ -52,7 +62,7 @@ pub enum SessionError {
   InvalidMessage,
   SerializationError(serde_json::Error),
   MessageError(database::MessageErrors),
-  AmqpError(lapin::Error),
+  BrokerError(BrokerError),
   InternalError,
   UnauthorizedDevice,
   PersistenceError(SdkError<PutItemError>),
@@ -68,6 +78,7 @@ pub async fn handle_first_message_from_device(
     Messages::ConnectionInitializationMessage(mut session_info) => {
       let device_info = DeviceInfo {
         device_id: session_info.device_id.clone(),
+        user_id: session_info.user_id.clone(),
         notify_token: session_info.notify_token.take(),
         device_type: session_info.device_type,
         device_app_version: session_info.device_app_version.take(),
@@ -111,7 +122,7 @@ pub async fn handle_first_message_from_device(
 
 async fn publish_persisted_messages(
   db_client: &DatabaseClient,
-  amqp_channel: &lapin::Channel,
+  broker: &Broker,
   device_info: &DeviceInfo,
 ) -> Result<(), SessionError> {
   let messages = db_client
@@ -123,17 +134,13 @@ async fn publish_persisted_messages(
     });
 
   for message in messages {
-    let message_to_device = MessageToDevice::from_hashmap(message)?;
+    let serialized_message = database::serialize_persisted_message(message)?;
 
-    let serialized_message = serde_json::to_string(&message_to_device)?;
-
-    amqp_channel
-      .basic_publish(
-        "",
-        &message_to_device.device_id,
-        BasicPublishOptions::default(),
+    broker
+      .publish(
+        &device_info.device_id,
         serialized_message.as_bytes(),
-        BasicProperties::default().with_priority(DDB_RMQ_MSG_PRIORITY),
+        DDB_RMQ_MSG_PRIORITY,
       )
       .await?;
   }
@@ -142,11 +149,11 @@ async fn publish_persisted_messages(
   Ok(())
 }
 
-pub async fn initialize_amqp(
+pub async fn initialize_broker(
   db_client: DatabaseClient,
   frame: Message,
-  amqp_channel: &lapin::Channel,
-) -> Result<(DeviceInfo, lapin::Consumer), SessionError> {
+  broker: &Broker,
+) -> Result<(DeviceInfo, BrokerConsumer), SessionError> {
   let device_info = match frame {
     Message::Text(payload) => {
       handle_first_message_from_device(&payload).await?
@@ -157,38 +164,185 @@ pub async fn initialize_amqp(
     }
   };
 
-  let mut args = FieldTable::default();
-  args.insert("x-max-priority".into(), MAX_RMQ_MSG_PRIORITY.into());
-  amqp_channel
-    .queue_declare(&device_info.device_id, QueueDeclareOptions::default(), args)
-    .await?;
-
-  publish_persisted_messages(&db_client, amqp_channel, &device_info).await?;
-
-  let amqp_consumer = amqp_channel
-    .basic_consume(
-      &device_info.device_id,
-      RMQ_CONSUMER_TAG,
-      BasicConsumeOptions::default(),
-      FieldTable::default(),
-    )
-    .await?;
-  Ok((device_info, amqp_consumer))
+  broker.declare_device_queue(&device_info.device_id).await?;
+
+  publish_persisted_messages(&db_client, broker, &device_info).await?;
+
+  let consumer = broker.consume(&device_info.device_id).await?;
+  Ok((device_info, consumer))
 }
 impl<S: AsyncRead + AsyncWrite + Unpin> WebsocketSession<S> {
   pub fn new(
     tx: SplitSink<WebSocketStream<S>, Message>,
     db_client: DatabaseClient,
     device_info: DeviceInfo,
-    amqp_channel: lapin::Channel,
-    amqp_consumer: lapin::Consumer,
+    broker: Broker,
+    consumer: BrokerConsumer,
   ) -> Self {
     Self {
       tx,
       db_client,
       device_info,
-      amqp_channel,
-      amqp_consumer,
+      broker,
+      consumer,
+      consuming: true,
+      deliveries: DeliveryTracker::new(),
+      connected_since: None,
+      last_presence_refresh: Instant::now(),
+    }
+  }
+
+  /// Records that the device is connected to this instance and notifies
+  /// subscribed devices of the same user
+  pub async fn register_presence(&mut self) {
+    match self
+      .db_client
+      .set_device_connected(
+        &self.device_info.device_id,
+        &self.device_info.user_id,
+      )
+      .await
+    {
+      Ok(connected_since) => {
+        self.connected_since = Some(connected_since);
+        self.last_presence_refresh = Instant::now();
+        self.notify_presence_subscribers().await;
+      }
+      Err(e) => error!("Failed to register device presence: {}", e),
+    }
+  }
+
+  /// Refreshes `lastHeartbeat` of the presence record, at most once
+  /// per `PRESENCE_REFRESH_INTERVAL`
+  pub async fn refresh_presence(&mut self) {
+    if self.connected_since.is_none()
+      || self.last_presence_refresh.elapsed() < PRESENCE_REFRESH_INTERVAL
+    {
+      return;
+    }
+    self.last_presence_refresh = Instant::now();
+
+    if let Err(e) = self
+      .db_client
+      .update_device_heartbeat(&self.device_info.device_id)
+      .await
+    {
+      debug!("Failed to refresh device presence: {}", e);
+    }
+  }
+
+  async fn unregister_presence(&mut self) {
+    let Some(connected_since) = self.connected_since.take() else {
+      return;
+    };
+
+    match self
+      .db_client
+      .set_device_disconnected(&self.device_info.device_id, &connected_since)
+      .await
+    {
+      Ok(()) => self.notify_presence_subscribers().await,
+      // The device has already opened a newer connection
+      Err(e) => debug!("Device presence not removed: {}", e),
+    }
+  }
+
+  fn presence_update(&self) -> DevicePresenceUpdate {
+    DevicePresenceUpdate {
+      device_id: self.device_info.device_id.clone(),
+      connected: self.connected_since.is_some(),
+      connected_since: self.connected_since.clone(),
+    }
+  }
+
+  /// Sends the current presence of this device to other connected devices
+  /// of the same user that subscribed to presence updates
+  async fn notify_presence_subscribers(&self) {
+    let presences = match self
+      .db_client
+      .get_user_devices_presence(&self.device_info.user_id)
+      .await
+    {
+      Ok(presences) => presences,
+      Err(e) => {
+        error!("Failed to retrieve presence of user devices: {}", e);
+        return;
+      }
+    };
+
+    let Ok(serialized_update) = serde_json::to_string(&self.presence_update())
+    else {
+      error!("Failed to serialize presence update");
+      return;
+    };
+
+    let subscribers = presences.into_iter().filter(|presence| {
+      presence.presence_subscription
+        && !presence.is_stale()
+        && presence.device_id != self.device_info.device_id
+    });
+
+    for subscriber in subscribers {
+      // Presence updates are not persisted. If the subscriber has no queue,
+      // the message is dropped.
+      if let Err(e) = self
+        .broker
+        .publish(
+          &subscriber.device_id,
+          serialized_update.as_bytes(),
+          CLIENT_RMQ_MSG_PRIORITY,
+        )
+        .await
+      {
+        error!(
+          "Failed to publish presence update to {}: {}",
+          subscriber.device_id, e
+        );
+      }
+    }
+  }
+
+  async fn handle_presence_subscription(&mut self, subscribe: bool) {
+    if let Err(e) = self
+      .db_client
+      .set_presence_subscription(&self.device_info.device_id, subscribe)
+      .await
+    {
+      error!("Failed to update presence subscription: {}", e);
+      return;
+    }
+    if !subscribe {
+      return;
+    }
+
+    // Let the device know which devices are connected at the moment
+    let presences = match self
+      .db_client
+      .get_user_devices_presence(&self.device_info.user_id)
+      .await
+    {
+      Ok(presences) => presences,
+      Err(e) => {
+        error!("Failed to retrieve presence of user devices: {}", e);
+        return;
+      }
+    };
+
+    for presence in presences {
+      if presence.is_stale() || presence.device_id == self.device_info.device_id
+      {
+        continue;
+      }
+      let update = DevicePresenceUpdate {
+        device_id: presence.device_id,
+        connected: true,
+        connected_since: Some(presence.connected_since.to_rfc3339()),
+      };
+      if let Ok(serialized_update) = serde_json::to_string(&update) {
+        self
+          .send_message_to_device(Message::Text(serialized_update))
+          .await;
+      }
     }
   }
 
@@ -202,6 +356,9 @@ impl<S: AsyncRead + AsyncWrite + Unpin> WebsocketSession<S> {
         &message_request.device_id,
         &message_request.payload,
         &message_request.client_message_id,
+        message_request
+          .request_delivery_receipt
+          .then_some(self.device_info.device_id.as_str()),
       )
       .await?;
 
@@ -214,13 +371,11 @@ impl<S: AsyncRead + AsyncWrite + Unpin> WebsocketSession<S> {
     let serialized_message = serde_json::to_string(&message_to_device)?;
 
     let publish_result = self
-      .amqp_channel
-      .basic_publish(
-        "",
+      .broker
+      .publish(
         &message_request.device_id,
-        BasicPublishOptions::default(),
         serialized_message.as_bytes(),
-        BasicProperties::default().with_priority(CLIENT_RMQ_MSG_PRIORITY),
+        CLIENT_RMQ_MSG_PRIORITY,
       )
       .await;
 
@@ -230,11 +385,41 @@ impl<S: AsyncRead + AsyncWrite + Unpin> WebsocketSession<S> {
         .delete_message(&self.device_info.device_id, &message_id)
         .await
         .expect("Error deleting message");
-      return Err(SessionError::AmqpError(publish_error));
+      return Err(SessionError::BrokerError(publish_error));
     }
     Ok(())
   }
 
+  /// Informs the sender that this device confirmed the message. The receipt
+  /// is persisted, so it's delivered even if the sender is offline.
+  async fn send_delivery_receipt(
+    &self,
+    sender_device_id: &str,
+    message_id: &str,
+  ) -> Result<(), SessionError> {
+    debug!("Sending delivery receipt to {}", sender_device_id);
+
+    let receipt = self
+      .db_client
+      .persist_delivery_receipt(
+        sender_device_id,
+        &self.device_info.device_id,
+        message_id,
+      )
+      .await?;
+
+    let serialized_receipt = serde_json::to_string(&receipt)?;
+    self
+      .broker
+      .publish(
+        sender_device_id,
+        serialized_receipt.as_bytes(),
+        CLIENT_RMQ_MSG_PRIORITY,
+      )
+      .await?;
+    Ok(())
+  }
+
   pub async fn handle_websocket_frame_from_device(
     &mut self,
     msg: String,
@@ -248,14 +433,44 @@ impl<S: AsyncRead + AsyncWrite + Unpin> WebsocketSession<S> {
         debug!("Received heartbeat from: {}", self.device_info.device_id);
         None
       }
+      Messages::DevicePresenceSubscription(subscription) => {
+        self
+          .handle_presence_subscription(subscription.subscribe)
+          .await;
+        None
+      }
       Messages::MessageReceiveConfirmation(confirmation) => {
         for message_id in confirmation.message_ids {
-          if let Err(e) = self
+          let deleted_message = match self
             .db_client
             .delete_message(&self.device_info.device_id, &message_id)
             .await
           {
-            error!("Failed to delete message: {}:", e);
+            Ok(output) => output.attributes,
+            Err(e) => {
+              error!("Failed to delete message: {}:", e);
+              continue;
+            }
+          };
+
+          if let Some(delivery) = self.deliveries.confirm(&message_id) {
+            if let Err(e) = delivery.ack().await {
+              error!("Failed to acknowledge message: {}", e);
+            }
+          }
+
+          // Message might have been already confirmed and deleted
+          let sender_device_id = deleted_message
+            .as_ref()
+            .and_then(|attributes| attributes.get(SENDER_DEVICE_ID))
+            .and_then(|sender| sender.as_s().ok());
+          if let Some(sender_device_id) = sender_device_id {
+            if let Err(e) = self
+              .send_delivery_receipt(sender_device_id, &message_id)
+              .await
+            {
+              error!("Failed to send delivery receipt: {}", e);
+            }
           }
         }
 
@@ -264,6 +479,20 @@ impl<S: AsyncRead + AsyncWrite + Unpin> WebsocketSession<S> {
       Messages::MessageToDeviceRequest(message_request) => {
         debug!("Received message for {}", message_request.device_id);
 
+        if let Err(retry_after) = self.check_rate_limits(&message_request) {
+          debug!(
+            "Throttled message from {} to {}",
+            self.device_info.device_id, message_request.device_id
+          );
+          return Some(MessageSentStatus::Throttled(Throttled {
+            id: message_request.client_message_id,
+            retry_after_ms: retry_after
+              .as_millis()
+              .try_into()
+              .unwrap_or(u64::MAX),
+          }));
+        }
+
         let result = self.handle_message_to_device(&message_request).await;
         Option::from(self.get_message_to_device_status(
           &message_request.client_message_id,
@@ -277,10 +506,53 @@ impl<S: AsyncRead + AsyncWrite + Unpin> WebsocketSession<S> {
     }
   }
 
-  pub async fn next_amqp_message(
+  /// Returns the time after which the message can be sent again if the
+  /// sender or the recipient exceeded the rate limit
+  fn check_rate_limits(
+    &self,
+    message_request: &MessageToDeviceRequest,
+  ) -> Result<(), Duration> {
+    SENDER_RATE_LIMITER.check(&self.device_info.device_id)?;
+    RECIPIENT_RATE_LIMITER.check(&message_request.device_id)
+  }
+
+  pub async fn next_broker_message(
     &mut self,
-  ) -> Option<Result<Delivery, lapin::Error>> {
-    self.amqp_consumer.next().await
+  ) -> Option<Result<BrokerDelivery, BrokerError>> {
+    self.consumer.next().await
+  }
+
+  /// Sends the message to the device. Messages which the device confirms
+  /// are acknowledged once confirmed, others right away.
+  pub async fn handle_broker_delivery(&mut self, delivery: BrokerDelivery) {
+    let message_id = delivered_message_id(&delivery.data);
+    if let Some(message_id) = &message_id {
+      if self.deliveries.is_duplicate(message_id) {
+        debug!("Skipping duplicate message: {}", message_id);
+        if let Err(e) = delivery.ack().await {
+          error!("Failed to acknowledge duplicate message: {}", e);
+        }
+        return;
+      }
+    }
+
+    match std::str::from_utf8(&delivery.data) {
+      Ok(message) => {
+        self
+          .send_message_to_device(Message::Text(message.to_string()))
+          .await
+      }
+      Err(_) => error!("Invalid payload"),
+    }
+
+    match message_id {
+      Some(message_id) => self.deliveries.track(message_id, delivery),
+      None => {
+        if let Err(e) = delivery.ack().await {
+          error!("Failed to acknowledge message: {}", e);
+        }
+      }
+    }
   }
 
   pub async fn send_message_to_device(&mut self, message: Message) {
@@ -289,29 +561,64 @@ impl<S: AsyncRead + AsyncWrite + Unpin> WebsocketSession<S> {
     }
   }
 
+  async fn cancel_consumer(&mut self) {
+    if !self.consuming {
+      return;
+    }
+    self.consuming = false;
+
+    if let Err(e) = self.broker.cancel(self.consumer.tag()).await {
+      error!("Failed to cancel consumer: {}", e);
+    }
+  }
+
+  /// Prepares the session to be closed during shutdown. Stops consuming from
+  /// the device queue, forwards messages which were already received from
+  /// the broker and asks the device to reconnect to another instance.
+  pub async fn drain(&mut self) {
+    self.cancel_consumer().await;
+
+    while let Some(delivery) = self.consumer.next().await {
+      match delivery {
+        Ok(delivery) => self.handle_broker_delivery(delivery).await,
+        Err(e) => {
+          error!("Failed to receive in-flight message: {}", e);
+          break;
+        }
+      }
+    }
+
+    let shutdown_message = ServerShutdown {
+      max_reconnect_delay_ms: SHUTDOWN_MAX_RECONNECT_DELAY.as_millis() as u64,
+    };
+    match serde_json::to_string(&shutdown_message) {
+      Ok(serialized) => {
+        self.send_message_to_device(Message::Text(serialized)).await
+      }
+      Err(e) => error!("Failed to serialize shutdown message: {}", e),
+    }
+  }
+
   // Release WebSocket and remove from active connections
   pub async fn close(&mut self) {
     if let Err(e) = self.tx.close().await {
       debug!("Failed to close WebSocket session: {}", e);
     }
 
-    if let Err(e) = self
-      .amqp_channel
-      .basic_cancel(
-        self.amqp_consumer.tag().as_str(),
-        BasicCancelOptions::default(),
-      )
-      .await
-    {
-      error!("Failed to cancel consumer: {}", e);
-    }
+    self.unregister_presence().await;
+
+    // Unconfirmed messages are returned to the queue by the broker once the
+    // consumer is cancelled. They remain persisted in DynamoDB and are
+    // redelivered when the device reconnects.
+    debug!(
+      "Closing session with {} unconfirmed messages",
+      self.deliveries.pending_count()
+    );
+    self.cancel_consumer().await;
 
     if let Err(e) = self
-      .amqp_channel
-      .queue_delete(
-        self.device_info.device_id.as_str(),
-        QueueDeleteOptions::default(),
-      )
+      .broker
+      .delete_device_queue(&self.device_info.device_id)
       .await
     {
       error!("Failed to delete queue: {}", e);
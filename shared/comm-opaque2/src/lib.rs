@@ -56,3 +56,49 @@ pub fn test_register_and_login() {
     server_login.session_key.unwrap()
   );
 }
+
+#[test]
+pub fn test_finish_deserialized_login() {
+  use rand::rngs::OsRng;
+
+  let pass = "test";
+  let username = "alice";
+
+  let server_setup = opaque_ke::ServerSetup::<Cipher>::new(&mut OsRng);
+
+  let mut client_register = client::Registration::new();
+  let client_message = client_register.start(pass).unwrap();
+  let mut server_register = server::Registration::new();
+  let server_response = server_register
+    .start(&server_setup, &client_message, username.as_bytes())
+    .unwrap();
+  let client_upload = client_register.finish(pass, &server_response).unwrap();
+  let password_file_bytes = server_register.finish(&client_upload).unwrap();
+
+  let mut client_login = client::Login::new();
+  let client_request = client_login.start(pass).unwrap();
+
+  let mut server_login = server::Login::new();
+  assert!(server_login.serialize().is_none());
+  let server_response = server_login
+    .start(
+      &server_setup,
+      &password_file_bytes,
+      &client_request,
+      username.as_bytes(),
+    )
+    .unwrap();
+
+  // Login finished by another server instance
+  let login_state = server_login.serialize().unwrap();
+  let mut restored_server_login =
+    server::Login::deserialize(&login_state).unwrap();
+
+  let client_upload = client_login.finish(&server_response).unwrap();
+  restored_server_login.finish(&client_upload).unwrap();
+
+  assert_eq!(
+    client_login.session_key().unwrap(),
+    restored_server_login.session_key.unwrap()
+  );
+}
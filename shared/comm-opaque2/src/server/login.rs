@@ -46,6 +46,22 @@ impl Login {
     Ok(result.message.serialize().to_vec())
   }
 
+  /// Serializes the state between `start` and `finish`, so that the login
+  /// can be finished by a different server instance
+  pub fn serialize(&self) -> Option<Vec<u8>> {
+    self.state.as_ref().map(|state| state.serialize().to_vec())
+  }
+
+  /// Restores a login serialized after `start`
+  pub fn deserialize(state_bytes: &[u8]) -> Result<Login, ProtocolError> {
+    let state = ServerLogin::deserialize(state_bytes)?;
+    Ok(Login {
+      state: Some(state),
+      rng: OsRng,
+      session_key: None,
+    })
+  }
+
   pub fn finish(
     &mut self,
     response_payload: &[u8],
@@ -1,4 +1,5 @@
 use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::str::FromStr;
 
 use crate::error::Error;
 pub use crate::identity::protos::client::DeviceType;
@@ -32,6 +33,23 @@ impl Display for DeviceType {
   }
 }
 
+impl FromStr for DeviceType {
+  type Err = crate::error::Error;
+
+  /// Parses the value returned by `DeviceType::to_string()`
+  fn from_str(value: &str) -> Result<Self, Self::Err> {
+    match value {
+      "keyserver" => Ok(DeviceType::Keyserver),
+      "web" => Ok(DeviceType::Web),
+      "ios" => Ok(DeviceType::Ios),
+      "android" => Ok(DeviceType::Android),
+      "windows" => Ok(DeviceType::Windows),
+      "macos" => Ok(DeviceType::MacOs),
+      _ => Err(Error::InvalidDeviceType),
+    }
+  }
+}
+
 #[cfg(test)]
 mod device_tests {
   use super::*;
@@ -54,4 +72,15 @@ mod device_tests {
   fn test_display_device_type() {
     assert_eq!(format!("{}", DeviceType::Ios), "ios");
   }
+
+  #[test]
+  fn test_parse_device_type() {
+    let device_type: DeviceType = "macos".parse().unwrap();
+    assert_eq!(device_type, DeviceType::MacOs);
+    assert_eq!(
+      DeviceType::Web.to_string().parse::<DeviceType>().unwrap(),
+      DeviceType::Web
+    );
+    assert!("MacOS".parse::<DeviceType>().is_err());
+  }
 }
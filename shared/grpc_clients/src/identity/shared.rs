@@ -4,6 +4,7 @@ use tonic::{
   Request, Status,
 };
 
+#[derive(Clone)]
 pub struct CodeVersionLayer {
   pub(crate) version: u64,
   pub(crate) device_type: String,
@@ -2,7 +2,9 @@
 use crate::error::Error;
 
 use super::get_unauthenticated_client;
-use crate::identity::protos::unauthenticated::VerifyUserAccessTokenRequest;
+use crate::identity::protos::unauthenticated::{
+  RefreshAccessTokenRequest, VerifyUserAccessTokenRequest,
+};
 
 use tonic::Request;
 
@@ -28,3 +30,27 @@ pub async fn verify_user_access_token(
   let response = grpc_client.verify_user_access_token(request).await?;
   Ok(response.into_inner().token_valid)
 }
+
+/// Replaces the access token of the device with a new one, which is returned.
+/// The previous token stops working.
+pub async fn refresh_access_token(
+  identity_url: &str,
+  user_id: &str,
+  device_id: &str,
+  access_token: &str,
+  code_version: u64,
+  device_type: String,
+) -> Result<String, Error> {
+  let mut grpc_client =
+    get_unauthenticated_client(identity_url, code_version, device_type).await?;
+
+  let message = RefreshAccessTokenRequest {
+    user_id: user_id.to_string(),
+    device_id_key: device_id.to_string(),
+    access_token: access_token.to_string(),
+  };
+
+  let request = Request::new(message);
+  let response = grpc_client.refresh_access_token(request).await?;
+  Ok(response.into_inner().access_token)
+}
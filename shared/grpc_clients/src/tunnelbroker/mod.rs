@@ -2,9 +2,13 @@ pub mod protos {
   tonic::include_proto!("tunnelbroker");
 }
 use protos::tunnelbroker_service_client::TunnelbrokerServiceClient;
-use tonic::transport::Channel;
+use tonic::{
+  codegen::InterceptedService, service::Interceptor, transport::Channel,
+  Request, Status,
+};
 
 use crate::error::Error;
+use crate::identity::shared::ToMetadataValueAscii;
 
 pub async fn create_tunnelbroker_client(
   url: &str,
@@ -12,3 +16,90 @@ pub async fn create_tunnelbroker_client(
   let channel = crate::get_grpc_service_channel(url).await?;
   Ok(TunnelbrokerServiceClient::new(channel))
 }
+
+/// Authenticates requests of other services, which is required by RPCs not
+/// meant to be called by devices
+pub struct ServicesAuthLayer {
+  authorization_token: String,
+}
+
+impl Interceptor for ServicesAuthLayer {
+  fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+    let bearer = format!("Bearer {}", self.authorization_token);
+    request
+      .metadata_mut()
+      .insert("authorization", bearer.parse_to_ascii()?);
+
+    Ok(request)
+  }
+}
+
+pub type AuthenticatedTunnelbrokerClient =
+  TunnelbrokerServiceClient<InterceptedService<Channel, ServicesAuthLayer>>;
+
+/// Creates a client which sends the given services token with each request.
+/// The token is the bearer value of `AuthorizationCredential::ServicesToken`
+/// from comm-services-lib.
+pub async fn create_authenticated_tunnelbroker_client(
+  url: &str,
+  authorization_token: String,
+) -> Result<AuthenticatedTunnelbrokerClient, Error> {
+  let channel = crate::get_grpc_service_channel(url).await?;
+  let interceptor = ServicesAuthLayer {
+    authorization_token,
+  };
+  Ok(TunnelbrokerServiceClient::with_interceptor(
+    channel,
+    interceptor,
+  ))
+}
+
+/// Tells Tunnelbroker to stop accepting revoked access tokens of the device,
+/// or of all devices of the user if `device_id` is not provided
+pub async fn invalidate_access_tokens(
+  client: &mut AuthenticatedTunnelbrokerClient,
+  user_id: &str,
+  device_id: Option<&str>,
+) -> Result<(), Error> {
+  let request = protos::InvalidateAccessTokensRequest {
+    user_id: user_id.to_string(),
+    device_id: device_id.map(str::to_string),
+  };
+  client.invalidate_access_tokens(request).await?;
+  Ok(())
+}
+
+/// Sends multiple messages in a single request. Returns messages which
+/// failed to be sent, others are delivered normally.
+pub async fn send_messages_to_devices(
+  client: &mut AuthenticatedTunnelbrokerClient,
+  messages: Vec<protos::MessageToDevice>,
+) -> Result<Vec<protos::MessageSendFailure>, Error> {
+  let request = protos::MessagesToDevices { messages };
+  let response = client.send_messages_to_devices(request).await?;
+  Ok(response.into_inner().failures)
+}
+
+pub async fn get_device_queue_stats(
+  client: &mut AuthenticatedTunnelbrokerClient,
+  device_id: &str,
+) -> Result<protos::DeviceQueueStats, Error> {
+  let request = protos::DeviceQueueRequest {
+    device_id: device_id.to_string(),
+  };
+  let response = client.get_device_queue_stats(request).await?;
+  Ok(response.into_inner())
+}
+
+/// Deletes all undelivered messages of the device. Returns the number of
+/// deleted messages.
+pub async fn purge_device_queue(
+  client: &mut AuthenticatedTunnelbrokerClient,
+  device_id: &str,
+) -> Result<u64, Error> {
+  let request = protos::DeviceQueueRequest {
+    device_id: device_id.to_string(),
+  };
+  let response = client.purge_device_queue(request).await?;
+  Ok(response.into_inner().deleted_messages_count)
+}
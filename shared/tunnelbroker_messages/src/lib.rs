@@ -1,4 +1,3 @@
 pub mod messages;
 
-pub use message_to_device_request_status::*;
 pub use messages::*;
@@ -0,0 +1,76 @@
+//! Messages used to subscribe to and receive presence updates about other
+//! devices of the same user.
+
+use serde::{Deserialize, Serialize};
+
+/// Message sent by a device to Tunnelbroker to start (or stop) receiving
+/// presence updates about other devices of the same user.
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub struct DevicePresenceSubscription {
+  pub subscribe: bool,
+}
+
+/// Message sent from Tunnelbroker to a subscribed device when another device
+/// of the same user opens or closes its websocket connection.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub struct DevicePresenceUpdate {
+  #[serde(rename = "deviceID")]
+  pub device_id: String,
+  pub connected: bool,
+  /// RFC 3339 timestamp of when the connection was opened.
+  /// Only present when `connected` is true.
+  pub connected_since: Option<String>,
+}
+
+#[cfg(test)]
+mod device_presence_tests {
+  use super::*;
+
+  #[test]
+  fn test_presence_subscription_deserialization() {
+    let example_payload = r#"{
+      "type": "DevicePresenceSubscription",
+      "subscribe": true
+    }"#;
+
+    let request =
+      serde_json::from_str::<DevicePresenceSubscription>(example_payload)
+        .unwrap();
+    assert!(request.subscribe);
+  }
+
+  #[test]
+  fn test_presence_update_deserialization() {
+    let example_payload = r#"{
+      "type": "DevicePresenceUpdate",
+      "deviceID": "alice",
+      "connected": true,
+      "connectedSince": "2023-10-10T10:00:00+00:00"
+    }"#;
+
+    let update =
+      serde_json::from_str::<DevicePresenceUpdate>(example_payload).unwrap();
+    assert_eq!(update.device_id, "alice");
+    assert!(update.connected);
+    assert_eq!(
+      update.connected_since.as_deref(),
+      Some("2023-10-10T10:00:00+00:00")
+    );
+  }
+
+  #[test]
+  fn test_presence_update_disconnected_deserialization() {
+    let example_payload = r#"{
+      "type": "DevicePresenceUpdate",
+      "deviceID": "alice",
+      "connected": false
+    }"#;
+
+    let update =
+      serde_json::from_str::<DevicePresenceUpdate>(example_payload).unwrap();
+    assert!(!update.connected);
+    assert_eq!(update.connected_since, None);
+  }
+}
@@ -0,0 +1,41 @@
+//! Message sent from Tunnelbroker to the sender of a message, once the
+//! recipient confirmed that it received the message. Sent only if the sender
+//! requested a delivery receipt in MessageToDeviceRequest.
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub struct MessageDelivered {
+  /// ID of this receipt. The sender should confirm it with
+  /// MessageReceiveConfirmation, like any other message.
+  #[serde(rename = "messageID")]
+  pub message_id: String,
+  /// ID of the delivered message, as provided by the sender
+  #[serde(rename = "clientMessageID")]
+  pub client_message_id: String,
+  /// Device that received the message
+  #[serde(rename = "deviceID")]
+  pub device_id: String,
+}
+
+#[cfg(test)]
+mod message_delivered_tests {
+  use super::*;
+
+  #[test]
+  fn test_message_delivered_deserialization() {
+    let example_payload = r#"{
+      "type": "MessageDelivered",
+      "messageID": "id234",
+      "clientMessageID": "client123",
+      "deviceID": "alice"
+    }"#;
+
+    let receipt =
+      serde_json::from_str::<MessageDelivered>(example_payload).unwrap();
+    assert_eq!(receipt.message_id, "id234");
+    assert_eq!(receipt.client_message_id, "client123");
+    assert_eq!(receipt.device_id, "alice");
+  }
+}
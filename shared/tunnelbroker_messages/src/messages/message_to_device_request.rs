@@ -10,6 +10,10 @@ pub struct MessageToDeviceRequest {
   #[serde(rename = "deviceID")]
   pub device_id: String,
   pub payload: String,
+  /// If set, Tunnelbroker sends MessageDelivered back to the sender once
+  /// the recipient confirms the message
+  #[serde(default)]
+  pub request_delivery_receipt: bool,
 }
 
 #[cfg(test)]
@@ -30,5 +34,21 @@ mod message_to_device_request_tests {
     assert_eq!(request.client_message_id, "client123");
     assert_eq!(request.device_id, "alice");
     assert_eq!(request.payload, "message from Bob");
+    assert!(!request.request_delivery_receipt);
+  }
+
+  #[test]
+  fn test_message_to_device_request_with_receipt_deserialization() {
+    let example_payload = r#"{
+      "type": "MessageToDeviceRequest",
+      "clientMessageID": "client123",
+      "deviceID": "alice",
+      "payload": "message from Bob",
+      "requestDeliveryReceipt": true
+    }"#;
+
+    let request =
+      serde_json::from_str::<MessageToDeviceRequest>(example_payload).unwrap();
+    assert!(request.request_delivery_receipt);
   }
 }
@@ -9,6 +9,14 @@ pub struct Failure {
   pub error: String,
 }
 
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Throttled {
+  pub id: String,
+  /// Time after which the message can be sent again
+  pub retry_after_ms: u64,
+}
+
 #[derive(Serialize, Deserialize, PartialEq, Debug)]
 #[serde(tag = "type", content = "data")]
 pub enum MessageSentStatus {
@@ -25,6 +33,9 @@ pub enum MessageSentStatus {
   /// returned back.
   /// It becomes impossible to retrieve the message ID in such circumstances.
   SerializationError(String),
+  /// The sender or the recipient exceeded the rate limit. The message wasn't
+  /// processed and should be sent again later.
+  Throttled(Throttled),
 }
 
 #[derive(Serialize, Deserialize, PartialEq, Debug)]
@@ -47,7 +58,8 @@ mod send_confirmation_tests {
             {"type": "Success", "data": "id456"},
             {"type": "Error", "data": {"id": "id789", "error": "Something went wrong"}},
             {"type": "SerializationError", "data": "message"},
-            {"type": "InvalidRequest"}
+            {"type": "InvalidRequest"},
+            {"type": "Throttled", "data": {"id": "id000", "retryAfterMs": 500}}
           ]
         }"#;
 
@@ -64,6 +76,10 @@ mod send_confirmation_tests {
       }),
       MessageSentStatus::SerializationError("message".to_string()),
       MessageSentStatus::InvalidRequest,
+      MessageSentStatus::Throttled(Throttled {
+        id: String::from("id000"),
+        retry_after_ms: 500,
+      }),
     ];
 
     assert_eq!(request.client_message_ids, expected_client_message_ids);
@@ -1,21 +1,27 @@
 //! Messages sent between Tunnelbroker and a device.
 
 pub mod connection_initialization_response;
+pub mod device_presence;
 pub mod heartbeat;
 pub mod keys;
+pub mod message_delivered;
 pub mod message_receive_confirmation;
 pub mod message_to_device;
 pub mod message_to_device_request;
 pub mod message_to_device_request_status;
+pub mod server_shutdown;
 pub mod session;
 
 pub use connection_initialization_response::*;
+pub use device_presence::*;
 pub use heartbeat::*;
 pub use keys::*;
+pub use message_delivered::*;
 pub use message_receive_confirmation::*;
 pub use message_to_device::*;
 pub use message_to_device_request::*;
 pub use message_to_device_request_status::*;
+pub use server_shutdown::*;
 pub use session::*;
 
 use serde::{Deserialize, Serialize};
@@ -41,5 +47,9 @@ pub enum Messages {
   MessageToDeviceRequest(MessageToDeviceRequest),
   MessageToDevice(MessageToDevice),
   MessageReceiveConfirmation(MessageReceiveConfirmation),
+  MessageDelivered(MessageDelivered),
+  DevicePresenceSubscription(DevicePresenceSubscription),
+  DevicePresenceUpdate(DevicePresenceUpdate),
+  ServerShutdown(ServerShutdown),
   Heartbeat(Heartbeat),
 }
@@ -0,0 +1,32 @@
+//! Message sent from Tunnelbroker to connected devices when the instance
+//! holding their connection is shutting down. The connection is closed
+//! shortly afterwards and the device should reconnect, which routes it to
+//! another instance.
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub struct ServerShutdown {
+  /// Devices should reconnect after a random delay up to this value,
+  /// so that they don't all reconnect at the same time
+  pub max_reconnect_delay_ms: u64,
+}
+
+#[cfg(test)]
+mod server_shutdown_tests {
+  use super::*;
+
+  #[test]
+  fn test_server_shutdown_serialization() {
+    let message = ServerShutdown {
+      max_reconnect_delay_ms: 5000,
+    };
+
+    let serialized = serde_json::to_string(&message).unwrap();
+    assert_eq!(
+      serialized,
+      r#"{"type":"ServerShutdown","maxReconnectDelayMs":5000}"#
+    );
+  }
+}